@@ -0,0 +1,75 @@
+use criterion::{criterion_group, criterion_main, Criterion};
+use mpris_nowplaying::artwork::{
+    self, ArtworkInfo, ArtworkOptions, ArtworkOrigin, ArtworkVariant, LoadedArtwork,
+};
+use std::path::Path;
+
+/// A cover of a common size, with enough detail that it doesn't compress to nothing.
+fn write_cover(path: &Path) {
+    let cover = image::RgbImage::from_fn(1200, 1200, |x, y| {
+        image::Rgb([(x % 256) as u8, (y % 256) as u8, ((x ^ y) % 256) as u8])
+    });
+    cover.save(path).unwrap();
+}
+
+/// `options` without the images loaded so far, so the file is read again.
+fn unloaded(options: &ArtworkOptions) -> ArtworkOptions {
+    ArtworkOptions {
+        loaded: LoadedArtwork::default(),
+        ..options.clone()
+    }
+}
+
+/// Reading a local cover as it is, generating each variant from scratch or serving it from the cache, and sharing what another client already loaded.
+fn load(c: &mut Criterion) {
+    let dir = std::env::temp_dir().join("mpris-nowplaying-bench");
+    std::fs::create_dir_all(&dir).unwrap();
+    let cover = dir.join("cover.png");
+    write_cover(&cover);
+
+    let info = |variant| ArtworkInfo {
+        src: format!("file://{}", cover.display()),
+        origin: ArtworkOrigin::Url,
+        variant,
+    };
+    let uncached = ArtworkOptions {
+        cache_dir: None,
+        ..Default::default()
+    };
+    let cached = ArtworkOptions {
+        cache_dir: Some(dir.join("cache")),
+        ..Default::default()
+    };
+
+    let mut group = c.benchmark_group("artwork");
+    group.bench_function("original", |b| {
+        b.iter(|| artwork::load(&info(None), &unloaded(&uncached)).unwrap())
+    });
+    artwork::load(&info(None), &uncached).unwrap();
+    group.bench_function("original/loaded", |b| {
+        b.iter(|| artwork::load(&info(None), &uncached).unwrap())
+    });
+
+    // Generating variants takes a while, fewer samples are plenty.
+    group.sample_size(10);
+    for variant in [
+        ArtworkVariant::Blur,
+        ArtworkVariant::Darken,
+        ArtworkVariant::Square,
+    ] {
+        let name = format!("{variant:?}").to_lowercase();
+        group.bench_function(format!("{name}/generated"), |b| {
+            b.iter(|| artwork::load(&info(Some(variant)), &unloaded(&uncached)).unwrap())
+        });
+
+        artwork::load(&info(Some(variant)), &cached).unwrap();
+        group.bench_function(format!("{name}/cached"), |b| {
+            b.iter(|| artwork::load(&info(Some(variant)), &unloaded(&cached)).unwrap())
+        });
+    }
+
+    group.finish();
+}
+
+criterion_group!(benches, load);
+criterion_main!(benches);
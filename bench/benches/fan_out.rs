@@ -0,0 +1,106 @@
+use criterion::{criterion_group, criterion_main, BenchmarkId, Criterion, Throughput};
+use mpris_nowplaying::status::StatusSnapshot;
+use mpris_nowplaying_bench::snapshot;
+use std::sync::Arc;
+use tokio::runtime::Runtime;
+use tokio::sync::{mpsc, watch};
+use tokio::task::JoinHandle;
+
+const CLIENTS: [usize; 3] = [1, 10, 100];
+
+/// Clients that each take every status from `status_rx` and turn it into a message with `message`, telling `done_tx` its size.
+fn clients<T: Send + Sync + 'static>(
+    runtime: &Runtime,
+    count: usize,
+    status_rx: &watch::Receiver<T>,
+    done_tx: &mpsc::UnboundedSender<usize>,
+    message: fn(&T) -> usize,
+) -> Vec<JoinHandle<()>> {
+    (0..count)
+        .map(|_| {
+            let (mut status_rx, done_tx) = (status_rx.clone(), done_tx.clone());
+            runtime.spawn(async move {
+                while status_rx.changed().await.is_ok() {
+                    let size = message(&status_rx.borrow_and_update());
+                    if done_tx.send(size).is_err() {
+                        return;
+                    }
+                }
+            })
+        })
+        .collect()
+}
+
+/// A position update reaching every client, like pushed statuses do: each connection serializing it on its own, as the server does now, or serialized once and shared.
+fn fan_out(c: &mut Criterion) {
+    let runtime = Runtime::new().unwrap();
+    let mut group = c.benchmark_group("fan_out");
+
+    for count in CLIENTS {
+        group.throughput(Throughput::Elements(count as u64));
+
+        group.bench_function(BenchmarkId::new("per_client", count), |b| {
+            let (status_tx, status_rx) = watch::channel(snapshot(1));
+            let (done_tx, mut done_rx) = mpsc::unbounded_channel();
+            let tasks = clients(
+                &runtime,
+                count,
+                &status_rx,
+                &done_tx,
+                |snapshot: &StatusSnapshot| {
+                    serde_json::to_string(&snapshot.active()).unwrap().len()
+                },
+            );
+
+            b.iter(|| {
+                status_tx.send_modify(|snapshot| {
+                    for status in snapshot.players.values_mut() {
+                        status.position += 250_000;
+                    }
+                });
+                runtime.block_on(async {
+                    for _ in 0..count {
+                        done_rx.recv().await.unwrap();
+                    }
+                });
+            });
+
+            tasks.iter().for_each(JoinHandle::abort);
+        });
+
+        group.bench_function(BenchmarkId::new("shared", count), |b| {
+            let mut snapshot = snapshot(1);
+            let serialize = |snapshot: &StatusSnapshot| -> Arc<str> {
+                serde_json::to_string(&snapshot.active()).unwrap().into()
+            };
+            let (status_tx, status_rx) = watch::channel(serialize(&snapshot));
+            let (done_tx, mut done_rx) = mpsc::unbounded_channel();
+            let tasks = clients(
+                &runtime,
+                count,
+                &status_rx,
+                &done_tx,
+                |status: &Arc<str>| status.len(),
+            );
+
+            b.iter(|| {
+                for status in snapshot.players.values_mut() {
+                    status.position += 250_000;
+                }
+                status_tx.send_replace(serialize(&snapshot));
+                runtime.block_on(async {
+                    for _ in 0..count {
+                        done_rx.recv().await.unwrap();
+                    }
+                });
+            });
+
+            tasks.iter().for_each(JoinHandle::abort);
+        });
+    }
+
+    group.finish();
+}
+
+criterion_group!(benches, fan_out);
+criterion_main!(benches);
@@ -0,0 +1,30 @@
+use criterion::{criterion_group, criterion_main, BenchmarkId, Criterion, Throughput};
+use mpris_nowplaying::status::CompactStatus;
+use mpris_nowplaying_bench::status;
+use std::hint::black_box;
+
+/// The status as clients get it, with more and more artwork entries, and the compact one of `--udp` and `--osc`.
+fn serialize(c: &mut Criterion) {
+    let mut group = c.benchmark_group("serialize");
+
+    for artwork in [0, 1, 4] {
+        let status = status(artwork);
+        let size = serde_json::to_vec(&status).unwrap().len();
+        group.throughput(Throughput::Bytes(size as u64));
+        group.bench_with_input(BenchmarkId::new("json", artwork), &status, |b, status| {
+            b.iter(|| serde_json::to_string(black_box(status)).unwrap())
+        });
+    }
+
+    let compact = CompactStatus::from(Some(&status(1)));
+    let size = serde_json::to_vec(&compact).unwrap().len();
+    group.throughput(Throughput::Bytes(size as u64));
+    group.bench_function("compact", |b| {
+        b.iter(|| serde_json::to_string(black_box(&compact)).unwrap())
+    });
+
+    group.finish();
+}
+
+criterion_group!(benches, serialize);
+criterion_main!(benches);
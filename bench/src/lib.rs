@@ -0,0 +1,61 @@
+//! Statuses for the benchmarks, shaped like a real player's.
+
+use mpris_nowplaying::artwork::{ArtworkInfo, ArtworkOrigin, ArtworkVariant};
+use mpris_nowplaying::status::{
+    PlaybackState, PlayerInfo, PlayerStatus, SampleTime, StatusMetadata, StatusSnapshot,
+};
+
+const BUS_NAME: &str = "org.mpris.MediaPlayer2.bench";
+
+/// A playing track with `artwork` entries: the cover, then its variants.
+pub fn status(artwork: usize) -> PlayerStatus {
+    let variants = [
+        None,
+        Some(ArtworkVariant::Blur),
+        Some(ArtworkVariant::Darken),
+        Some(ArtworkVariant::Square),
+    ];
+
+    PlayerStatus {
+        player: PlayerInfo {
+            bus_name: BUS_NAME.to_string(),
+            identity: String::from("Bench Player"),
+            alias: None,
+            attachment: 1,
+        },
+        metadata: StatusMetadata {
+            title: String::from("Neon Tide (Extended Mix)"),
+            artist: String::from("The Midnight Arcade, Someone Else"),
+            album: String::from("Coastline Static"),
+            artwork: variants
+                .into_iter()
+                .cycle()
+                .take(artwork)
+                .map(|variant| ArtworkInfo {
+                    src: String::from("file:///home/user/Music/Coastline%20Static/cover.jpg"),
+                    origin: ArtworkOrigin::Url,
+                    variant,
+                })
+                .collect(),
+            length: 245_000_000,
+            url: String::from("file:///home/user/Music/Coastline%20Static/01%20Neon%20Tide.flac"),
+            track_id: String::from("/org/mpris/MediaPlayer2/Track/1"),
+        },
+        playback_state: PlaybackState::Playing,
+        position: 61_500_000,
+        position_time: SampleTime::now(),
+        degraded: false,
+        stale: false,
+        lyrics: None,
+        musicbrainz: None,
+        spotify: None,
+    }
+}
+
+/// The statuses with [`status`] as the only, active player.
+pub fn snapshot(artwork: usize) -> StatusSnapshot {
+    StatusSnapshot {
+        active: Some(BUS_NAME.to_string()),
+        players: [(BUS_NAME.to_string(), status(artwork))].into(),
+    }
+}
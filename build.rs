@@ -0,0 +1,7 @@
+fn main() -> Result<(), Box<dyn std::error::Error>> {
+    // So building doesn't need protoc installed.
+    std::env::set_var("PROTOC", protoc_bin_vendored::protoc_bin_path()?);
+
+    tonic_prost_build::compile_protos("proto/nowplaying.proto")?;
+    Ok(())
+}
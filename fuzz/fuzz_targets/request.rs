@@ -0,0 +1,9 @@
+#![no_main]
+
+use libfuzzer_sys::fuzz_target;
+use mpris_nowplaying::request::Request;
+
+// Whatever a client sends, reading it must not panic.
+fuzz_target!(|data: &[u8]| {
+    let _ = Request::parse(data);
+});
@@ -0,0 +1,150 @@
+use crate::logging::{self, LogLine};
+use crate::player::Action;
+use crate::server::Client;
+use crate::status::{PlayerStatus, StatusSnapshot};
+use serde::Serialize;
+use std::collections::BTreeMap;
+use std::sync::{mpsc, Arc, Mutex, MutexGuard, PoisonError};
+use std::time::{SystemTime, UNIX_EPOCH};
+
+/// The page served at `/admin`.
+pub const PAGE: &str = include_str!("admin.html");
+
+/// What the admin page needs besides what every connection does, with --admin.
+#[derive(Clone)]
+pub struct Admin {
+    /// The effective configuration, with credentials hidden.
+    pub config: String,
+    /// Tells the player thread to search again or detach a player. Sending fails when no players are watched, e.g. with --demo.
+    pub actions_tx: mpsc::Sender<Action>,
+}
+
+/// Everything the admin page shows, answered at `/admin/state`.
+#[derive(Serialize)]
+#[serde(rename_all = "camelCase")]
+struct AdminState<'a> {
+    version: &'static str,
+    /// Bus name of the player reported by default.
+    active: Option<String>,
+    players: BTreeMap<String, PlayerStatus>,
+    clients: Vec<ClientInfo>,
+    config: &'a str,
+    log: Vec<LogLine>,
+}
+
+/// The admin page's state as JSON.
+pub fn state_json(admin: &Admin, snapshot: &StatusSnapshot, clients: &Clients) -> String {
+    serde_json::to_string(&AdminState {
+        version: env!("CARGO_PKG_VERSION"),
+        active: snapshot.active.clone(),
+        players: snapshot.players.clone(),
+        clients: clients.list(),
+        config: &admin.config,
+        log: logging::recent_lines(),
+    })
+    .unwrap()
+}
+
+fn unix_millis() -> u64 {
+    SystemTime::now()
+        .duration_since(UNIX_EPOCH)
+        .unwrap_or_default()
+        .as_millis() as u64
+}
+
+/// A client connected for longer than a single request, as the admin page shows it.
+#[derive(Debug, Clone, Serialize)]
+#[serde(rename_all = "camelCase")]
+pub struct ClientInfo {
+    pub id: u64,
+    pub address: String,
+    /// `webSocket`, `connect` for the server --connect connects to, `hub` for an instance pushing to --hub, or `events` for `/events`.
+    pub connection: &'static str,
+    /// What it's sent without asking: `status`, `events` or `lyrics`.
+    pub subscriptions: Vec<&'static str>,
+    /// In milliseconds since the Unix epoch.
+    pub connected_at: u64,
+    /// When it last sent a request, or was sent a status over `/events`, in milliseconds since the Unix epoch.
+    pub last_active: u64,
+}
+
+/// The WebSocket clients and event streams connected right now.
+#[derive(Clone, Default)]
+pub struct Clients(Arc<Mutex<BTreeMap<u64, ClientInfo>>>);
+
+impl Clients {
+    /// Never poisoned, a client is listed or it isn't.
+    fn lock(&self) -> MutexGuard<'_, BTreeMap<u64, ClientInfo>> {
+        self.0.lock().unwrap_or_else(PoisonError::into_inner)
+    }
+
+    /// Lists the client until the returned guard is dropped.
+    pub fn connect(
+        &self,
+        client: &Client,
+        connection: &'static str,
+        subscriptions: Vec<&'static str>,
+    ) -> ConnectedClient {
+        let now = unix_millis();
+        self.lock().insert(
+            client.id,
+            ClientInfo {
+                id: client.id,
+                address: client.address.clone(),
+                connection,
+                subscriptions,
+                connected_at: now,
+                last_active: now,
+            },
+        );
+
+        ConnectedClient {
+            clients: self.clone(),
+            id: client.id,
+        }
+    }
+
+    /// The clients connected right now, longest connected first.
+    pub fn list(&self) -> Vec<ClientInfo> {
+        self.lock().values().cloned().collect()
+    }
+
+    /// How many clients are connected right now.
+    pub fn count(&self) -> usize {
+        self.lock().len()
+    }
+}
+
+/// Keeps a client listed in [`Clients`] while it's connected.
+pub struct ConnectedClient {
+    clients: Clients,
+    id: u64,
+}
+
+impl ConnectedClient {
+    fn update(&self, update: impl FnOnce(&mut ClientInfo)) {
+        if let Some(info) = self.clients.lock().get_mut(&self.id) {
+            update(info);
+        }
+    }
+
+    /// Notes that the client was just active.
+    pub fn active(&self) {
+        self.update(|info| info.last_active = unix_millis());
+    }
+
+    /// Notes that the client is sent `subscription` from now on.
+    pub fn subscribe(&self, subscription: &'static str) {
+        self.update(|info| {
+            if !info.subscriptions.contains(&subscription) {
+                info.subscriptions.push(subscription);
+            }
+        });
+    }
+}
+
+impl Drop for ConnectedClient {
+    fn drop(&mut self) {
+        self.clients.lock().remove(&self.id);
+    }
+}
@@ -0,0 +1,446 @@
+use bytes::Bytes;
+use image::imageops::FilterType;
+use image::ImageFormat;
+use serde::{Deserialize, Serialize};
+use std::collections::hash_map::DefaultHasher;
+use std::collections::VecDeque;
+use std::fmt;
+use std::fs::{self, File};
+use std::hash::{Hash, Hasher};
+use std::io::{self, Cursor};
+use std::panic::{self, AssertUnwindSafe};
+use std::path::{Path, PathBuf};
+use std::sync::{Arc, Mutex, MutexGuard, OnceLock, PoisonError};
+use std::time::SystemTime;
+use symphonia::core::formats::FormatOptions;
+use symphonia::core::io::MediaSourceStream;
+use symphonia::core::meta::{MetadataOptions, MetadataRevision, StandardVisualKey};
+use symphonia::core::probe::Hint;
+
+/// Where the bytes of an artwork come from. Not sent to clients.
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub enum ArtworkOrigin {
+    /// `src` is the art URL reported by the player.
+    #[default]
+    Url,
+    /// `src` is a local audio file whose tags contain the cover.
+    Embedded,
+}
+
+/// A processed version of the artwork, generated server-side.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash, Serialize, Deserialize, clap::ValueEnum)]
+#[serde(rename_all = "camelCase")]
+pub enum ArtworkVariant {
+    /// Gaussian blurred, for full-screen backgrounds.
+    Blur,
+    /// Darkened, so text stays readable on top of it.
+    Darken,
+    /// Center-cropped to a square.
+    Square,
+}
+
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "camelCase")]
+pub struct ArtworkInfo {
+    pub src: String,
+    #[serde(skip)]
+    pub origin: ArtworkOrigin,
+    /// Set when this entry is a processed copy of the first artwork.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub variant: Option<ArtworkVariant>,
+}
+
+/// The size of the chunks artwork is sent in by default, see [`ArtworkOptions::chunk_size`].
+pub const DEFAULT_CHUNK_SIZE: usize = 16 * 1024;
+
+/// Settings for finding artwork when the player doesn't report any.
+#[derive(Debug, Clone)]
+pub struct ArtworkOptions {
+    /// Read cover art embedded in the tags of the local file being played.
+    pub embedded: bool,
+    /// File names to look for next to the local file being played, in order of preference.
+    pub folder_names: Vec<String>,
+    /// Processed variants of local artwork to list after the original.
+    pub variants: Vec<ArtworkVariant>,
+    /// Size of the binary chunks used when a client requests chunked transfer.
+    pub chunk_size: usize,
+    /// Where processed variants are kept, so they're only generated once per image.
+    pub cache_dir: Option<PathBuf>,
+    /// Never list or read any artwork.
+    pub disabled: bool,
+    /// The images read recently, shared by every clone of these options.
+    pub loaded: LoadedArtwork,
+}
+
+impl Default for ArtworkOptions {
+    /// Only the artwork the player reports, cached in [`default_cache_dir`], like on the command line.
+    fn default() -> Self {
+        Self {
+            embedded: false,
+            folder_names: Vec::new(),
+            variants: Vec::new(),
+            chunk_size: DEFAULT_CHUNK_SIZE,
+            cache_dir: default_cache_dir(),
+            disabled: false,
+            loaded: LoadedArtwork::default(),
+        }
+    }
+}
+
+/// Artwork as sent to a client.
+pub enum ArtworkData {
+    /// The image itself, read from a local file. Shared with everyone else who loaded it, see [`LoadedArtwork`].
+    Binary(Bytes),
+    /// A link to the image that the client has to fetch on its own.
+    Link(String),
+}
+
+/// `~/.cache/mpris-nowplaying` on Linux.
+pub fn default_cache_dir() -> Option<PathBuf> {
+    Some(dirs::cache_dir()?.join("mpris-nowplaying"))
+}
+
+/// Converts a `file://` URL into a local path, decoding any percent-escapes.
+pub fn file_url_to_path(src: &str) -> Option<PathBuf> {
+    if !src.starts_with("file://") {
+        return None;
+    }
+
+    url::Url::parse(src).ok()?.to_file_path().ok()
+}
+
+/// Looks for one of the configured folder artwork files next to a local track.
+fn find_folder_artwork(track: &Path, options: &ArtworkOptions) -> Option<PathBuf> {
+    let dir = track.parent()?;
+
+    options
+        .folder_names
+        .iter()
+        .map(|name| dir.join(name))
+        .find(|path| path.is_file())
+}
+
+/// Lists the artwork for a track, followed by the configured variants if the artwork is local.
+pub fn resolve(metadata: &mpris::Metadata, options: &ArtworkOptions) -> Vec<ArtworkInfo> {
+    if options.disabled {
+        return Vec::new();
+    }
+
+    with_variants(resolve_base(metadata, options), options)
+}
+
+/// `base`, followed by the configured variants if it's a local file.
+pub fn with_variants(base: ArtworkInfo, options: &ArtworkOptions) -> Vec<ArtworkInfo> {
+    let mut artwork = Vec::with_capacity(1 + options.variants.len());
+    if file_url_to_path(&base.src).is_some() {
+        artwork.extend(options.variants.iter().map(|&variant| ArtworkInfo {
+            variant: Some(variant),
+            ..base.clone()
+        }));
+    }
+    artwork.insert(0, base);
+
+    artwork
+}
+
+/// Finds the artwork for a track.
+///
+/// When the player has no art URL, falls back to the cover embedded in the local file itself,
+/// then to artwork files in the same folder.
+fn resolve_base(metadata: &mpris::Metadata, options: &ArtworkOptions) -> ArtworkInfo {
+    let art_url = metadata.art_url().unwrap_or_default();
+
+    if art_url.is_empty() {
+        if let Some((url, path)) = metadata
+            .url()
+            .and_then(|url| Some((url, file_url_to_path(url)?)))
+        {
+            if options.embedded {
+                return ArtworkInfo {
+                    src: url.to_string(),
+                    origin: ArtworkOrigin::Embedded,
+                    variant: None,
+                };
+            }
+
+            if let Some(cover) = find_folder_artwork(&path, options) {
+                if let Ok(src) = url::Url::from_file_path(cover) {
+                    return ArtworkInfo {
+                        src: src.into(),
+                        origin: ArtworkOrigin::Url,
+                        variant: None,
+                    };
+                }
+            }
+        }
+    }
+
+    ArtworkInfo {
+        src: art_url.to_string(),
+        origin: ArtworkOrigin::Url,
+        variant: None,
+    }
+}
+
+/// How many images [`LoadedArtwork`] keeps, the least recently read ones go first. A few tracks' worth of covers and their variants.
+const LOADED_SIZE: usize = 8;
+
+/// The images read recently, so clients and outputs asking for the same one share a single copy instead of each reading and processing the file again.
+///
+/// Clients asking while it's still being read wait for it. Cheap to clone, the clones share the images.
+#[derive(Clone, Default)]
+pub struct LoadedArtwork(Arc<Mutex<VecDeque<Loaded>>>);
+
+struct Loaded {
+    artwork: ArtworkInfo,
+    /// The size and modification time of the file, so a replaced cover is read again.
+    stamp: (u64, Option<SystemTime>),
+    /// Errors are kept as their kind and message, [`io::Error`] can't be cloned.
+    data: Arc<OnceLock<Result<Bytes, (io::ErrorKind, String)>>>,
+}
+
+impl fmt::Debug for LoadedArtwork {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.debug_tuple("LoadedArtwork")
+            .field(&self.lock().len())
+            .finish()
+    }
+}
+
+impl LoadedArtwork {
+    /// Never poisoned, the images are whole whatever panicked while it was held.
+    fn lock(&self) -> MutexGuard<'_, VecDeque<Loaded>> {
+        self.0.lock().unwrap_or_else(PoisonError::into_inner)
+    }
+
+    /// The image for `artwork` in the file at `path`, reading it with `read` unless someone else already did.
+    fn get_or_read(
+        &self,
+        artwork: &ArtworkInfo,
+        path: &Path,
+        read: impl FnOnce() -> io::Result<Vec<u8>>,
+    ) -> io::Result<Bytes> {
+        let metadata = fs::metadata(path)?;
+        let stamp = (metadata.len(), metadata.modified().ok());
+
+        let data = {
+            let mut loaded = self.lock();
+            loaded.retain(|loaded| loaded.artwork != *artwork || loaded.stamp == stamp);
+
+            match loaded.iter().find(|loaded| loaded.artwork == *artwork) {
+                Some(loaded) => loaded.data.clone(),
+                None => {
+                    let data = Arc::new(OnceLock::new());
+                    loaded.push_front(Loaded {
+                        artwork: artwork.clone(),
+                        stamp,
+                        data: data.clone(),
+                    });
+                    loaded.truncate(LOADED_SIZE);
+                    data
+                }
+            }
+        };
+
+        // Only one of the clients asking at once reads it, the others wait here.
+        let result = data.get_or_init(|| {
+            read()
+                .map(Bytes::from)
+                .map_err(|e| (e.kind(), e.to_string()))
+        });
+
+        result.clone().map_err(|(kind, message)| {
+            // Not kept, so it's tried again next time.
+            self.lock()
+                .retain(|loaded| !Arc::ptr_eq(&loaded.data, &data));
+            io::Error::new(kind, message)
+        })
+    }
+}
+
+/// Loads the artwork so it can be sent to a client. Local images are only read once for everyone asking for them, see [`LoadedArtwork`].
+pub fn load(artwork: &ArtworkInfo, options: &ArtworkOptions) -> io::Result<ArtworkData> {
+    if options.disabled {
+        return Err(io::Error::new(
+            io::ErrorKind::PermissionDenied,
+            "artwork is disabled",
+        ));
+    }
+
+    let Some(path) = file_url_to_path(&artwork.src) else {
+        return Ok(ArtworkData::Link(artwork.src.clone()));
+    };
+
+    options
+        .loaded
+        .get_or_read(artwork, &path, || {
+            // Decoders can panic on broken files, which is no reason to take down whoever asked.
+            panic::catch_unwind(AssertUnwindSafe(|| read(artwork, &path, options))).unwrap_or_else(
+                |_| {
+                    Err(io::Error::new(
+                        io::ErrorKind::InvalidData,
+                        format!("could not decode {}", path.display()),
+                    ))
+                },
+            )
+        })
+        .map(ArtworkData::Binary)
+}
+
+/// Reads a local image, processing it into its variant or reading the processed one from the cache.
+fn read(artwork: &ArtworkInfo, path: &Path, options: &ArtworkOptions) -> io::Result<Vec<u8>> {
+    let cached = match (artwork.variant, &options.cache_dir) {
+        (Some(variant), Some(dir)) => cache_path(dir, path, artwork, variant),
+        _ => None,
+    };
+    if let Some(data) = cached.as_ref().and_then(|cached| fs::read(cached).ok()) {
+        return Ok(data);
+    }
+
+    let data = match artwork.origin {
+        ArtworkOrigin::Url => fs::read(path)?,
+        ArtworkOrigin::Embedded => {
+            read_embedded(path).or_else(|e| match find_folder_artwork(path, options) {
+                Some(cover) => fs::read(cover),
+                None => Err(e),
+            })?
+        }
+    };
+
+    let Some(variant) = artwork.variant else {
+        return Ok(data);
+    };
+
+    let data = process(&data, variant)?;
+    if let Some(cached) = cached {
+        if let Err(e) = store(&cached, &data) {
+            log::debug!("Could not cache artwork in {}: {e}", cached.display());
+        }
+    }
+
+    Ok(data)
+}
+
+/// How many processed images are kept in the cache, the least recently written ones go first.
+const CACHE_SIZE: usize = 256;
+
+/// Where a processed variant of the image at `path` is cached. Keyed by its size and modification time too, so a replaced cover isn't served stale.
+fn cache_path(
+    dir: &Path,
+    path: &Path,
+    artwork: &ArtworkInfo,
+    variant: ArtworkVariant,
+) -> Option<PathBuf> {
+    let metadata = fs::metadata(path).ok()?;
+
+    let mut hasher = DefaultHasher::new();
+    path.hash(&mut hasher);
+    metadata.len().hash(&mut hasher);
+    metadata.modified().ok()?.hash(&mut hasher);
+    (artwork.origin == ArtworkOrigin::Embedded).hash(&mut hasher);
+    variant.hash(&mut hasher);
+
+    Some(
+        dir.join("artwork")
+            .join(format!("{:016x}.jpg", hasher.finish())),
+    )
+}
+
+/// Writes an image to the cache, dropping the oldest ones in its folder beyond the 256 most recent.
+pub fn store(cached: &Path, data: &[u8]) -> io::Result<()> {
+    let Some(dir) = cached.parent() else {
+        return Ok(());
+    };
+    fs::create_dir_all(dir)?;
+    fs::write(cached, data)?;
+
+    let mut entries = fs::read_dir(dir)?
+        .filter_map(|entry| {
+            let entry = entry.ok()?;
+            Some((entry.metadata().ok()?.modified().ok()?, entry.path()))
+        })
+        .collect::<Vec<_>>();
+    if entries.len() > CACHE_SIZE {
+        entries.sort();
+        for (_, path) in &entries[..entries.len() - CACHE_SIZE] {
+            fs::remove_file(path)?;
+        }
+    }
+
+    Ok(())
+}
+
+/// Blurs are done on a downscaled copy, the result is meant to be stretched anyway.
+const BLUR_MAX_SIZE: u32 = 512;
+const BLUR_SIGMA: f32 = 12.0;
+const DARKEN_AMOUNT: i32 = -96;
+
+/// Applies a variant to an image, re-encoding it as JPEG.
+fn process(data: &[u8], variant: ArtworkVariant) -> io::Result<Vec<u8>> {
+    let image =
+        image::load_from_memory(data).map_err(|e| io::Error::new(io::ErrorKind::InvalidData, e))?;
+
+    let image = match variant {
+        ArtworkVariant::Blur => image
+            .resize(BLUR_MAX_SIZE, BLUR_MAX_SIZE, FilterType::Triangle)
+            .blur(BLUR_SIGMA),
+        ArtworkVariant::Darken => image.brighten(DARKEN_AMOUNT),
+        ArtworkVariant::Square => {
+            let size = image.width().min(image.height());
+            image.crop_imm(
+                (image.width() - size) / 2,
+                (image.height() - size) / 2,
+                size,
+                size,
+            )
+        }
+    };
+
+    let mut out = Cursor::new(Vec::new());
+    image
+        .to_rgb8()
+        .write_to(&mut out, ImageFormat::Jpeg)
+        .map_err(io::Error::other)?;
+
+    Ok(out.into_inner())
+}
+
+fn read_embedded(path: &Path) -> io::Result<Vec<u8>> {
+    let file = File::open(path)?;
+    let stream = MediaSourceStream::new(Box::new(file), Default::default());
+
+    let mut hint = Hint::new();
+    if let Some(extension) = path.extension().and_then(|v| v.to_str()) {
+        hint.with_extension(extension);
+    }
+
+    let mut probed = symphonia::default::get_probe()
+        .format(
+            &hint,
+            stream,
+            &FormatOptions::default(),
+            &MetadataOptions::default(),
+        )
+        .map_err(|e| io::Error::new(io::ErrorKind::InvalidData, e))?;
+
+    let mut cover = None;
+
+    if let Some(metadata) = probed.metadata.get() {
+        cover = metadata.current().and_then(pick_cover);
+    }
+    if cover.is_none() {
+        cover = probed.format.metadata().current().and_then(pick_cover);
+    }
+
+    cover.ok_or_else(|| io::Error::new(io::ErrorKind::NotFound, "no embedded artwork"))
+}
+
+fn pick_cover(revision: &MetadataRevision) -> Option<Vec<u8>> {
+    let visuals = revision.visuals();
+
+    visuals
+        .iter()
+        .find(|v| v.usage == Some(StandardVisualKey::FrontCover))
+        .or_else(|| visuals.first())
+        .map(|v| v.data.to_vec())
+}
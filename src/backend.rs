@@ -0,0 +1,46 @@
+use crate::demand::Demand;
+use crate::event::Event;
+use crate::player::{self, Action, PlayerOptions};
+use crate::status::StatusSnapshot;
+use std::sync::mpsc;
+use tokio::sync::{broadcast, watch};
+
+/// A platform's way of finding media players and reading them. Whatever it reads ends up as [`crate::status::PlayerStatus`]es in `status_tx` and [`Event`]s in `events_tx`, so the server, the outputs and the clients don't know where it came from.
+pub trait Backend: Send {
+    /// Finds players and keeps the attached players' statuses in `status_tx` up to date, telling `events_tx` when one comes or goes. Blocks, so run it on a thread of its own.
+    ///
+    /// New options sent to `options_rx` take over on the next update, and so do the actions sent to `actions_rx`. While `demand` is idle, nothing should be read.
+    fn run(
+        &self,
+        options: PlayerOptions,
+        options_rx: mpsc::Receiver<PlayerOptions>,
+        actions_rx: mpsc::Receiver<Action>,
+        status_tx: watch::Sender<StatusSnapshot>,
+        events_tx: broadcast::Sender<Event>,
+        demand: Demand,
+    );
+}
+
+/// MPRIS players on D-Bus, see [`player::run`].
+pub struct Mpris;
+
+impl Backend for Mpris {
+    fn run(
+        &self,
+        options: PlayerOptions,
+        options_rx: mpsc::Receiver<PlayerOptions>,
+        actions_rx: mpsc::Receiver<Action>,
+        status_tx: watch::Sender<StatusSnapshot>,
+        events_tx: broadcast::Sender<Event>,
+        demand: Demand,
+    ) {
+        player::run(
+            options, options_rx, actions_rx, status_tx, events_tx, demand,
+        )
+    }
+}
+
+/// The backend for the platform this was built for. That's MPRIS everywhere for now: macOS' Now Playing info (MediaRemote) and Windows' System Media Transport Controls have no backend yet.
+pub fn platform() -> Box<dyn Backend> {
+    Box::new(Mpris)
+}
@@ -0,0 +1,268 @@
+use crate::status::{CompactStatus, PlaybackState, StatusSnapshot};
+use serde::Serialize;
+use tokio::io::{AsyncWrite, AsyncWriteExt};
+use tokio::sync::watch;
+
+/// A value that can be put in a format string.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+enum Field {
+    Player,
+    Title,
+    Artist,
+    Album,
+    TitleLatin,
+    ArtistLatin,
+    State,
+    Position,
+    Length,
+}
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+enum Piece {
+    Text(String),
+    Field(Field),
+}
+
+/// A format string like `{artist} – {title} [{position}/{length}]`. Braces are written as `{{` and `}}`.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Format(Vec<Piece>);
+
+impl Format {
+    /// Parses a --format value, rejecting unknown fields and unmatched braces.
+    pub fn parse(value: &str) -> Result<Self, String> {
+        let mut pieces = Vec::new();
+        let mut text = String::new();
+        let mut chars = value.chars();
+
+        while let Some(c) = chars.next() {
+            match c {
+                '{' if chars.as_str().starts_with('{') => {
+                    chars.next();
+                    text.push('{');
+                }
+                '}' if chars.as_str().starts_with('}') => {
+                    chars.next();
+                    text.push('}');
+                }
+                '{' => {
+                    let rest = chars.as_str();
+                    let Some(end) = rest.find('}') else {
+                        return Err(String::from("unmatched `{`, write `{{` for a brace"));
+                    };
+
+                    let field = match &rest[..end] {
+                        "player" => Field::Player,
+                        "title" => Field::Title,
+                        "artist" => Field::Artist,
+                        "album" => Field::Album,
+                        "title_latin" => Field::TitleLatin,
+                        "artist_latin" => Field::ArtistLatin,
+                        "state" => Field::State,
+                        "position" => Field::Position,
+                        "length" => Field::Length,
+                        name => {
+                            return Err(format!(
+                                "unknown field `{{{name}}}`, expected one of {{player}}, {{title}}, {{artist}}, {{album}}, {{title_latin}}, {{artist_latin}}, {{state}}, {{position}} or {{length}}"
+                            ))
+                        }
+                    };
+
+                    if !text.is_empty() {
+                        pieces.push(Piece::Text(std::mem::take(&mut text)));
+                    }
+                    pieces.push(Piece::Field(field));
+                    chars = rest[end + 1..].chars();
+                }
+                '}' => return Err(String::from("unmatched `}`, write `}}` for a brace")),
+                c => text.push(c),
+            }
+        }
+
+        if !text.is_empty() {
+            pieces.push(Piece::Text(text));
+        }
+
+        Ok(Self(pieces))
+    }
+
+    /// Fills in the fields from `status`.
+    pub fn render(&self, status: &CompactStatus) -> String {
+        self.render_escaped(status, str::to_string)
+    }
+
+    /// Fills in the fields from `status`, passing each value through `escape` first.
+    pub fn render_escaped(
+        &self,
+        status: &CompactStatus,
+        escape: impl Fn(&str) -> String,
+    ) -> String {
+        let mut out = String::new();
+
+        for piece in &self.0 {
+            let value = match piece {
+                Piece::Text(text) => {
+                    out.push_str(text);
+                    continue;
+                }
+                Piece::Field(Field::Player) => escape(&status.player),
+                Piece::Field(Field::Title) => escape(&status.title),
+                Piece::Field(Field::Artist) => escape(&status.artist),
+                Piece::Field(Field::Album) => escape(&status.album),
+                // The originals without --romanize.
+                Piece::Field(Field::TitleLatin) => escape(or(&status.title_latin, &status.title)),
+                Piece::Field(Field::ArtistLatin) => {
+                    escape(or(&status.artist_latin, &status.artist))
+                }
+                Piece::Field(Field::State) => escape(state_name(&status.playback_state)),
+                Piece::Field(Field::Position) => escape(&clock(status.position)),
+                Piece::Field(Field::Length) => escape(&clock(status.length)),
+            };
+            out.push_str(&value);
+        }
+
+        out
+    }
+}
+
+/// `value`, or `fallback` when it's empty.
+fn or<'a>(value: &'a str, fallback: &'a str) -> &'a str {
+    match value.is_empty() {
+        true => fallback,
+        false => value,
+    }
+}
+
+/// The name `{state}` is filled in with.
+pub fn state_name(state: &PlaybackState) -> &'static str {
+    match state {
+        PlaybackState::Playing => "playing",
+        PlaybackState::Paused => "paused",
+        PlaybackState::None => "none",
+    }
+}
+
+/// Formats microseconds as `m:ss`, or `h:mm:ss` from an hour on.
+pub fn clock(microseconds: u64) -> String {
+    let seconds = microseconds / 1_000_000;
+    let (hours, minutes, seconds) = (seconds / 3600, seconds / 60 % 60, seconds % 60);
+
+    if hours > 0 {
+        format!("{hours}:{minutes:02}:{seconds:02}")
+    } else {
+        format!("{minutes}:{seconds:02}")
+    }
+}
+
+/// Escapes the characters Pango markup would trip over, Waybar renders `text` and `tooltip` as markup (as do many notification servers).
+pub fn escape_markup(value: &str) -> String {
+    value
+        .replace('&', "&amp;")
+        .replace('<', "&lt;")
+        .replace('>', "&gt;")
+}
+
+/// How each --format line is written.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Style {
+    /// Just the formatted line.
+    Text,
+    /// Waybar's JSON, see `WaybarLine`.
+    Waybar,
+    /// i3status-rust's JSON, see `I3statusRsLine`.
+    I3statusRs,
+}
+
+/// A line for a Waybar custom module with `"return-type": "json"`.
+#[derive(Debug, Serialize)]
+struct WaybarLine {
+    text: String,
+    tooltip: String,
+    /// The playback state, for styling the module and picking `format-icons`.
+    class: &'static str,
+    alt: &'static str,
+    percentage: u64,
+}
+
+/// A line for an i3status-rust `custom` block with `json = true`.
+#[derive(Debug, Serialize)]
+struct I3statusRsLine {
+    text: String,
+    /// Shown when the bar runs out of room.
+    short_text: String,
+    icon: &'static str,
+    /// `Info` while playing, `Idle` otherwise.
+    state: &'static str,
+}
+
+/// Writes the active player's status as a line in `format` whenever the line changes, or as the JSON of the `style`. Empty when no player is attached, which hides the module.
+pub async fn write<W: AsyncWrite + Unpin>(
+    mut writer: W,
+    format: Format,
+    style: Style,
+    mut status_rx: watch::Receiver<StatusSnapshot>,
+) {
+    let mut last_sent = None;
+
+    loop {
+        let status = status_rx.borrow_and_update().active().cloned();
+
+        let mut line = match (&status, style) {
+            (None, Style::Text) => String::new(),
+            (Some(status), Style::Text) => format.render(&CompactStatus::from(Some(status))),
+            (status, Style::I3statusRs) => {
+                let compact = CompactStatus::from(status.as_ref());
+
+                serde_json::to_string(&I3statusRsLine {
+                    text: match status {
+                        Some(_) => escape_markup(&format.render(&compact)),
+                        None => String::new(),
+                    },
+                    short_text: escape_markup(&compact.title),
+                    icon: match (status, &compact.playback_state) {
+                        (Some(_), PlaybackState::Playing) => "music_play",
+                        (Some(_), PlaybackState::Paused) => "music_pause",
+                        _ => "music",
+                    },
+                    state: match compact.playback_state {
+                        PlaybackState::Playing => "Info",
+                        _ => "Idle",
+                    },
+                })
+                .unwrap()
+            }
+            (status, Style::Waybar) => {
+                let compact = CompactStatus::from(status.as_ref());
+                let state = state_name(&compact.playback_state);
+
+                serde_json::to_string(&WaybarLine {
+                    text: match status {
+                        Some(_) => escape_markup(&format.render(&compact)),
+                        None => String::new(),
+                    },
+                    tooltip: escape_markup(&compact.player),
+                    class: state,
+                    alt: state,
+                    percentage: (compact.position * 100)
+                        .checked_div(compact.length)
+                        .unwrap_or_default()
+                        .min(100),
+                })
+                .unwrap()
+            }
+        };
+
+        // Positions are only shown to the second, most updates don't change the line.
+        if last_sent.as_ref() != Some(&line) {
+            last_sent = Some(line.clone());
+
+            line.push('\n');
+            if writer.write_all(line.as_bytes()).await.is_err() || writer.flush().await.is_err() {
+                return;
+            }
+        }
+
+        if status_rx.changed().await.is_err() {
+            return;
+        }
+    }
+}
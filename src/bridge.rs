@@ -0,0 +1,491 @@
+use crate::artwork::ArtworkOrigin;
+use crate::bus::BusAddress;
+use crate::control;
+use crate::event::Event;
+use crate::sink::{Sink, SinkContext};
+use crate::status::{PlaybackState, PlayerStatus, StatusSnapshot};
+use dbus::arg::{PropMap, RefArg, Variant};
+use dbus::blocking::Connection;
+use dbus::message::MessageType;
+use dbus::Message;
+use futures_util::future::BoxFuture;
+use std::ffi::CString;
+use std::os::fd::{AsRawFd, RawFd};
+use std::time::Duration;
+use tokio::io::unix::AsyncFd;
+use tokio::sync::broadcast::{self, error::RecvError};
+use tokio::sync::watch;
+
+/// The name the daemon takes as a player of its own with --mpris-bridge. Never attached to itself.
+pub const BUS_NAME: &str = "org.mpris.MediaPlayer2.nowplaying";
+
+const PATH: &str = "/org/mpris/MediaPlayer2";
+const ROOT_INTERFACE: &str = "org.mpris.MediaPlayer2";
+const PLAYER_INTERFACE: &str = "org.mpris.MediaPlayer2.Player";
+const PROPERTIES_INTERFACE: &str = "org.freedesktop.DBus.Properties";
+const INTROSPECTABLE_INTERFACE: &str = "org.freedesktop.DBus.Introspectable";
+/// The track id reported when the player has none, as the spec asks.
+const NO_TRACK: &str = "/org/mpris/MediaPlayer2/TrackList/NoTrack";
+
+const INTROSPECTION: &str = r#"<node>
+  <interface name="org.mpris.MediaPlayer2">
+    <method name="Raise"/>
+    <method name="Quit"/>
+    <property name="CanQuit" type="b" access="read"/>
+    <property name="CanRaise" type="b" access="read"/>
+    <property name="HasTrackList" type="b" access="read"/>
+    <property name="Identity" type="s" access="read"/>
+    <property name="SupportedUriSchemes" type="as" access="read"/>
+    <property name="SupportedMimeTypes" type="as" access="read"/>
+  </interface>
+  <interface name="org.mpris.MediaPlayer2.Player">
+    <method name="Next"/>
+    <method name="Previous"/>
+    <method name="Pause"/>
+    <method name="PlayPause"/>
+    <method name="Stop"/>
+    <method name="Play"/>
+    <method name="Seek"><arg name="Offset" type="x" direction="in"/></method>
+    <method name="SetPosition"><arg name="TrackId" type="o" direction="in"/><arg name="Position" type="x" direction="in"/></method>
+    <method name="OpenUri"><arg name="Uri" type="s" direction="in"/></method>
+    <signal name="Seeked"><arg name="Position" type="x"/></signal>
+    <property name="PlaybackStatus" type="s" access="read"/>
+    <property name="Rate" type="d" access="read"/>
+    <property name="Metadata" type="a{sv}" access="read"/>
+    <property name="Position" type="x" access="read"/>
+    <property name="MinimumRate" type="d" access="read"/>
+    <property name="MaximumRate" type="d" access="read"/>
+    <property name="CanGoNext" type="b" access="read"/>
+    <property name="CanGoPrevious" type="b" access="read"/>
+    <property name="CanPlay" type="b" access="read"/>
+    <property name="CanPause" type="b" access="read"/>
+    <property name="CanSeek" type="b" access="read"/>
+    <property name="CanControl" type="b" access="read"/>
+  </interface>
+</node>"#;
+
+/// A call to pass on to the active player.
+#[derive(Debug, Clone, PartialEq)]
+enum Forward {
+    /// A method without arguments, like `PlayPause`.
+    Method(String),
+    Seek(i64),
+    SetPosition(u64),
+}
+
+/// The active player as re-exported, without the position, which moves on by itself.
+#[derive(Debug, Clone, PartialEq, Eq)]
+struct View {
+    status: Option<PlayerStatus>,
+}
+
+impl View {
+    fn new(status: Option<&PlayerStatus>) -> Self {
+        Self {
+            status: status.map(|status| PlayerStatus {
+                position: 0,
+                ..status.clone()
+            }),
+        }
+    }
+
+    fn identity(&self) -> String {
+        match &self.status {
+            Some(status) => status
+                .player
+                .alias
+                .clone()
+                .unwrap_or_else(|| status.player.identity.clone()),
+            None => String::from(env!("CARGO_PKG_NAME")),
+        }
+    }
+
+    fn playback_status(&self) -> &'static str {
+        match self.status.as_ref().map(|status| &status.playback_state) {
+            Some(PlaybackState::Playing) => "Playing",
+            Some(PlaybackState::Paused) => "Paused",
+            _ => "Stopped",
+        }
+    }
+
+    fn metadata(&self) -> PropMap {
+        let mut metadata = PropMap::new();
+        let Some(status) = &self.status else {
+            metadata.insert(
+                String::from("mpris:trackid"),
+                Variant(Box::new(dbus::Path::from(NO_TRACK))),
+            );
+            return metadata;
+        };
+        let mut insert = |key: &str, value: Box<dyn RefArg>| {
+            metadata.insert(key.to_string(), Variant(value));
+        };
+
+        let track = &status.metadata;
+        let track_id =
+            dbus::Path::new(track.track_id.clone()).unwrap_or_else(|_| dbus::Path::from(NO_TRACK));
+        insert("mpris:trackid", Box::new(track_id));
+        insert("xesam:title", Box::new(track.title.clone()));
+        if !track.artist.is_empty() {
+            insert("xesam:artist", Box::new(vec![track.artist.clone()]));
+        }
+        if !track.album.is_empty() {
+            insert("xesam:album", Box::new(track.album.clone()));
+        }
+        if track.length > 0 {
+            insert("mpris:length", Box::new(track.length as i64));
+        }
+        if !track.url.is_empty() {
+            insert("xesam:url", Box::new(track.url.clone()));
+        }
+        // Covers read from audio files aren't at a URL others can load.
+        if let Some(artwork) = track
+            .artwork
+            .iter()
+            .find(|artwork| artwork.origin == ArtworkOrigin::Url)
+        {
+            insert("mpris:artUrl", Box::new(artwork.src.clone()));
+        }
+
+        metadata
+    }
+
+    fn root_properties(&self) -> PropMap {
+        let properties: [(&str, Box<dyn RefArg>); 6] = [
+            ("CanQuit", Box::new(false)),
+            ("CanRaise", Box::new(false)),
+            ("HasTrackList", Box::new(false)),
+            ("Identity", Box::new(self.identity())),
+            ("SupportedUriSchemes", Box::new(Vec::<String>::new())),
+            ("SupportedMimeTypes", Box::new(Vec::<String>::new())),
+        ];
+        properties
+            .into_iter()
+            .map(|(key, value)| (key.to_string(), Variant(value)))
+            .collect()
+    }
+
+    /// The player's properties, without `Position` unless `position` is given.
+    fn player_properties(&self, position: Option<i64>) -> PropMap {
+        let attached = self.status.is_some();
+        let mut properties: PropMap = [
+            (
+                "PlaybackStatus",
+                Box::new(String::from(self.playback_status())) as Box<dyn RefArg>,
+            ),
+            ("Rate", Box::new(1.0)),
+            ("Metadata", Box::new(self.metadata())),
+            ("MinimumRate", Box::new(1.0)),
+            ("MaximumRate", Box::new(1.0)),
+            ("CanGoNext", Box::new(attached)),
+            ("CanGoPrevious", Box::new(attached)),
+            ("CanPlay", Box::new(attached)),
+            ("CanPause", Box::new(attached)),
+            ("CanSeek", Box::new(attached)),
+            ("CanControl", Box::new(true)),
+        ]
+        .into_iter()
+        .map(|(key, value)| (key.to_string(), Variant(value)))
+        .collect();
+
+        if let Some(position) = position {
+            properties.insert(String::from("Position"), Variant(Box::new(position)));
+        }
+        properties
+    }
+}
+
+/// The active player's position by now, in microseconds.
+fn position(status_rx: &watch::Receiver<StatusSnapshot>) -> i64 {
+    status_rx
+        .borrow()
+        .active()
+        .map_or(0, |status| status.position_now() as i64)
+}
+
+fn error_reply(message: &Message, name: &str, text: &str) -> Message {
+    message.error(&name.into(), &CString::new(text).unwrap_or_default())
+}
+
+/// Answers a call from a desktop widget, returning what to pass on to the active player, if anything.
+fn handle(
+    message: &Message,
+    view: &View,
+    status_rx: &watch::Receiver<StatusSnapshot>,
+) -> (Option<Message>, Option<Forward>) {
+    let (Some(path), Some(interface), Some(member)) =
+        (message.path(), message.interface(), message.member())
+    else {
+        return (dbus::channel::default_reply(message), None);
+    };
+    if &*path != PATH {
+        return (dbus::channel::default_reply(message), None);
+    }
+
+    match (&*interface, &*member) {
+        (INTROSPECTABLE_INTERFACE, "Introspect") => {
+            (Some(message.method_return().append1(INTROSPECTION)), None)
+        }
+        (PROPERTIES_INTERFACE, "Get" | "GetAll") => {
+            let interface = message.get1::<&str>().unwrap_or_default();
+            let mut properties = match interface {
+                ROOT_INTERFACE => view.root_properties(),
+                PLAYER_INTERFACE => view.player_properties(Some(position(status_rx))),
+                _ => PropMap::new(),
+            };
+
+            let reply = match &*member {
+                "GetAll" => message.method_return().append1(properties),
+                _ => {
+                    let name = message.read2::<&str, &str>().map(|(_, name)| name);
+                    match name.ok().and_then(|name| properties.remove(name)) {
+                        Some(value) => message.method_return().append1(value),
+                        None => error_reply(
+                            message,
+                            "org.freedesktop.DBus.Error.UnknownProperty",
+                            "no such property",
+                        ),
+                    }
+                }
+            };
+            (Some(reply), None)
+        }
+        (PROPERTIES_INTERFACE, _) => (
+            Some(error_reply(
+                message,
+                "org.freedesktop.DBus.Error.PropertyReadOnly",
+                "properties are read-only",
+            )),
+            None,
+        ),
+
+        // Nothing to raise or quit, the widget's player is whichever is active.
+        (ROOT_INTERFACE, "Raise" | "Quit") => (Some(message.method_return()), None),
+
+        (PLAYER_INTERFACE, "Next" | "Previous" | "Pause" | "PlayPause" | "Stop" | "Play") => (
+            Some(message.method_return()),
+            Some(Forward::Method(member.to_string())),
+        ),
+        (PLAYER_INTERFACE, "Seek") => match message.read1::<i64>() {
+            Ok(offset) => (Some(message.method_return()), Some(Forward::Seek(offset))),
+            Err(e) => (
+                Some(error_reply(
+                    message,
+                    "org.freedesktop.DBus.Error.InvalidArgs",
+                    &e.to_string(),
+                )),
+                None,
+            ),
+        },
+        (PLAYER_INTERFACE, "SetPosition") => {
+            // Positions for another track than the current one are ignored, as the spec says.
+            let forward = message
+                .read2::<dbus::Path, i64>()
+                .ok()
+                .filter(|(track_id, position)| {
+                    *position >= 0
+                        && view
+                            .metadata()
+                            .get("mpris:trackid")
+                            .and_then(|id| id.0.as_str())
+                            == Some(&**track_id)
+                })
+                .map(|(_, position)| Forward::SetPosition(position as u64));
+            (Some(message.method_return()), forward)
+        }
+        (PLAYER_INTERFACE, "OpenUri") => (
+            Some(error_reply(
+                message,
+                "org.freedesktop.DBus.Error.NotSupported",
+                "URIs can't be opened through mpris-nowplaying",
+            )),
+            None,
+        ),
+
+        _ => (dbus::channel::default_reply(message), None),
+    }
+}
+
+/// Passes a call on to the active player without waiting for it, logging failures.
+fn forward(call: Forward, status: Option<&PlayerStatus>, player_bus: &BusAddress) {
+    let Some(status) = status.cloned() else {
+        return;
+    };
+    let bus = player_bus.clone();
+    tokio::task::spawn_blocking(move || {
+        let bus_name = &status.player.bus_name;
+        let result = match &call {
+            Forward::Method(method) => control::call_player(&bus, bus_name, method),
+            Forward::Seek(offset) => control::seek(&bus, bus_name, *offset),
+            Forward::SetPosition(position) => control::set_position(
+                &bus,
+                bus_name,
+                &status.metadata.track_id,
+                *position,
+                status.position_now(),
+            ),
+        };
+        if let Err(e) = result {
+            log::warn!(player = bus_name.as_str(); "{call:?} failed on player {bus_name}: {e}");
+        }
+    });
+}
+
+/// Tells widgets what changed.
+fn announce(connection: &Connection, previous: &View, view: &View, position: i64) {
+    let changed = |old: PropMap, new: PropMap| {
+        new.into_iter()
+            .filter(|(key, value)| {
+                old.get(key).map(|old| format!("{old:?}")) != Some(format!("{value:?}"))
+            })
+            .collect::<PropMap>()
+    };
+
+    let interfaces = [
+        (
+            ROOT_INTERFACE,
+            changed(previous.root_properties(), view.root_properties()),
+        ),
+        (
+            PLAYER_INTERFACE,
+            changed(
+                previous.player_properties(None),
+                view.player_properties(None),
+            ),
+        ),
+    ];
+    for (interface, mut properties) in interfaces {
+        if properties.is_empty() {
+            continue;
+        }
+        if interface == PLAYER_INTERFACE {
+            properties.insert(String::from("Position"), Variant(Box::new(position)));
+        }
+        if let Ok(signal) = Message::new_signal(PATH, PROPERTIES_INTERFACE, "PropertiesChanged") {
+            let _ = connection.channel().send(signal.append3(
+                interface,
+                properties,
+                Vec::<String>::new(),
+            ));
+        }
+    }
+}
+
+/// The bus connection's socket, to wait for messages on.
+struct Socket(RawFd);
+
+impl AsRawFd for Socket {
+    fn as_raw_fd(&self) -> RawFd {
+        self.0
+    }
+}
+
+/// Settings of --mpris-bridge, which has none.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct BridgeOptions;
+
+/// Registers as [`BUS_NAME`] on `bus`, re-exporting the active player's status and passing calls on to it, so desktop widgets follow whichever player the daemon picked.
+pub async fn run(
+    bus: BusAddress,
+    mut status_rx: watch::Receiver<StatusSnapshot>,
+    mut events_rx: broadcast::Receiver<Event>,
+) {
+    let connection = match bus.channel() {
+        Ok(mut channel) => {
+            channel.set_watch_enabled(true);
+            Connection::from(channel)
+        }
+        Err(e) => {
+            log::warn!("Could not connect to the bus for --mpris-bridge: {e}");
+            return;
+        }
+    };
+
+    if let Err(e) = connection.request_name(BUS_NAME, false, false, true) {
+        log::warn!("Could not take the bus name {BUS_NAME} for --mpris-bridge, is another instance using it? {e}");
+        return;
+    }
+    log::info!("Re-exporting the active player as {BUS_NAME}.");
+
+    let socket = match AsyncFd::new(Socket(connection.channel().watch().fd)) {
+        Ok(socket) => socket,
+        Err(e) => {
+            log::warn!("Could not watch the bus for --mpris-bridge: {e}");
+            return;
+        }
+    };
+
+    let mut view = View::new(status_rx.borrow_and_update().active());
+
+    loop {
+        if connection
+            .channel()
+            .read_write(Some(Duration::ZERO))
+            .is_err()
+        {
+            log::warn!("Lost the bus connection, {BUS_NAME} is gone.");
+            return;
+        }
+
+        while let Some(message) = connection.channel().pop_message() {
+            if message.msg_type() != MessageType::MethodCall {
+                continue;
+            }
+            let (reply, call) = handle(&message, &view, &status_rx);
+            if let Some(reply) = reply {
+                let _ = connection.channel().send(reply);
+            }
+            if let Some(call) = call {
+                forward(call, status_rx.borrow().active(), &bus);
+            }
+        }
+        connection.channel().flush();
+
+        tokio::select! {
+            ready = socket.readable() => match ready {
+                Ok(mut guard) => guard.clear_ready(),
+                Err(e) => {
+                    log::warn!("Could not watch the bus for --mpris-bridge: {e}");
+                    return;
+                }
+            },
+            changed = status_rx.changed() => {
+                if changed.is_err() {
+                    return;
+                }
+
+                let new_view = View::new(status_rx.borrow_and_update().active());
+                if new_view != view {
+                    announce(&connection, &view, &new_view, position(&status_rx));
+                    view = new_view;
+                }
+            }
+            event = events_rx.recv() => match event {
+                Ok(Event::Seeked { bus_name, position, .. }) => {
+                    let active = view.status.as_ref().map(|status| &status.player.bus_name);
+                    if active == Some(&bus_name) {
+                        if let Ok(signal) = Message::new_signal(PATH, PLAYER_INTERFACE, "Seeked") {
+                            let _ = connection.channel().send(signal.append1(position as i64));
+                        }
+                    }
+                }
+                Ok(_) | Err(RecvError::Lagged(_)) => {}
+                Err(RecvError::Closed) => return,
+            },
+        }
+    }
+}
+
+impl Sink for BridgeOptions {
+    fn name(&self) -> &'static str {
+        "mpris-bridge"
+    }
+
+    fn run(&self, context: &SinkContext) -> BoxFuture<'static, ()> {
+        Box::pin(run(
+            context.state.bus.clone(),
+            context.status_rx.clone(),
+            context.state.events_tx.subscribe(),
+        ))
+    }
+}
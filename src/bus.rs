@@ -0,0 +1,183 @@
+use dbus::arg::PropMap;
+use dbus::blocking::Connection;
+use dbus::channel::{BusType, Channel};
+use dbus::message::MatchRule;
+use std::sync::mpsc;
+use std::time::{Duration, Instant};
+
+const MPRIS2_PATH: &str = "/org/mpris/MediaPlayer2";
+const MPRIS2_PREFIX: &str = "org.mpris.MediaPlayer2.";
+
+/// The bus to look for players on.
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub enum BusAddress {
+    #[default]
+    Session,
+    System,
+    /// A D-Bus address, like `unix:path=/run/user/1000/bus`.
+    Custom(String),
+}
+
+impl BusAddress {
+    /// Opens a connection of the kind used by the `mpris` crate.
+    pub fn connect_ffidisp(&self) -> Result<dbus::ffidisp::Connection, dbus::Error> {
+        match self {
+            BusAddress::Session => {
+                dbus::ffidisp::Connection::get_private(dbus::ffidisp::BusType::Session)
+            }
+            BusAddress::System => {
+                dbus::ffidisp::Connection::get_private(dbus::ffidisp::BusType::System)
+            }
+            BusAddress::Custom(address) => {
+                let connection = dbus::ffidisp::Connection::open_private(address)?;
+                connection.register()?;
+                Ok(connection)
+            }
+        }
+    }
+
+    pub fn connect(&self) -> Result<Connection, dbus::Error> {
+        Ok(self.channel()?.into())
+    }
+
+    /// Opens a bare channel, for when messages are read by hand.
+    pub fn channel(&self) -> Result<Channel, dbus::Error> {
+        Ok(match self {
+            BusAddress::Session => Channel::get_private(BusType::Session)?,
+            BusAddress::System => Channel::get_private(BusType::System)?,
+            BusAddress::Custom(address) => {
+                let mut channel = Channel::open_private(address)?;
+                channel.register()?;
+                channel
+            }
+        })
+    }
+}
+
+/// A signal from the bus that's relevant to the status loop.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum BusEvent {
+    /// A player's properties (metadata, playback status, ...) changed. `properties` names the ones that did, or is `None` if the signal couldn't be read.
+    PropertiesChanged {
+        sender: String,
+        properties: Option<Vec<String>>,
+    },
+    /// A player jumped to a new position, in microseconds.
+    Seeked { sender: String, position: i64 },
+    /// An MPRIS bus name got a new owner (a player started) or lost its owner (a player quit).
+    NameOwnerChanged { name: String, new_owner: String },
+}
+
+impl BusEvent {
+    /// Whether the event might be about `property` changing.
+    pub fn may_change(&self, property: &str) -> bool {
+        match self {
+            BusEvent::PropertiesChanged { properties, .. } => properties
+                .as_ref()
+                .is_none_or(|properties| properties.iter().any(|name| name == property)),
+            _ => false,
+        }
+    }
+
+    /// Whether a player showed up on the bus.
+    pub fn is_player_appeared(&self) -> bool {
+        matches!(self, BusEvent::NameOwnerChanged { new_owner, .. } if !new_owner.is_empty())
+    }
+}
+
+/// A separate D-Bus connection subscribed to MPRIS signals.
+pub struct BusWatcher {
+    connection: Connection,
+    events: mpsc::Receiver<BusEvent>,
+}
+
+impl BusWatcher {
+    pub fn new(address: &BusAddress) -> Result<Self, dbus::Error> {
+        let connection = address.connect()?;
+        let (tx, events) = mpsc::channel();
+
+        let properties_tx = tx.clone();
+        connection.add_match(
+            MatchRule::new_signal("org.freedesktop.DBus.Properties", "PropertiesChanged")
+                .with_path(MPRIS2_PATH),
+            move |_: (), _, msg| {
+                if let Some(sender) = msg.sender() {
+                    // Both the changed and the invalidated ones.
+                    let properties = msg.read3::<&str, PropMap, Vec<String>>().ok().map(
+                        |(_, changed, mut invalidated)| {
+                            invalidated.extend(changed.into_keys());
+                            invalidated
+                        },
+                    );
+                    let _ = properties_tx.send(BusEvent::PropertiesChanged {
+                        sender: sender.to_string(),
+                        properties,
+                    });
+                }
+                true
+            },
+        )?;
+
+        let seeked_tx = tx.clone();
+        connection.add_match(
+            MatchRule::new_signal("org.mpris.MediaPlayer2.Player", "Seeked").with_path(MPRIS2_PATH),
+            move |(position,): (i64,), _, msg| {
+                if let Some(sender) = msg.sender() {
+                    let _ = seeked_tx.send(BusEvent::Seeked {
+                        sender: sender.to_string(),
+                        position,
+                    });
+                }
+                true
+            },
+        )?;
+
+        let owner_tx = tx.clone();
+        connection.add_match(
+            MatchRule::new_signal("org.freedesktop.DBus", "NameOwnerChanged")
+                .with_sender("org.freedesktop.DBus"),
+            move |(name, _, new_owner): (String, String, String), _, _| {
+                if name.starts_with(MPRIS2_PREFIX) {
+                    let _ = owner_tx.send(BusEvent::NameOwnerChanged { name, new_owner });
+                }
+                true
+            },
+        )?;
+
+        Ok(Self { connection, events })
+    }
+
+    /// Blocks until a player shows up on the bus or the timeout runs out. Returns whether one did.
+    pub fn wait_for_player(&self, timeout: Duration) -> Result<bool, dbus::Error> {
+        let deadline = Instant::now() + timeout;
+
+        loop {
+            let remaining = deadline.saturating_duration_since(Instant::now());
+            if self
+                .wait(remaining)?
+                .iter()
+                .any(BusEvent::is_player_appeared)
+            {
+                return Ok(true);
+            }
+            if remaining.is_zero() {
+                return Ok(false);
+            }
+        }
+    }
+
+    /// Blocks until at least one event arrives or the timeout runs out, then returns all pending events.
+    pub fn wait(&self, timeout: Duration) -> Result<Vec<BusEvent>, dbus::Error> {
+        let deadline = Instant::now() + timeout;
+
+        loop {
+            let remaining = deadline.saturating_duration_since(Instant::now());
+            let received = self.connection.process(remaining)?;
+
+            let events: Vec<_> = self.events.try_iter().collect();
+            if !events.is_empty() || !received || remaining.is_zero() {
+                return Ok(events);
+            }
+        }
+    }
+}
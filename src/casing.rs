@@ -0,0 +1,85 @@
+use serde::Serialize;
+use serde_json::{Map, Value};
+
+/// How the keys of the JSON sent to clients and outputs are cased.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, clap::ValueEnum)]
+pub enum FieldCase {
+    /// `playbackState`, as the daemon names its fields.
+    #[default]
+    Camel,
+    /// `playback_state`.
+    Snake,
+}
+
+impl FieldCase {
+    /// Reads `?case=camel` or `?case=snake` from a query string, if it's there.
+    pub fn from_query(query: &str) -> Option<Self> {
+        url::form_urlencoded::parse(query.as_bytes()).find_map(|(key, value)| match key {
+            _ if key != "case" => None,
+            _ if value == "camel" => Some(Self::Camel),
+            _ if value == "snake" => Some(Self::Snake),
+            _ => None,
+        })
+    }
+
+    /// Serializes `value` to JSON with its keys in this case.
+    pub fn to_string<T: Serialize + ?Sized>(self, value: &T) -> String {
+        match self {
+            Self::Camel => serde_json::to_string(value).unwrap(),
+            Self::Snake => {
+                let mut value = serde_json::to_value(value).unwrap();
+                to_snake_keys(&mut value);
+                value.to_string()
+            }
+        }
+    }
+
+    /// Puts the keys of JSON serialized as the daemon names its fields into this case. Anything that isn't JSON is left alone.
+    pub fn recase(self, json: String) -> String {
+        match self {
+            Self::Camel => json,
+            Self::Snake => match serde_json::from_str::<Value>(&json) {
+                Ok(mut value) => {
+                    to_snake_keys(&mut value);
+                    value.to_string()
+                }
+                Err(_) => json,
+            },
+        }
+    }
+}
+
+/// `trackId` as `track_id`. Only keys that look like field names are changed, not bus names or anything else with dots, slashes or spaces.
+fn snake_case(key: &str) -> Option<String> {
+    let field = key.starts_with(|c: char| c.is_ascii_lowercase())
+        && key.chars().all(|c| c.is_ascii_alphanumeric())
+        && key.chars().any(|c| c.is_ascii_uppercase());
+    if !field {
+        return None;
+    }
+
+    let mut snake = String::with_capacity(key.len() + 4);
+    for c in key.chars() {
+        if c.is_ascii_uppercase() {
+            snake.push('_');
+        }
+        snake.push(c.to_ascii_lowercase());
+    }
+    Some(snake)
+}
+
+fn to_snake_keys(value: &mut Value) {
+    match value {
+        Value::Object(object) => {
+            *object = std::mem::take(object)
+                .into_iter()
+                .map(|(key, mut value)| {
+                    to_snake_keys(&mut value);
+                    (snake_case(&key).unwrap_or(key), value)
+                })
+                .collect::<Map<_, _>>();
+        }
+        Value::Array(values) => values.iter_mut().for_each(to_snake_keys),
+        _ => {}
+    }
+}
@@ -0,0 +1,115 @@
+use serde::{Deserialize, Serialize};
+use std::collections::HashMap;
+use std::path::{Path, PathBuf};
+use std::time::Duration;
+use std::{fs, io};
+
+/// The sections of the config file that can't be given on the command line.
+#[derive(Debug, Clone, Default, Deserialize, Serialize)]
+#[serde(rename_all = "kebab-case", default, deny_unknown_fields)]
+pub struct Config {
+    /// Friendly names for players, keyed by bus name (without the `org.mpris.MediaPlayer2.` prefix) or displayed name.
+    pub aliases: HashMap<String, String>,
+    /// Workarounds for misbehaving players, keyed like `aliases`. Replaces the built-in entry for the same name.
+    pub quirks: HashMap<String, Quirks>,
+    /// How often to read each player's status, in seconds, keyed like `aliases`. Overrides --interval for that player.
+    pub intervals: HashMap<String, f32>,
+}
+
+/// The sections of the config file, and the command line options set at its top level (e.g. `interval = 0.5`), by their long names.
+#[derive(Debug, Clone, Default)]
+pub struct ConfigFile {
+    pub config: Config,
+    pub options: toml::Table,
+}
+
+/// The config file's sections, the rest of its top level is left to the command line parser.
+const SECTIONS: [&str; 3] = ["aliases", "quirks", "intervals"];
+
+/// Workarounds for a player that doesn't follow the MPRIS spec.
+#[derive(Debug, Clone, Default, PartialEq, Eq, Deserialize, Serialize)]
+#[serde(rename_all = "kebab-case", default, deny_unknown_fields)]
+pub struct Quirks {
+    /// The player's position is garbage, always report 0 instead.
+    pub ignore_position: bool,
+    /// The player reports the track length in milliseconds instead of microseconds.
+    pub length_in_millis: bool,
+    /// The player briefly reports being stopped (e.g. between tracks), keep the previous state for a moment.
+    pub ignore_transient_stopped: bool,
+    /// The player doesn't signal when its metadata changes, read it on every update instead of only after a change.
+    pub unsignalled_metadata: bool,
+}
+
+/// Quirks of well-known players, used unless the config has an entry for them.
+fn builtin_quirks(name: &str) -> Option<Quirks> {
+    match name.to_lowercase().as_str() {
+        // The Linux client has reported a position of 0 for years.
+        "spotify" => Some(Quirks {
+            ignore_position: true,
+            ..Default::default()
+        }),
+        _ => None,
+    }
+}
+
+impl Config {
+    /// The friendly name of the first of a player's names that has one, ignoring case.
+    pub fn alias(&self, names: &[&str]) -> Option<&str> {
+        names.iter().find_map(|name| {
+            self.aliases
+                .iter()
+                .find(|(key, _)| key.eq_ignore_ascii_case(name))
+                .map(|(_, alias)| alias.as_str())
+        })
+    }
+
+    /// The update interval of the first of a player's names that has one, ignoring case.
+    pub fn interval(&self, names: &[&str]) -> Option<Duration> {
+        names.iter().find_map(|name| {
+            self.intervals
+                .iter()
+                .find(|(key, _)| key.eq_ignore_ascii_case(name))
+                .and_then(|(_, &secs)| Duration::try_from_secs_f32(secs).ok())
+                .filter(|interval| !interval.is_zero())
+        })
+    }
+
+    /// The quirks of the first of a player's names that has any, ignoring case.
+    pub fn quirks(&self, names: &[&str]) -> Quirks {
+        names
+            .iter()
+            .find_map(|name| {
+                self.quirks
+                    .iter()
+                    .find(|(key, _)| key.eq_ignore_ascii_case(name))
+                    .map(|(_, quirks)| quirks.clone())
+                    .or_else(|| builtin_quirks(name))
+            })
+            .unwrap_or_default()
+    }
+}
+
+/// `~/.config/mpris-nowplaying/config.toml` on Linux.
+pub fn default_path() -> Option<PathBuf> {
+    Some(
+        dirs::config_dir()?
+            .join("mpris-nowplaying")
+            .join("config.toml"),
+    )
+}
+
+pub fn load(path: &Path) -> io::Result<ConfigFile> {
+    let text = fs::read_to_string(path)?;
+    let invalid = |e| io::Error::new(io::ErrorKind::InvalidData, e);
+
+    let mut options: toml::Table = toml::from_str(&text).map_err(invalid)?;
+    let sections = SECTIONS
+        .iter()
+        .filter_map(|&key| Some((key.to_string(), options.remove(key)?)))
+        .collect::<toml::Table>();
+
+    Ok(ConfigFile {
+        config: sections.try_into().map_err(invalid)?,
+        options,
+    })
+}
@@ -0,0 +1,102 @@
+use crate::event::Event;
+use crate::status::{PlaybackState, PlayerStatus, StatusMetadata, StatusSnapshot};
+use std::collections::BTreeMap;
+use std::time::Duration;
+use tokio::sync::{broadcast, watch};
+use tokio::time::Instant;
+
+/// How long a player has played its track so far.
+struct Play {
+    metadata: StatusMetadata,
+    /// Until it last stopped playing.
+    played: Duration,
+    /// When it last started playing, while it plays.
+    playing_since: Option<Instant>,
+    confirmed: bool,
+}
+
+impl Play {
+    fn new(metadata: &StatusMetadata) -> Self {
+        Self {
+            metadata: metadata.clone(),
+            played: Duration::ZERO,
+            playing_since: None,
+            confirmed: false,
+        }
+    }
+
+    fn played(&self) -> Duration {
+        self.played
+            + self
+                .playing_since
+                .map_or(Duration::ZERO, |since| since.elapsed())
+    }
+
+    /// When it will have played for `after`, if it's playing and wasn't confirmed yet.
+    fn due(&self, after: Duration) -> Option<Instant> {
+        let since = self.playing_since.filter(|_| !self.confirmed)?;
+        Some(since + after.saturating_sub(self.played))
+    }
+}
+
+/// Follows a player's status into its play, starting over with another track.
+fn update(play: &mut Play, status: &PlayerStatus) {
+    if !play.metadata.is_same_track(&status.metadata) {
+        *play = Play::new(&status.metadata);
+    }
+    // Filled in later by --musicbrainz and the like.
+    play.metadata = status.metadata.clone();
+
+    let playing = status.playback_state == PlaybackState::Playing
+        && !status.stale
+        && !status.metadata.title.is_empty();
+    match (playing, play.playing_since) {
+        (true, None) => play.playing_since = Some(Instant::now()),
+        (false, Some(since)) => {
+            play.played += since.elapsed();
+            play.playing_since = None;
+        }
+        _ => {}
+    }
+}
+
+/// Sends [`Event::NowPlayingConfirmed`] to `events_tx` once a player has actually played its track for `after`, pauses and seeks aside. Tracks skipped before then never get one.
+pub async fn run(
+    after: Duration,
+    mut status_rx: watch::Receiver<StatusSnapshot>,
+    events_tx: broadcast::Sender<Event>,
+) {
+    let mut plays: BTreeMap<String, Play> = BTreeMap::new();
+
+    loop {
+        {
+            let snapshot = status_rx.borrow_and_update();
+            plays.retain(|bus_name, _| snapshot.players.contains_key(bus_name));
+            for (bus_name, status) in &snapshot.players {
+                let play = plays
+                    .entry(bus_name.clone())
+                    .or_insert_with(|| Play::new(&status.metadata));
+                update(play, status);
+            }
+        }
+
+        for (bus_name, play) in &mut plays {
+            if play.confirmed || play.playing_since.is_none() || play.played() < after {
+                continue;
+            }
+            play.confirmed = true;
+            let _ = events_tx.send(Event::NowPlayingConfirmed {
+                bus_name: bus_name.clone(),
+                metadata: play.metadata.clone(),
+            });
+        }
+
+        let next = plays.values().filter_map(|play| play.due(after)).min();
+        tokio::select! {
+            changed = status_rx.changed() => if changed.is_err() {
+                return;
+            },
+            () = async { tokio::time::sleep_until(next.unwrap()).await }, if next.is_some() => {}
+        }
+    }
+}
@@ -0,0 +1,225 @@
+use crate::bus::BusAddress;
+use dbus::arg::prop_cast;
+use dbus::blocking::stdintf::org_freedesktop_dbus::Properties;
+use dbus::blocking::{Connection, Proxy};
+use dbus::Path;
+use serde::{Deserialize, Serialize};
+use std::fmt;
+use std::time::Duration;
+
+const MPRIS2_PATH: &str = "/org/mpris/MediaPlayer2";
+const PLAYER_INTERFACE: &str = "org.mpris.MediaPlayer2.Player";
+const PLAYLISTS_INTERFACE: &str = "org.mpris.MediaPlayer2.Playlists";
+const CALL_TIMEOUT: Duration = Duration::from_millis(500);
+
+/// The most playlists listed at once.
+const MAX_PLAYLISTS: u32 = 1000;
+
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "camelCase")]
+pub struct Playlist {
+    /// The playlist's object path, used to activate it.
+    pub id: String,
+    pub name: String,
+    pub icon: String,
+}
+
+/// Why a command wasn't carried out.
+#[derive(Debug)]
+pub enum ControlError {
+    /// The player says it doesn't allow the command, e.g. `Next` with `CanGoNext` false, so it wasn't sent.
+    NotPermitted(String),
+    /// The player rejected the command or didn't answer.
+    Failed(dbus::Error),
+}
+
+impl From<dbus::Error> for ControlError {
+    fn from(e: dbus::Error) -> Self {
+        Self::Failed(e)
+    }
+}
+
+impl fmt::Display for ControlError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            ControlError::NotPermitted(command) => {
+                write!(f, "{command} isn't allowed by the player")
+            }
+            ControlError::Failed(e) => write!(f, "{e}"),
+        }
+    }
+}
+
+/// What the player says it allows, from its `Can*` properties, so controls it would ignore can be hidden or refused.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "camelCase")]
+pub struct Capabilities {
+    /// Whether it can be controlled at all. Without it, none of the others are allowed either.
+    pub can_control: bool,
+    pub can_play: bool,
+    pub can_pause: bool,
+    pub can_go_next: bool,
+    pub can_go_previous: bool,
+    pub can_seek: bool,
+}
+
+impl Capabilities {
+    /// A player that allows nothing.
+    pub const NONE: Self = Self {
+        can_control: false,
+        can_play: false,
+        can_pause: false,
+        can_go_next: false,
+        can_go_previous: false,
+        can_seek: false,
+    };
+
+    /// Whether the player allows `method`, e.g. `Next`, or `SetVolume` for setting its volume. Anything that doesn't depend on a capability, like reading the volume, is always allowed.
+    pub fn permits(&self, method: &str) -> bool {
+        match method {
+            "Play" => self.can_control && self.can_play,
+            "Pause" | "PlayPause" => self.can_control && self.can_pause,
+            "Next" => self.can_control && self.can_go_next,
+            "Stop" => self.can_control,
+            "Previous" => self.can_control && self.can_go_previous,
+            "Seek" | "SetPosition" => self.can_control && self.can_seek,
+            "SetVolume" => self.can_control,
+            _ => true,
+        }
+    }
+}
+
+/// Reads what the player allows. Capabilities it doesn't report are taken as allowed, and left for the player to refuse.
+#[tracing::instrument(skip(bus))]
+pub fn capabilities(bus: &BusAddress, bus_name: &str) -> Result<Capabilities, dbus::Error> {
+    let connection = bus.connect()?;
+    let proxy = connection.with_proxy(bus_name, MPRIS2_PATH, CALL_TIMEOUT);
+
+    read_capabilities(&proxy)
+}
+
+fn read_capabilities(proxy: &Proxy<&Connection>) -> Result<Capabilities, dbus::Error> {
+    let properties = proxy.get_all(PLAYER_INTERFACE)?;
+    let can = |name: &str| {
+        prop_cast::<bool>(&properties, name)
+            .copied()
+            .unwrap_or(true)
+    };
+
+    Ok(Capabilities {
+        can_control: can("CanControl"),
+        can_play: can("CanPlay"),
+        can_pause: can("CanPause"),
+        can_go_next: can("CanGoNext"),
+        can_go_previous: can("CanGoPrevious"),
+        can_seek: can("CanSeek"),
+    })
+}
+
+/// Fails with [`ControlError::NotPermitted`] when the player says it doesn't allow `method`, see [`Capabilities::permits`]. Players that can't tell what they allow are left to refuse by themselves.
+fn check_permitted(proxy: &Proxy<&Connection>, method: &str) -> Result<(), ControlError> {
+    if Capabilities::NONE.permits(method) {
+        return Ok(());
+    }
+    match read_capabilities(proxy) {
+        Ok(capabilities) if !capabilities.permits(method) => {
+            Err(ControlError::NotPermitted(method.to_string()))
+        }
+        _ => Ok(()),
+    }
+}
+
+/// Lists the player's playlists through the MPRIS Playlists interface.
+#[tracing::instrument(skip(bus))]
+pub fn list_playlists(bus: &BusAddress, bus_name: &str) -> Result<Vec<Playlist>, dbus::Error> {
+    let connection = bus.connect()?;
+    let proxy = connection.with_proxy(bus_name, MPRIS2_PATH, CALL_TIMEOUT);
+
+    let (playlists,): (Vec<(Path, String, String)>,) = proxy.method_call(
+        PLAYLISTS_INTERFACE,
+        "GetPlaylists",
+        (0u32, MAX_PLAYLISTS, "Alphabetical", false),
+    )?;
+
+    Ok(playlists
+        .into_iter()
+        .map(|(id, name, icon)| Playlist {
+            id: id.to_string(),
+            name,
+            icon,
+        })
+        .collect())
+}
+
+/// Starts playing one of the player's playlists.
+#[tracing::instrument(skip(bus))]
+pub fn activate_playlist(bus: &BusAddress, bus_name: &str, id: &str) -> Result<(), dbus::Error> {
+    let id = Path::new(id).map_err(|e| dbus::Error::new_failed(&e))?;
+
+    let connection = bus.connect()?;
+    let proxy = connection.with_proxy(bus_name, MPRIS2_PATH, CALL_TIMEOUT);
+
+    proxy.method_call(PLAYLISTS_INTERFACE, "ActivatePlaylist", (id,))
+}
+
+/// Calls one of the player's methods that take no arguments, like `PlayPause` or `Next`, if it allows it.
+#[tracing::instrument(skip(bus))]
+pub fn call_player(bus: &BusAddress, bus_name: &str, method: &str) -> Result<(), ControlError> {
+    let connection = bus.connect()?;
+    let proxy = connection.with_proxy(bus_name, MPRIS2_PATH, CALL_TIMEOUT);
+
+    check_permitted(&proxy, method)?;
+    Ok(proxy.method_call(PLAYER_INTERFACE, method, ())?)
+}
+
+/// Moves the player to `position` microseconds into the track, if it's still `track_id`.
+///
+/// Without a track id, seeks by the difference to `from`, where the player was last seen.
+#[tracing::instrument(skip(bus))]
+pub fn set_position(
+    bus: &BusAddress,
+    bus_name: &str,
+    track_id: &str,
+    position: u64,
+    from: u64,
+) -> Result<(), ControlError> {
+    let connection = bus.connect()?;
+    let proxy = connection.with_proxy(bus_name, MPRIS2_PATH, CALL_TIMEOUT);
+
+    check_permitted(&proxy, "SetPosition")?;
+    Ok(match Path::new(track_id) {
+        Ok(track_id) => {
+            proxy.method_call(PLAYER_INTERFACE, "SetPosition", (track_id, position as i64))
+        }
+        Err(_) => proxy.method_call(PLAYER_INTERFACE, "Seek", (position as i64 - from as i64,)),
+    }?)
+}
+
+/// Moves the player `offset` microseconds forwards, or backwards if negative.
+#[tracing::instrument(skip(bus))]
+pub fn seek(bus: &BusAddress, bus_name: &str, offset: i64) -> Result<(), ControlError> {
+    let connection = bus.connect()?;
+    let proxy = connection.with_proxy(bus_name, MPRIS2_PATH, CALL_TIMEOUT);
+
+    check_permitted(&proxy, "Seek")?;
+    Ok(proxy.method_call(PLAYER_INTERFACE, "Seek", (offset,))?)
+}
+
+/// Reads the player's volume, from 0 to 1 (or above, for players that amplify).
+#[tracing::instrument(skip(bus))]
+pub fn volume(bus: &BusAddress, bus_name: &str) -> Result<f64, dbus::Error> {
+    let connection = bus.connect()?;
+    let proxy = connection.with_proxy(bus_name, MPRIS2_PATH, CALL_TIMEOUT);
+
+    proxy.get(PLAYER_INTERFACE, "Volume")
+}
+
+/// Sets the player's volume, from 0 to 1.
+#[tracing::instrument(skip(bus))]
+pub fn set_volume(bus: &BusAddress, bus_name: &str, volume: f64) -> Result<(), ControlError> {
+    let connection = bus.connect()?;
+    let proxy = connection.with_proxy(bus_name, MPRIS2_PATH, CALL_TIMEOUT);
+
+    check_permitted(&proxy, "SetVolume")?;
+    Ok(proxy.set(PLAYER_INTERFACE, "Volume", volume)?)
+}
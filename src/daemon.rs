@@ -0,0 +1,161 @@
+use crate::exit_code;
+use std::fs::{self, File, OpenOptions};
+use std::io::{self, Read, Write};
+use std::os::fd::{AsRawFd, FromRawFd};
+use std::path::{Path, PathBuf};
+use std::thread;
+use std::time::Duration;
+
+/// How long `stop-daemon` waits for the daemon to exit.
+const STOP_TIMEOUT: Duration = Duration::from_secs(5);
+const STOP_POLL_INTERVAL: Duration = Duration::from_millis(100);
+
+/// `$XDG_RUNTIME_DIR/mpris-nowplaying.pid`, or in the temporary folder without one.
+pub fn default_pidfile() -> PathBuf {
+    dirs::runtime_dir()
+        .unwrap_or_else(std::env::temp_dir)
+        .join("mpris-nowplaying.pid")
+}
+
+/// `~/.local/state/mpris-nowplaying/log` on Linux, or in the temporary folder without a state folder.
+pub fn default_log_file() -> PathBuf {
+    match dirs::state_dir() {
+        Some(dir) => dir.join("mpris-nowplaying").join("log"),
+        None => std::env::temp_dir().join("mpris-nowplaying.log"),
+    }
+}
+
+/// Kept by the daemon until it's up, so the command that started it exits only then, see [`daemonize`].
+pub struct Readiness {
+    pipe: File,
+}
+
+impl Readiness {
+    /// Tells the parent everything was bound and connected, so it exits with 0.
+    pub fn ready(mut self) {
+        let _ = self.pipe.write_all(&[0]);
+    }
+}
+
+/// Waits in the parent until the daemon is up or has exited, and exits the same way: with 0 once it's ready, or with the daemon's own exit code if it didn't make it, e.g. [`crate::exit_code::BIND_FAILED`].
+fn exit_when_ready(mut pipe: File, child: libc::pid_t) -> ! {
+    let mut ready = [0u8];
+    if pipe.read(&mut ready).is_ok_and(|read| read == 1) {
+        std::process::exit(exit_code::OK);
+    }
+
+    // The daemon exited without being ready, closing its end.
+    let mut status = 0;
+    // SAFETY: `status` outlives the call, and `child` is our own child.
+    if unsafe { libc::waitpid(child, &mut status, 0) } == child && libc::WIFEXITED(status) {
+        std::process::exit(libc::WEXITSTATUS(status));
+    }
+    std::process::exit(exit_code::FAILURE);
+}
+
+/// Forks into the background and writes our pid to `pidfile`, with stdout and stderr appended to `log_file`. Returns in the child, the parent waits for it to call [`Readiness::ready`] or exit, and exits with the same code.
+///
+/// Must be called before any other thread is started, the child only keeps the calling thread.
+pub fn daemonize(pidfile: &Path, log_file: &Path) -> io::Result<Readiness> {
+    if let Some(dir) = log_file.parent() {
+        fs::create_dir_all(dir)?;
+    }
+    let log = OpenOptions::new()
+        .create(true)
+        .append(true)
+        .open(log_file)?;
+    let null = File::open("/dev/null")?;
+
+    let mut fds = [0; 2];
+    // SAFETY: `fds` has room for both ends. Close-on-exec, so hooks and scripts don't keep the parent waiting.
+    if unsafe { libc::pipe2(fds.as_mut_ptr(), libc::O_CLOEXEC) } == -1 {
+        return Err(io::Error::last_os_error());
+    }
+    // SAFETY: both were just opened and aren't owned by anything else.
+    let (read_end, write_end) = unsafe { (File::from_raw_fd(fds[0]), File::from_raw_fd(fds[1])) };
+
+    // SAFETY: no other threads are running yet, so the child doesn't inherit locks held by them.
+    match unsafe { libc::fork() } {
+        -1 => return Err(io::Error::last_os_error()),
+        0 => drop(read_end),
+        child => {
+            drop(write_end);
+            exit_when_ready(read_end, child);
+        }
+    }
+
+    // Leave the terminal's session, so closing it doesn't hang us up.
+    // SAFETY: plain system call without pointers.
+    if unsafe { libc::setsid() } == -1 {
+        return Err(io::Error::last_os_error());
+    }
+
+    fs::write(pidfile, format!("{}\n", std::process::id()))?;
+
+    for (from, to) in [(&null, 0), (&log, 1), (&log, 2)] {
+        // SAFETY: both are valid descriptors, `to` is one of the standard ones we're replacing.
+        if unsafe { libc::dup2(from.as_raw_fd(), to) } == -1 {
+            return Err(io::Error::last_os_error());
+        }
+    }
+
+    Ok(Readiness { pipe: write_end })
+}
+
+/// Signals the daemon in `pidfile` to exit and waits for it, returning the exit code.
+pub fn stop(pidfile: &Path) -> i32 {
+    let pid = match fs::read_to_string(pidfile) {
+        Ok(contents) => match contents.trim().parse::<libc::pid_t>() {
+            Ok(pid) if pid > 0 => pid,
+            _ => {
+                eprintln!("{} doesn't contain a pid.", pidfile.display());
+                return 1;
+            }
+        },
+        Err(e) if e.kind() == io::ErrorKind::NotFound => {
+            eprintln!(
+                "No daemon is running ({} doesn't exist).",
+                pidfile.display()
+            );
+            return 1;
+        }
+        Err(e) => {
+            eprintln!("Could not read {}: {e}", pidfile.display());
+            return 1;
+        }
+    };
+
+    // SAFETY: plain system call without pointers.
+    if unsafe { libc::kill(pid, libc::SIGTERM) } == -1 {
+        let e = io::Error::last_os_error();
+        if e.raw_os_error() == Some(libc::ESRCH) {
+            let _ = fs::remove_file(pidfile);
+            eprintln!("The daemon (pid {pid}) wasn't running anymore, removed its pidfile.");
+        } else {
+            eprintln!("Could not stop the daemon (pid {pid}): {e}");
+        }
+        return 1;
+    }
+
+    for _ in 0..STOP_TIMEOUT.as_millis() / STOP_POLL_INTERVAL.as_millis() {
+        thread::sleep(STOP_POLL_INTERVAL);
+
+        // SAFETY: as above, signal 0 only checks whether the process exists.
+        if unsafe { libc::kill(pid, 0) } == -1 {
+            let e = io::Error::last_os_error();
+            // Anything else, like EPERM, means it's still there or can't be told.
+            if e.raw_os_error() != Some(libc::ESRCH) {
+                eprintln!("Could not tell whether the daemon (pid {pid}) exited: {e}");
+                return 1;
+            }
+            let _ = fs::remove_file(pidfile);
+            return 0;
+        }
+    }
+
+    eprintln!(
+        "The daemon (pid {pid}) didn't exit within {} seconds.",
+        STOP_TIMEOUT.as_secs()
+    );
+    1
+}
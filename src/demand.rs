@@ -0,0 +1,103 @@
+use std::sync::{Arc, Condvar, Mutex, MutexGuard, PoisonError};
+use std::time::Duration;
+use tokio::sync::watch;
+
+/// How long a client that woke up the player thread waits for fresh statuses, at most.
+const CATCH_UP_TIMEOUT: Duration = Duration::from_secs(1);
+
+/// Who wants the players' statuses right now, so --pause-when-idle can stop reading them while nobody does.
+///
+/// Clients and outputs hold a [`DemandGuard`] while they're connected or running. Without --pause-when-idle, the statuses are always read.
+#[derive(Clone)]
+pub struct Demand(Arc<Shared>);
+
+struct Shared {
+    enabled: bool,
+    holders: Mutex<usize>,
+    held: Condvar,
+    /// Whether the player thread is paused, or resumed but hasn't read the players yet.
+    paused: watch::Sender<bool>,
+}
+
+impl Shared {
+    /// The count is right whatever panicked while it was held, so a panicking client doesn't take everyone else down with it.
+    fn holders(&self) -> MutexGuard<'_, usize> {
+        self.holders.lock().unwrap_or_else(PoisonError::into_inner)
+    }
+}
+
+impl Default for Demand {
+    /// Never pauses.
+    fn default() -> Self {
+        Self::new(false)
+    }
+}
+
+impl Demand {
+    /// Pauses reading the statuses while nobody holds a guard if `enabled`.
+    pub fn new(enabled: bool) -> Self {
+        Self(Arc::new(Shared {
+            enabled,
+            holders: Mutex::new(0),
+            held: Condvar::new(),
+            paused: watch::Sender::new(false),
+        }))
+    }
+
+    /// Wants the statuses until the guard is dropped, without waiting for them to be fresh.
+    pub fn hold_now(&self) -> DemandGuard {
+        *self.0.holders() += 1;
+        self.0.held.notify_all();
+        DemandGuard(self.0.clone())
+    }
+
+    /// Wants the statuses until the guard is dropped. If that woke up the player thread, waits a moment for it to read the players, so the first status answered isn't an old one.
+    pub async fn hold(&self) -> DemandGuard {
+        let guard = self.hold_now();
+
+        let mut paused = self.0.paused.subscribe();
+        let _ = tokio::time::timeout(CATCH_UP_TIMEOUT, paused.wait_for(|paused| !paused)).await;
+
+        guard
+    }
+
+    /// Blocks the player thread while nobody wants the statuses. [`Demand::caught_up`] has to follow once the players were read again.
+    pub fn wait(&self) {
+        if !self.0.enabled {
+            return;
+        }
+
+        let holders = self.0.holders();
+        if *holders > 0 {
+            return;
+        }
+
+        log::info!("Nothing is listening, pausing until a client connects.");
+        self.0.paused.send_replace(true);
+        let _holders = self
+            .0
+            .held
+            .wait_while(holders, |holders| *holders == 0)
+            .unwrap_or_else(PoisonError::into_inner);
+        log::info!("A client connected, resuming.");
+    }
+
+    /// Tells the clients waiting in [`Demand::hold`] that the players were read after a pause.
+    pub fn caught_up(&self) {
+        self.0.paused.send_if_modified(std::mem::take);
+    }
+
+    /// Whether nobody wants the statuses, and the player thread would pause.
+    pub fn is_idle(&self) -> bool {
+        self.0.enabled && *self.0.holders() == 0
+    }
+}
+
+/// Keeps the statuses wanted until dropped, see [`Demand`].
+pub struct DemandGuard(Arc<Shared>);
+
+impl Drop for DemandGuard {
+    fn drop(&mut self) {
+        *self.0.holders() -= 1;
+    }
+}
@@ -0,0 +1,232 @@
+use crate::artwork::{self, ArtworkInfo, ArtworkOrigin};
+use crate::event::Event;
+use crate::player::PlayerOptions;
+use crate::status::{
+    send_changed, PlaybackState, PlayerInfo, PlayerStatus, SampleTime, StatusMetadata,
+    StatusSnapshot,
+};
+use image::{ImageFormat, Rgb, RgbImage};
+use std::fs;
+use std::io;
+use std::path::{Path, PathBuf};
+use std::sync::mpsc;
+use std::thread;
+use std::time::{Duration, Instant};
+use tokio::sync::{broadcast, watch};
+
+const BUS_NAME: &str = "org.mpris.MediaPlayer2.demo";
+const IDENTITY: &str = "Demo";
+
+/// A made-up track. Lengths are kept short so track changes come around quickly.
+struct Track {
+    title: &'static str,
+    artist: &'static str,
+    album: &'static str,
+    length: Duration,
+    /// The artwork's gradient, from the top left to the bottom right corner.
+    colors: [[u8; 3]; 2],
+    /// Pause halfway through for a while, to show off the paused state.
+    pauses: bool,
+}
+
+const TRACKS: [Track; 4] = [
+    Track {
+        title: "Neon Tide",
+        artist: "The Midnight Arcade",
+        album: "Coastline Static",
+        length: Duration::from_secs(48),
+        colors: [[255, 94, 135], [72, 52, 212]],
+        pauses: false,
+    },
+    Track {
+        title: "Paper Satellites",
+        artist: "Juniper & the Lanterns",
+        album: "Small Hours",
+        length: Duration::from_secs(63),
+        colors: [[252, 196, 80], [224, 86, 36]],
+        pauses: true,
+    },
+    Track {
+        title: "Undertow (Extended Mix)",
+        artist: "Kessler, Moth Radio",
+        album: "Undertow",
+        length: Duration::from_secs(95),
+        colors: [[64, 224, 208], [18, 62, 112]],
+        pauses: false,
+    },
+    Track {
+        title: "A Song With an Unreasonably Long Title, to Check That It Wraps or Scrolls",
+        artist: "Nobody in Particular",
+        album: "Edge Cases, Vol. 1",
+        length: Duration::from_secs(37),
+        colors: [[168, 230, 104], [40, 120, 60]],
+        pauses: false,
+    },
+];
+
+/// How long the demo player stays paused in tracks that pause.
+const PAUSE_TIME: Duration = Duration::from_secs(5);
+const ARTWORK_SIZE: u32 = 300;
+
+/// Writes the tracks' artwork as images to `dir`, returning their `file://` URLs.
+fn write_artwork(dir: &Path) -> io::Result<Vec<String>> {
+    fs::create_dir_all(dir)?;
+
+    TRACKS
+        .iter()
+        .enumerate()
+        .map(|(index, track)| {
+            let path = dir.join(format!("{index}.png"));
+            if !path.is_file() {
+                let [from, to] = track.colors;
+                let image = RgbImage::from_fn(ARTWORK_SIZE, ARTWORK_SIZE, |x, y| {
+                    let t = (x + y) as f32 / (2 * (ARTWORK_SIZE - 1)) as f32;
+                    Rgb(std::array::from_fn(|i| {
+                        (from[i] as f32 + (to[i] as f32 - from[i] as f32) * t) as u8
+                    }))
+                });
+                image
+                    .save_with_format(&path, ImageFormat::Png)
+                    .map_err(io::Error::other)?;
+            }
+
+            url::Url::from_file_path(&path)
+                .map(String::from)
+                .map_err(|_| io::Error::other(format!("{} isn't absolute", path.display())))
+        })
+        .collect()
+}
+
+fn artwork_dir(options: &PlayerOptions) -> PathBuf {
+    options
+        .artwork
+        .cache_dir
+        .clone()
+        .unwrap_or_else(std::env::temp_dir)
+        .join("demo")
+}
+
+/// Plays the made-up playlist over and over, reporting it like an attached player. D-Bus isn't touched.
+pub fn run(
+    mut options: PlayerOptions,
+    options_rx: mpsc::Receiver<PlayerOptions>,
+    status_tx: watch::Sender<StatusSnapshot>,
+    events_tx: broadcast::Sender<Event>,
+) {
+    let artwork = if options.artwork.disabled {
+        Vec::new()
+    } else {
+        write_artwork(&artwork_dir(&options)).unwrap_or_else(|e| {
+            log::warn!("Could not write the demo artwork, playing without: {e}");
+            Vec::new()
+        })
+    };
+
+    log::info!("Playing the demo playlist instead of looking for players.");
+    let _ = events_tx.send(Event::Attached {
+        bus_name: BUS_NAME.to_string(),
+        identity: IDENTITY.to_string(),
+    });
+
+    let mut index = 0;
+    let mut position = Duration::ZERO;
+    let mut paused_until = None;
+    let mut paused_once = false;
+    let mut last_tick = Instant::now();
+
+    loop {
+        if let Some(new_options) = options_rx.try_iter().last() {
+            options = new_options;
+            log::info!("Reloaded the player options!");
+        }
+
+        let now = Instant::now();
+        let elapsed = now - last_tick;
+        last_tick = now;
+
+        let track = &TRACKS[index];
+        match paused_until {
+            Some(until) if now < until => {}
+            Some(_) => paused_until = None,
+            None => {
+                position += elapsed;
+
+                if track.pauses && !paused_once && position >= track.length / 2 {
+                    paused_until = Some(now + PAUSE_TIME);
+                    paused_once = true;
+                }
+                if position >= track.length {
+                    index = (index + 1) % TRACKS.len();
+                    position = Duration::ZERO;
+                    paused_once = false;
+                }
+            }
+        }
+
+        let track = &TRACKS[index];
+        let artwork = match artwork.get(index) {
+            Some(src) if !options.artwork.disabled => artwork::with_variants(
+                ArtworkInfo {
+                    src: src.clone(),
+                    origin: ArtworkOrigin::Url,
+                    variant: None,
+                },
+                &options.artwork,
+            ),
+            _ => Vec::new(),
+        };
+
+        let status = PlayerStatus {
+            player: PlayerInfo {
+                bus_name: BUS_NAME.to_string(),
+                identity: IDENTITY.to_string(),
+                alias: options
+                    .config
+                    .alias(&[
+                        BUS_NAME.trim_start_matches("org.mpris.MediaPlayer2."),
+                        IDENTITY,
+                    ])
+                    .map(str::to_string),
+                // Only ever attached once.
+                attachment: 1,
+            },
+            metadata: StatusMetadata {
+                title: track.title.to_string(),
+                artist: track.artist.to_string(),
+                album: track.album.to_string(),
+                artwork,
+                length: track.length.as_micros() as u64,
+                url: String::new(),
+                track_id: format!("/org/mpris/MediaPlayer2/demo/{index}"),
+                title_latin: String::new(),
+                artist_latin: String::new(),
+            },
+            playback_state: if paused_until.is_some() {
+                PlaybackState::Paused
+            } else {
+                PlaybackState::Playing
+            },
+            position: position.min(track.length).as_micros() as u64,
+            position_time: SampleTime::now(),
+            degraded: false,
+            stale: false,
+            lyrics: None,
+            musicbrainz: None,
+            spotify: None,
+        };
+
+        let track_change = Event::track_change(status_tx.borrow().active(), &status);
+        if let Some(event) = track_change {
+            let _ = events_tx.send(event);
+        }
+        send_changed(
+            &status_tx,
+            StatusSnapshot {
+                active: Some(BUS_NAME.to_string()),
+                players: [(BUS_NAME.to_string(), status)].into(),
+            },
+        );
+
+        thread::sleep(options.update_interval);
+    }
+}
@@ -0,0 +1,337 @@
+use crate::bar::Format;
+use crate::sink::{Sink, SinkContext};
+use crate::status::{CompactStatus, PlaybackState, PlayerStatus, StatusSnapshot};
+use futures_util::future::BoxFuture;
+use serde::{Deserialize, Serialize};
+use std::io;
+use std::path::PathBuf;
+use std::time::{Duration, SystemTime, UNIX_EPOCH};
+use tokio::io::{AsyncReadExt, AsyncWriteExt};
+use tokio::net::UnixStream;
+use tokio::sync::watch;
+
+const RECONNECT_DELAY: Duration = Duration::from_secs(15);
+/// Discord allows 5 activity updates per 20 seconds.
+const UPDATE_COOLDOWN: Duration = Duration::from_secs(4);
+/// How far the reported start may drift before it's sent again, positions aren't read at exactly the same moment.
+const SEEK_TOLERANCE: Duration = Duration::from_secs(2);
+/// Discord rejects activity texts outside of this length.
+const TEXT_LENGTH: std::ops::RangeInclusive<usize> = 2..=128;
+
+const OP_HANDSHAKE: u32 = 0;
+const OP_FRAME: u32 = 1;
+const OP_CLOSE: u32 = 2;
+
+/// What --discord-client-id publishes.
+#[derive(Debug, Clone, PartialEq)]
+pub struct DiscordOptions {
+    /// The application's id from the Discord developer portal. Its name is what's shown as "Listening to …".
+    pub client_id: String,
+    pub details: Format,
+    pub state: Format,
+    /// An asset key or URL shown when the artwork isn't a public URL.
+    pub image: Option<String>,
+}
+
+#[derive(Serialize)]
+struct Handshake<'a> {
+    v: u32,
+    client_id: &'a str,
+}
+
+#[derive(Serialize)]
+struct Command {
+    cmd: &'static str,
+    args: SetActivity,
+    nonce: String,
+}
+
+#[derive(Serialize)]
+struct SetActivity {
+    pid: u32,
+    activity: Option<Activity>,
+}
+
+#[derive(Debug, Clone, PartialEq, Serialize)]
+struct Activity {
+    /// 2 is "Listening to".
+    r#type: u32,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    details: Option<String>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    state: Option<String>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    timestamps: Option<Timestamps>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    assets: Option<Assets>,
+}
+
+/// Milliseconds since the epoch, Discord shows a progress bar between them.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize)]
+struct Timestamps {
+    start: u64,
+    end: u64,
+}
+
+#[derive(Debug, Clone, PartialEq, Serialize)]
+struct Assets {
+    large_image: String,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    large_text: Option<String>,
+}
+
+#[derive(Deserialize)]
+struct Response {
+    evt: Option<String>,
+    #[serde(default)]
+    data: Option<ErrorData>,
+}
+
+#[derive(Deserialize)]
+struct ErrorData {
+    #[serde(default)]
+    message: String,
+}
+
+/// Where Discord might have its IPC socket: plain installs, Flatpak and Snap.
+fn socket_paths() -> Vec<PathBuf> {
+    let dirs = ["XDG_RUNTIME_DIR", "TMPDIR", "TMP", "TEMP"]
+        .iter()
+        .filter_map(std::env::var_os)
+        .map(PathBuf::from)
+        .chain([PathBuf::from("/tmp")]);
+
+    let mut paths = Vec::new();
+    for dir in dirs {
+        for sub in ["", "app/com.discordapp.Discord", "snap.discord"] {
+            for i in 0..10 {
+                paths.push(dir.join(sub).join(format!("discord-ipc-{i}")));
+            }
+        }
+    }
+
+    paths
+}
+
+async fn write_frame(stream: &mut UnixStream, op: u32, payload: &impl Serialize) -> io::Result<()> {
+    let payload = serde_json::to_vec(payload)?;
+
+    let mut frame = Vec::with_capacity(8 + payload.len());
+    frame.extend(op.to_le_bytes());
+    frame.extend((payload.len() as u32).to_le_bytes());
+    frame.extend(payload);
+    stream.write_all(&frame).await
+}
+
+async fn read_frame(stream: &mut UnixStream) -> io::Result<(u32, Vec<u8>)> {
+    let mut header = [0; 8];
+    stream.read_exact(&mut header).await?;
+    let op = u32::from_le_bytes(header[..4].try_into().unwrap());
+    let len = u32::from_le_bytes(header[4..].try_into().unwrap());
+
+    let mut payload = vec![0; len as usize];
+    stream.read_exact(&mut payload).await?;
+    Ok((op, payload))
+}
+
+/// Reads Discord's answer to a handshake or command, turning rejections into errors.
+async fn read_response(stream: &mut UnixStream) -> io::Result<()> {
+    let (op, payload) = read_frame(stream).await?;
+    let response = serde_json::from_slice::<Response>(&payload)?;
+    let message = || response.data.as_ref().map(|data| data.message.clone());
+
+    if op == OP_CLOSE {
+        return Err(io::Error::new(
+            io::ErrorKind::ConnectionRefused,
+            message().unwrap_or_else(|| String::from("closed by Discord")),
+        ));
+    }
+    if response.evt.as_deref() == Some("ERROR") {
+        return Err(io::Error::other(message().unwrap_or_default()));
+    }
+
+    Ok(())
+}
+
+async fn connect(client_id: &str) -> io::Result<UnixStream> {
+    let mut last_error = io::Error::new(io::ErrorKind::NotFound, "Discord isn't running");
+
+    for path in socket_paths() {
+        let mut stream = match UnixStream::connect(&path).await {
+            Ok(stream) => stream,
+            Err(e) if e.kind() == io::ErrorKind::NotFound => continue,
+            Err(e) => {
+                last_error = e;
+                continue;
+            }
+        };
+
+        write_frame(&mut stream, OP_HANDSHAKE, &Handshake { v: 1, client_id }).await?;
+        // The first answer is the READY event.
+        read_response(&mut stream).await?;
+        return Ok(stream);
+    }
+
+    Err(last_error)
+}
+
+/// Cuts `text` down to what Discord takes, leaving it out if it's too short.
+fn activity_text(text: String) -> Option<String> {
+    let text = if text.chars().count() > *TEXT_LENGTH.end() {
+        let mut text = text
+            .chars()
+            .take(*TEXT_LENGTH.end() - 1)
+            .collect::<String>();
+        text.push('…');
+        text
+    } else {
+        text
+    };
+
+    (text.chars().count() >= *TEXT_LENGTH.start()).then_some(text)
+}
+
+fn activity(status: &PlayerStatus, options: &DiscordOptions) -> Activity {
+    let compact = CompactStatus::from(Some(status));
+
+    let timestamps = (status.metadata.length > 0).then(|| {
+        let now = SystemTime::now()
+            .duration_since(UNIX_EPOCH)
+            .unwrap_or_default();
+        let start = now.saturating_sub(Duration::from_micros(status.position));
+
+        Timestamps {
+            start: start.as_millis() as u64,
+            end: (start + Duration::from_micros(status.metadata.length)).as_millis() as u64,
+        }
+    });
+
+    let image = status
+        .metadata
+        .artwork
+        .first()
+        .map(|artwork| artwork.src.as_str())
+        .filter(|src| src.starts_with("https://") || src.starts_with("http://"))
+        .or(options.image.as_deref())
+        .map(str::to_string);
+
+    Activity {
+        r#type: 2,
+        details: activity_text(options.details.render(&compact)),
+        state: activity_text(options.state.render(&compact)),
+        timestamps,
+        assets: image.map(|large_image| Assets {
+            large_image,
+            large_text: activity_text(compact.album.clone()),
+        }),
+    }
+}
+
+/// Whether the activity needs sending again: anything but the timestamps changed, or they moved by a seek.
+fn changed(last: &Activity, current: &Activity) -> bool {
+    let moved = match (last.timestamps, current.timestamps) {
+        (Some(last), Some(current)) => {
+            last.start.abs_diff(current.start) > SEEK_TOLERANCE.as_millis() as u64
+        }
+        (last, current) => last != current,
+    };
+
+    moved
+        || Activity {
+            timestamps: None,
+            ..last.clone()
+        } != Activity {
+            timestamps: None,
+            ..current.clone()
+        }
+}
+
+/// Publishes the active player's track as Discord Rich Presence while it plays, clearing it otherwise. Reconnects whenever Discord restarts.
+pub async fn run(options: DiscordOptions, mut status_rx: watch::Receiver<StatusSnapshot>) {
+    let mut failing = false;
+
+    loop {
+        let mut stream = match connect(&options.client_id).await {
+            Ok(stream) => stream,
+            Err(e) => {
+                if !failing {
+                    log::warn!(
+                        "Could not connect to Discord, retrying every {} seconds: {e}",
+                        RECONNECT_DELAY.as_secs()
+                    );
+                    failing = true;
+                }
+
+                tokio::time::sleep(RECONNECT_DELAY).await;
+                continue;
+            }
+        };
+        log::info!("Connected to Discord!");
+        failing = false;
+
+        status_rx.mark_changed();
+        let mut last_sent: Option<Option<Activity>> = None;
+        let mut nonce = 0u64;
+
+        loop {
+            if status_rx.changed().await.is_err() {
+                return;
+            }
+
+            let status = status_rx
+                .borrow_and_update()
+                .active()
+                .filter(|status| status.playback_state == PlaybackState::Playing)
+                .cloned();
+            let activity = status.as_ref().map(|status| activity(status, &options));
+
+            let send = match (&last_sent, &activity) {
+                (Some(Some(last)), Some(current)) => changed(last, current),
+                (Some(last), current) => last.is_some() != current.is_some(),
+                (None, _) => true,
+            };
+            if !send {
+                continue;
+            }
+
+            nonce += 1;
+            let command = Command {
+                cmd: "SET_ACTIVITY",
+                args: SetActivity {
+                    pid: std::process::id(),
+                    activity: activity.clone(),
+                },
+                nonce: nonce.to_string(),
+            };
+
+            let result = async {
+                write_frame(&mut stream, OP_FRAME, &command).await?;
+                read_response(&mut stream).await
+            }
+            .await;
+            match result {
+                Ok(()) => {}
+                Err(e) if e.kind() == io::ErrorKind::Other => {
+                    log::warn!("Discord rejected the activity: {e}");
+                }
+                Err(e) => {
+                    log::warn!("Lost connection to Discord: {e}");
+                    break;
+                }
+            }
+
+            last_sent = Some(activity);
+            tokio::time::sleep(UPDATE_COOLDOWN).await;
+        }
+    }
+}
+
+impl Sink for DiscordOptions {
+    fn name(&self) -> &'static str {
+        "discord"
+    }
+
+    fn run(&self, context: &SinkContext) -> BoxFuture<'static, ()> {
+        Box::pin(run(self.clone(), context.status_rx.clone()))
+    }
+}
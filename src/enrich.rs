@@ -0,0 +1,116 @@
+use crate::status::{send_changed, PlayerStatus, StatusSnapshot};
+use std::collections::{HashMap, VecDeque};
+use std::hash::Hash;
+use std::sync::Arc;
+use std::time::Duration;
+use tokio::sync::watch;
+use tokio::task::JoinHandle;
+use tokio::time::Instant;
+
+/// How long to wait before looking up a track again after it failed.
+const RETRY_DELAY: Duration = Duration::from_secs(60);
+/// How many looked up tracks are remembered.
+const CACHE_SIZE: usize = 256;
+
+/// A service the active player's track is looked up on, to add what the player doesn't report.
+pub trait Source: Send + Sync + 'static {
+    /// What a track is looked up by. Lookups are remembered by it.
+    type Key: Clone + Eq + Hash + Send + 'static;
+    type Found: Send + 'static;
+
+    /// Shown in logs.
+    const NAME: &'static str;
+
+    /// What to look up for `status`, `None` if there's nothing to look up.
+    fn key(&self, status: &PlayerStatus) -> Option<Self::Key>;
+    /// Looks up a track, `None` if the service doesn't know it. Blocks.
+    fn lookup(&self, key: &Self::Key) -> Result<Option<Self::Found>, String>;
+    /// Merges what was found into the status.
+    fn apply(&self, status: &mut PlayerStatus, found: &Self::Found);
+}
+
+type Lookup<S> = JoinHandle<Result<Option<<S as Source>::Found>, String>>;
+
+/// Forwards every status from `raw_rx` to `status_tx`, looking up the active player's track on `source` when it changes and merging in what was found. Failed lookups are retried after a minute.
+pub async fn run<S: Source>(
+    source: S,
+    mut raw_rx: watch::Receiver<StatusSnapshot>,
+    status_tx: watch::Sender<StatusSnapshot>,
+) {
+    let source = Arc::new(source);
+    let mut snapshot = raw_rx.borrow_and_update().clone();
+    let mut found: HashMap<S::Key, Option<S::Found>> = HashMap::new();
+    let mut found_order: VecDeque<S::Key> = VecDeque::new();
+    let mut loading: Option<(S::Key, Lookup<S>)> = None;
+    let mut retry_at: Option<(S::Key, Instant)> = None;
+
+    loop {
+        let key = snapshot.active().and_then(|status| source.key(status));
+
+        let waiting = retry_at
+            .as_ref()
+            .is_some_and(|(failed, at)| Some(failed) == key.as_ref() && *at > Instant::now());
+        let needed = key.as_ref().filter(|key| {
+            !found.contains_key(key)
+                && loading.as_ref().is_none_or(|(loading, _)| loading != *key)
+                && !waiting
+        });
+        if let Some(key) = needed {
+            let (source, lookup_key) = (source.clone(), key.clone());
+            let handle = tokio::task::spawn_blocking(move || source.lookup(&lookup_key));
+            loading = Some((key.clone(), handle));
+        }
+
+        let mut forwarded = snapshot.clone();
+        if let (Some(Some(found)), Some(status)) = (
+            key.as_ref().and_then(|key| found.get(key)),
+            forwarded
+                .active
+                .as_ref()
+                .and_then(|active| forwarded.players.get_mut(active)),
+        ) {
+            source.apply(status, found);
+        }
+        if !send_changed(&status_tx, forwarded) {
+            return;
+        }
+
+        tokio::select! {
+            changed = raw_rx.changed() => {
+                if changed.is_err() {
+                    return;
+                }
+                snapshot = raw_rx.borrow_and_update().clone();
+            }
+            result = async { (&mut loading.as_mut().unwrap().1).await }, if loading.is_some() => {
+                let (key, _) = loading.take().unwrap();
+                match result.unwrap() {
+                    Ok(result) => {
+                        if result.is_none() {
+                            log::debug!("{} doesn't know the track.", S::NAME);
+                        }
+
+                        if found_order.len() >= CACHE_SIZE {
+                            if let Some(oldest) = found_order.pop_front() {
+                                found.remove(&oldest);
+                            }
+                        }
+                        found_order.push_back(key.clone());
+                        found.insert(key, result);
+                    }
+                    Err(e) => {
+                        log::warn!(
+                            "Could not look up the track on {}, retrying in {} seconds: {e}",
+                            S::NAME,
+                            RETRY_DELAY.as_secs()
+                        );
+                        retry_at = Some((key, Instant::now() + RETRY_DELAY));
+                    }
+                }
+            }
+            () = async { tokio::time::sleep_until(retry_at.as_ref().unwrap().1).await }, if waiting => {
+                retry_at = None;
+            }
+        }
+    }
+}
@@ -0,0 +1,98 @@
+use crate::status::{PlaybackState, PlayerStatus, StatusMetadata};
+use serde::{Deserialize, Serialize};
+
+/// Something that happened to the attached players, pushed to subscribed clients.
+///
+/// Unlike the statuses, which only keep the latest one, each is sent on its own, so two quick track changes are two events.
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+#[serde(rename_all = "camelCase", tag = "event")]
+pub enum Event {
+    /// A player was attached to.
+    #[serde(rename_all = "camelCase")]
+    Attached { bus_name: String, identity: String },
+    /// A player quit or was replaced by another one.
+    #[serde(rename_all = "camelCase")]
+    Detached { bus_name: String },
+    /// No player could be found, the next try is in `retry_in` seconds.
+    #[serde(rename_all = "camelCase")]
+    Searching { attempts: u32, retry_in: f32 },
+    /// A player started another track, or its first one after being attached to.
+    #[serde(rename_all = "camelCase")]
+    TrackChanged {
+        bus_name: String,
+        metadata: StatusMetadata,
+    },
+    /// A player has played its track for --confirm-after, not counting pauses, so it wasn't skipped right away. Sent once per track.
+    #[serde(rename_all = "camelCase")]
+    NowPlayingConfirmed {
+        bus_name: String,
+        metadata: StatusMetadata,
+    },
+    /// A player jumped from `from` to `position`, in microseconds. Either it said so, or its position moved other than by playing.
+    #[serde(rename_all = "camelCase")]
+    Seeked {
+        bus_name: String,
+        position: u64,
+        /// Where it would have been by then without the jump.
+        #[serde(default)]
+        from: u64,
+    },
+}
+
+/// How far, in microseconds, a position may be from where playing would have taken it before it counts as a jump. Players report positions a little late or rounded, by well under this.
+const SEEK_THRESHOLD: u64 = 1_500_000;
+
+impl Event {
+    /// The [`Event::TrackChanged`] for a player's `status`, if it plays another track than its `previous` one.
+    pub fn track_change(previous: Option<&PlayerStatus>, status: &PlayerStatus) -> Option<Self> {
+        let changed = match previous {
+            Some(previous) => !previous.metadata.is_same_track(&status.metadata),
+            None => !status.metadata.title.is_empty(),
+        };
+
+        changed.then(|| Event::TrackChanged {
+            bus_name: status.player.bus_name.clone(),
+            metadata: status.metadata.clone(),
+        })
+    }
+
+    /// The [`Event::Seeked`] for a player whose position moved from its `previous` one other than by playing, for players that don't signal seeks.
+    pub fn position_jump(previous: Option<&PlayerStatus>, status: &PlayerStatus) -> Option<Self> {
+        let previous = previous?;
+        if !previous.metadata.is_same_track(&status.metadata)
+            || previous.degraded
+            || previous.stale
+            || status.degraded
+        {
+            return None;
+        }
+
+        let elapsed = status
+            .position_time
+            .instant
+            .saturating_duration_since(previous.position_time.instant)
+            .as_micros() as u64;
+        let clamp = |position: u64| match status.metadata.length {
+            0 => position,
+            length => position.min(length),
+        };
+        let played = clamp(previous.position + elapsed);
+
+        // Started or stopped playing in between, so it played for anywhere up to `elapsed`.
+        let playing = |status: &PlayerStatus| status.playback_state == PlaybackState::Playing;
+        let (from, lowest, highest) = match (playing(previous), playing(status)) {
+            (true, true) => (played, played, played),
+            (false, false) => (previous.position, previous.position, previous.position),
+            (true, false) => (played, previous.position, played),
+            (false, true) => (previous.position, previous.position, played),
+        };
+
+        let jumped =
+            status.position + SEEK_THRESHOLD < lowest || status.position > highest + SEEK_THRESHOLD;
+        jumped.then(|| Event::Seeked {
+            bus_name: status.player.bus_name.clone(),
+            position: status.position,
+            from,
+        })
+    }
+}
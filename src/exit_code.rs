@@ -0,0 +1,14 @@
+//! The process exit codes, so wrapper scripts and service managers can tell failures apart.
+//!
+//! An invalid command line or config file exits with 2, like clap's usage errors.
+
+/// Exited normally, including on SIGTERM and SIGINT.
+pub const OK: i32 = 0;
+/// A command found no player, or the player refused.
+pub const FAILURE: i32 = 1;
+/// An address or socket couldn't be bound, most likely because it's taken.
+pub const BIND_FAILED: i32 = 3;
+/// D-Bus couldn't be reached.
+pub const DBUS_UNAVAILABLE: i32 = 4;
+/// A server stopped on an unexpected error, after the last status was saved.
+pub const INTERNAL_ERROR: i32 = 5;
@@ -0,0 +1,107 @@
+use crate::bar::{self, Format, Style};
+use crate::casing::FieldCase;
+use crate::lines;
+use crate::sink::{Sink, SinkContext};
+use crate::status::StatusSnapshot;
+use futures_util::future::BoxFuture;
+use std::ffi::CString;
+use std::io;
+use std::os::unix::ffi::OsStrExt;
+use std::os::unix::fs::FileTypeExt;
+use std::path::{Path, PathBuf};
+use std::time::Duration;
+use tokio::net::unix::pipe;
+use tokio::sync::watch;
+
+/// How often to check whether a reader opened the pipe. Readers block until then.
+const READER_POLL_INTERVAL: Duration = Duration::from_millis(250);
+
+/// A named pipe fed with the active player's status, one line per change.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Fifo {
+    pub path: PathBuf,
+    /// Lines in this format, or the status as JSON if not set.
+    pub format: Option<Format>,
+}
+
+/// Creates the named pipe at `path` unless there already is one. Anything else in its place is left alone.
+fn create(path: &Path) -> io::Result<()> {
+    match std::fs::metadata(path) {
+        Ok(metadata) if metadata.file_type().is_fifo() => return Ok(()),
+        Ok(_) => {
+            return Err(io::Error::new(
+                io::ErrorKind::AlreadyExists,
+                "it exists and isn't a named pipe",
+            ))
+        }
+        Err(e) if e.kind() != io::ErrorKind::NotFound => return Err(e),
+        Err(_) => {}
+    }
+
+    let c_path = CString::new(path.as_os_str().as_bytes())?;
+    if unsafe { libc::mkfifo(c_path.as_ptr(), 0o644) } == -1 {
+        return Err(io::Error::last_os_error());
+    }
+    Ok(())
+}
+
+/// Waits for a reader to open the pipe, then opens it for writing.
+async fn open(path: &Path) -> io::Result<pipe::Sender> {
+    loop {
+        match pipe::OpenOptions::new().open_sender(path) {
+            // Nobody is reading yet.
+            Err(e) if e.raw_os_error() == Some(libc::ENXIO) => {
+                tokio::time::sleep(READER_POLL_INTERVAL).await;
+            }
+            result => return result,
+        }
+    }
+}
+
+/// Feeds the active player's status to the named pipe, creating it if needed. Each reader gets the current line as soon as it opens the pipe, then a line whenever it changes; when it goes away, the next one is waited for.
+pub async fn run(fifo: Fifo, status_rx: watch::Receiver<StatusSnapshot>, case: FieldCase) {
+    if let Err(e) = create(&fifo.path) {
+        log::warn!(
+            "Could not create the named pipe {}: {e}",
+            fifo.path.display()
+        );
+        return;
+    }
+
+    loop {
+        let writer = match open(&fifo.path).await {
+            Ok(writer) => writer,
+            Err(e) => {
+                log::warn!("Could not open the named pipe {}: {e}", fifo.path.display());
+                return;
+            }
+        };
+        log::debug!("A reader opened {}.", fifo.path.display());
+
+        // Both return once the reader goes away, or the daemon stops.
+        match &fifo.format {
+            Some(format) => {
+                bar::write(writer, format.clone(), Style::Text, status_rx.clone()).await
+            }
+            None => lines::write(writer, status_rx.clone(), case).await,
+        }
+        if status_rx.has_changed().is_err() {
+            return;
+        }
+        log::debug!("The reader of {} went away.", fifo.path.display());
+    }
+}
+
+impl Sink for Fifo {
+    fn name(&self) -> &'static str {
+        "fifo"
+    }
+
+    fn run(&self, context: &SinkContext) -> BoxFuture<'static, ()> {
+        Box::pin(run(
+            self.clone(),
+            context.status_rx.clone(),
+            context.state.json_case,
+        ))
+    }
+}
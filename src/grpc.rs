@@ -0,0 +1,196 @@
+use crate::bus::BusAddress;
+use crate::control;
+use crate::demand::Demand;
+use crate::status::{self, PlaybackState, StatusSnapshot};
+use futures_util::stream::{self, BoxStream, StreamExt};
+use proto::now_playing_server::{NowPlaying, NowPlayingServer};
+use tokio::net::TcpListener;
+use tokio::sync::watch;
+use tonic::transport::server::TcpIncoming;
+use tonic::transport::Server;
+use tonic::{Request, Response, Status};
+
+/// The types generated from `proto/nowplaying.proto`.
+pub mod proto {
+    tonic::include_proto!("mpris_nowplaying.v1");
+}
+
+impl From<&status::PlayerStatus> for proto::PlayerStatus {
+    fn from(value: &status::PlayerStatus) -> Self {
+        Self {
+            player: Some(proto::Player {
+                bus_name: value.player.bus_name.clone(),
+                identity: value.player.identity.clone(),
+                alias: value.player.alias.clone(),
+            }),
+            metadata: Some(proto::Metadata {
+                title: value.metadata.title.clone(),
+                artist: value.metadata.artist.clone(),
+                album: value.metadata.album.clone(),
+                artwork: value
+                    .metadata
+                    .artwork
+                    .iter()
+                    .map(|artwork| proto::Artwork {
+                        src: artwork.src.clone(),
+                        variant: artwork.variant.and_then(|variant| {
+                            serde_json::to_value(variant)
+                                .ok()
+                                .and_then(|v| v.as_str().map(str::to_string))
+                        }),
+                    })
+                    .collect(),
+                length: value.metadata.length,
+            }),
+            playback_state: match value.playback_state {
+                PlaybackState::Playing => proto::PlaybackState::Playing,
+                PlaybackState::Paused => proto::PlaybackState::Paused,
+                PlaybackState::None => proto::PlaybackState::None,
+            }
+            .into(),
+            position: value.position,
+            position_time: value.position_time.unix_millis(),
+            degraded: value.degraded,
+            stale: value.stale,
+        }
+    }
+}
+
+fn status_update(snapshot: &StatusSnapshot) -> proto::StatusUpdate {
+    proto::StatusUpdate {
+        status: snapshot.active().map(Into::into),
+    }
+}
+
+struct Service {
+    status_rx: watch::Receiver<StatusSnapshot>,
+    bus: BusAddress,
+    demand: Demand,
+}
+
+impl Service {
+    /// Runs a playlist call on the active player, off the executor.
+    async fn on_active<T: Send + 'static>(
+        &self,
+        call: impl FnOnce(&BusAddress, &str) -> Result<T, dbus::Error> + Send + 'static,
+    ) -> Result<T, Status> {
+        let Some(bus_name) = self.status_rx.borrow().active.clone() else {
+            return Err(Status::failed_precondition("no player attached"));
+        };
+        let bus = self.bus.clone();
+
+        tokio::task::spawn_blocking(move || call(&bus, &bus_name))
+            .await
+            .map_err(|e| Status::internal(e.to_string()))?
+            .map_err(|e| Status::unavailable(e.message().unwrap_or_default()))
+    }
+}
+
+#[tonic::async_trait]
+impl NowPlaying for Service {
+    async fn get_status(
+        &self,
+        _: Request<proto::GetStatusRequest>,
+    ) -> Result<Response<proto::StatusUpdate>, Status> {
+        let _demand = self.demand.hold().await;
+        Ok(Response::new(status_update(&self.status_rx.borrow())))
+    }
+
+    type WatchStatusStream = BoxStream<'static, Result<proto::StatusUpdate, Status>>;
+
+    async fn watch_status(
+        &self,
+        _: Request<proto::WatchStatusRequest>,
+    ) -> Result<Response<Self::WatchStatusStream>, Status> {
+        // Kept along with the stream, until the client goes away.
+        let demand = self.demand.hold().await;
+        let mut status_rx = self.status_rx.clone();
+        status_rx.mark_changed();
+
+        let updates = stream::unfold(
+            (status_rx, None, demand),
+            |(mut status_rx, last_sent, demand)| async move {
+                loop {
+                    status_rx.changed().await.ok()?;
+
+                    // Other players changing wake this up too, only send actual changes.
+                    let update = status_update(&status_rx.borrow_and_update());
+                    if last_sent.as_ref() == Some(&update) {
+                        continue;
+                    }
+
+                    return Some((Ok(update.clone()), (status_rx, Some(update), demand)));
+                }
+            },
+        );
+
+        Ok(Response::new(updates.boxed()))
+    }
+
+    async fn list_players(
+        &self,
+        _: Request<proto::ListPlayersRequest>,
+    ) -> Result<Response<proto::ListPlayersResponse>, Status> {
+        let _demand = self.demand.hold().await;
+        let players = self
+            .status_rx
+            .borrow()
+            .players
+            .iter()
+            .map(|(bus_name, status)| (bus_name.clone(), status.into()))
+            .collect();
+
+        Ok(Response::new(proto::ListPlayersResponse { players }))
+    }
+
+    async fn list_playlists(
+        &self,
+        _: Request<proto::ListPlaylistsRequest>,
+    ) -> Result<Response<proto::ListPlaylistsResponse>, Status> {
+        let playlists = self.on_active(control::list_playlists).await?;
+
+        Ok(Response::new(proto::ListPlaylistsResponse {
+            playlists: playlists
+                .into_iter()
+                .map(|playlist| proto::Playlist {
+                    id: playlist.id,
+                    name: playlist.name,
+                    icon: playlist.icon,
+                })
+                .collect(),
+        }))
+    }
+
+    async fn activate_playlist(
+        &self,
+        request: Request<proto::ActivatePlaylistRequest>,
+    ) -> Result<Response<proto::ActivatePlaylistResponse>, Status> {
+        let id = request.into_inner().id;
+        self.on_active(move |bus, bus_name| control::activate_playlist(bus, bus_name, &id))
+            .await?;
+
+        Ok(Response::new(proto::ActivatePlaylistResponse {}))
+    }
+}
+
+/// Serves the gRPC API on the listener.
+pub async fn run(
+    listener: TcpListener,
+    status_rx: watch::Receiver<StatusSnapshot>,
+    bus: BusAddress,
+    demand: Demand,
+) {
+    let service = Service {
+        status_rx,
+        bus,
+        demand,
+    };
+
+    if let Err(e) = Server::builder()
+        .add_service(NowPlayingServer::new(service))
+        .serve_with_incoming(TcpIncoming::from(listener))
+        .await
+    {
+        log::error!("The gRPC server stopped: {e}");
+    }
+}
@@ -0,0 +1,112 @@
+use crate::event::Event;
+use crate::status::StatusSnapshot;
+use serde::Serialize;
+use std::sync::atomic::{AtomicU64, Ordering};
+use std::sync::{Arc, Mutex, PoisonError};
+use std::time::Instant;
+use tokio::sync::broadcast::{self, error::RecvError};
+
+/// What the daemon counts about itself, answered to `serverStats`.
+#[derive(Debug, Clone)]
+pub struct Health(Arc<Counters>);
+
+#[derive(Debug)]
+struct Counters {
+    started: Instant,
+    messages_sent: AtomicU64,
+    artwork_sent: AtomicU64,
+    /// The last search for a player, until one is attached.
+    searching: Mutex<Option<Searching>>,
+}
+
+/// The player thread's backoff while it finds no player.
+#[derive(Debug, Clone, Copy, PartialEq, Serialize)]
+#[serde(rename_all = "camelCase")]
+pub struct Searching {
+    pub attempts: u32,
+    /// In seconds.
+    pub retry_in: f32,
+}
+
+/// The daemon's own view of its health.
+#[derive(Debug, Clone, PartialEq, Serialize)]
+#[serde(rename_all = "camelCase")]
+pub struct ServerStats {
+    pub version: &'static str,
+    /// In seconds.
+    pub uptime: f64,
+    /// WebSocket clients and event streams connected right now.
+    pub clients: usize,
+    /// How many players are attached.
+    pub players: usize,
+    /// Bus name of the player reported by default.
+    pub active: Option<String>,
+    /// Set while no player is found.
+    pub searching: Option<Searching>,
+    /// WebSocket messages sent to clients: answers, pushed statuses and events.
+    pub messages_sent: u64,
+    /// Artwork sent to WebSocket clients, as images or links.
+    pub artwork_sent: u64,
+}
+
+impl Health {
+    /// Starts counting now, following the searches for a player in `events_tx`. Has to be called within a Tokio runtime.
+    pub fn new(events_tx: &broadcast::Sender<Event>) -> Self {
+        let health = Self(Arc::new(Counters {
+            started: Instant::now(),
+            messages_sent: AtomicU64::new(0),
+            artwork_sent: AtomicU64::new(0),
+            searching: Mutex::new(None),
+        }));
+
+        let mut events_rx = events_tx.subscribe();
+        let counters = Arc::downgrade(&health.0);
+        tokio::spawn(async move {
+            loop {
+                let searching = match events_rx.recv().await {
+                    Ok(Event::Searching { attempts, retry_in }) => {
+                        Some(Searching { attempts, retry_in })
+                    }
+                    Ok(Event::Attached { .. }) => None,
+                    Ok(_) | Err(RecvError::Lagged(_)) => continue,
+                    Err(RecvError::Closed) => return,
+                };
+                // Nobody asks anymore.
+                let Some(counters) = counters.upgrade() else {
+                    return;
+                };
+                *counters
+                    .searching
+                    .lock()
+                    .unwrap_or_else(PoisonError::into_inner) = searching;
+            }
+        });
+
+        health
+    }
+
+    pub fn message_sent(&self) {
+        self.0.messages_sent.fetch_add(1, Ordering::Relaxed);
+    }
+
+    pub fn artwork_sent(&self) {
+        self.0.artwork_sent.fetch_add(1, Ordering::Relaxed);
+    }
+
+    pub fn stats(&self, snapshot: &StatusSnapshot, clients: usize) -> ServerStats {
+        ServerStats {
+            version: env!("CARGO_PKG_VERSION"),
+            uptime: self.0.started.elapsed().as_secs_f64(),
+            clients,
+            players: snapshot.players.len(),
+            active: snapshot.active.clone(),
+            searching: *self
+                .0
+                .searching
+                .lock()
+                .unwrap_or_else(PoisonError::into_inner),
+            messages_sent: self.0.messages_sent.load(Ordering::Relaxed),
+            artwork_sent: self.0.artwork_sent.load(Ordering::Relaxed),
+        }
+    }
+}
@@ -0,0 +1,281 @@
+use crate::status::{PlaybackState, PlayerInfo, PlayerStatus, StatusMetadata, StatusSnapshot};
+use serde::{Deserialize, Serialize};
+use std::collections::VecDeque;
+use std::time::{Duration, Instant, SystemTime, UNIX_EPOCH};
+use tokio::sync::watch;
+
+/// How many plays [`record`] keeps by default.
+pub const DEFAULT_SIZE: usize = 50;
+
+/// Longer gaps between status updates (e.g. after a suspend) don't count as listening.
+const MAX_STEP: Duration = Duration::from_secs(5);
+
+/// Tracks that end before they played this long, or half their length if that's shorter, were skipped.
+pub const SKIP_THRESHOLD: Duration = Duration::from_secs(30);
+
+/// Whether a track of `length` that ended after playing for `listened`, both in microseconds, was skipped, see [`SKIP_THRESHOLD`].
+pub fn is_skip(listened: u64, length: u64) -> bool {
+    let threshold = SKIP_THRESHOLD.as_micros() as u64;
+    let threshold = if length > 0 {
+        threshold.min(length / 2)
+    } else {
+        threshold
+    };
+
+    listened < threshold
+}
+
+/// A track the active player played.
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "camelCase")]
+pub struct HistoryEntry {
+    pub player: PlayerInfo,
+    pub metadata: StatusMetadata,
+    /// When it started playing, in milliseconds since the epoch.
+    pub started_at: u64,
+    /// When another track took over or the player went away, in milliseconds since the epoch. Not set while it's still the active track.
+    pub ended_at: Option<u64>,
+    /// How long it was actually played, without pauses and skipped parts, in microseconds like `position`.
+    pub listened: u64,
+    /// Whether it ended before it played for 30 seconds or half its length, whichever is shorter. Never set while it's still the active track.
+    #[serde(default)]
+    pub skipped: bool,
+}
+
+impl HistoryEntry {
+    /// Marks the track as no longer playing, as of `now`.
+    fn end(&mut self, now: u64) {
+        self.ended_at = Some(now);
+        self.skipped = is_skip(self.listened, self.metadata.length);
+    }
+
+    /// Whether `status` is still this play: the same track, from the same attachment to the player. Pausing, seeking, stopping for a moment or details the player or --musicbrainz only filled in later don't make it another one, but the player restarting does.
+    ///
+    /// Track ids tell apart the same track queued twice. They aren't trusted alone, some players give every track the same one.
+    fn is_play_of(&self, status: &PlayerStatus) -> bool {
+        let (ours, theirs) = (&self.metadata, &status.metadata);
+
+        self.player.bus_name == status.player.bus_name
+            && self.player.attachment == status.player.attachment
+            && (ours.track_id.is_empty() || ours.track_id == theirs.track_id)
+            && ours.title == theirs.title
+            && ours.url == theirs.url
+            && (ours.artist.is_empty() || ours.artist == theirs.artist)
+            && (ours.album.is_empty() || ours.album == theirs.album)
+    }
+}
+
+fn now_millis() -> u64 {
+    SystemTime::now()
+        .duration_since(UNIX_EPOCH)
+        .unwrap_or_default()
+        .as_millis() as u64
+}
+
+/// Keeps the last `size` distinct tracks the active player played, most recent first, after the `restored` ones. The active track is the first one, until it ends.
+pub fn record(
+    mut status_rx: watch::Receiver<StatusSnapshot>,
+    size: usize,
+    restored: Vec<HistoryEntry>,
+) -> watch::Receiver<VecDeque<HistoryEntry>> {
+    let mut history = VecDeque::with_capacity(size);
+    history.extend(restored.into_iter().take(size));
+    let (history_tx, history_rx) = watch::channel(history);
+    if size == 0 {
+        return history_rx;
+    }
+
+    tokio::spawn(async move {
+        let mut last_update = Instant::now();
+
+        loop {
+            let status = status_rx
+                .borrow_and_update()
+                .active()
+                .filter(|status| !status.metadata.title.is_empty())
+                .cloned();
+            let step = last_update.elapsed().min(MAX_STEP);
+            last_update = Instant::now();
+
+            // Only adds to the listened time while playing.
+            history_tx.send_if_modified(|history| {
+                let now = now_millis();
+
+                let Some(status) = &status else {
+                    return match history.front_mut() {
+                        Some(last) if last.ended_at.is_none() => {
+                            last.end(now);
+                            true
+                        }
+                        _ => false,
+                    };
+                };
+
+                if let Some(last) = history.front_mut().filter(|last| last.is_play_of(status)) {
+                    let playing = status.playback_state == PlaybackState::Playing;
+                    let changed =
+                        playing || last.ended_at.is_some() || last.metadata != status.metadata;
+                    if playing {
+                        last.listened += step.as_micros() as u64;
+                    }
+                    last.ended_at = None;
+                    last.skipped = false;
+                    last.metadata = status.metadata.clone();
+                    return changed;
+                }
+
+                if let Some(last) = history.front_mut().filter(|last| last.ended_at.is_none()) {
+                    last.end(now);
+                }
+                history.truncate(size - 1);
+                history.push_front(HistoryEntry {
+                    player: status.player.clone(),
+                    metadata: status.metadata.clone(),
+                    started_at: now
+                        .saturating_sub(Duration::from_micros(status.position).as_millis() as u64),
+                    ended_at: None,
+                    listened: 0,
+                    skipped: false,
+                });
+                true
+            });
+
+            // Unchanged statuses aren't sent again, keep counting while the same one plays on.
+            if let Ok(Err(_)) = tokio::time::timeout(MAX_STEP / 2, status_rx.changed()).await {
+                return;
+            }
+        }
+    });
+
+    history_rx
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use serde_json::json;
+
+    const SECOND: u64 = 1_000_000;
+
+    fn status(bus_name: &str, title: &str, track_id: &str) -> PlayerStatus {
+        serde_json::from_value(json!({
+            "player": { "busName": bus_name, "identity": "Player" },
+            "metadata": {
+                "title": title,
+                "artist": "Someone",
+                "album": "Somewhere",
+                "artwork": [],
+                "length": 180 * SECOND,
+                "url": "",
+                "trackId": track_id
+            },
+            "playbackState": "playing",
+            "position": 0,
+            "degraded": false
+        }))
+        .unwrap()
+    }
+
+    /// The play `status` starts, like [`record`] makes it.
+    fn play(status: &PlayerStatus) -> HistoryEntry {
+        HistoryEntry {
+            player: status.player.clone(),
+            metadata: status.metadata.clone(),
+            started_at: 0,
+            ended_at: None,
+            listened: 0,
+            skipped: false,
+        }
+    }
+
+    #[test]
+    fn same_track_is_the_same_play() {
+        let playing = status("org.mpris.MediaPlayer2.mpd", "Neon Tide", "/track/1");
+        let mut paused = playing.clone();
+        paused.playback_state = PlaybackState::Paused;
+        paused.position = 60 * SECOND;
+        assert!(play(&playing).is_play_of(&playing));
+        assert!(play(&playing).is_play_of(&paused));
+    }
+
+    #[test]
+    fn another_track_is_another_play() {
+        let playing = status("org.mpris.MediaPlayer2.mpd", "Neon Tide", "/track/1");
+        let next = status("org.mpris.MediaPlayer2.mpd", "Glass Rain", "/track/2");
+        assert!(!play(&playing).is_play_of(&next));
+    }
+
+    #[test]
+    fn same_track_queued_again_is_another_play() {
+        let playing = status("org.mpris.MediaPlayer2.mpd", "Neon Tide", "/track/1");
+        let repeat = status("org.mpris.MediaPlayer2.mpd", "Neon Tide", "/track/2");
+        assert!(!play(&playing).is_play_of(&repeat));
+    }
+
+    #[test]
+    fn track_ids_are_only_compared_when_known() {
+        let without_id = status("org.mpris.MediaPlayer2.mpd", "Neon Tide", "");
+        let with_id = status("org.mpris.MediaPlayer2.mpd", "Neon Tide", "/track/1");
+        assert!(play(&without_id).is_play_of(&with_id));
+        // Some players give every track the same id, the title still tells them apart.
+        let other = status("org.mpris.MediaPlayer2.mpd", "Glass Rain", "/track/1");
+        assert!(!play(&with_id).is_play_of(&other));
+    }
+
+    #[test]
+    fn artist_and_album_are_only_compared_when_known() {
+        let mut bare = status("org.mpris.MediaPlayer2.radio", "Neon Tide", "");
+        bare.metadata.artist.clear();
+        bare.metadata.album.clear();
+        let filled_in = status("org.mpris.MediaPlayer2.radio", "Neon Tide", "");
+        assert!(play(&bare).is_play_of(&filled_in));
+
+        let mut other_artist = filled_in.clone();
+        other_artist.metadata.artist = String::from("Someone Else");
+        assert!(!play(&filled_in).is_play_of(&other_artist));
+        let mut other_album = filled_in.clone();
+        other_album.metadata.album = String::from("Elsewhere");
+        assert!(!play(&filled_in).is_play_of(&other_album));
+    }
+
+    #[test]
+    fn another_player_is_another_play() {
+        let mpd = status("org.mpris.MediaPlayer2.mpd", "Neon Tide", "/track/1");
+        let vlc = status("org.mpris.MediaPlayer2.vlc", "Neon Tide", "/track/1");
+        assert!(!play(&mpd).is_play_of(&vlc));
+    }
+
+    #[test]
+    fn restarted_player_is_another_play() {
+        let playing = status("org.mpris.MediaPlayer2.mpd", "Neon Tide", "/track/1");
+        let mut restarted = playing.clone();
+        restarted.player.attachment = 1;
+        assert!(!play(&playing).is_play_of(&restarted));
+    }
+
+    #[test]
+    fn skipped_before_30_seconds() {
+        assert!(is_skip(29 * SECOND, 240 * SECOND));
+        assert!(is_skip(30 * SECOND - 1, 240 * SECOND));
+        assert!(!is_skip(30 * SECOND, 240 * SECOND));
+        assert!(!is_skip(240 * SECOND, 240 * SECOND));
+    }
+
+    #[test]
+    fn short_tracks_only_need_half_their_length() {
+        assert!(is_skip(20 * SECOND - 1, 40 * SECOND));
+        assert!(!is_skip(20 * SECOND, 40 * SECOND));
+        assert!(is_skip(SECOND, 4 * SECOND));
+        assert!(!is_skip(2 * SECOND, 4 * SECOND));
+        // Half of 60 seconds is the threshold itself.
+        assert!(is_skip(30 * SECOND - 1, 60 * SECOND));
+        assert!(!is_skip(30 * SECOND, 60 * SECOND));
+    }
+
+    #[test]
+    fn unknown_length_needs_30_seconds() {
+        assert!(is_skip(0, 0));
+        assert!(is_skip(30 * SECOND - 1, 0));
+        assert!(!is_skip(30 * SECOND, 0));
+    }
+}
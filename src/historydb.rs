@@ -0,0 +1,882 @@
+use crate::artwork::ArtworkInfo;
+use crate::exit_code;
+use crate::history::{self, HistoryEntry};
+use crate::status::{PlayerInfo, StatusMetadata};
+use rusqlite::{params, Connection, OpenFlags, OptionalExtension};
+use serde::Serialize;
+use std::collections::VecDeque;
+use std::io::Write;
+use std::path::Path;
+use std::sync::{Arc, Mutex, PoisonError};
+use std::time::{Duration, Instant, SystemTime, UNIX_EPOCH};
+use tokio::sync::watch;
+
+/// How often the listened time of the track that's playing is saved. Plays are saved right away when they start and end.
+const SAVE_INTERVAL: Duration = Duration::from_secs(15);
+
+/// How often old plays are pruned while new ones are saved, besides on startup.
+const PRUNE_INTERVAL: Duration = Duration::from_secs(60 * 60);
+
+/// How long to wait for the daemon, or a command run next to it, to finish writing.
+const BUSY_TIMEOUT: Duration = Duration::from_secs(5);
+
+const SCHEMA: &str = "
+CREATE TABLE IF NOT EXISTS plays (
+    bus_name TEXT NOT NULL,
+    identity TEXT NOT NULL,
+    alias TEXT,
+    title TEXT NOT NULL,
+    artist TEXT NOT NULL,
+    album TEXT NOT NULL,
+    url TEXT NOT NULL,
+    -- The artwork list as JSON.
+    artwork TEXT NOT NULL,
+    -- In microseconds, 0 if unknown.
+    length INTEGER NOT NULL,
+    -- In milliseconds since the epoch.
+    started_at INTEGER NOT NULL,
+    ended_at INTEGER,
+    -- In microseconds.
+    listened INTEGER NOT NULL,
+    -- How much of the track was listened to, from 0 to 1. NULL if its length is unknown.
+    completion REAL,
+    PRIMARY KEY (bus_name, started_at)
+);
+CREATE INDEX IF NOT EXISTS plays_started_at ON plays (started_at);
+";
+
+/// Whether a play was skipped, like [`history::is_skip`], with `?3` bound to [`history::SKIP_THRESHOLD`] in microseconds. Only for plays that ended.
+const SKIPPED: &str = "listened < MIN(?3, CASE WHEN length > 0 THEN length / 2 ELSE ?3 END)";
+
+/// Whether a play matches a search, with `?1` and `?2` bound to the title and artist patterns (or NULL for any) and `?3` and `?4` to the start and end of the time range.
+const SEARCH_MATCHES: &str = "(?1 IS NULL OR title LIKE ?1 ESCAPE '\\')
+    AND (?2 IS NULL OR artist LIKE ?2 ESCAPE '\\')
+    AND started_at >= ?3 AND started_at < ?4";
+
+/// How many tracks, artists and players the statistics list by default, and at most.
+const DEFAULT_STATS_LIMIT: usize = 10;
+const MAX_STATS_LIMIT: usize = 100;
+
+/// How many plays a search answers by default, and at most.
+const DEFAULT_SEARCH_LIMIT: usize = 20;
+const MAX_SEARCH_LIMIT: usize = 100;
+
+/// The columns [`play`] reads, in order.
+const PLAY_COLUMNS: &str =
+    "bus_name, identity, alias, title, artist, album, url, artwork, length, started_at, ended_at, listened";
+
+/// The most played tracks and artists since a point in time.
+#[derive(Debug, Clone, PartialEq, Eq, Serialize)]
+#[serde(rename_all = "camelCase")]
+pub struct TopPlays {
+    /// As asked for, e.g. `7d`, or `all`.
+    pub period: String,
+    /// Where the period starts, in milliseconds since the epoch. Not set for `all`.
+    pub since: Option<u64>,
+    pub tracks: Vec<TrackPlays>,
+    pub artists: Vec<ArtistPlays>,
+}
+
+#[derive(Debug, Clone, PartialEq, Eq, Serialize)]
+#[serde(rename_all = "camelCase")]
+pub struct TrackPlays {
+    pub title: String,
+    pub artist: String,
+    /// The album of its last play.
+    pub album: String,
+    pub plays: u64,
+}
+
+#[derive(Debug, Clone, PartialEq, Eq, Serialize)]
+#[serde(rename_all = "camelCase")]
+pub struct ArtistPlays {
+    pub artist: String,
+    pub plays: u64,
+}
+
+/// How long was listened to since a point in time, in microseconds like `position`.
+#[derive(Debug, Clone, PartialEq, Eq, Serialize)]
+#[serde(rename_all = "camelCase")]
+pub struct ListeningTime {
+    /// As asked for, e.g. `7d`, or `all`.
+    pub period: String,
+    /// Where the period starts, in milliseconds since the epoch. Not set for `all`.
+    pub since: Option<u64>,
+    pub total: u64,
+    /// Every day anything was listened to, most recent first. Plays count for the day they started on.
+    pub days: Vec<DayTime>,
+    pub artists: Vec<ArtistTime>,
+    pub players: Vec<PlayerTime>,
+}
+
+#[derive(Debug, Clone, PartialEq, Eq, Serialize)]
+#[serde(rename_all = "camelCase")]
+pub struct DayTime {
+    /// In local time, e.g. `2024-05-31`.
+    pub day: String,
+    pub listened: u64,
+}
+
+#[derive(Debug, Clone, PartialEq, Eq, Serialize)]
+#[serde(rename_all = "camelCase")]
+pub struct ArtistTime {
+    pub artist: String,
+    pub listened: u64,
+}
+
+#[derive(Debug, Clone, PartialEq, Eq, Serialize)]
+#[serde(rename_all = "camelCase")]
+pub struct PlayerTime {
+    /// The player's alias, or its displayed name.
+    pub player: String,
+    pub listened: u64,
+}
+
+/// How long was listened to at an hour of the day, in microseconds like `position`.
+#[derive(Debug, Clone, PartialEq, Eq, Serialize)]
+#[serde(rename_all = "camelCase")]
+pub struct HourTime {
+    /// In local time, from 0 to 23.
+    pub hour: u8,
+    pub listened: u64,
+}
+
+/// How many plays were skipped since a point in time. Only plays that ended count.
+#[derive(Debug, Clone, PartialEq, Serialize)]
+#[serde(rename_all = "camelCase")]
+pub struct SkipStats {
+    /// As asked for, e.g. `7d`, or `all`.
+    pub period: String,
+    /// Where the period starts, in milliseconds since the epoch. Not set for `all`.
+    pub since: Option<u64>,
+    pub plays: u64,
+    pub skipped: u64,
+    /// `skipped` out of `plays`, from 0 to 1. 0 without any plays.
+    pub rate: f64,
+    /// The tracks skipped most often, and the artists.
+    pub tracks: Vec<TrackSkips>,
+    pub artists: Vec<ArtistSkips>,
+}
+
+#[derive(Debug, Clone, PartialEq, Eq, Serialize)]
+#[serde(rename_all = "camelCase")]
+pub struct TrackSkips {
+    pub title: String,
+    pub artist: String,
+    pub plays: u64,
+    pub skipped: u64,
+}
+
+#[derive(Debug, Clone, PartialEq, Eq, Serialize)]
+#[serde(rename_all = "camelCase")]
+pub struct ArtistSkips {
+    pub artist: String,
+    pub plays: u64,
+    pub skipped: u64,
+}
+
+/// Which plays [`HistoryDb::search`] looks for. Whatever isn't set matches every play.
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub struct Search {
+    /// Part of the title, ignoring case.
+    pub title: Option<String>,
+    /// Part of the artist, ignoring case.
+    pub artist: Option<String>,
+    /// The first and last day the plays started on, e.g. `2024-05-31` in local time, or a point in time in milliseconds since the epoch.
+    pub from: Option<String>,
+    pub to: Option<String>,
+    /// How many plays to answer, and how many to skip before them, for paging through the results.
+    pub limit: Option<usize>,
+    pub offset: usize,
+}
+
+/// A page of the plays found by [`HistoryDb::search`].
+#[derive(Debug, Clone, PartialEq, Eq, Serialize)]
+#[serde(rename_all = "camelCase")]
+pub struct SearchResults {
+    /// How many plays were found, on every page.
+    pub total: u64,
+    pub offset: usize,
+    /// The plays on this page, most recent first.
+    pub plays: Vec<HistoryEntry>,
+}
+
+/// Reads a period like `24h`, `7d`, `4w`, `6m` (30 days each) or `1y` (365 days), or `all` for no limit.
+fn parse_period(period: &str) -> Result<Option<Duration>, String> {
+    if period == "all" {
+        return Ok(None);
+    }
+
+    let unit = match period.chars().last() {
+        Some('h') => 60 * 60,
+        Some('d') => 24 * 60 * 60,
+        Some('w') => 7 * 24 * 60 * 60,
+        Some('m') => 30 * 24 * 60 * 60,
+        Some('y') => 365 * 24 * 60 * 60,
+        _ => {
+            return Err(String::from(
+                "expected e.g. `24h`, `7d`, `4w`, `6m`, `1y` or `all`",
+            ))
+        }
+    };
+    let count = period[..period.len() - 1]
+        .parse::<u64>()
+        .map_err(|_| String::from("expected a number before the unit, e.g. `7d`"))?;
+
+    Ok(Some(Duration::from_secs(count.saturating_mul(unit))))
+}
+
+/// Reads an age like `90d` or `1y`, in the units of `stats/top` periods.
+pub fn parse_age(age: &str) -> Result<Duration, String> {
+    parse_period(age)?.ok_or_else(|| String::from("expected e.g. `90d` or `1y`"))
+}
+
+/// How many plays are kept, for --history-max-plays and --history-max-age.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub struct Retention {
+    /// The most recent plays to keep.
+    pub max_plays: Option<u64>,
+    /// Plays that started longer ago are removed.
+    pub max_age: Option<Duration>,
+}
+
+/// Where a period up to now starts, in milliseconds since the epoch. `None` for `all`.
+fn period_start(period: &str) -> Result<Option<u64>, String> {
+    Ok(parse_period(period)?.map(|period| {
+        let now = SystemTime::now()
+            .duration_since(UNIX_EPOCH)
+            .unwrap_or_default();
+        now.saturating_sub(period).as_millis() as u64
+    }))
+}
+
+fn stats_limit(limit: Option<usize>) -> i64 {
+    limit.unwrap_or(DEFAULT_STATS_LIMIT).min(MAX_STATS_LIMIT) as i64
+}
+
+/// A `LIKE` pattern matching `text` anywhere, with `\` escaping.
+fn contains_pattern(text: &str) -> String {
+    let escaped = text
+        .replace('\\', "\\\\")
+        .replace('%', "\\%")
+        .replace('_', "\\_");
+    format!("%{escaped}%")
+}
+
+/// Reads a play from a row of [`PLAY_COLUMNS`].
+fn play(row: &rusqlite::Row) -> rusqlite::Result<HistoryEntry> {
+    let artwork: String = row.get(7)?;
+    let length = row.get(8)?;
+    let ended_at: Option<u64> = row.get(10)?;
+    let listened = row.get(11)?;
+
+    Ok(HistoryEntry {
+        player: PlayerInfo {
+            bus_name: row.get(0)?,
+            identity: row.get(1)?,
+            alias: row.get(2)?,
+            attachment: 0,
+        },
+        metadata: StatusMetadata {
+            title: row.get(3)?,
+            artist: row.get(4)?,
+            album: row.get(5)?,
+            url: row.get(6)?,
+            // Not kept, plays from before aren't resumed anyway.
+            track_id: String::new(),
+            artwork: serde_json::from_str::<Vec<ArtworkInfo>>(&artwork).unwrap_or_default(),
+            length,
+            title_latin: String::new(),
+            artist_latin: String::new(),
+        },
+        started_at: row.get(9)?,
+        ended_at,
+        listened,
+        skipped: ended_at.is_some() && history::is_skip(listened, length),
+    })
+}
+
+/// The play history, kept in an SQLite database for --history-db.
+pub struct HistoryDb {
+    connection: Connection,
+}
+
+impl HistoryDb {
+    /// Opens the database, creating it if needed. Plays that were still going when the daemon last stopped end where they were last saved. Blocks.
+    pub fn open(path: &Path) -> rusqlite::Result<Self> {
+        if let Some(dir) = path.parent().filter(|dir| !dir.as_os_str().is_empty()) {
+            let _ = std::fs::create_dir_all(dir);
+        }
+
+        let connection = Connection::open(path)?;
+        connection.busy_timeout(BUSY_TIMEOUT)?;
+        // Removed plays are overwritten, rather than left in the file until the space is reused.
+        connection.pragma_update(None, "secure_delete", true)?;
+        connection.execute_batch(SCHEMA)?;
+        connection.execute(
+            "UPDATE plays SET ended_at = started_at + listened / 1000 WHERE ended_at IS NULL",
+            [],
+        )?;
+
+        Ok(Self { connection })
+    }
+
+    /// Opens an existing database without changing anything in it, e.g. while the daemon keeps writing to it. Blocks.
+    pub fn open_read_only(path: &Path) -> rusqlite::Result<Self> {
+        let connection = Connection::open_with_flags(path, OpenFlags::SQLITE_OPEN_READ_ONLY)?;
+        connection.busy_timeout(BUSY_TIMEOUT)?;
+
+        Ok(Self { connection })
+    }
+
+    /// The last `count` plays, most recent first. Blocks.
+    pub fn recent(&self, count: usize) -> rusqlite::Result<Vec<HistoryEntry>> {
+        let mut statement = self.connection.prepare(&format!(
+            "SELECT {PLAY_COLUMNS} FROM plays ORDER BY started_at DESC LIMIT ?1"
+        ))?;
+
+        let entries = statement.query_map([count as i64], play)?;
+        entries.collect()
+    }
+
+    /// Where a day in local time starts, or the day after it if `end`, in milliseconds since the epoch. Points in time are taken as they are.
+    fn day_bound(&self, day: &str, end: bool) -> Result<i64, String> {
+        if let Ok(millis) = day.parse() {
+            return Ok(millis);
+        }
+
+        let start: Option<i64> = self
+            .connection
+            .query_row(
+                // `utc` takes the day as local time. Anything but a date gives NULL.
+                "SELECT CAST(strftime('%s', date(?1, ?2), 'utc') AS INTEGER) * 1000 WHERE date(?1) = ?1",
+                params![day, if end { "+1 day" } else { "+0 days" }],
+                |row| row.get(0),
+            )
+            .optional()
+            .map_err(|e| e.to_string())?
+            .flatten();
+
+        start.ok_or_else(|| format!("invalid day `{day}`, expected e.g. `2024-05-31`"))
+    }
+
+    /// The plays matching `search`, most recent first, a page at a time. Blocks.
+    pub fn search(&self, search: &Search) -> Result<SearchResults, String> {
+        let from = match &search.from {
+            Some(from) => self.day_bound(from, false)?,
+            None => 0,
+        };
+        let to = match &search.to {
+            Some(to) => self.day_bound(to, true)?,
+            None => i64::MAX,
+        };
+        let title = search.title.as_deref().map(contains_pattern);
+        let artist = search.artist.as_deref().map(contains_pattern);
+        let limit = search
+            .limit
+            .unwrap_or(DEFAULT_SEARCH_LIMIT)
+            .min(MAX_SEARCH_LIMIT) as i64;
+
+        let total = self
+            .stats(
+                &format!("SELECT COUNT(*) FROM plays WHERE {SEARCH_MATCHES}"),
+                params![title, artist, from, to],
+                |row| row.get::<_, u64>(0),
+            )?
+            .pop()
+            .unwrap_or_default();
+
+        let plays = self.stats(
+            &format!(
+                "SELECT {PLAY_COLUMNS} FROM plays WHERE {SEARCH_MATCHES}
+                ORDER BY started_at DESC LIMIT ?5 OFFSET ?6"
+            ),
+            params![title, artist, from, to, limit, search.offset as i64],
+            play,
+        )?;
+
+        Ok(SearchResults {
+            total,
+            offset: search.offset,
+            plays,
+        })
+    }
+
+    /// Every play that started between the days `since` and `until` (both included, see [`Search::from`]), oldest first. Blocks.
+    pub fn export(
+        &self,
+        since: Option<&str>,
+        until: Option<&str>,
+    ) -> Result<Vec<HistoryEntry>, String> {
+        let since = match since {
+            Some(since) => self.day_bound(since, false)?,
+            None => 0,
+        };
+        let until = match until {
+            Some(until) => self.day_bound(until, true)?,
+            None => i64::MAX,
+        };
+
+        self.stats(
+            &format!(
+                "SELECT {PLAY_COLUMNS} FROM plays WHERE started_at >= ?1 AND started_at < ?2 ORDER BY started_at"
+            ),
+            params![since, until],
+            play,
+        )
+    }
+
+    /// Runs a query for statistics or a search, collecting its rows.
+    fn stats<T>(
+        &self,
+        sql: &str,
+        params: impl rusqlite::Params,
+        map: impl FnMut(&rusqlite::Row) -> rusqlite::Result<T>,
+    ) -> Result<Vec<T>, String> {
+        self.connection
+            .prepare(sql)
+            .and_then(|mut statement| statement.query_map(params, map)?.collect())
+            .map_err(|e| e.to_string())
+    }
+
+    /// The most played tracks and artists in the `period` up to now, at most `limit` of each. Blocks.
+    pub fn top(&self, period: &str, limit: Option<usize>) -> Result<TopPlays, String> {
+        let since = period_start(period)?;
+        let limit = stats_limit(limit);
+
+        let tracks = self.stats(
+            // The bare `album` comes from the row with the latest `started_at`.
+            "SELECT title, artist, album, COUNT(*) AS plays, MAX(started_at) AS last
+            FROM plays WHERE started_at >= ?1
+            GROUP BY title, artist ORDER BY plays DESC, last DESC LIMIT ?2",
+            params![since.unwrap_or_default(), limit],
+            |row| {
+                Ok(TrackPlays {
+                    title: row.get(0)?,
+                    artist: row.get(1)?,
+                    album: row.get(2)?,
+                    plays: row.get(3)?,
+                })
+            },
+        )?;
+
+        let artists = self.stats(
+            "SELECT artist, COUNT(*) AS plays, MAX(started_at) AS last
+            FROM plays WHERE started_at >= ?1 AND artist != ''
+            GROUP BY artist ORDER BY plays DESC, last DESC LIMIT ?2",
+            params![since.unwrap_or_default(), limit],
+            |row| {
+                Ok(ArtistPlays {
+                    artist: row.get(0)?,
+                    plays: row.get(1)?,
+                })
+            },
+        )?;
+
+        Ok(TopPlays {
+            period: period.to_string(),
+            since,
+            tracks,
+            artists,
+        })
+    }
+
+    /// How long was listened to in the `period` up to now, per day, and for at most `limit` artists and players. Blocks.
+    pub fn time(&self, period: &str, limit: Option<usize>) -> Result<ListeningTime, String> {
+        let since = period_start(period)?;
+        let limit = stats_limit(limit);
+
+        let days = self.stats(
+            "SELECT date(started_at / 1000, 'unixepoch', 'localtime') AS day, SUM(listened)
+            FROM plays WHERE started_at >= ?1
+            GROUP BY day ORDER BY day DESC",
+            [since.unwrap_or_default()],
+            |row| {
+                Ok(DayTime {
+                    day: row.get(0)?,
+                    listened: row.get(1)?,
+                })
+            },
+        )?;
+
+        let artists = self.stats(
+            "SELECT artist, SUM(listened) AS listened
+            FROM plays WHERE started_at >= ?1 AND artist != ''
+            GROUP BY artist ORDER BY listened DESC LIMIT ?2",
+            params![since.unwrap_or_default(), limit],
+            |row| {
+                Ok(ArtistTime {
+                    artist: row.get(0)?,
+                    listened: row.get(1)?,
+                })
+            },
+        )?;
+
+        let players = self.stats(
+            "SELECT COALESCE(alias, identity) AS player, SUM(listened) AS listened
+            FROM plays WHERE started_at >= ?1
+            GROUP BY player ORDER BY listened DESC LIMIT ?2",
+            params![since.unwrap_or_default(), limit],
+            |row| {
+                Ok(PlayerTime {
+                    player: row.get(0)?,
+                    listened: row.get(1)?,
+                })
+            },
+        )?;
+
+        Ok(ListeningTime {
+            period: period.to_string(),
+            since,
+            total: days.iter().map(|day| day.listened).sum(),
+            days,
+            artists,
+            players,
+        })
+    }
+
+    /// How long was listened to at each hour of the day in the `period` up to now, for all 24 of them in order. Plays count for the hour they started in. Blocks.
+    pub fn hours(&self, period: &str) -> Result<Vec<HourTime>, String> {
+        let since = period_start(period)?;
+
+        let listened = self.stats(
+            "SELECT CAST(strftime('%H', started_at / 1000, 'unixepoch', 'localtime') AS INTEGER) AS hour, SUM(listened)
+            FROM plays WHERE started_at >= ?1 GROUP BY hour",
+            [since.unwrap_or_default()],
+            |row| Ok((row.get::<_, u8>(0)?, row.get::<_, u64>(1)?)),
+        )?;
+
+        Ok((0..24)
+            .map(|hour| HourTime {
+                hour,
+                listened: listened
+                    .iter()
+                    .find(|(h, _)| *h == hour)
+                    .map(|(_, listened)| *listened)
+                    .unwrap_or_default(),
+            })
+            .collect())
+    }
+
+    /// How many plays in the `period` up to now were skipped, and the at most `limit` tracks and artists skipped most often. Blocks.
+    pub fn skips(&self, period: &str, limit: Option<usize>) -> Result<SkipStats, String> {
+        let since = period_start(period)?;
+        let limit = stats_limit(limit);
+        let threshold = history::SKIP_THRESHOLD.as_micros() as i64;
+
+        let (plays, skipped) = self
+            .stats(
+                &format!(
+                    "SELECT COUNT(*), COALESCE(SUM({SKIPPED}), 0) FROM plays
+                    WHERE started_at >= ?1 AND ended_at IS NOT NULL"
+                ),
+                params![since.unwrap_or_default(), limit, threshold],
+                |row| Ok((row.get::<_, u64>(0)?, row.get::<_, u64>(1)?)),
+            )?
+            .pop()
+            .unwrap_or_default();
+
+        let tracks = self.stats(
+            &format!(
+                "SELECT title, artist, COUNT(*), SUM({SKIPPED}) AS skipped FROM plays
+                WHERE started_at >= ?1 AND ended_at IS NOT NULL
+                GROUP BY title, artist HAVING skipped > 0
+                ORDER BY skipped DESC, COUNT(*) ASC LIMIT ?2"
+            ),
+            params![since.unwrap_or_default(), limit, threshold],
+            |row| {
+                Ok(TrackSkips {
+                    title: row.get(0)?,
+                    artist: row.get(1)?,
+                    plays: row.get(2)?,
+                    skipped: row.get(3)?,
+                })
+            },
+        )?;
+
+        let artists = self.stats(
+            &format!(
+                "SELECT artist, COUNT(*), SUM({SKIPPED}) AS skipped FROM plays
+                WHERE started_at >= ?1 AND ended_at IS NOT NULL AND artist != ''
+                GROUP BY artist HAVING skipped > 0
+                ORDER BY skipped DESC, COUNT(*) ASC LIMIT ?2"
+            ),
+            params![since.unwrap_or_default(), limit, threshold],
+            |row| {
+                Ok(ArtistSkips {
+                    artist: row.get(0)?,
+                    plays: row.get(1)?,
+                    skipped: row.get(2)?,
+                })
+            },
+        )?;
+
+        Ok(SkipStats {
+            period: period.to_string(),
+            since,
+            plays,
+            skipped,
+            rate: if plays > 0 {
+                skipped as f64 / plays as f64
+            } else {
+                0.0
+            },
+            tracks,
+            artists,
+        })
+    }
+
+    /// Removes the plays `retention` doesn't keep, returning how many. Blocks.
+    pub fn prune(&self, retention: Retention) -> rusqlite::Result<usize> {
+        let mut removed = 0;
+
+        if let Some(max_age) = retention.max_age {
+            let since = SystemTime::now()
+                .duration_since(UNIX_EPOCH)
+                .unwrap_or_default()
+                .saturating_sub(max_age)
+                .as_millis() as u64;
+            removed += self
+                .connection
+                .execute("DELETE FROM plays WHERE started_at < ?1", [since])?;
+        }
+
+        if let Some(max_plays) = retention.max_plays {
+            removed += self.connection.execute(
+                "DELETE FROM plays WHERE rowid NOT IN (SELECT rowid FROM plays ORDER BY started_at DESC LIMIT ?1)",
+                [max_plays],
+            )?;
+        }
+
+        Ok(removed)
+    }
+
+    /// Removes the plays that started before `older_than` ago, or all of them, and compacts the file so nothing is left of them. Blocks.
+    pub fn purge(&self, older_than: Option<Duration>) -> rusqlite::Result<usize> {
+        let removed = match older_than {
+            Some(max_age) => self.prune(Retention {
+                max_plays: None,
+                max_age: Some(max_age),
+            })?,
+            None => self.connection.execute("DELETE FROM plays", [])?,
+        };
+        self.connection.execute("VACUUM", [])?;
+
+        Ok(removed)
+    }
+
+    /// Adds a play, or updates it if it's already there. Blocks.
+    fn save(&self, entry: &HistoryEntry) -> rusqlite::Result<()> {
+        let completion = (entry.metadata.length > 0)
+            .then(|| (entry.listened as f64 / entry.metadata.length as f64).min(1.0));
+
+        self.connection.execute(
+            "INSERT INTO plays (bus_name, identity, alias, title, artist, album, url, artwork, length, started_at, ended_at, listened, completion)
+            VALUES (?1, ?2, ?3, ?4, ?5, ?6, ?7, ?8, ?9, ?10, ?11, ?12, ?13)
+            ON CONFLICT (bus_name, started_at) DO UPDATE SET
+                identity = excluded.identity, alias = excluded.alias, title = excluded.title, artist = excluded.artist,
+                album = excluded.album, url = excluded.url, artwork = excluded.artwork, length = excluded.length,
+                ended_at = excluded.ended_at, listened = excluded.listened, completion = excluded.completion",
+            params![
+                entry.player.bus_name,
+                entry.player.identity,
+                entry.player.alias,
+                entry.metadata.title,
+                entry.metadata.artist,
+                entry.metadata.album,
+                entry.metadata.url,
+                serde_json::to_string(&entry.metadata.artwork).unwrap(),
+                entry.metadata.length,
+                entry.started_at,
+                entry.ended_at,
+                entry.listened,
+                completion,
+            ],
+        )?;
+
+        Ok(())
+    }
+}
+
+/// Removes plays from the database at `path` for the `purge-history` command, see [`HistoryDb::purge`]. Returns the exit code.
+pub fn purge_history(path: &Path, older_than: Option<Duration>) -> i32 {
+    match HistoryDb::open(path).and_then(|db| db.purge(older_than)) {
+        Ok(removed) => {
+            println!("Removed {removed} plays from {}.", path.display());
+            exit_code::OK
+        }
+        Err(e) => {
+            eprintln!("Could not purge the history in {}: {e}", path.display());
+            exit_code::FAILURE
+        }
+    }
+}
+
+/// What the `export-history` command writes the plays as.
+#[derive(clap::ValueEnum, Debug, Clone, Copy, PartialEq, Eq)]
+pub enum ExportFormat {
+    /// A header line, then a line per play, for spreadsheets. Times are in milliseconds since the epoch, lengths in microseconds.
+    Csv,
+    /// An array of plays, like the ones in `history`.
+    Json,
+}
+
+/// The columns of an `export-history` CSV, see [`csv_line`].
+const CSV_HEADER: &str =
+    "bus_name,identity,alias,title,artist,album,url,length,started_at,ended_at,listened,skipped";
+
+/// A play as a line of CSV, quoting the fields that need it.
+fn csv_line(entry: &HistoryEntry) -> String {
+    let field = |text: &str| {
+        if text.contains([',', '"', '\n', '\r']) {
+            format!("\"{}\"", text.replace('"', "\"\""))
+        } else {
+            text.to_owned()
+        }
+    };
+
+    [
+        field(&entry.player.bus_name),
+        field(&entry.player.identity),
+        field(entry.player.alias.as_deref().unwrap_or_default()),
+        field(&entry.metadata.title),
+        field(&entry.metadata.artist),
+        field(&entry.metadata.album),
+        field(&entry.metadata.url),
+        entry.metadata.length.to_string(),
+        entry.started_at.to_string(),
+        entry
+            .ended_at
+            .map(|ended_at| ended_at.to_string())
+            .unwrap_or_default(),
+        entry.listened.to_string(),
+        entry.skipped.to_string(),
+    ]
+    .join(",")
+}
+
+/// Prints the plays in the database at `path` for the `export-history` command, see [`HistoryDb::export`]. Returns the exit code.
+pub fn export_history(
+    path: &Path,
+    format: ExportFormat,
+    since: Option<&str>,
+    until: Option<&str>,
+) -> i32 {
+    let plays = HistoryDb::open_read_only(path)
+        .map_err(|e| e.to_string())
+        .and_then(|db| db.export(since, until));
+    let plays = match plays {
+        Ok(plays) => plays,
+        Err(e) => {
+            eprintln!("Could not export the history from {}: {e}", path.display());
+            return exit_code::FAILURE;
+        }
+    };
+
+    match format {
+        ExportFormat::Csv => {
+            let mut out = std::io::stdout().lock();
+            let lines = std::iter::once(String::from(CSV_HEADER)).chain(plays.iter().map(csv_line));
+            for line in lines {
+                // The reader went away, e.g. `| head`.
+                if writeln!(out, "{line}").is_err() {
+                    break;
+                }
+            }
+        }
+        ExportFormat::Json => println!("{}", serde_json::to_string(&plays).unwrap()),
+    }
+    exit_code::OK
+}
+
+/// Whether two entries are the same play.
+fn same_play(a: &HistoryEntry, b: &HistoryEntry) -> bool {
+    a.player.bus_name == b.player.bus_name && a.started_at == b.started_at
+}
+
+/// Saves the plays in `history_rx` to the database as they start, change and end, removing the ones `retention` doesn't keep now and then.
+pub async fn run(
+    db: Arc<Mutex<HistoryDb>>,
+    retention: Retention,
+    mut history_rx: watch::Receiver<VecDeque<HistoryEntry>>,
+) {
+    // What the current play looked like when it was last saved, starting with the last one in the database.
+    let mut saved = {
+        let db = db.clone();
+        tokio::task::spawn_blocking(move || {
+            let db = db.lock().unwrap_or_else(PoisonError::into_inner);
+            db.recent(1).ok().and_then(|mut recent| recent.pop())
+        })
+        .await
+        .ok()
+        .flatten()
+    };
+    let mut last_save = Instant::now();
+    let mut last_prune = Instant::now();
+
+    while history_rx.changed().await.is_ok() {
+        let mut changed = Vec::new();
+        {
+            let history = history_rx.borrow_and_update();
+            let Some(current) = history.front() else {
+                continue;
+            };
+
+            match &saved {
+                Some(previous) if same_play(previous, current) => {
+                    // Only the listened time moves on while playing, it's enough to save it now and then.
+                    let only_listened = previous.ended_at == current.ended_at
+                        && previous.metadata == current.metadata;
+                    if only_listened && last_save.elapsed() < SAVE_INTERVAL {
+                        continue;
+                    }
+                }
+                previous => {
+                    log::debug!(
+                        "Adding {} – {} to the history.",
+                        current.metadata.artist,
+                        current.metadata.title
+                    );
+
+                    // The play before ended, save how it ended.
+                    if let Some(previous) = previous.as_ref().and_then(|previous| {
+                        history.iter().find(|entry| same_play(entry, previous))
+                    }) {
+                        changed.push(previous.clone());
+                    }
+                }
+            }
+
+            changed.push(current.clone());
+        }
+
+        saved = changed.last().cloned();
+        last_save = Instant::now();
+        let prune = (last_prune.elapsed() >= PRUNE_INTERVAL).then_some(retention);
+        if prune.is_some() {
+            last_prune = Instant::now();
+        }
+
+        let db = db.clone();
+        let Ok((result, pruned)) = tokio::task::spawn_blocking(move || {
+            let db = db.lock().unwrap_or_else(PoisonError::into_inner);
+            let result = changed.iter().try_for_each(|entry| db.save(entry));
+            (result, prune.map(|retention| db.prune(retention)))
+        })
+        .await
+        .inspect_err(|e| log::warn!("Could not save the play history: {e}")) else {
+            continue;
+        };
+
+        if let Err(e) = result {
+            log::warn!("Could not save the play history: {e}");
+        }
+        match pruned {
+            Some(Ok(removed)) if removed > 0 => {
+                log::debug!("Removed {removed} old plays from the history.")
+            }
+            Some(Err(e)) => log::warn!("Could not remove old plays from the history: {e}"),
+            _ => {}
+        }
+    }
+}
@@ -0,0 +1,259 @@
+use crate::bar;
+use crate::event::Event;
+use crate::sink::{Sink, SinkContext};
+use crate::status::{CompactStatus, PlaybackState, PlayerStatus, StatusMetadata, StatusSnapshot};
+use clap::ValueEnum;
+use futures_util::future::BoxFuture;
+use std::collections::BTreeMap;
+use std::process::Stdio;
+use std::time::Duration;
+use tokio::sync::broadcast::{self, error::RecvError};
+use tokio::sync::watch;
+
+/// When a --hook runs.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, ValueEnum)]
+pub enum HookEvent {
+    /// The active player started another track, or another player became the active one.
+    TrackChange,
+    /// The active player's track has played for --confirm-after, so it wasn't skipped right away.
+    TrackConfirmed,
+    /// The active player started playing.
+    Play,
+    /// The active player paused.
+    Pause,
+    /// The active player stopped.
+    Stop,
+    /// A player was attached to.
+    Attach,
+    /// A player quit or was replaced by another one.
+    Detach,
+}
+
+/// A command to run on an event.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Hook {
+    pub event: HookEvent,
+    /// Run with `sh -c`.
+    pub command: String,
+}
+
+impl Hook {
+    /// Parses a --hook value, `<event>=<command>`.
+    pub fn parse(value: &str) -> Result<Self, String> {
+        let Some((event, command)) = value.split_once('=') else {
+            return Err(String::from(
+                "expected <event>=<command>, e.g. `track-change=notify-send \"$MPRIS_NOWPLAYING_TITLE\"`",
+            ));
+        };
+
+        let event = HookEvent::from_str(event.trim(), true).map_err(|_| {
+            let names = HookEvent::value_variants()
+                .iter()
+                .filter_map(|event| event.to_possible_value())
+                .map(|value| value.get_name().to_string())
+                .collect::<Vec<_>>();
+            format!(
+                "unknown event `{event}`, expected one of {}",
+                names.join(", ")
+            )
+        })?;
+        if command.trim().is_empty() {
+            return Err(String::from("the command is empty"));
+        }
+
+        Ok(Self {
+            event,
+            command: command.to_string(),
+        })
+    }
+}
+
+fn event_name(event: HookEvent) -> String {
+    event
+        .to_possible_value()
+        .map(|value| value.get_name().to_string())
+        .unwrap_or_default()
+}
+
+/// The status handed to a command, as `MPRIS_NOWPLAYING_*` environment variables.
+fn environment(event: HookEvent, status: &PlayerStatus) -> Vec<(&'static str, String)> {
+    let compact = CompactStatus::from(Some(status));
+
+    vec![
+        ("MPRIS_NOWPLAYING_EVENT", event_name(event)),
+        ("MPRIS_NOWPLAYING_BUS_NAME", status.player.bus_name.clone()),
+        ("MPRIS_NOWPLAYING_PLAYER", compact.player),
+        ("MPRIS_NOWPLAYING_TITLE", compact.title),
+        ("MPRIS_NOWPLAYING_ARTIST", compact.artist),
+        ("MPRIS_NOWPLAYING_ALBUM", compact.album),
+        (
+            "MPRIS_NOWPLAYING_STATE",
+            String::from(bar::state_name(&status.playback_state)),
+        ),
+        (
+            "MPRIS_NOWPLAYING_POSITION",
+            Duration::from_micros(compact.position)
+                .as_secs()
+                .to_string(),
+        ),
+        (
+            "MPRIS_NOWPLAYING_LENGTH",
+            Duration::from_micros(compact.length).as_secs().to_string(),
+        ),
+        (
+            "MPRIS_NOWPLAYING_ARTWORK",
+            status
+                .metadata
+                .artwork
+                .first()
+                .map(|artwork| artwork.src.clone())
+                .unwrap_or_default(),
+        ),
+    ]
+}
+
+/// Starts every hook for `event` without waiting for it, logging commands that fail.
+fn fire(hooks: &[Hook], event: HookEvent, status: &PlayerStatus) {
+    for hook in hooks.iter().filter(|hook| hook.event == event) {
+        log::debug!("Running the {} hook: {}", event_name(event), hook.command);
+
+        let child = tokio::process::Command::new("sh")
+            .arg("-c")
+            .arg(&hook.command)
+            .envs(environment(event, status))
+            .stdin(Stdio::null())
+            .spawn();
+        let mut child = match child {
+            Ok(child) => child,
+            Err(e) => {
+                log::warn!("Could not run `{}`: {e}", hook.command);
+                continue;
+            }
+        };
+
+        let command = hook.command.clone();
+        tokio::spawn(async move {
+            match child.wait().await {
+                Ok(status) if status.success() => {}
+                Ok(status) => log::warn!("`{command}` failed: {status}"),
+                Err(e) => log::warn!("Could not wait for `{command}`: {e}"),
+            }
+        });
+    }
+}
+
+/// The commands --hook runs.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Hooks(pub Vec<Hook>);
+
+/// Runs the hooks as players come and go, and as the active player's track and playback state change.
+///
+/// The active player's track changes and confirmations are taken from `events_rx`, so each one runs the hooks, even when the statuses only show the last of several quick ones.
+pub async fn run(
+    hooks: Vec<Hook>,
+    mut status_rx: watch::Receiver<StatusSnapshot>,
+    mut events_rx: broadcast::Receiver<Event>,
+) {
+    let mut players: BTreeMap<String, PlayerStatus> = BTreeMap::new();
+    let mut last_active = None;
+    let mut last_track: Option<StatusMetadata> = None;
+    let mut last_state = None;
+
+    loop {
+        let snapshot = status_rx.borrow_and_update().clone();
+
+        for (bus_name, status) in &players {
+            if !snapshot.players.contains_key(bus_name) {
+                fire(&hooks, HookEvent::Detach, status);
+            }
+        }
+        for (bus_name, status) in &snapshot.players {
+            if !players.contains_key(bus_name) {
+                fire(&hooks, HookEvent::Attach, status);
+            }
+        }
+        players = snapshot.players.clone();
+
+        if let Some(status) = snapshot.active() {
+            if last_active.as_ref() != Some(&status.player.bus_name) {
+                fire(&hooks, HookEvent::TrackChange, status);
+                last_active = Some(status.player.bus_name.clone());
+                last_track = Some(status.metadata.clone());
+            }
+
+            if last_state.as_ref() != Some(&status.playback_state) {
+                let event = match status.playback_state {
+                    PlaybackState::Playing => HookEvent::Play,
+                    PlaybackState::Paused => HookEvent::Pause,
+                    PlaybackState::None => HookEvent::Stop,
+                };
+                fire(&hooks, event, status);
+                last_state = Some(status.playback_state.clone());
+            }
+        } else {
+            last_active = None;
+            last_track = None;
+            last_state = None;
+        }
+
+        loop {
+            tokio::select! {
+                changed = status_rx.changed() => {
+                    if changed.is_err() {
+                        return;
+                    }
+                    break;
+                }
+                event = events_rx.recv() => match event {
+                    Ok(Event::TrackChanged { bus_name, metadata }) => {
+                        // The change that made it the active player already ran them.
+                        let Some(status) = players.get(&bus_name).filter(|_| {
+                            last_active.as_ref() == Some(&bus_name)
+                                && !last_track.as_ref().is_some_and(|track| track.is_same_track(&metadata))
+                        }) else {
+                            continue;
+                        };
+
+                        let status = PlayerStatus {
+                            metadata: metadata.clone(),
+                            position: 0,
+                            ..status.clone()
+                        };
+                        fire(&hooks, HookEvent::TrackChange, &status);
+                        last_track = Some(metadata);
+                    }
+                    Ok(Event::NowPlayingConfirmed { bus_name, metadata }) => {
+                        let Some(status) = players
+                            .get(&bus_name)
+                            .filter(|_| last_active.as_ref() == Some(&bus_name))
+                        else {
+                            continue;
+                        };
+
+                        let status = PlayerStatus {
+                            metadata,
+                            ..status.clone()
+                        };
+                        fire(&hooks, HookEvent::TrackConfirmed, &status);
+                    }
+                    Ok(_) | Err(RecvError::Lagged(_)) => {}
+                    Err(RecvError::Closed) => return,
+                },
+            }
+        }
+    }
+}
+
+impl Sink for Hooks {
+    fn name(&self) -> &'static str {
+        "hook"
+    }
+
+    fn run(&self, context: &SinkContext) -> BoxFuture<'static, ()> {
+        Box::pin(run(
+            self.0.clone(),
+            context.status_rx.clone(),
+            context.state.events_tx.subscribe(),
+        ))
+    }
+}
@@ -0,0 +1,195 @@
+use crate::admin::ConnectedClient;
+use crate::event::Event;
+use crate::server::Client;
+use crate::status::{self, PlayerStatus, StatusSnapshot};
+use futures_util::{Stream, StreamExt};
+use std::collections::BTreeMap;
+use std::time::Duration;
+use tokio::sync::{broadcast, watch};
+use tokio_tungstenite::tungstenite::{self, Message};
+
+/// What a source pushed last, and on which connection, so a lingering old one doesn't remove what its reconnection pushed.
+#[derive(Debug, Clone)]
+struct Source {
+    connection: u64,
+    status: Option<PlayerStatus>,
+}
+
+/// The statuses other instances push to `/hub` with --connect, by the name they connect with.
+#[derive(Debug, Clone)]
+pub struct Hub {
+    sources_tx: watch::Sender<BTreeMap<String, Source>>,
+    events_tx: broadcast::Sender<Event>,
+}
+
+impl Hub {
+    /// Takes pushed statuses, sending the events for the players in them to `events_tx`.
+    pub fn new(events_tx: broadcast::Sender<Event>) -> Self {
+        Self {
+            sources_tx: watch::channel(BTreeMap::new()).0,
+            events_tx,
+        }
+    }
+
+    /// Names a source's player in the merged statuses, so players on different machines never clash.
+    fn bus_name(source: &str, status: &PlayerStatus) -> String {
+        format!("{source}/{}", status.player.bus_name)
+    }
+
+    /// Keeps what `source` pushed on `connection`, `None` when no player is attached there.
+    fn update(&self, source: &str, connection: u64, status: Option<PlayerStatus>) {
+        let status = status.map(|mut status| {
+            status.player.bus_name = Self::bus_name(source, &status);
+            // Paths and file URLs are on the other machine, and aren't for a pushing client to make this one read.
+            status.metadata.artwork.retain(|artwork| {
+                ["http://", "https://", "data:"]
+                    .iter()
+                    .any(|scheme| artwork.src.starts_with(scheme))
+            });
+            status
+        });
+
+        let previous = self
+            .sources_tx
+            .borrow()
+            .get(source)
+            .and_then(|previous| previous.status.clone());
+        self.announce(previous.as_ref(), status.as_ref());
+
+        self.sources_tx.send_modify(|sources| {
+            sources.insert(source.to_string(), Source { connection, status });
+        });
+    }
+
+    /// Forgets `source` once `connection` is gone, unless it already reconnected.
+    fn remove(&self, source: &str, connection: u64) {
+        let mut removed = None;
+        self.sources_tx.send_if_modified(|sources| {
+            if sources.get(source).map(|source| source.connection) != Some(connection) {
+                return false;
+            }
+            removed = sources.remove(source).and_then(|source| source.status);
+            true
+        });
+        self.announce(removed.as_ref(), None);
+    }
+
+    /// Sends the events for a source's player going from `previous` to `status`, like the player thread does for local ones.
+    fn announce(&self, previous: Option<&PlayerStatus>, status: Option<&PlayerStatus>) {
+        let same_player = previous
+            .zip(status)
+            .is_some_and(|(previous, status)| previous.player.bus_name == status.player.bus_name);
+
+        if let Some(previous) = previous.filter(|_| !same_player) {
+            let _ = self.events_tx.send(Event::Detached {
+                bus_name: previous.player.bus_name.clone(),
+            });
+        }
+        if let Some(status) = status {
+            if !same_player {
+                let _ = self.events_tx.send(Event::Attached {
+                    bus_name: status.player.bus_name.clone(),
+                    identity: status.player.identity.clone(),
+                });
+            }
+            let previous = previous.filter(|_| same_player);
+            if let Some(event) = Event::track_change(previous, status) {
+                let _ = self.events_tx.send(event);
+            }
+        }
+    }
+}
+
+/// Adds the players pushed to `hub` to the local ones in `local_rx`. The active player is the one playing, local ones first, then sources by name.
+pub fn merge(
+    mut local_rx: watch::Receiver<StatusSnapshot>,
+    hub: &Hub,
+) -> watch::Receiver<StatusSnapshot> {
+    let mut sources_rx = hub.sources_tx.subscribe();
+    let merged = |local: &StatusSnapshot, sources: &BTreeMap<String, Source>| {
+        let mut snapshot = local.clone();
+        let remote = sources.values().filter_map(|source| source.status.clone());
+        for status in remote {
+            snapshot
+                .players
+                .insert(status.player.bus_name.clone(), status);
+        }
+
+        // `min_by_key` keeps the first of equals, so local players win ties.
+        snapshot.active = local
+            .active()
+            .into_iter()
+            .chain(sources.values().filter_map(|source| source.status.as_ref()))
+            .min_by_key(|status| &status.playback_state)
+            .map(|status| status.player.bus_name.clone());
+        snapshot
+    };
+
+    let (merged_tx, merged_rx) = watch::channel(merged(
+        &local_rx.borrow_and_update(),
+        &sources_rx.borrow_and_update(),
+    ));
+    tokio::spawn(async move {
+        loop {
+            tokio::select! {
+                changed = local_rx.changed() => if changed.is_err() {
+                    return;
+                },
+                changed = sources_rx.changed() => if changed.is_err() {
+                    return;
+                },
+            }
+
+            let snapshot = merged(
+                &local_rx.borrow_and_update(),
+                &sources_rx.borrow_and_update(),
+            );
+            status::send_changed(&merged_tx, snapshot);
+        }
+    });
+
+    merged_rx
+}
+
+/// Reads the statuses an instance pushes as `source` until it disconnects, or stays silent for `timeout`. It pings when it has nothing to push, and pings are answered while reading.
+pub async fn receive<S>(
+    mut stream: S,
+    hub: &Hub,
+    source: &str,
+    client: &Client,
+    connected: &ConnectedClient,
+    timeout: Option<Duration>,
+) where
+    S: Stream<Item = Result<Message, tungstenite::Error>> + Unpin,
+{
+    log::info!(client_id = client.id, client:% = client.address; "{client} is pushing to the hub as {source}.");
+
+    loop {
+        let next = match timeout {
+            Some(timeout) => match tokio::time::timeout(timeout, stream.next()).await {
+                Ok(next) => next,
+                Err(_) => {
+                    log::warn!(client_id = client.id, client:% = client.address; "{source} didn't push anything for {} seconds, dropping it.", timeout.as_secs_f32());
+                    break;
+                }
+            },
+            None => stream.next().await,
+        };
+        let text = match next {
+            Some(Ok(Message::Text(text))) => text,
+            Some(Ok(Message::Close(_))) | Some(Err(_)) | None => break,
+            Some(Ok(_)) => continue,
+        };
+        connected.active();
+
+        match serde_json::from_str::<Option<PlayerStatus>>(&text) {
+            Ok(status) => hub.update(source, client.id, status),
+            Err(e) => {
+                log::debug!(client_id = client.id, client:% = client.address; "{source} pushed something that isn't a status: {e}")
+            }
+        }
+    }
+
+    hub.remove(source, client.id);
+    log::info!(client_id = client.id, client:% = client.address; "{source} stopped pushing to the hub.");
+}
@@ -0,0 +1,140 @@
+use crate::bar::Format;
+use crate::bus::BusAddress;
+use crate::control;
+use crate::status::{CompactStatus, StatusSnapshot};
+use serde::{Deserialize, Serialize};
+use tokio::io::{AsyncBufRead, AsyncBufReadExt, AsyncWrite, AsyncWriteExt};
+use tokio::sync::watch;
+
+/// The block's name, clicks on other blocks are left alone.
+const BLOCK_NAME: &str = "nowplaying";
+
+#[derive(Debug, Serialize)]
+struct Header {
+    version: u32,
+    click_events: bool,
+}
+
+#[derive(Debug, Serialize)]
+struct Block {
+    name: &'static str,
+    full_text: String,
+    /// Shown when the bar runs out of room.
+    short_text: String,
+    /// The text is taken as is, not as Pango markup.
+    markup: &'static str,
+}
+
+/// A click event sent by the bar.
+#[derive(Debug, Deserialize)]
+struct Click {
+    #[serde(default)]
+    name: String,
+    button: u32,
+}
+
+/// The player method a mouse button runs.
+fn method(button: u32) -> Option<&'static str> {
+    match button {
+        1 => Some("PlayPause"),
+        3 => Some("Next"),
+        _ => None,
+    }
+}
+
+/// Reads a click event from a line of the bar's input: one JSON object per line, after a `[` opening the endless array and separated by commas.
+fn parse_click(line: &str) -> Option<Click> {
+    let line = line.trim().trim_start_matches(['[', ',']).trim_start();
+    serde_json::from_str(line).ok()
+}
+
+/// Runs a click's player method on the active player without waiting for it, logging failures.
+fn click(button: u32, bus: &BusAddress, status_rx: &watch::Receiver<StatusSnapshot>) {
+    let Some(method) = method(button) else {
+        return;
+    };
+    let Some(bus_name) = status_rx
+        .borrow()
+        .active()
+        .map(|status| status.player.bus_name.clone())
+    else {
+        return;
+    };
+
+    let bus = bus.clone();
+    tokio::task::spawn_blocking(move || {
+        if let Err(e) = control::call_player(&bus, &bus_name, method) {
+            log::warn!(player = bus_name.as_str(); "{method} failed on player {bus_name}: {e}");
+        }
+    });
+}
+
+/// Speaks the i3bar protocol on `writer`, for i3bar and swaybar's `status_command`: a single block with the active player's status in `format`, updated whenever it changes and left out when no player is attached. Clicks read from `reader` play or pause (left button) and skip to the next track (right button), on `bus`.
+pub async fn run<R: AsyncBufRead + Unpin, W: AsyncWrite + Unpin>(
+    reader: R,
+    mut writer: W,
+    format: Format,
+    bus: BusAddress,
+    mut status_rx: watch::Receiver<StatusSnapshot>,
+) {
+    let header = serde_json::to_string(&Header {
+        version: 1,
+        click_events: true,
+    })
+    .unwrap();
+    if writer
+        .write_all(format!("{header}\n[\n").as_bytes())
+        .await
+        .is_err()
+    {
+        return;
+    }
+
+    let mut clicks = reader.lines();
+    let mut reading = true;
+    let mut last_sent: Option<String> = None;
+
+    loop {
+        let blocks = match status_rx.borrow_and_update().active() {
+            Some(status) => {
+                let compact = CompactStatus::from(Some(status));
+                vec![Block {
+                    name: BLOCK_NAME,
+                    full_text: format.render(&compact),
+                    short_text: compact.title.clone(),
+                    markup: "none",
+                }]
+            }
+            None => Vec::new(),
+        };
+        let line = serde_json::to_string(&blocks).unwrap();
+
+        // Positions are only shown to the second, most updates don't change the line.
+        if last_sent.as_ref() != Some(&line) {
+            let separator = if last_sent.is_some() { "," } else { "" };
+            last_sent = Some(line.clone());
+
+            let line = format!("{separator}{line}\n");
+            if writer.write_all(line.as_bytes()).await.is_err() || writer.flush().await.is_err() {
+                return;
+            }
+        }
+
+        tokio::select! {
+            changed = status_rx.changed() => {
+                if changed.is_err() {
+                    return;
+                }
+            }
+            line = clicks.next_line(), if reading => match line {
+                Ok(Some(line)) => {
+                    if let Some(event) = parse_click(&line).filter(|event| event.name == BLOCK_NAME) {
+                        click(event.button, &bus, &status_rx);
+                    }
+                }
+                // The bar doesn't send clicks, keep writing anyway.
+                Ok(None) | Err(_) => reading = false,
+            },
+        }
+    }
+}
@@ -0,0 +1,115 @@
+use std::collections::hash_map::DefaultHasher;
+use std::fs::{self, File, OpenOptions};
+use std::hash::{Hash, Hasher};
+use std::io::{self, Read, Seek, Write};
+use std::net::SocketAddr;
+use std::os::fd::AsRawFd;
+use std::path::{Path, PathBuf};
+use std::thread;
+use std::time::Duration;
+
+/// How long to wait for a replaced instance to exit.
+const REPLACE_TIMEOUT: Duration = Duration::from_secs(5);
+const REPLACE_POLL_INTERVAL: Duration = Duration::from_millis(100);
+
+/// What to do when another instance with the same addresses and config is already running.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, clap::ValueEnum)]
+pub enum IfRunning {
+    /// Leave it be and exit.
+    Exit,
+    /// Stop it and take over.
+    Replace,
+}
+
+/// Held for as long as we run, so other instances can find us. The file holds our pid.
+#[derive(Debug)]
+pub struct InstanceLock(File);
+
+/// The lock file for instances serving on `addrs` with the config at `config`, in `$XDG_RUNTIME_DIR` (or the temporary folder).
+pub fn lock_path(addrs: &[SocketAddr], config: Option<&Path>) -> PathBuf {
+    let mut addrs = addrs.to_vec();
+    addrs.sort();
+
+    let mut hasher = DefaultHasher::new();
+    addrs.hash(&mut hasher);
+    config
+        .map(|config| fs::canonicalize(config).unwrap_or_else(|_| config.to_path_buf()))
+        .hash(&mut hasher);
+
+    dirs::runtime_dir()
+        .unwrap_or_else(std::env::temp_dir)
+        .join(format!("mpris-nowplaying-{:016x}.lock", hasher.finish()))
+}
+
+/// Tries to lock the file without waiting.
+fn try_lock(file: &File) -> io::Result<bool> {
+    // SAFETY: the descriptor is valid for as long as `file` is.
+    if unsafe { libc::flock(file.as_raw_fd(), libc::LOCK_EX | libc::LOCK_NB) } == 0 {
+        return Ok(true);
+    }
+
+    let e = io::Error::last_os_error();
+    if e.raw_os_error() == Some(libc::EWOULDBLOCK) {
+        Ok(false)
+    } else {
+        Err(e)
+    }
+}
+
+/// Takes the lock at `path`. If another instance holds it, returns its pid after exiting or replacing it as asked, `Ok(Err(pid))` meaning we should exit.
+pub fn acquire(path: &Path, if_running: IfRunning) -> io::Result<Result<InstanceLock, u32>> {
+    let mut file = OpenOptions::new()
+        .read(true)
+        .write(true)
+        .create(true)
+        .truncate(false)
+        .open(path)?;
+
+    if !try_lock(&file)? {
+        let mut contents = String::new();
+        file.read_to_string(&mut contents)?;
+        let pid = contents.trim().parse::<u32>().unwrap_or_default();
+
+        if if_running == IfRunning::Exit || pid == 0 {
+            return Ok(Err(pid));
+        }
+
+        log::info!("Stopping the running instance (pid {pid}) to take over.");
+        // SAFETY: plain system call without pointers.
+        if unsafe { libc::kill(pid as libc::pid_t, libc::SIGTERM) } == -1 {
+            return Err(io::Error::last_os_error());
+        }
+
+        let mut locked = false;
+        for _ in 0..REPLACE_TIMEOUT.as_millis() / REPLACE_POLL_INTERVAL.as_millis() {
+            thread::sleep(REPLACE_POLL_INTERVAL);
+            if try_lock(&file)? {
+                locked = true;
+                break;
+            }
+        }
+
+        if !locked {
+            return Err(io::Error::new(
+                io::ErrorKind::TimedOut,
+                format!(
+                    "the running instance (pid {pid}) didn't exit within {} seconds",
+                    REPLACE_TIMEOUT.as_secs()
+                ),
+            ));
+        }
+    }
+
+    let mut lock = InstanceLock(file);
+    lock.write_pid()?;
+    Ok(Ok(lock))
+}
+
+impl InstanceLock {
+    /// Writes our pid into the lock file, again after forking into the background.
+    pub fn write_pid(&mut self) -> io::Result<()> {
+        self.0.set_len(0)?;
+        self.0.rewind()?;
+        writeln!(self.0, "{}", std::process::id())
+    }
+}
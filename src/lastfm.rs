@@ -0,0 +1,327 @@
+use crate::scrobble::{self, Scrobble, Service};
+use crate::sink::{Sink, SinkContext};
+use crate::status::StatusSnapshot;
+use futures_util::future::BoxFuture;
+use serde::Deserialize;
+use std::fmt;
+use std::fs;
+use std::io;
+use std::path::{Path, PathBuf};
+use std::thread;
+use std::time::{Duration, Instant};
+use tokio::sync::watch;
+
+pub const DEFAULT_API_URL: &str = "https://ws.audioscrobbler.com/2.0/";
+
+const HTTP_TIMEOUT: Duration = Duration::from_secs(15);
+
+const LOGIN_POLL_INTERVAL: Duration = Duration::from_secs(3);
+const LOGIN_TIMEOUT: Duration = Duration::from_secs(5 * 60);
+
+/// Last.fm's error codes that mean trying again later might work.
+const RETRYABLE_ERRORS: [u32; 3] = [
+    11, // service offline
+    16, // temporarily unavailable
+    29, // rate limit exceeded
+];
+/// Returned by `auth.getSession` until the user allowed access.
+const NOT_AUTHORIZED: u32 = 14;
+/// The session key was revoked, or never valid.
+const INVALID_SESSION: u32 = 9;
+
+/// Settings for --lastfm-api-key.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct LastfmOptions {
+    pub api_url: String,
+    pub api_key: String,
+    pub api_secret: String,
+    /// Where `lastfm-login` stores the session key.
+    pub session_file: PathBuf,
+    /// Where scrobbles that couldn't be sent yet are kept.
+    pub queue_file: PathBuf,
+}
+
+#[derive(Debug)]
+enum Error {
+    /// The service couldn't be reached, or answered with something that isn't an API response.
+    Http(String),
+    /// The service rejected the request.
+    Api { code: u32, message: String },
+}
+
+impl From<Error> for scrobble::Error {
+    fn from(e: Error) -> Self {
+        match e {
+            Error::Http(_) => Self::Unavailable(e.to_string()),
+            Error::Api { code, .. } if RETRYABLE_ERRORS.contains(&code) => {
+                Self::Unavailable(e.to_string())
+            }
+            Error::Api {
+                code: INVALID_SESSION,
+                ..
+            } => Self::Unauthorized(format!("{e}, run `mpris-nowplaying lastfm-login` again")),
+            Error::Api { .. } => Self::Rejected(e.to_string()),
+        }
+    }
+}
+
+impl fmt::Display for Error {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            Error::Http(e) => write!(f, "{e}"),
+            Error::Api { code, message } => write!(f, "{message} (error {code})"),
+        }
+    }
+}
+
+#[derive(Deserialize)]
+struct ApiError {
+    error: u32,
+    #[serde(default)]
+    message: String,
+}
+
+/// The hex MD5 digest Last.fm signs requests with. Not used for anything needing security.
+fn md5(data: &[u8]) -> String {
+    const SHIFTS: [u32; 16] = [7, 12, 17, 22, 5, 9, 14, 20, 4, 11, 16, 23, 6, 10, 15, 21];
+    let constants: [u32; 64] =
+        std::array::from_fn(|i| ((i as f64 + 1.0).sin().abs() * 4294967296.0) as u32);
+
+    let mut message = data.to_vec();
+    message.push(0x80);
+    while message.len() % 64 != 56 {
+        message.push(0);
+    }
+    message.extend((data.len() as u64).wrapping_mul(8).to_le_bytes());
+
+    let mut state: [u32; 4] = [0x67452301, 0xefcdab89, 0x98badcfe, 0x10325476];
+    for block in message.chunks(64) {
+        let words: [u32; 16] = std::array::from_fn(|i| {
+            u32::from_le_bytes(block[i * 4..i * 4 + 4].try_into().unwrap())
+        });
+        let [mut a, mut b, mut c, mut d] = state;
+
+        for i in 0..64 {
+            let (f, g) = match i / 16 {
+                0 => ((b & c) | (!b & d), i),
+                1 => ((d & b) | (!d & c), (5 * i + 1) % 16),
+                2 => (b ^ c ^ d, (3 * i + 5) % 16),
+                _ => (c ^ (b | !d), (7 * i) % 16),
+            };
+            let f = f
+                .wrapping_add(a)
+                .wrapping_add(constants[i])
+                .wrapping_add(words[g]);
+            a = d;
+            d = c;
+            c = b;
+            b = b.wrapping_add(f.rotate_left(SHIFTS[i / 16 * 4 + i % 4]));
+        }
+
+        for (value, add) in state.iter_mut().zip([a, b, c, d]) {
+            *value = value.wrapping_add(add);
+        }
+    }
+
+    state
+        .iter()
+        .flat_map(|value| value.to_le_bytes())
+        .map(|byte| format!("{byte:02x}"))
+        .collect()
+}
+
+/// Calls an API method, signing it with the secret. Blocks.
+fn call(
+    options: &LastfmOptions,
+    method: &str,
+    params: &[(String, String)],
+) -> Result<serde_json::Value, Error> {
+    let mut params = params.to_vec();
+    params.push((String::from("method"), method.to_string()));
+    params.push((String::from("api_key"), options.api_key.clone()));
+    params.sort();
+
+    let mut signed = params
+        .iter()
+        .map(|(key, value)| format!("{key}{value}"))
+        .collect::<String>();
+    signed.push_str(&options.api_secret);
+    params.push((String::from("api_sig"), md5(signed.as_bytes())));
+    params.push((String::from("format"), String::from("json")));
+
+    let agent: ureq::Agent = ureq::Agent::config_builder()
+        .http_status_as_error(false)
+        .timeout_global(Some(HTTP_TIMEOUT))
+        .build()
+        .into();
+    let body = agent
+        .post(&options.api_url)
+        .send_form(params)
+        .and_then(|mut response| response.body_mut().read_to_string())
+        .map_err(|e| Error::Http(e.to_string()))?;
+
+    let value = serde_json::from_str::<serde_json::Value>(&body)
+        .map_err(|e| Error::Http(format!("invalid response: {e}")))?;
+    match ApiError::deserialize(&value) {
+        Ok(e) => Err(Error::Api {
+            code: e.error,
+            message: e.message,
+        }),
+        Err(_) => Ok(value),
+    }
+}
+
+/// Where the user allows access to their account for `token`.
+fn auth_page(options: &LastfmOptions, token: &str) -> String {
+    let base = match url::Url::parse(&options.api_url) {
+        Ok(url) if url.host_str() != Some("ws.audioscrobbler.com") => {
+            format!("{}/api/auth/", url.origin().ascii_serialization())
+        }
+        _ => String::from("https://www.last.fm/api/auth/"),
+    };
+
+    format!("{base}?api_key={}&token={token}", options.api_key)
+}
+
+/// Has the user allow scrobbling to their account, storing the session key in the session file. Returns the exit code.
+pub fn login(options: &LastfmOptions) -> i32 {
+    let token = match call(options, "auth.getToken", &[]) {
+        Ok(response) => response["token"].as_str().unwrap_or_default().to_string(),
+        Err(e) => {
+            eprintln!("Could not start logging in: {e}");
+            return 1;
+        }
+    };
+
+    println!(
+        "Open this page and allow access to your account:\n\n    {}\n",
+        auth_page(options, &token)
+    );
+    println!("Waiting for it…");
+
+    let started = Instant::now();
+    let params = [(String::from("token"), token)];
+    let session = loop {
+        thread::sleep(LOGIN_POLL_INTERVAL);
+
+        match call(options, "auth.getSession", &params) {
+            Ok(response) => break response["session"].clone(),
+            Err(Error::Api {
+                code: NOT_AUTHORIZED,
+                ..
+            }) if started.elapsed() < LOGIN_TIMEOUT => {}
+            Err(e) => {
+                eprintln!("Could not log in: {e}");
+                return 1;
+            }
+        }
+    };
+
+    let key = session["key"].as_str().unwrap_or_default();
+    if let Err(e) = write_private(&options.session_file, key) {
+        eprintln!(
+            "Could not store the session in {}: {e}",
+            options.session_file.display()
+        );
+        return 1;
+    }
+
+    println!(
+        "Logged in as {}, scrobbles will be sent to their account.",
+        session["name"].as_str().unwrap_or_default()
+    );
+    0
+}
+
+/// Writes a file only we can read, creating its folder.
+fn write_private(path: &Path, contents: &str) -> io::Result<()> {
+    use std::io::Write;
+    use std::os::unix::fs::OpenOptionsExt;
+
+    if let Some(dir) = path.parent() {
+        fs::create_dir_all(dir)?;
+    }
+    fs::OpenOptions::new()
+        .create(true)
+        .write(true)
+        .truncate(true)
+        .mode(0o600)
+        .open(path)?
+        .write_all(contents.as_bytes())
+}
+
+/// The session key stored by `lastfm-login`, read again every time so logging in again doesn't need a restart.
+fn session_key(options: &LastfmOptions) -> Result<String, scrobble::Error> {
+    fs::read_to_string(&options.session_file)
+        .ok()
+        .map(|key| key.trim().to_string())
+        .filter(|key| !key.is_empty())
+        .ok_or_else(|| {
+            scrobble::Error::Unauthorized(String::from(
+                "not logged in, run `mpris-nowplaying lastfm-login`",
+            ))
+        })
+}
+
+struct Lastfm(LastfmOptions);
+
+impl Service for Lastfm {
+    const NAME: &'static str = "Last.fm";
+    const BATCH_SIZE: usize = 50;
+    const MAX_AGE: Option<Duration> = Some(Duration::from_secs(14 * 24 * 60 * 60));
+
+    fn now_playing(&self, track: &Scrobble) -> Result<(), scrobble::Error> {
+        let mut params = vec![
+            (String::from("sk"), session_key(&self.0)?),
+            (String::from("artist"), track.artist.clone()),
+            (String::from("track"), track.track.clone()),
+        ];
+        if !track.album.is_empty() {
+            params.push((String::from("album"), track.album.clone()));
+        }
+        if track.duration > 0 {
+            params.push((String::from("duration"), track.duration.to_string()));
+        }
+
+        call(&self.0, "track.updateNowPlaying", &params)?;
+        Ok(())
+    }
+
+    fn submit(&self, scrobbles: &[Scrobble]) -> Result<(), scrobble::Error> {
+        let mut params = vec![(String::from("sk"), session_key(&self.0)?)];
+        for (i, scrobble) in scrobbles.iter().enumerate() {
+            params.push((format!("artist[{i}]"), scrobble.artist.clone()));
+            params.push((format!("track[{i}]"), scrobble.track.clone()));
+            params.push((format!("timestamp[{i}]"), scrobble.timestamp.to_string()));
+            if !scrobble.album.is_empty() {
+                params.push((format!("album[{i}]"), scrobble.album.clone()));
+            }
+            if scrobble.duration > 0 {
+                params.push((format!("duration[{i}]"), scrobble.duration.to_string()));
+            }
+        }
+
+        call(&self.0, "track.scrobble", &params)?;
+        Ok(())
+    }
+}
+
+/// Scrobbles the active player's tracks to Last.fm, see [`scrobble::run`].
+pub async fn run(options: LastfmOptions, status_rx: watch::Receiver<StatusSnapshot>) {
+    if let Err(e) = session_key(&options) {
+        log::warn!("Scrobbles to Last.fm are queued until logged in: {e}.");
+    }
+
+    let queue_file = options.queue_file.clone();
+    scrobble::run(Lastfm(options), queue_file, status_rx).await
+}
+
+impl Sink for LastfmOptions {
+    fn name(&self) -> &'static str {
+        "lastfm"
+    }
+
+    fn run(&self, context: &SinkContext) -> BoxFuture<'static, ()> {
+        Box::pin(run(self.clone(), context.status_rx.clone()))
+    }
+}
@@ -0,0 +1,181 @@
+use crate::status::{send_changed, PlaybackState, PlayerStatus, StatusSnapshot};
+use std::io;
+use std::path::{Path, PathBuf};
+use std::time::{Duration, Instant};
+use tokio::sync::watch;
+
+/// How often the position of the track that's playing is saved. Anything else is saved right away.
+const SAVE_INTERVAL: Duration = Duration::from_secs(15);
+
+/// Reads the status saved by [`run`], if there is one.
+pub fn load(path: &Path) -> Option<PlayerStatus> {
+    let json = match std::fs::read_to_string(path) {
+        Ok(json) => json,
+        Err(e) if e.kind() == io::ErrorKind::NotFound => return None,
+        Err(e) => {
+            log::warn!(
+                "Could not read the last status from {}: {e}",
+                path.display()
+            );
+            return None;
+        }
+    };
+
+    serde_json::from_str(&json)
+        .inspect_err(|e| {
+            log::warn!(
+                "Could not read the last status from {}: {e}",
+                path.display()
+            )
+        })
+        .ok()
+}
+
+/// Saves `status` to `path`, replacing the file in one go so a crash never leaves half of it. Blocks.
+pub fn save(path: &Path, status: &PlayerStatus) {
+    let result = (|| {
+        if let Some(dir) = path.parent().filter(|dir| !dir.as_os_str().is_empty()) {
+            std::fs::create_dir_all(dir)?;
+        }
+        let partial = path.with_extension("json.part");
+        std::fs::write(&partial, serde_json::to_vec(status).unwrap())?;
+        std::fs::rename(&partial, path)
+    })();
+
+    if let Err(e) = result {
+        log::warn!("Could not save the last status to {}: {e}", path.display());
+    }
+}
+
+/// Saves the active player's status to `path` whenever it changes. When no player is attached the last one is kept.
+pub async fn run(path: PathBuf, mut status_rx: watch::Receiver<StatusSnapshot>) {
+    let mut saved: Option<PlayerStatus> = None;
+    let mut last_save = Instant::now();
+
+    loop {
+        let status = status_rx.borrow_and_update().active().cloned();
+
+        if let Some(status) = status {
+            // The position moves on every update tick while playing, it's enough to save it now and then.
+            let only_position = saved.as_ref().is_some_and(|saved| {
+                PlayerStatus {
+                    position: status.position,
+                    ..saved.clone()
+                } == status
+            });
+            if !only_position || last_save.elapsed() >= SAVE_INTERVAL {
+                saved = Some(status.clone());
+                last_save = Instant::now();
+
+                let path = path.clone();
+                if let Err(e) = tokio::task::spawn_blocking(move || save(&path, &status)).await {
+                    log::warn!("Could not save the last status: {e}");
+                }
+            }
+        }
+
+        if status_rx.changed().await.is_err() {
+            return;
+        }
+    }
+}
+
+/// `last` flagged as `stale` and paused where it was, for a player that isn't there anymore.
+fn stale(mut last: PlayerStatus) -> PlayerStatus {
+    last.position = last.position_now();
+    last.stale = true;
+    if last.playback_state == PlaybackState::Playing {
+        last.playback_state = PlaybackState::Paused;
+    }
+    last.lyrics = None;
+    last
+}
+
+/// Passes the statuses on, but reports `last` as the active player, flagged as `stale` and paused, until a player is attached for the first time.
+pub fn with_last(
+    mut status_rx: watch::Receiver<StatusSnapshot>,
+    last: Option<PlayerStatus>,
+) -> watch::Receiver<StatusSnapshot> {
+    let Some(last) = last else {
+        return status_rx;
+    };
+
+    let last = stale(last);
+    let stale = StatusSnapshot {
+        active: Some(last.player.bus_name.clone()),
+        players: [(last.player.bus_name.clone(), last)].into(),
+    };
+    let snapshot = status_rx.borrow_and_update().clone();
+    let found = snapshot.active.is_some();
+    let (tx, rx) = watch::channel(if found { snapshot } else { stale });
+
+    tokio::spawn(async move {
+        let mut found = found;
+
+        while status_rx.changed().await.is_ok() {
+            let snapshot = status_rx.borrow_and_update().clone();
+            found |= snapshot.active.is_some();
+            if found && tx.send(snapshot).is_err() {
+                return;
+            }
+        }
+    });
+
+    rx
+}
+
+/// What clients are served once the last player is gone, until another one is attached (--on-player-loss).
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, clap::ValueEnum)]
+pub enum PlayerLoss {
+    /// No player: `null`, as when none was ever attached.
+    #[default]
+    Clear,
+    /// The lost player's last status, flagged as `stale` and paused.
+    Stale,
+    /// The lost player's last status as it was, so clients aren't sent anything new.
+    Hold,
+}
+
+/// Passes the statuses on, but once no player is attached anymore, keeps reporting the one that was active last as `loss` says, until a player is attached again.
+pub fn on_player_loss(
+    mut status_rx: watch::Receiver<StatusSnapshot>,
+    loss: PlayerLoss,
+) -> watch::Receiver<StatusSnapshot> {
+    if loss == PlayerLoss::Clear {
+        return status_rx;
+    }
+
+    let (tx, rx) = watch::channel(status_rx.borrow_and_update().clone());
+
+    tokio::spawn(async move {
+        let mut last = tx.borrow().active().cloned();
+
+        while status_rx.changed().await.is_ok() {
+            let snapshot = status_rx.borrow_and_update().clone();
+            let served = match (snapshot.active(), &mut last, loss) {
+                (Some(status), ..) => {
+                    last = Some(status.clone());
+                    snapshot
+                }
+                (None, None, _) | (None, _, PlayerLoss::Clear) => snapshot,
+                // Nothing changes for clients until a player is attached.
+                (None, Some(_), PlayerLoss::Hold) => continue,
+                (None, Some(last), PlayerLoss::Stale) => {
+                    // Paused where it was lost, so it stays the same from then on.
+                    *last = stale(last.clone());
+                    let mut players = snapshot.players;
+                    players.insert(last.player.bus_name.clone(), last.clone());
+                    StatusSnapshot {
+                        active: Some(last.player.bus_name.clone()),
+                        players,
+                    }
+                }
+            };
+            if !send_changed(&tx, served) {
+                return;
+            }
+        }
+    });
+
+    rx
+}
@@ -0,0 +1,103 @@
+//! Watches MPRIS players on D-Bus and serves their status over WebSocket, HTTP and more, for stream overlays, status bars and the like.
+//!
+//! This is what the `mpris-nowplaying` command runs, as a library for embedding the player watching in other servers:
+//!
+//! - [`player::run`] finds players and keeps their [`status::PlayerStatus`] up to date in a [`tokio::sync::watch`] channel.
+//! - [`backend::Backend`] is what it does for any platform, with [`backend::platform`] the one this was built for. Only MPRIS is there yet.
+//! - [`server::run`] serves those statuses like the command does, with [`server::ServerState::new`] for its defaults.
+//! - [`cli::main`] is the whole command line.
+//!
+//! ```no_run
+//! use mpris_nowplaying::demand::Demand;
+//! use mpris_nowplaying::player::{self, PlayerOptions};
+//! use mpris_nowplaying::status::StatusSnapshot;
+//! use std::sync::mpsc;
+//! use tokio::sync::{broadcast, watch};
+//!
+//! #[tokio::main]
+//! async fn main() {
+//!     let (status_tx, mut status_rx) = watch::channel(StatusSnapshot::default());
+//!     let (events_tx, _) = broadcast::channel(64);
+//!     // Send new options here to change them while it runs.
+//!     let (_options_tx, options_rx) = mpsc::channel();
+//!     // Send player::Action here to search for players again or detach one.
+//!     let (_actions_tx, actions_rx) = mpsc::channel();
+//!     std::thread::spawn(move || {
+//!         let options = PlayerOptions::default();
+//!         player::run(options, options_rx, actions_rx, status_tx, events_tx, Demand::default())
+//!     });
+//!
+//!     while status_rx.changed().await.is_ok() {
+//!         if let Some(status) = status_rx.borrow_and_update().active() {
+//!             println!("{} – {}", status.metadata.artist, status.metadata.title);
+//!         }
+//!     }
+//! }
+//! ```
+
+pub mod admin;
+pub mod artwork;
+pub mod backend;
+pub mod bar;
+mod bridge;
+pub mod bus;
+pub mod casing;
+pub mod cli;
+pub mod config;
+mod confirm;
+pub mod control;
+mod daemon;
+pub mod demand;
+mod demo;
+mod discord;
+mod enrich;
+pub mod event;
+mod exit_code;
+mod fifo;
+mod grpc;
+pub mod health;
+pub mod history;
+#[cfg(feature = "history-db")]
+pub mod historydb;
+mod hook;
+pub mod hub;
+mod i3bar;
+mod instance;
+mod lastfm;
+mod laststatus;
+mod lines;
+mod listenbrainz;
+mod logging;
+mod lyrics;
+mod mdns;
+mod mqtt;
+mod musicbrainz;
+mod notify;
+mod osc;
+mod outbox;
+pub mod player;
+mod plugin;
+mod record;
+pub mod redact;
+mod replay;
+#[cfg(feature = "history-db")]
+mod report;
+pub mod request;
+mod romanize;
+mod runtime;
+mod script;
+mod scrobble;
+mod scrobblerlog;
+pub mod server;
+pub mod session;
+mod sink;
+mod spotify;
+pub mod status;
+pub mod streamdeck;
+mod telemetry;
+pub mod template;
+mod terminal;
+mod textfile;
+mod tray;
+mod udp;
+mod webhook;
@@ -0,0 +1,72 @@
+use crate::casing::FieldCase;
+use crate::server::{self, Client, ServerState};
+use crate::status::StatusSnapshot;
+use tokio::io::{AsyncWrite, AsyncWriteExt};
+use tokio::net::TcpListener;
+use tokio::sync::watch;
+
+/// Writes the active player's status to every client as a line of JSON, right away and then whenever it changes.
+pub async fn run(listener: TcpListener, state: ServerState) {
+    loop {
+        let (stream, addr) = match listener.accept().await {
+            Ok(accepted) => accepted,
+            Err(e) => {
+                server::accept_failed(e).await;
+                continue;
+            }
+        };
+
+        let state = state.clone();
+        let client = Client::new(addr.to_string());
+        tokio::spawn(async move {
+            let _slot = match &state.client_slots {
+                Some(slots) => match slots.clone().try_acquire_owned() {
+                    Ok(slot) => Some(slot),
+                    Err(_) => {
+                        log::warn!(
+                            client_id = client.id, client:% = client.address;
+                            "Too many clients connected, turning {client} away."
+                        );
+                        return;
+                    }
+                },
+                None => None,
+            };
+
+            let _demand = state.demand.hold().await;
+
+            log::debug!(
+                client_id = client.id, client:% = client.address;
+                "{client} connected for JSON lines."
+            );
+            write(stream, state.status_rx, state.json_case).await;
+            log::debug!(client_id = client.id, client:% = client.address; "{client} disconnected.");
+        });
+    }
+}
+
+/// Writes the active player's status as a line of JSON with its keys in `case`, right away and then whenever it changes, until writing fails.
+pub async fn write<W: AsyncWrite + Unpin>(
+    mut writer: W,
+    mut status_rx: watch::Receiver<StatusSnapshot>,
+    case: FieldCase,
+) {
+    let mut last_sent = None;
+
+    loop {
+        // Other players changing wake this up too, only send actual changes.
+        let mut status = case.to_string(&status_rx.borrow_and_update().active());
+        if last_sent.as_ref() != Some(&status) {
+            last_sent = Some(status.clone());
+
+            status.push('\n');
+            if writer.write_all(status.as_bytes()).await.is_err() || writer.flush().await.is_err() {
+                return;
+            }
+        }
+
+        if status_rx.changed().await.is_err() {
+            return;
+        }
+    }
+}
@@ -0,0 +1,207 @@
+use crate::scrobble::{self, Scrobble, Service};
+use crate::sink::{Sink, SinkContext};
+use crate::status::StatusSnapshot;
+use futures_util::future::BoxFuture;
+use serde::{Deserialize, Serialize};
+use std::path::PathBuf;
+use std::time::Duration;
+use tokio::sync::watch;
+
+pub const DEFAULT_API_URL: &str = "https://api.listenbrainz.org";
+
+const HTTP_TIMEOUT: Duration = Duration::from_secs(15);
+
+/// Settings for --listenbrainz-token.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct ListenbrainzOptions {
+    pub api_url: String,
+    /// The user token from https://listenbrainz.org/settings/.
+    pub token: String,
+    /// Where listens that couldn't be sent yet are kept.
+    pub queue_file: PathBuf,
+}
+
+#[derive(Serialize)]
+struct Submission<'a> {
+    listen_type: &'static str,
+    payload: Vec<Listen<'a>>,
+}
+
+#[derive(Serialize)]
+struct Listen<'a> {
+    /// Left out for `playing_now`.
+    #[serde(skip_serializing_if = "Option::is_none")]
+    listened_at: Option<u64>,
+    track_metadata: TrackMetadata<'a>,
+}
+
+#[derive(Serialize)]
+struct TrackMetadata<'a> {
+    artist_name: &'a str,
+    track_name: &'a str,
+    #[serde(skip_serializing_if = "str::is_empty")]
+    release_name: &'a str,
+    additional_info: AdditionalInfo<'a>,
+}
+
+#[derive(Serialize)]
+struct AdditionalInfo<'a> {
+    #[serde(skip_serializing_if = "Option::is_none")]
+    duration_ms: Option<u64>,
+    #[serde(skip_serializing_if = "str::is_empty")]
+    media_player: &'a str,
+    submission_client: &'static str,
+    submission_client_version: &'static str,
+}
+
+#[derive(Deserialize)]
+struct TokenValidation {
+    #[serde(default)]
+    valid: bool,
+    #[serde(default)]
+    user_name: String,
+}
+
+fn listen(scrobble: &Scrobble, listened_at: Option<u64>) -> Listen<'_> {
+    Listen {
+        listened_at,
+        track_metadata: TrackMetadata {
+            artist_name: &scrobble.artist,
+            track_name: &scrobble.track,
+            release_name: &scrobble.album,
+            additional_info: AdditionalInfo {
+                duration_ms: (scrobble.duration > 0).then_some(scrobble.duration * 1000),
+                media_player: &scrobble.player,
+                submission_client: env!("CARGO_PKG_NAME"),
+                submission_client_version: env!("CARGO_PKG_VERSION"),
+            },
+        },
+    }
+}
+
+struct Listenbrainz {
+    options: ListenbrainzOptions,
+    agent: ureq::Agent,
+}
+
+impl Listenbrainz {
+    fn new(options: ListenbrainzOptions) -> Self {
+        Self {
+            options,
+            agent: ureq::Agent::config_builder()
+                .http_status_as_error(false)
+                .timeout_global(Some(HTTP_TIMEOUT))
+                .build()
+                .into(),
+        }
+    }
+
+    fn url(&self, path: &str) -> String {
+        format!("{}/1/{path}", self.options.api_url.trim_end_matches('/'))
+    }
+
+    /// Sends listens, sorting failures by whether they're worth retrying.
+    fn post(&self, submission: &Submission) -> Result<(), scrobble::Error> {
+        let mut response = self
+            .agent
+            .post(self.url("submit-listens"))
+            .header("Authorization", format!("Token {}", self.options.token))
+            .header("Content-Type", "application/json")
+            .send(serde_json::to_string(submission).unwrap())
+            .map_err(|e| scrobble::Error::Unavailable(e.to_string()))?;
+
+        let status = response.status().as_u16();
+        if status == 200 {
+            return Ok(());
+        }
+
+        let body = response.body_mut().read_to_string().unwrap_or_default();
+        let e = format!("{status} {body}");
+        Err(match status {
+            401 => scrobble::Error::Unauthorized(format!("{e}, check --listenbrainz-token")),
+            400 => scrobble::Error::Rejected(e),
+            _ => scrobble::Error::Unavailable(e),
+        })
+    }
+
+    /// Checks the token, returning the user's name.
+    fn validate_token(&self) -> Result<String, String> {
+        let validation = self
+            .agent
+            .get(self.url("validate-token"))
+            .header("Authorization", format!("Token {}", self.options.token))
+            .call()
+            .and_then(|mut response| response.body_mut().read_to_string())
+            .map_err(|e| e.to_string())
+            .and_then(|body| {
+                serde_json::from_str::<TokenValidation>(&body).map_err(|e| e.to_string())
+            })?;
+
+        match validation.valid {
+            true => Ok(validation.user_name),
+            false => Err(String::from("the token isn't valid")),
+        }
+    }
+}
+
+impl Service for Listenbrainz {
+    const NAME: &'static str = "ListenBrainz";
+    const BATCH_SIZE: usize = 100;
+    const MAX_AGE: Option<Duration> = None;
+
+    fn now_playing(&self, track: &Scrobble) -> Result<(), scrobble::Error> {
+        self.post(&Submission {
+            listen_type: "playing_now",
+            payload: vec![listen(track, None)],
+        })
+    }
+
+    fn submit(&self, scrobbles: &[Scrobble]) -> Result<(), scrobble::Error> {
+        self.post(&Submission {
+            listen_type: if scrobbles.len() == 1 {
+                "single"
+            } else {
+                "import"
+            },
+            payload: scrobbles
+                .iter()
+                .map(|scrobble| listen(scrobble, Some(scrobble.timestamp)))
+                .collect(),
+        })
+    }
+}
+
+/// Submits the active player's tracks to ListenBrainz, see [`scrobble::run`].
+pub async fn run(options: ListenbrainzOptions, status_rx: watch::Receiver<StatusSnapshot>) {
+    let queue_file = options.queue_file.clone();
+    let service = Listenbrainz::new(options);
+
+    let Ok((service, validation)) = tokio::task::spawn_blocking(move || {
+        let validation = service.validate_token();
+        (service, validation)
+    })
+    .await
+    .inspect_err(|e| {
+        log::error!("Could not check the ListenBrainz token, not submitting listens: {e}")
+    }) else {
+        return;
+    };
+    match validation {
+        Ok(user_name) => log::info!("Submitting listens to ListenBrainz as {user_name}."),
+        Err(e) => log::warn!(
+            "Could not check the ListenBrainz token, listens are queued until it works: {e}"
+        ),
+    }
+
+    scrobble::run(service, queue_file, status_rx).await
+}
+
+impl Sink for ListenbrainzOptions {
+    fn name(&self) -> &'static str {
+        "listenbrainz"
+    }
+
+    fn run(&self, context: &SinkContext) -> BoxFuture<'static, ()> {
+        Box::pin(run(self.clone(), context.status_rx.clone()))
+    }
+}
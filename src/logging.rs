@@ -0,0 +1,194 @@
+use env_logger::fmt::Formatter;
+use log::kv::{self, Key, Value, VisitSource};
+use log::{LevelFilter, Log, Metadata, Record};
+use serde::Serialize;
+use serde_json::Map;
+use std::collections::VecDeque;
+use std::io::{self, Write};
+use std::sync::{Mutex, PoisonError};
+use std::time::{SystemTime, UNIX_EPOCH};
+
+/// How log lines are written.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, clap::ValueEnum)]
+pub enum LogFormat {
+    /// Human readable lines.
+    #[default]
+    Text,
+    /// A JSON object per line, with `timestamp`, `level`, `target` and `message`, plus `client` and `player` where they apply.
+    Json,
+}
+
+/// Modules finding players and reading their status, for --player-log-level.
+const PLAYER_MODULES: [&str; 8] = [
+    "artwork", "bus", "control", "demand", "demo", "player", "replay", "status",
+];
+/// Modules serving clients and sending outputs, for --server-log-level.
+const SERVER_MODULES: [&str; 29] = [
+    "discord",
+    "enrich",
+    "event",
+    "fifo",
+    "grpc",
+    "historydb",
+    "hook",
+    "i3bar",
+    "lastfm",
+    "laststatus",
+    "lines",
+    "listenbrainz",
+    "lyrics",
+    "mdns",
+    "mqtt",
+    "musicbrainz",
+    "notify",
+    "osc",
+    "scrobble",
+    "scrobblerlog",
+    "server",
+    "sink",
+    "spotify",
+    "streamdeck",
+    "telemetry",
+    "textfile",
+    "tray",
+    "udp",
+    "webhook",
+];
+
+/// `level` moved up by `verbose` steps and down by `quiet` steps.
+pub fn shift(level: LevelFilter, verbose: u8, quiet: u8) -> LevelFilter {
+    let index = (level as usize + verbose as usize).saturating_sub(quiet as usize);
+    LevelFilter::iter().nth(index).unwrap_or(LevelFilter::Trace)
+}
+
+/// Filter directives logging this crate at `level`, with the player and server sides overridden if given. Dependencies never log more than info, their debug logs drown ours.
+pub fn filters(
+    level: LevelFilter,
+    player: Option<LevelFilter>,
+    server: Option<LevelFilter>,
+) -> String {
+    let mut filters = format!(
+        "{},{}={level}",
+        level.min(LevelFilter::Info),
+        env!("CARGO_CRATE_NAME")
+    );
+
+    let sides = [(player, &PLAYER_MODULES[..]), (server, &SERVER_MODULES[..])];
+    for (side_level, modules) in sides {
+        let Some(side_level) = side_level else {
+            continue;
+        };
+
+        for module in modules {
+            filters.push_str(&format!(
+                ",{}::{module}={side_level}",
+                env!("CARGO_CRATE_NAME")
+            ));
+        }
+    }
+
+    filters
+}
+
+/// Collects a record's key-values into the JSON object.
+struct Fields<'a>(&'a mut Map<String, serde_json::Value>);
+
+impl<'kvs> VisitSource<'kvs> for Fields<'_> {
+    fn visit_pair(&mut self, key: Key<'kvs>, value: Value<'kvs>) -> Result<(), kv::Error> {
+        self.0.insert(key.to_string(), value.to_string().into());
+        Ok(())
+    }
+}
+
+/// Writes a record as a line of JSON, for log shippers like Promtail or Filebeat.
+pub fn format_json(buf: &mut Formatter, record: &Record) -> io::Result<()> {
+    let mut line = Map::new();
+    line.insert(
+        String::from("timestamp"),
+        buf.timestamp_millis().to_string().into(),
+    );
+    line.insert(String::from("level"), record.level().as_str().into());
+    line.insert(String::from("target"), record.target().into());
+    line.insert(String::from("message"), record.args().to_string().into());
+    // Visiting our own fields can't fail.
+    let _ = record.key_values().visit(&mut Fields(&mut line));
+
+    serde_json::to_writer(&mut *buf, &line)?;
+    writeln!(buf)
+}
+
+/// How many of the last log lines are kept for the admin page.
+const RECENT_LINES: usize = 100;
+
+static RECENT: Mutex<VecDeque<LogLine>> = Mutex::new(VecDeque::new());
+
+/// A line that was logged, as the admin page shows it.
+#[derive(Debug, Clone, Serialize)]
+#[serde(rename_all = "camelCase")]
+pub struct LogLine {
+    /// In milliseconds since the Unix epoch.
+    pub time: u64,
+    pub level: &'static str,
+    pub target: String,
+    pub message: String,
+}
+
+/// Logs like the logger it wraps, keeping the last lines it wrote for [`recent_lines`].
+struct Recorder(env_logger::Logger);
+
+impl Log for Recorder {
+    fn enabled(&self, metadata: &Metadata) -> bool {
+        self.0.enabled(metadata)
+    }
+
+    fn log(&self, record: &Record) {
+        if !self.0.matches(record) {
+            return;
+        }
+
+        let line = LogLine {
+            time: SystemTime::now()
+                .duration_since(UNIX_EPOCH)
+                .unwrap_or_default()
+                .as_millis() as u64,
+            level: record.level().as_str(),
+            target: record.target().to_string(),
+            message: record.args().to_string(),
+        };
+        {
+            let mut recent = RECENT.lock().unwrap_or_else(PoisonError::into_inner);
+            if recent.len() == RECENT_LINES {
+                recent.pop_front();
+            }
+            recent.push_back(line);
+        }
+
+        self.0.log(record);
+    }
+
+    fn flush(&self) {
+        self.0.flush();
+    }
+}
+
+/// Installs the logger built by `builder`, keeping the last lines it writes for the admin page if `record`.
+pub fn init(mut builder: env_logger::Builder, record: bool) {
+    if !record {
+        builder.init();
+        return;
+    }
+
+    let logger = builder.build();
+    log::set_max_level(logger.filter());
+    log::set_boxed_logger(Box::new(Recorder(logger))).expect("the logger is only set once");
+}
+
+/// The last lines logged, oldest first. Empty unless [`init`] was asked to keep them.
+pub fn recent_lines() -> Vec<LogLine> {
+    RECENT
+        .lock()
+        .unwrap_or_else(PoisonError::into_inner)
+        .iter()
+        .cloned()
+        .collect()
+}
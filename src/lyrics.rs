@@ -0,0 +1,324 @@
+use crate::status::{send_changed, PlaybackState, PlayerStatus, StatusSnapshot};
+use serde::{Deserialize, Serialize};
+use std::collections::hash_map::DefaultHasher;
+use std::fs;
+use std::hash::{Hash, Hasher};
+use std::io;
+use std::path::{Path, PathBuf};
+use std::time::{Duration, SystemTime};
+use tokio::sync::watch;
+use tokio::task::JoinHandle;
+use tokio::time::Instant;
+
+pub const DEFAULT_API_URL: &str = "https://lrclib.net";
+
+const HTTP_TIMEOUT: Duration = Duration::from_secs(15);
+/// How long to wait before looking up a track's lyrics again after it failed.
+const RETRY_DELAY: Duration = Duration::from_secs(30);
+/// How long a track without lyrics is remembered, they might be added in the meantime.
+const NOT_FOUND_TTL: Duration = Duration::from_secs(7 * 24 * 60 * 60);
+
+/// Settings for --lyrics.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct LyricsOptions {
+    pub api_url: String,
+    /// Where looked up lyrics are kept, if anywhere.
+    pub cache_dir: Option<PathBuf>,
+}
+
+/// A line of synced lyrics.
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "camelCase")]
+pub struct LyricLine {
+    /// When the line starts, in microseconds like the position.
+    pub time: u64,
+    /// Empty for instrumental breaks.
+    pub text: String,
+}
+
+/// The synced lyric lines around the active player's position.
+#[derive(Debug, Clone, Default, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "camelCase")]
+pub struct LyricsStatus {
+    /// The line being sung, `None` before the first one.
+    pub current: Option<LyricLine>,
+    /// The line after it, `None` after the last one.
+    pub next: Option<LyricLine>,
+}
+
+/// What lyrics are looked up by.
+#[derive(Debug, Clone, PartialEq, Eq, Hash)]
+struct Track {
+    artist: String,
+    title: String,
+    album: String,
+    /// In seconds, 0 if unknown.
+    duration: u64,
+}
+
+impl Track {
+    fn from_status(status: &PlayerStatus) -> Option<Self> {
+        (!status.metadata.title.is_empty()).then(|| Self {
+            artist: status.metadata.artist.clone(),
+            title: status.metadata.title.clone(),
+            album: status.metadata.album.clone(),
+            duration: Duration::from_micros(status.metadata.length).as_secs(),
+        })
+    }
+}
+
+#[derive(Deserialize)]
+#[serde(rename_all = "camelCase")]
+struct Record {
+    #[serde(default)]
+    synced_lyrics: Option<String>,
+}
+
+/// Parses an LRC timestamp like `01:23.45`, in microseconds.
+fn parse_timestamp(stamp: &str) -> Option<u64> {
+    let (minutes, seconds) = stamp.split_once(':')?;
+    let minutes = minutes.parse::<u64>().ok()?;
+    let seconds = seconds
+        .parse::<f64>()
+        .ok()
+        .filter(|seconds| *seconds >= 0.0)?;
+
+    Some(minutes * 60_000_000 + (seconds * 1_000_000.0) as u64)
+}
+
+/// Reads the timed lines of LRC lyrics, in order. Lines may carry several timestamps, tags like `[ar:…]` are skipped.
+fn parse_lrc(lrc: &str) -> Vec<LyricLine> {
+    let mut lines = Vec::new();
+
+    for line in lrc.lines() {
+        let mut rest = line.trim();
+        let mut times = Vec::new();
+        while let Some((time, after)) = rest
+            .strip_prefix('[')
+            .and_then(|tag| tag.split_once(']'))
+            .and_then(|(stamp, after)| Some((parse_timestamp(stamp)?, after)))
+        {
+            times.push(time);
+            rest = after;
+        }
+
+        let text = rest.trim();
+        lines.extend(times.into_iter().map(|time| LyricLine {
+            time,
+            text: text.to_string(),
+        }));
+    }
+
+    lines.sort_by_key(|line| line.time);
+    lines
+}
+
+/// The lines around `position`.
+fn lyrics_at(lines: &[LyricLine], position: u64) -> LyricsStatus {
+    let index = lines.partition_point(|line| line.time <= position);
+
+    LyricsStatus {
+        current: index.checked_sub(1).map(|index| lines[index].clone()),
+        next: lines.get(index).cloned(),
+    }
+}
+
+/// Looks up a track on LRCLIB, returning its synced lyrics if it has any. Blocks.
+fn fetch(agent: &ureq::Agent, api_url: &str, track: &Track) -> Result<Option<String>, String> {
+    let api_url = api_url.trim_end_matches('/');
+
+    // Exact lookups need the length, searching does without.
+    let request = if track.duration > 0 {
+        agent
+            .get(format!("{api_url}/api/get"))
+            .query("album_name", &track.album)
+            .query("duration", track.duration.to_string())
+    } else {
+        agent.get(format!("{api_url}/api/search"))
+    };
+
+    let mut response = request
+        .query("artist_name", &track.artist)
+        .query("track_name", &track.title)
+        .header(
+            "User-Agent",
+            concat!(
+                env!("CARGO_PKG_NAME"),
+                "/",
+                env!("CARGO_PKG_VERSION"),
+                " (",
+                env!("CARGO_PKG_REPOSITORY"),
+                ")"
+            ),
+        )
+        .call()
+        .map_err(|e| e.to_string())?;
+
+    match response.status().as_u16() {
+        200 => {}
+        404 => return Ok(None),
+        status => return Err(format!("LRCLIB answered {status}")),
+    }
+
+    let body = response
+        .body_mut()
+        .read_to_string()
+        .map_err(|e| e.to_string())?;
+    let synced = if track.duration > 0 {
+        serde_json::from_str::<Record>(&body)
+            .map_err(|e| e.to_string())?
+            .synced_lyrics
+    } else {
+        serde_json::from_str::<Vec<Record>>(&body)
+            .map_err(|e| e.to_string())?
+            .into_iter()
+            .find_map(|record| record.synced_lyrics)
+    };
+
+    Ok(synced.filter(|lrc| !lrc.trim().is_empty()))
+}
+
+/// Where a track's lyrics are cached. Tracks without any get an empty file.
+fn cache_path(options: &LyricsOptions, track: &Track) -> Option<PathBuf> {
+    let mut hasher = DefaultHasher::new();
+    track.hash(&mut hasher);
+
+    Some(
+        options
+            .cache_dir
+            .as_ref()?
+            .join("lyrics")
+            .join(format!("{:016x}.lrc", hasher.finish())),
+    )
+}
+
+fn read_cache(path: &Path) -> Option<Option<String>> {
+    let lrc = fs::read_to_string(path).ok()?;
+    if !lrc.is_empty() {
+        return Some(Some(lrc));
+    }
+
+    let age = fs::metadata(path)
+        .and_then(|metadata| metadata.modified())
+        .ok()
+        .and_then(|modified| SystemTime::now().duration_since(modified).ok())?;
+    (age < NOT_FOUND_TTL).then_some(None)
+}
+
+fn write_cache(path: &Path, lrc: Option<&str>) -> io::Result<()> {
+    if let Some(dir) = path.parent() {
+        fs::create_dir_all(dir)?;
+    }
+    fs::write(path, lrc.unwrap_or_default())
+}
+
+/// A track's synced lyrics, from the cache or LRCLIB. Blocks.
+fn load(
+    agent: &ureq::Agent,
+    options: &LyricsOptions,
+    track: &Track,
+) -> Result<Vec<LyricLine>, String> {
+    let cached = cache_path(options, track);
+    if let Some(lrc) = cached.as_ref().and_then(|cached| read_cache(cached)) {
+        return Ok(lrc.as_deref().map(parse_lrc).unwrap_or_default());
+    }
+
+    let lrc = fetch(agent, &options.api_url, track)?;
+    if let Some(cached) = &cached {
+        if let Err(e) = write_cache(cached, lrc.as_deref()) {
+            log::debug!("Could not cache lyrics in {}: {e}", cached.display());
+        }
+    }
+
+    Ok(lrc.as_deref().map(parse_lrc).unwrap_or_default())
+}
+
+/// Forwards every status from `raw_rx` to `status_tx`, adding the active player's current and next lyric lines. Lyrics are looked up on LRCLIB when the track changes, and a status is also sent whenever the next line starts between updates.
+pub async fn run(
+    options: LyricsOptions,
+    mut raw_rx: watch::Receiver<StatusSnapshot>,
+    status_tx: watch::Sender<StatusSnapshot>,
+) {
+    let agent: ureq::Agent = ureq::Agent::config_builder()
+        .http_status_as_error(false)
+        .timeout_global(Some(HTTP_TIMEOUT))
+        .build()
+        .into();
+
+    let mut snapshot = raw_rx.borrow_and_update().clone();
+    let mut track: Option<Track> = None;
+    let mut lines: Vec<LyricLine> = Vec::new();
+    let mut loading: Option<JoinHandle<Result<Vec<LyricLine>, String>>> = None;
+    let mut retry_at: Option<Instant> = None;
+
+    loop {
+        let active = snapshot.active().cloned();
+        let wanted = active.as_ref().and_then(Track::from_status);
+        if wanted != track || retry_at.is_some_and(|at| at <= Instant::now()) {
+            track = wanted;
+            lines.clear();
+            retry_at = None;
+            loading = track.clone().map(|track| {
+                let (agent, options) = (agent.clone(), options.clone());
+                tokio::task::spawn_blocking(move || load(&agent, &options, &track))
+            });
+        }
+
+        // Statuses are read every update tick, the position has moved on since.
+        let position = active.as_ref().map(PlayerStatus::position_now);
+
+        let mut forwarded = snapshot.clone();
+        let mut next_line_at = None;
+        if let (Some(position), false) = (position, lines.is_empty()) {
+            let lyrics = lyrics_at(&lines, position);
+            if active
+                .as_ref()
+                .is_some_and(|status| status.playback_state == PlaybackState::Playing)
+            {
+                next_line_at = lyrics.next.as_ref().map(|next| {
+                    Instant::now() + Duration::from_micros(next.time.saturating_sub(position))
+                });
+            }
+
+            if let Some(status) = forwarded
+                .active
+                .as_ref()
+                .and_then(|active| forwarded.players.get_mut(active))
+            {
+                status.lyrics = Some(lyrics);
+            }
+        }
+        if !send_changed(&status_tx, forwarded) {
+            return;
+        }
+
+        tokio::select! {
+            changed = raw_rx.changed() => {
+                if changed.is_err() {
+                    return;
+                }
+                snapshot = raw_rx.borrow_and_update().clone();
+            }
+            result = async { loading.as_mut().unwrap().await }, if loading.is_some() => {
+                loading = None;
+                match result.unwrap() {
+                    Ok(loaded) => {
+                        if let Some(track) = track.as_ref().filter(|_| loaded.is_empty()) {
+                            log::debug!("No synced lyrics found for {} – {}.", track.artist, track.title);
+                        }
+                        lines = loaded;
+                    }
+                    Err(e) => {
+                        log::warn!(
+                            "Could not look up lyrics, retrying in {} seconds: {e}",
+                            RETRY_DELAY.as_secs()
+                        );
+                        retry_at = Some(Instant::now() + RETRY_DELAY);
+                    }
+                }
+            }
+            () = async { tokio::time::sleep_until(next_line_at.unwrap()).await }, if next_line_at.is_some() => {}
+            () = async { tokio::time::sleep_until(retry_at.unwrap()).await }, if retry_at.is_some() => {}
+        }
+    }
+}
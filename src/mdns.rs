@@ -0,0 +1,71 @@
+use mdns_sd::{ServiceDaemon, ServiceInfo};
+use std::collections::HashMap;
+use std::fs;
+use std::net::SocketAddr;
+
+const SERVICE_TYPE: &str = "_mpris-nowplaying._tcp.local.";
+
+fn hostname() -> String {
+    fs::read_to_string("/proc/sys/kernel/hostname")
+        .map(|v| v.trim().to_string())
+        .ok()
+        .filter(|v| !v.is_empty())
+        .unwrap_or_else(|| String::from("mpris-nowplaying"))
+}
+
+/// Advertises every non-loopback address on the local network. The advertisement stops when the daemon is dropped.
+pub fn advertise(
+    addrs: &[SocketAddr],
+    tls: bool,
+    base_path: &str,
+) -> Result<ServiceDaemon, mdns_sd::Error> {
+    let daemon = ServiceDaemon::new()?;
+    let hostname = hostname();
+
+    let properties = HashMap::from([
+        (
+            String::from("tls"),
+            String::from(if tls { "1" } else { "0" }),
+        ),
+        (String::from("path"), format!("{base_path}/")),
+        (String::from("overlay"), format!("{base_path}/overlay")),
+    ]);
+
+    for addr in addrs {
+        if addr.ip().is_loopback() {
+            log::warn!(
+                "Not advertising {addr} over mDNS, it can only be reached from this machine."
+            );
+            continue;
+        }
+
+        let instance = format!("{hostname} ({})", addr.port());
+        let host = format!("{hostname}.local.");
+
+        let service = if addr.ip().is_unspecified() {
+            ServiceInfo::new(
+                SERVICE_TYPE,
+                &instance,
+                &host,
+                (),
+                addr.port(),
+                properties.clone(),
+            )?
+            .enable_addr_auto()
+        } else {
+            ServiceInfo::new(
+                SERVICE_TYPE,
+                &instance,
+                &host,
+                addr.ip(),
+                addr.port(),
+                properties.clone(),
+            )?
+        };
+
+        daemon.register(service)?;
+        log::info!("Advertising {addr} over mDNS as \"{instance}\".");
+    }
+
+    Ok(daemon)
+}
@@ -0,0 +1,409 @@
+use crate::artwork::{self, ArtworkInfo, ArtworkOrigin};
+use crate::enrich::{self, Source};
+use crate::status::{PlayerStatus, StatusSnapshot};
+use serde::{Deserialize, Serialize};
+use std::fs;
+use std::path::{Path, PathBuf};
+use std::sync::{Mutex, PoisonError};
+use std::time::{Duration, Instant, SystemTime};
+use tokio::sync::watch;
+
+pub const DEFAULT_API_URL: &str = "https://musicbrainz.org";
+
+const HTTP_TIMEOUT: Duration = Duration::from_secs(15);
+/// MusicBrainz allows one request per second.
+const REQUEST_INTERVAL: Duration = Duration::from_secs(1);
+/// Search results scoring lower than this (out of 100) aren't trusted.
+const MIN_SCORE: u32 = 90;
+/// How far a recording's length may be off from the track's to still match.
+const LENGTH_TOLERANCE: Duration = Duration::from_secs(5);
+/// MusicBrainz asks clients to identify themselves.
+const USER_AGENT: &str = concat!(
+    env!("CARGO_PKG_NAME"),
+    "/",
+    env!("CARGO_PKG_VERSION"),
+    " (",
+    env!("CARGO_PKG_REPOSITORY"),
+    ")"
+);
+/// The Cover Art Archive, serving the front covers of releases and release groups.
+const COVER_ART_URL: &str = "https://coverartarchive.org";
+/// How long a release without a cover is remembered, one might be uploaded in the meantime.
+const NO_COVER_TTL: Duration = Duration::from_secs(7 * 24 * 60 * 60);
+
+/// Settings for --musicbrainz.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct MusicbrainzOptions {
+    pub api_url: String,
+    /// Add the release's cover from the Cover Art Archive to tracks without artwork, with --cover-art-archive.
+    pub cover_art_archive: bool,
+    /// Where downloaded covers are kept. Without one, clients are sent a link to the Cover Art Archive instead.
+    pub cache_dir: Option<PathBuf>,
+}
+
+/// The MusicBrainz ids a track was matched to.
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "camelCase")]
+pub struct MusicbrainzIds {
+    pub recording_id: String,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub release_id: Option<String>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub release_group_id: Option<String>,
+    pub artist_ids: Vec<String>,
+}
+
+/// What a track was matched to, filled into its status.
+#[derive(Debug, Clone, PartialEq, Eq)]
+struct Match {
+    ids: MusicbrainzIds,
+    artist: String,
+    album: String,
+    /// In microseconds, 0 if unknown.
+    length: u64,
+    /// The artwork to add if the track has none, from the Cover Art Archive.
+    cover: Option<String>,
+}
+
+/// What tracks are looked up by.
+#[derive(Debug, Clone, PartialEq, Eq, Hash)]
+struct Track {
+    title: String,
+    artist: String,
+    album: String,
+    /// In microseconds, 0 if unknown.
+    length: u64,
+    /// Whether to look for a cover too, when the player has none and --cover-art-archive is set.
+    cover: bool,
+}
+
+impl Track {
+    fn from_status(status: &PlayerStatus, options: &MusicbrainzOptions) -> Option<Self> {
+        (!status.metadata.title.is_empty()).then(|| Self {
+            title: status.metadata.title.clone(),
+            artist: status.metadata.artist.clone(),
+            album: status.metadata.album.clone(),
+            length: status.metadata.length,
+            cover: options.cover_art_archive && status.metadata.artwork.is_empty(),
+        })
+    }
+}
+
+#[derive(Deserialize)]
+struct Search {
+    #[serde(default)]
+    recordings: Vec<Recording>,
+}
+
+#[derive(Deserialize)]
+struct Recording {
+    id: String,
+    #[serde(default)]
+    score: u32,
+    /// In milliseconds.
+    #[serde(default)]
+    length: Option<u64>,
+    #[serde(default, rename = "artist-credit")]
+    artist_credit: Vec<Credit>,
+    #[serde(default)]
+    releases: Vec<Release>,
+}
+
+#[derive(Deserialize)]
+struct Credit {
+    name: String,
+    #[serde(default)]
+    joinphrase: String,
+    artist: Artist,
+}
+
+#[derive(Deserialize)]
+struct Artist {
+    id: String,
+}
+
+#[derive(Deserialize)]
+struct Release {
+    id: String,
+    title: String,
+    #[serde(default, rename = "release-group")]
+    release_group: Option<ReleaseGroup>,
+}
+
+#[derive(Deserialize)]
+struct ReleaseGroup {
+    id: String,
+}
+
+/// Quotes a value as a Lucene phrase for the search query.
+fn phrase(value: &str) -> String {
+    format!("\"{}\"", value.replace('\\', "\\\\").replace('"', "\\\""))
+}
+
+/// Picks the best recording, if any is good enough: the highest scoring one of about the track's length.
+fn best_match(track: &Track, recordings: Vec<Recording>) -> Option<Match> {
+    let recording = recordings.into_iter().find(|recording| {
+        recording.score >= MIN_SCORE
+            && match (track.length, recording.length) {
+                (0, _) | (_, None) => true,
+                (length, Some(recording_length)) => {
+                    Duration::from_micros(length).abs_diff(Duration::from_millis(recording_length))
+                        <= LENGTH_TOLERANCE
+                }
+            }
+    })?;
+
+    let release = recording
+        .releases
+        .iter()
+        .find(|release| release.title.eq_ignore_ascii_case(&track.album))
+        .or(recording.releases.first());
+
+    Some(Match {
+        ids: MusicbrainzIds {
+            recording_id: recording.id,
+            release_id: release.map(|release| release.id.clone()),
+            release_group_id: release
+                .and_then(|release| release.release_group.as_ref())
+                .map(|group| group.id.clone()),
+            artist_ids: recording
+                .artist_credit
+                .iter()
+                .map(|credit| credit.artist.id.clone())
+                .collect(),
+        },
+        artist: recording
+            .artist_credit
+            .iter()
+            .map(|credit| format!("{}{}", credit.name, credit.joinphrase))
+            .collect(),
+        album: release
+            .map(|release| release.title.clone())
+            .unwrap_or_default(),
+        length: recording.length.unwrap_or_default() * 1000,
+        cover: None,
+    })
+}
+
+/// Searches MusicBrainz for the track's recording. Blocks.
+fn lookup(agent: &ureq::Agent, api_url: &str, track: &Track) -> Result<Option<Match>, String> {
+    let mut query = format!("recording:{}", phrase(&track.title));
+    if !track.artist.is_empty() {
+        query.push_str(&format!(" AND artist:{}", phrase(&track.artist)));
+    }
+    if !track.album.is_empty() {
+        query.push_str(&format!(" AND release:{}", phrase(&track.album)));
+    }
+
+    let mut response = agent
+        .get(format!("{}/ws/2/recording", api_url.trim_end_matches('/')))
+        .query("query", query)
+        .query("fmt", "json")
+        .query("limit", "10")
+        .header("User-Agent", USER_AGENT)
+        .call()
+        .map_err(|e| e.to_string())?;
+
+    let status = response.status().as_u16();
+    let body = response
+        .body_mut()
+        .read_to_string()
+        .map_err(|e| e.to_string())?;
+    if status != 200 {
+        return Err(format!("MusicBrainz answered {status} {body}"));
+    }
+
+    let search = serde_json::from_str::<Search>(&body).map_err(|e| e.to_string())?;
+    Ok(best_match(track, search.recordings))
+}
+
+/// Downloads the 500px front cover of a release, or of its release group if the release has none. `None` if neither has one. Blocks.
+fn fetch_cover(agent: &ureq::Agent, ids: &MusicbrainzIds) -> Result<Option<Vec<u8>>, String> {
+    let releases = [
+        ids.release_id.as_ref().map(|id| ("release", id)),
+        ids.release_group_id
+            .as_ref()
+            .map(|id| ("release-group", id)),
+    ];
+
+    for (kind, id) in releases.into_iter().flatten() {
+        let mut response = agent
+            .get(format!("{COVER_ART_URL}/{kind}/{id}/front-500"))
+            .header("User-Agent", USER_AGENT)
+            .call()
+            .map_err(|e| e.to_string())?;
+
+        match response.status().as_u16() {
+            200 => {}
+            404 => continue,
+            status => return Err(format!("the Cover Art Archive answered {status}")),
+        }
+        return response
+            .body_mut()
+            .read_to_vec()
+            .map(Some)
+            .map_err(|e| e.to_string());
+    }
+
+    Ok(None)
+}
+
+/// Where a release's cover is cached. Releases without one get an empty file.
+fn cover_path(dir: &Path, release_id: &str) -> Option<PathBuf> {
+    // Ids come from the server, and end up in a path.
+    if release_id.is_empty()
+        || !release_id
+            .chars()
+            .all(|c| c.is_ascii_alphanumeric() || c == '-')
+    {
+        return None;
+    }
+
+    Some(
+        dir.join("cover-art-archive")
+            .join(format!("{release_id}.jpg")),
+    )
+}
+
+/// Whether the cached cover is there, `None` if the release has to be looked up (again).
+fn read_cover_cache(path: &Path) -> Option<bool> {
+    let metadata = fs::metadata(path).ok()?;
+    if metadata.len() > 0 {
+        return Some(true);
+    }
+
+    let age = metadata
+        .modified()
+        .ok()
+        .and_then(|modified| SystemTime::now().duration_since(modified).ok())?;
+    (age < NO_COVER_TTL).then_some(false)
+}
+
+/// The artwork for a matched release from the Cover Art Archive, downloaded to the cache once. Blocks.
+fn cover(
+    agent: &ureq::Agent,
+    options: &MusicbrainzOptions,
+    ids: &MusicbrainzIds,
+) -> Option<String> {
+    let release_id = ids.release_id.as_ref()?;
+    let Some(dir) = &options.cache_dir else {
+        return Some(format!("{COVER_ART_URL}/release/{release_id}/front-500"));
+    };
+    let cached = cover_path(dir, release_id)?;
+
+    let found = match read_cover_cache(&cached) {
+        Some(found) => found,
+        None => {
+            let data = match fetch_cover(agent, ids) {
+                Ok(data) => data,
+                Err(e) => {
+                    log::warn!("Could not download the cover of release {release_id}: {e}");
+                    return None;
+                }
+            };
+            if let Err(e) = artwork::store(&cached, data.as_deref().unwrap_or_default()) {
+                log::warn!("Could not cache the cover in {}: {e}", cached.display());
+                return None;
+            }
+            data.is_some()
+        }
+    };
+
+    if !found {
+        log::debug!("The Cover Art Archive has no cover for release {release_id}.");
+    }
+    found
+        .then(|| url::Url::from_file_path(&cached).ok())
+        .flatten()
+        .map(String::from)
+}
+
+/// Fills in what the player left out, and adds the ids.
+fn apply(status: &mut PlayerStatus, found: &Match) {
+    let metadata = &mut status.metadata;
+    if metadata.artist.is_empty() {
+        metadata.artist = found.artist.clone();
+    }
+    if metadata.album.is_empty() {
+        metadata.album = found.album.clone();
+    }
+    if metadata.length == 0 {
+        metadata.length = found.length;
+    }
+    if let (true, Some(cover)) = (metadata.artwork.is_empty(), &found.cover) {
+        metadata.artwork.push(ArtworkInfo {
+            src: cover.clone(),
+            origin: ArtworkOrigin::Url,
+            variant: None,
+        });
+    }
+
+    status.musicbrainz = Some(found.ids.clone());
+}
+
+struct Musicbrainz {
+    options: MusicbrainzOptions,
+    agent: ureq::Agent,
+    /// When the last request was sent, to keep to the rate limit.
+    last_request: Mutex<Option<Instant>>,
+}
+
+impl Source for Musicbrainz {
+    type Key = Track;
+    type Found = Match;
+
+    const NAME: &'static str = "MusicBrainz";
+
+    fn key(&self, status: &PlayerStatus) -> Option<Track> {
+        Track::from_status(status, &self.options)
+    }
+
+    fn lookup(&self, track: &Track) -> Result<Option<Match>, String> {
+        let mut last_request = self
+            .last_request
+            .lock()
+            .unwrap_or_else(PoisonError::into_inner);
+        if let Some(wait) = last_request.map(|at| REQUEST_INTERVAL.saturating_sub(at.elapsed())) {
+            std::thread::sleep(wait);
+        }
+        *last_request = Some(Instant::now());
+
+        let mut found = lookup(&self.agent, &self.options.api_url, track)?;
+        drop(last_request);
+        if let Some(found) = &mut found {
+            log::debug!(
+                "Matched {} – {} to MusicBrainz recording {}.",
+                found.artist,
+                track.title,
+                found.ids.recording_id
+            );
+            if track.cover {
+                found.cover = cover(&self.agent, &self.options, &found.ids);
+            }
+        }
+        Ok(found)
+    }
+
+    fn apply(&self, status: &mut PlayerStatus, found: &Match) {
+        apply(status, found);
+    }
+}
+
+/// Matches the active player's track against MusicBrainz when it changes, merging in what was found, see [`enrich::run`].
+pub async fn run(
+    options: MusicbrainzOptions,
+    raw_rx: watch::Receiver<StatusSnapshot>,
+    status_tx: watch::Sender<StatusSnapshot>,
+) {
+    let source = Musicbrainz {
+        options,
+        agent: ureq::Agent::config_builder()
+            .http_status_as_error(false)
+            .timeout_global(Some(HTTP_TIMEOUT))
+            .build()
+            .into(),
+        last_request: Mutex::new(None),
+    };
+
+    enrich::run(source, raw_rx, status_tx).await
+}
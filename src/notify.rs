@@ -0,0 +1,187 @@
+use crate::artwork::{self, ArtworkData, ArtworkOptions};
+use crate::bar;
+use crate::bus::BusAddress;
+use crate::sink::{Sink, SinkContext};
+use crate::status::{PlaybackState, PlayerStatus, StatusSnapshot};
+use dbus::arg::{PropMap, Variant};
+use futures_util::future::BoxFuture;
+use std::time::{Duration, Instant};
+use tokio::sync::watch;
+
+const NOTIFICATIONS_NAME: &str = "org.freedesktop.Notifications";
+const NOTIFICATIONS_PATH: &str = "/org/freedesktop/Notifications";
+const DBUS_TIMEOUT: Duration = Duration::from_secs(5);
+/// Shown when there's no artwork to show.
+const ICON: &str = "audio-x-generic";
+/// Artwork is scaled down to fit this, notifications show it small anyway.
+const THUMBNAIL_SIZE: u32 = 128;
+
+/// The `image-data` hint: width, height, row stride, alpha, bits per sample, channels and the pixels.
+type ImageData = (i32, i32, i32, bool, i32, i32, Vec<u8>);
+
+/// The active player's first artwork, scaled down to a thumbnail. Blocks.
+fn thumbnail(status: &PlayerStatus, options: &ArtworkOptions) -> Option<ImageData> {
+    let artwork = status.metadata.artwork.first()?;
+    // Notification servers don't fetch URLs.
+    let ArtworkData::Binary(data) = artwork::load(artwork, options).ok()? else {
+        return None;
+    };
+
+    let image = image::load_from_memory(&data)
+        .ok()?
+        .thumbnail(THUMBNAIL_SIZE, THUMBNAIL_SIZE)
+        .to_rgba8();
+    let (width, height) = image.dimensions();
+
+    Some((
+        width as i32,
+        height as i32,
+        width as i32 * 4,
+        true,
+        8,
+        4,
+        image.into_raw(),
+    ))
+}
+
+/// Shows a notification for `status`, replacing the one with `replaces_id` if it's still up. Returns the new one's id. Blocks.
+#[tracing::instrument(skip_all, fields(player = status.player.bus_name))]
+fn notify(
+    bus: &BusAddress,
+    replaces_id: u32,
+    status: &PlayerStatus,
+    artwork_options: &ArtworkOptions,
+) -> Result<u32, dbus::Error> {
+    let mut body = bar::escape_markup(&status.metadata.artist);
+    if !status.metadata.album.is_empty() {
+        body.push('\n');
+        body.push_str(&bar::escape_markup(&status.metadata.album));
+    }
+
+    let mut hints = PropMap::new();
+    if let Some(image) = thumbnail(status, artwork_options) {
+        hints.insert(String::from("image-data"), Variant(Box::new(image)));
+    }
+    hints.insert(
+        String::from("category"),
+        Variant(Box::new(String::from("x-mpris-nowplaying.track"))),
+    );
+
+    let connection = bus.connect()?;
+    let proxy = connection.with_proxy(NOTIFICATIONS_NAME, NOTIFICATIONS_PATH, DBUS_TIMEOUT);
+    let (id,): (u32,) = proxy.method_call(
+        NOTIFICATIONS_NAME,
+        "Notify",
+        (
+            env!("CARGO_PKG_NAME"),
+            replaces_id,
+            ICON,
+            status.metadata.title.as_str(),
+            body,
+            Vec::<String>::new(),
+            hints,
+            // Left to the notification server.
+            -1i32,
+        ),
+    )?;
+
+    Ok(id)
+}
+
+/// How often --notify shows notifications at most.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct NotifyOptions {
+    pub interval: Duration,
+}
+
+/// Shows a desktop notification whenever the active player starts playing another track, at most once per `interval`. Skipping through tracks faster than that only shows the one landed on, and each notification replaces the last.
+pub async fn run(
+    interval: Duration,
+    bus: BusAddress,
+    artwork_options: ArtworkOptions,
+    mut status_rx: watch::Receiver<StatusSnapshot>,
+) {
+    let mut shown = None;
+    let mut last_shown: Option<Instant> = None;
+    let mut replaces_id = 0;
+    let mut failing = false;
+
+    loop {
+        let status = status_rx
+            .borrow_and_update()
+            .active()
+            .filter(|status| {
+                status.playback_state == PlaybackState::Playing && !status.metadata.title.is_empty()
+            })
+            .cloned();
+        let track = status.as_ref().map(|status| {
+            (
+                status.player.bus_name.clone(),
+                status.metadata.title.clone(),
+                status.metadata.artist.clone(),
+                status.metadata.album.clone(),
+            )
+        });
+
+        if let Some(status) = status.filter(|_| track != shown) {
+            let wait = interval.saturating_sub(last_shown.map_or(interval, |at| at.elapsed()));
+            if !wait.is_zero() {
+                tokio::select! {
+                    changed = status_rx.changed() => if changed.is_err() {
+                        return;
+                    },
+                    () = tokio::time::sleep(wait) => {}
+                }
+                continue;
+            }
+
+            let (bus, artwork_options) = (bus.clone(), artwork_options.clone());
+            let result = tokio::task::spawn_blocking(move || {
+                notify(&bus, replaces_id, &status, &artwork_options)
+            })
+            .await
+            .unwrap_or_else(|e| Err(dbus::Error::new_failed(&e.to_string())));
+
+            // Not retried either way, a late notification is worse than none.
+            shown = track;
+            last_shown = Some(Instant::now());
+            match result {
+                Ok(id) => {
+                    replaces_id = id;
+                    failing = false;
+                }
+                Err(e) => {
+                    if !failing {
+                        log::warn!("Could not show a notification: {e}");
+                        failing = true;
+                    }
+                }
+            }
+        }
+
+        if status_rx.changed().await.is_err() {
+            return;
+        }
+    }
+}
+
+impl Sink for NotifyOptions {
+    fn name(&self) -> &'static str {
+        "notify"
+    }
+
+    fn run(&self, context: &SinkContext) -> BoxFuture<'static, ()> {
+        // The system bus has no notification server, only --dbus-address can point elsewhere.
+        let bus = match &context.state.bus {
+            BusAddress::System => BusAddress::Session,
+            bus => bus.clone(),
+        };
+
+        Box::pin(run(
+            self.interval,
+            bus,
+            context.state.artwork_options.clone(),
+            context.status_rx.clone(),
+        ))
+    }
+}
@@ -0,0 +1,155 @@
+use crate::sink::{Sink, SinkContext};
+use crate::status::{CompactStatus, PlaybackState, StatusSnapshot};
+use crate::udp;
+use futures_util::future::BoxFuture;
+use std::net::SocketAddr;
+use std::time::Duration;
+use tokio::sync::watch;
+
+/// An OSC message argument.
+#[derive(Debug, Clone, PartialEq)]
+enum Arg {
+    String(String),
+    Float(f32),
+}
+
+/// Appends `bytes` with a terminating null, padded to a multiple of 4 bytes as OSC requires.
+fn push_padded(out: &mut Vec<u8>, bytes: &[u8]) {
+    out.extend_from_slice(bytes);
+    out.resize((out.len() / 4 + 1) * 4, 0);
+}
+
+/// Encodes a message with a single argument.
+fn encode(address: &str, arg: &Arg) -> Vec<u8> {
+    let mut out = Vec::new();
+    push_padded(&mut out, address.as_bytes());
+
+    match arg {
+        Arg::String(value) => {
+            push_padded(&mut out, b",s");
+            push_padded(&mut out, value.as_bytes());
+        }
+        Arg::Float(value) => {
+            push_padded(&mut out, b",f");
+            out.extend_from_slice(&value.to_be_bytes());
+        }
+    }
+
+    out
+}
+
+/// Each value sent, by address suffix. Times are in seconds.
+fn values(status: &CompactStatus) -> [(&'static str, Arg); 8] {
+    let seconds = |microseconds: u64| microseconds as f32 / 1_000_000.0;
+
+    [
+        ("player", Arg::String(status.player.clone())),
+        ("title", Arg::String(status.title.clone())),
+        ("artist", Arg::String(status.artist.clone())),
+        ("album", Arg::String(status.album.clone())),
+        (
+            "state",
+            Arg::String(String::from(match status.playback_state {
+                PlaybackState::Playing => "playing",
+                PlaybackState::Paused => "paused",
+                PlaybackState::None => "none",
+            })),
+        ),
+        ("position", Arg::Float(seconds(status.position))),
+        ("length", Arg::Float(seconds(status.length))),
+        (
+            "progress",
+            Arg::Float(if status.length > 0 {
+                status.position as f32 / status.length as f32
+            } else {
+                0.0
+            }),
+        ),
+    ]
+}
+
+/// Sends each value that changed to `target` as an OSC message under `prefix`, and every value once per `interval`.
+/// Where --osc-target sends the status, under which address prefix, and how often at most.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct OscOptions {
+    pub target: SocketAddr,
+    pub prefix: String,
+    pub interval: Duration,
+}
+
+pub async fn run(
+    target: SocketAddr,
+    prefix: String,
+    interval: Duration,
+    mut status_rx: watch::Receiver<StatusSnapshot>,
+) {
+    let socket = match udp::socket(target).await {
+        Ok(socket) => socket,
+        Err(e) => {
+            log::error!("Could not open a UDP socket for {target}: {e}");
+            return;
+        }
+    };
+
+    log::info!("Sending OSC to {target}!");
+
+    let prefix = prefix.trim_end_matches('/');
+    let mut ticker = tokio::time::interval(interval);
+    let mut last_sent = None;
+    let mut failing = false;
+
+    loop {
+        let tick = tokio::select! {
+            _ = ticker.tick() => true,
+            changed = status_rx.changed() => {
+                if changed.is_err() {
+                    return;
+                }
+                false
+            }
+        };
+
+        let values = values(&CompactStatus::from(status_rx.borrow_and_update().active()));
+        let everything = tick || last_sent.is_none();
+
+        for (i, (name, arg)) in values.iter().enumerate() {
+            let unchanged = last_sent
+                .as_ref()
+                .is_some_and(|last: &[(&str, Arg); 8]| last[i].1 == *arg);
+            if unchanged && !everything {
+                continue;
+            }
+
+            let message = encode(&format!("{prefix}/{name}"), arg);
+            match socket.send_to(&message, target).await {
+                Ok(_) => failing = false,
+                // Only logged once, like with --udp-target.
+                Err(e) if !failing => {
+                    log::warn!("Could not send OSC to {target}: {e}");
+                    failing = true;
+                }
+                Err(_) => {}
+            }
+        }
+
+        last_sent = Some(values);
+        if everything {
+            ticker.reset();
+        }
+    }
+}
+
+impl Sink for OscOptions {
+    fn name(&self) -> &'static str {
+        "osc"
+    }
+
+    fn run(&self, context: &SinkContext) -> BoxFuture<'static, ()> {
+        Box::pin(run(
+            self.target,
+            self.prefix.clone(),
+            self.interval,
+            context.status_rx.clone(),
+        ))
+    }
+}
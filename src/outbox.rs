@@ -0,0 +1,117 @@
+use std::collections::VecDeque;
+use std::sync::{Mutex, MutexGuard, PoisonError};
+use tokio::sync::Notify;
+use tokio_tungstenite::tungstenite::Message;
+
+/// The messages waiting to be sent to a WebSocket client, so one that reads slowly, or not at all, can't make them pile up in memory.
+///
+/// Replies and events are queued up to a limit. While the replies are over it, the client's next request isn't read, and once the events are, the oldest ones are dropped. Pushed statuses and lyrics aren't queued at all: only the newest one waits to be sent, the ones before it would be outdated by then anyway.
+pub struct Outbox {
+    queued: Mutex<Queued>,
+    limit: usize,
+    /// Woken when there's something to send.
+    ready: Notify,
+    /// Woken when something was sent.
+    sent: Notify,
+}
+
+/// Which of an [`Outbox`]'s queues a message came from.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Queue {
+    Replies,
+    Events,
+    Status,
+    Lyrics,
+}
+
+#[derive(Default)]
+struct Queued {
+    replies: VecDeque<Message>,
+    events: VecDeque<Message>,
+    status: Option<Message>,
+    lyrics: Option<Message>,
+}
+
+impl Outbox {
+    /// Queues up to `limit` replies and events each, or any number without one.
+    pub fn new(limit: Option<usize>) -> Self {
+        Self {
+            queued: Mutex::default(),
+            limit: limit.unwrap_or(usize::MAX),
+            ready: Notify::new(),
+            sent: Notify::new(),
+        }
+    }
+
+    /// Never poisoned, the messages are whole whatever panicked while it was held.
+    fn lock(&self) -> MutexGuard<'_, Queued> {
+        self.queued.lock().unwrap_or_else(PoisonError::into_inner)
+    }
+
+    /// Queues the replies to a request, all of them even if that goes over the limit.
+    pub fn reply(&self, replies: Vec<Message>) {
+        self.lock().replies.extend(replies);
+        self.ready.notify_one();
+    }
+
+    /// Whether the client's next request should be read, see [`Outbox::wait_for_room`] otherwise.
+    pub fn has_room(&self) -> bool {
+        self.lock().replies.len() < self.limit
+    }
+
+    /// Waits until the replies are under the limit again.
+    pub async fn wait_for_room(&self) {
+        while !self.has_room() {
+            self.sent.notified().await;
+        }
+    }
+
+    /// Queues an event, returning whether the oldest one had to be dropped for it.
+    pub fn event(&self, event: Message) -> bool {
+        let mut queued = self.lock();
+        queued.events.push_back(event);
+        let dropped = queued.events.len() > self.limit && queued.events.pop_front().is_some();
+        drop(queued);
+
+        self.ready.notify_one();
+        dropped
+    }
+
+    /// Replaces the status waiting to be pushed, if there is one.
+    pub fn status(&self, status: Message) {
+        self.lock().status = Some(status);
+        self.ready.notify_one();
+    }
+
+    /// Replaces the lyrics waiting to be pushed, if there are any.
+    pub fn lyrics(&self, lyrics: Message) {
+        self.lock().lyrics = Some(lyrics);
+        self.ready.notify_one();
+    }
+
+    /// Waits for the next message to send: replies first, then events, then the status and lyrics.
+    pub async fn next(&self) -> (Queue, Message) {
+        loop {
+            let next = {
+                let mut queued = self.lock();
+                if let Some(reply) = queued.replies.pop_front() {
+                    Some((Queue::Replies, reply))
+                } else if let Some(event) = queued.events.pop_front() {
+                    Some((Queue::Events, event))
+                } else if let Some(status) = queued.status.take() {
+                    Some((Queue::Status, status))
+                } else {
+                    queued.lyrics.take().map(|lyrics| (Queue::Lyrics, lyrics))
+                }
+            };
+
+            match next {
+                Some(next) => {
+                    self.sent.notify_one();
+                    return next;
+                }
+                None => self.ready.notified().await,
+            }
+        }
+    }
+}
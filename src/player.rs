@@ -0,0 +1,1606 @@
+use crate::artwork::ArtworkOptions;
+use crate::bridge;
+use crate::bus::{BusAddress, BusEvent, BusWatcher};
+use crate::config::{Config, Quirks};
+use crate::demand::Demand;
+use crate::event::Event;
+use crate::redact::Redaction;
+use crate::status::{
+    is_timeout, read_status, read_status_with_metadata, sanitize_position, send_changed,
+    PlaybackState, PlayerStatus, SampleTime, StatusSnapshot,
+};
+use regex::Regex;
+use std::any::Any;
+use std::collections::HashMap;
+use std::panic::{self, AssertUnwindSafe};
+use std::sync::mpsc;
+use std::thread;
+use std::time::{Duration, Instant};
+use tokio::sync::{broadcast, watch};
+
+fn lerp(a: f32, b: f32, t: f32) -> f32 {
+    a + t * (b - a)
+}
+
+/// A random factor within `jitter` of 1, e.g. between 0.9 and 1.1 for 0.1. Always 1 for a jitter outside of 0 to 1, which would make durations negative.
+fn jitter_factor(jitter: f32) -> f32 {
+    if jitter == 0.0 || !(0.0..1.0).contains(&jitter) {
+        return 1.0;
+    }
+
+    1.0 + jitter * (fastrand::f32() * 2.0 - 1.0)
+}
+
+/// Settings for finding players and reading their status.
+#[derive(Debug, Clone)]
+pub struct PlayerOptions {
+    pub min_retry_time: f32,
+    /// Infinite when the retry time isn't capped.
+    pub max_retry_time: f32,
+    pub backoff: Backoff,
+    /// Retries it takes to get from `min_retry_time` to `max_retry_time`.
+    pub backoff_steps: u32,
+    pub app_name: String,
+    /// Players with a name matching any of these are never attached to.
+    pub ignore_names: Vec<Regex>,
+    pub update_interval: Duration,
+    pub idle_interval: Duration,
+    /// How long a player gets to answer each D-Bus call. One that doesn't keeps its last status, flagged as `stale`, and is left alone for a while.
+    pub dbus_timeout: Duration,
+    /// How far intervals are randomly stretched or shortened, as a fraction of them.
+    pub jitter: f32,
+    /// The bus to look for players on.
+    pub bus: BusAddress,
+    /// Subscribe to the players' D-Bus signals instead of only polling.
+    pub events: bool,
+    /// Attach to every matching player at once instead of just one.
+    pub all_players: bool,
+    /// Don't switch away from the current player while it's playing.
+    pub sticky: bool,
+    /// How to choose between several matching players.
+    pub tie_break: TieBreak,
+    /// Player names in order of preference. The first one that isn't stopped is reported.
+    pub fallback_chain: Vec<String>,
+    /// Attach to exactly this bus name, skipping discovery.
+    pub bus_name: Option<String>,
+    /// Bus names detached from the admin page, left alone until [`Action::Rediscover`].
+    pub detached: Vec<String>,
+    pub artwork: ArtworkOptions,
+    pub config: Config,
+}
+
+impl Default for PlayerOptions {
+    /// Attaches to one player at a time on the session bus, like the command line does without any options.
+    fn default() -> Self {
+        Self {
+            min_retry_time: 1.0,
+            max_retry_time: 4.0,
+            backoff: Backoff::Linear,
+            backoff_steps: 16,
+            app_name: String::new(),
+            ignore_names: Vec::new(),
+            update_interval: Duration::from_millis(250),
+            idle_interval: Duration::from_secs(5),
+            dbus_timeout: Duration::from_millis(500),
+            jitter: 0.0,
+            bus: BusAddress::Session,
+            events: true,
+            all_players: false,
+            sticky: false,
+            tie_break: TieBreak::Playing,
+            fallback_chain: Vec::new(),
+            bus_name: None,
+            detached: Vec::new(),
+            artwork: ArtworkOptions::default(),
+            config: Config::default(),
+        }
+    }
+}
+
+/// How the retry time grows between `min_retry_time` and `max_retry_time`.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, clap::ValueEnum)]
+pub enum Backoff {
+    /// Grow by the same amount on every retry. Without a cap, by `min_retry_time`.
+    #[default]
+    Linear,
+    /// Grow by the same factor on every retry. Without a cap, it doubles.
+    Exponential,
+}
+
+fn backoff(times_tried: &mut u32, options: &PlayerOptions) -> Duration {
+    let (min, max) = (options.min_retry_time, options.max_retry_time);
+    // Keeps `powi` in range, the result is capped long before this anyway.
+    let times = (*times_tried).min(1024);
+
+    let try_again_time = if max.is_finite() {
+        let times_normalized =
+            times.min(options.backoff_steps) as f32 / options.backoff_steps as f32;
+        match options.backoff {
+            Backoff::Linear => lerp(min, max, times_normalized),
+            Backoff::Exponential => min * (max / min).powf(times_normalized),
+        }
+    } else {
+        match options.backoff {
+            Backoff::Linear => min * (times + 1) as f32,
+            Backoff::Exponential => min * 2f32.powi(times as i32),
+        }
+    };
+
+    *times_tried = times_tried.saturating_add(1);
+
+    Duration::try_from_secs_f32(try_again_time).unwrap_or(Duration::MAX)
+}
+
+/// `min_retry_time` as a duration, or a second when it isn't a valid one, so bad options can't take the player thread down.
+fn min_retry_time(options: &PlayerOptions) -> Duration {
+    Duration::try_from_secs_f32(options.min_retry_time).unwrap_or(Duration::from_secs(1))
+}
+
+fn retry_time(times_tried: &mut u32, options: &PlayerOptions) -> Duration {
+    let try_again_time = backoff(times_tried, options);
+    log::info!("Could not find a currently playing media player. Been trying for {} time(s). Trying again in {} seconds.", times_tried, try_again_time.as_secs_f32());
+
+    try_again_time
+}
+
+/// The D-Bus connection used for discovery, rebuilt whenever it breaks.
+#[derive(Default)]
+struct Finder {
+    finder: Option<mpris::PlayerFinder>,
+    times_failed: u32,
+    reconnected: bool,
+    /// How many searches in a row had a player time out, and when to search again. Every search asks every player on the bus for its name, so one hung player would hold up each of them.
+    slowed: Option<(u32, Instant)>,
+}
+
+impl Finder {
+    /// Lists all players on the bus, or `None` if D-Bus can't be reached right now. Players that don't answer are left out.
+    fn find_all(&mut self, options: &PlayerOptions) -> Option<Vec<mpris::Player>> {
+        if self.finder.is_none() {
+            match options.bus.connect_ffidisp() {
+                Ok(connection) => {
+                    if self.times_failed > 0 {
+                        log::info!(
+                            "Reconnected to D-Bus after {} attempt(s)!",
+                            self.times_failed
+                        );
+                        self.reconnected = true;
+                    }
+
+                    self.finder = Some(mpris::PlayerFinder::for_connection(connection));
+                    self.times_failed = 0;
+                }
+                Err(e) => {
+                    log::warn!("Could not connect to D-Bus: {e}");
+                    self.times_failed = self.times_failed.saturating_add(1);
+                    return None;
+                }
+            }
+        }
+
+        let finder = self.finder.as_mut()?;
+        // Set every time, it may have been reloaded.
+        finder.set_player_timeout_ms(timeout_ms(options.dbus_timeout));
+
+        match finder.iter_players() {
+            Ok(players) => {
+                let mut timed_out = false;
+                let players = players
+                    .filter_map(|player| {
+                        player
+                            .inspect_err(|e| {
+                                timed_out |= is_timeout(e);
+                                log::debug!("Skipped a player that could not be attached to: {e}")
+                            })
+                            .ok()
+                    })
+                    .collect();
+
+                self.slowed = timed_out.then(|| {
+                    let times = self.slowed.map_or(1, |(times, _)| times.saturating_add(1));
+                    let search_in = unresponsive_retry(options.update_interval, times);
+                    log::debug!(
+                        "A player didn't answer while searching, searching again in {} seconds.",
+                        search_in.as_secs_f32()
+                    );
+                    (times, Instant::now() + search_in)
+                });
+
+                Some(players)
+            }
+            Err(e) => {
+                log::warn!("Lost the D-Bus connection: {e}");
+                self.finder = None;
+                self.times_failed = self.times_failed.saturating_add(1);
+                None
+            }
+        }
+    }
+
+    /// Whether the last search had a player time out, and the next one should wait while other players are attached.
+    fn is_slowed(&self) -> bool {
+        self.slowed
+            .is_some_and(|(_, search_at)| Instant::now() < search_at)
+    }
+
+    /// Whether the connection was rebuilt since the last call.
+    fn take_reconnected(&mut self) -> bool {
+        std::mem::take(&mut self.reconnected)
+    }
+
+    /// How long to wait before trying to reconnect.
+    fn retry_time(&self, options: &PlayerOptions) -> Duration {
+        let mut times_tried = self.times_failed.saturating_sub(1);
+        let try_again_time = backoff(&mut times_tried, options);
+        log::info!(
+            "Trying to reconnect to D-Bus in {} seconds.",
+            try_again_time.as_secs_f32()
+        );
+
+        try_again_time
+    }
+}
+
+/// A timeout as the milliseconds the `mpris` crate takes.
+fn timeout_ms(timeout: Duration) -> i32 {
+    timeout.as_millis().try_into().unwrap_or(i32::MAX)
+}
+
+/// The names a player can be matched by: its bus name without the MPRIS prefix, its `Identity` and its `DesktopEntry`.
+fn player_names(player: &mpris::Player) -> Vec<String> {
+    let mut names = vec![
+        player.bus_name_trimmed().to_string(),
+        player.identity().to_string(),
+    ];
+    if let Ok(Some(desktop_entry)) = player.get_desktop_entry() {
+        names.push(desktop_entry);
+    }
+
+    names
+}
+
+/// The player's friendly name from the config, if it has one.
+fn alias<'a>(player: &mpris::Player, options: &'a PlayerOptions) -> Option<&'a str> {
+    options
+        .config
+        .alias(&[player.bus_name_trimmed(), player.identity()])
+}
+
+fn quirks(player: &mpris::Player, options: &PlayerOptions) -> Quirks {
+    options
+        .config
+        .quirks(&[player.bus_name_trimmed(), player.identity()])
+}
+
+/// How often the player's status is read, from the config or --interval.
+fn poll_interval(player: &mpris::Player, options: &PlayerOptions) -> Duration {
+    options
+        .config
+        .interval(&[player.bus_name_trimmed(), player.identity()])
+        .unwrap_or(options.update_interval)
+}
+
+/// The name used for the player in logs.
+fn display_name(player: &mpris::Player, options: &PlayerOptions) -> String {
+    match alias(player, options) {
+        Some(alias) => format!("{alias} ({})", player.bus_name_trimmed()),
+        None => player.bus_name_trimmed().to_string(),
+    }
+}
+
+/// Whether the player passes the configured app name and ignore list, and wasn't detached from the admin page. The --mpris-bridge never does, it only shows what's picked here.
+fn is_candidate(player: &mpris::Player, options: &PlayerOptions) -> bool {
+    if player.bus_name() == bridge::BUS_NAME
+        || options
+            .detached
+            .iter()
+            .any(|bus_name| bus_name == player.bus_name())
+    {
+        return false;
+    }
+
+    let names = player_names(player);
+
+    if !options.app_name.is_empty()
+        && !names
+            .iter()
+            .any(|name| name.to_lowercase() == options.app_name.to_lowercase())
+    {
+        return false;
+    }
+
+    !options
+        .ignore_names
+        .iter()
+        .any(|v| names.iter().any(|name| v.is_match(name)))
+}
+
+/// Prints every player on the bus with its status, with `redaction` applied, for the `players` subcommand.
+pub fn print_players(
+    options: &PlayerOptions,
+    redaction: &Redaction,
+) -> Result<(), Box<dyn std::error::Error>> {
+    let mut finder = mpris::PlayerFinder::for_connection(options.bus.connect_ffidisp()?);
+    finder.set_player_timeout_ms(timeout_ms(options.dbus_timeout));
+    let players = finder.find_all()?;
+
+    if players.is_empty() {
+        println!("No players found.");
+    }
+
+    for player in players {
+        let quirks = quirks(&player, options);
+        let status = read_status(
+            &player,
+            None,
+            &options.artwork,
+            alias(&player, options),
+            &quirks,
+        );
+
+        println!("{}", player.bus_name());
+        println!("  identity: {}", player.identity());
+        if let Some(alias) = alias(&player, options) {
+            println!("  alias: {alias}");
+        }
+        if let Ok(Some(desktop_entry)) = player.get_desktop_entry() {
+            println!("  desktop entry: {desktop_entry}");
+        }
+        if let Ok(mut status) = status {
+            redaction.redact_status(&mut status);
+            println!("  status: {:?}", status.playback_state);
+            if !status.metadata.title.is_empty() {
+                println!(
+                    "  track: {} - {}",
+                    status.metadata.artist, status.metadata.title
+                );
+            }
+        }
+        if !is_candidate(&player, options) {
+            println!("  ignored by --app-name / --ignore-names");
+        }
+    }
+
+    Ok(())
+}
+
+/// Finds the player that would be attached to right now, searching once. `None` if no player matches.
+pub fn find_once(
+    options: &PlayerOptions,
+) -> Result<Option<mpris::Player>, Box<dyn std::error::Error>> {
+    let mut finder = Finder::default();
+
+    match find_player(
+        &mut finder,
+        &mut 0,
+        options,
+        None,
+        &mut PlayStarts::default(),
+        &mut Unresponsive::default(),
+    ) {
+        Ok(player) => Ok(Some(player)),
+        Err(_) if finder.times_failed > 0 => Err("could not connect to D-Bus".into()),
+        Err(_) => Ok(None),
+    }
+}
+
+/// Reads a player's status once, honouring the aliases and quirks in the options.
+pub fn read_once(player: &mpris::Player, options: &PlayerOptions) -> Option<PlayerStatus> {
+    read_status(
+        player,
+        None,
+        &options.artwork,
+        alias(player, options),
+        &quirks(player, options),
+    )
+    .ok()
+}
+
+/// Lists every player on the bus that passes [`is_candidate`], or `None` if D-Bus can't be reached.
+#[tracing::instrument(skip_all)]
+fn find_candidates(finder: &mut Finder, options: &PlayerOptions) -> Option<Vec<mpris::Player>> {
+    Some(
+        finder
+            .find_all(options)?
+            .into_iter()
+            .filter(|player| is_candidate(player, options))
+            .collect(),
+    )
+}
+
+/// How strongly a player should be preferred when several match, lower is better.
+///
+/// Playing players come first, then paused ones, then ones that at least have a track loaded.
+fn priority(state: &PlaybackState, has_track: bool) -> u8 {
+    match state {
+        PlaybackState::Playing => 0,
+        PlaybackState::Paused => 1,
+        PlaybackState::None if has_track => 2,
+        PlaybackState::None => 3,
+    }
+}
+
+/// How to choose between several matching players.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, clap::ValueEnum)]
+pub enum TieBreak {
+    /// Prefer playing players, then paused ones, then stopped ones. Ties go to the lowest bus name.
+    #[default]
+    Playing,
+    /// Prefer the player that most recently started playing, falling back to `playing`.
+    RecentlyStarted,
+    /// Always prefer the lowest bus name, whatever the players are doing.
+    BusName,
+    /// Always prefer the player that showed up on the bus last.
+    LastAttached,
+}
+
+/// Numbers the processes that own each bus name, for `PlayerInfo::attachment`. Kept across reloads, which attach to the same players again.
+#[derive(Debug, Default)]
+struct Attachments {
+    owners: HashMap<String, (String, u64)>,
+    last: u64,
+}
+
+impl Attachments {
+    /// The player's attachment, a new one if its bus name is owned by another process than before. Starts at 1.
+    fn of(&mut self, player: &mpris::Player) -> u64 {
+        match self.owners.get(player.bus_name()) {
+            Some((owner, attachment)) if owner == player.unique_name() => *attachment,
+            _ => {
+                self.last += 1;
+                self.owners.insert(
+                    player.bus_name().to_string(),
+                    (player.unique_name().to_string(), self.last),
+                );
+                self.last
+            }
+        }
+    }
+}
+
+/// Remembers when each player was first seen and when it last started playing, for [`TieBreak`].
+#[derive(Debug, Default)]
+struct PlayStarts {
+    states: HashMap<String, (PlaybackState, Instant)>,
+    first_seen: HashMap<String, Instant>,
+}
+
+impl PlayStarts {
+    fn observe(&mut self, bus_name: &str, state: &PlaybackState) {
+        match self.states.get_mut(bus_name) {
+            Some((last_state, _)) if last_state == state => {}
+            Some(entry) => *entry = (state.clone(), Instant::now()),
+            None => {
+                self.states
+                    .insert(bus_name.to_string(), (state.clone(), Instant::now()));
+                self.first_seen
+                    .entry(bus_name.to_string())
+                    .or_insert_with(Instant::now);
+            }
+        }
+    }
+
+    fn first_seen(&self, bus_name: &str) -> Option<Instant> {
+        self.first_seen.get(bus_name).copied()
+    }
+
+    /// Forgets the players that aren't in `players` anymore, so short-lived ones (e.g. a browser tab each) don't pile up.
+    fn retain(&mut self, players: &[mpris::Player]) {
+        let kept = |bus_name: &String| players.iter().any(|player| player.bus_name() == bus_name);
+        self.states.retain(|bus_name, _| kept(bus_name));
+        self.first_seen.retain(|bus_name, _| kept(bus_name));
+    }
+
+    /// When the player started playing, if it still is.
+    fn playing_since(&self, bus_name: &str) -> Option<Instant> {
+        match self.states.get(bus_name)? {
+            (PlaybackState::Playing, since) => Some(*since),
+            _ => None,
+        }
+    }
+}
+
+/// How long a position may stay the same while playing before it's considered frozen.
+const FROZEN_POSITION_TIME: Duration = Duration::from_secs(3);
+
+/// Remembers when each player's position last moved, to catch players that stop updating it.
+#[derive(Default)]
+struct PositionWatch {
+    positions: HashMap<String, (u64, Instant)>,
+}
+
+impl PositionWatch {
+    /// Flags the status as degraded if it's playing but the position hasn't moved in a while.
+    fn check(&mut self, bus_name: &str, status: &mut PlayerStatus) {
+        if status.playback_state != PlaybackState::Playing {
+            self.positions.remove(bus_name);
+            return;
+        }
+
+        let now = Instant::now();
+        let (last_position, moved_at) = self
+            .positions
+            .entry(bus_name.to_string())
+            .or_insert((status.position, now));
+
+        if *last_position != status.position {
+            *last_position = status.position;
+            *moved_at = now;
+        } else if now.duration_since(*moved_at) >= FROZEN_POSITION_TIME
+            // Some players stay "playing" for a moment at the end of a track.
+            && status.position < status.metadata.length
+        {
+            status.degraded = true;
+        }
+    }
+}
+
+/// Remembers when each player's status was last read, so players with a longer interval are read less often.
+#[derive(Default)]
+struct ReadSchedule {
+    /// When each player was last read, and the jitter factor for its next interval.
+    last_read: HashMap<String, (Instant, f32)>,
+}
+
+impl ReadSchedule {
+    fn is_due(&self, bus_name: &str, interval: Duration) -> bool {
+        self.last_read
+            .get(bus_name)
+            .is_none_or(|(last_read, factor)| last_read.elapsed() >= interval.mul_f32(*factor))
+    }
+
+    fn mark_read(&mut self, bus_name: &str, jitter: f32) {
+        self.last_read.insert(
+            bus_name.to_string(),
+            (Instant::now(), jitter_factor(jitter)),
+        );
+    }
+
+    /// Makes the player's status get read on the next update, e.g. because it changed.
+    fn invalidate(&mut self, bus_name: &str) {
+        self.last_read.remove(bus_name);
+    }
+
+    /// Forgets the players that aren't attached anymore.
+    fn retain(&mut self, players: &[mpris::Player]) {
+        self.last_read
+            .retain(|bus_name, _| players.iter().any(|player| player.bus_name() == bus_name));
+    }
+}
+
+/// How long a player's metadata is trusted without it signalling a change, in case a signal got lost.
+const METADATA_MAX_AGE: Duration = Duration::from_secs(30);
+
+/// The players' metadata as last read, so updates only read their playback status and position until a player signals that its metadata changed. Keyed by unique name, so a player that restarted under the same bus name is read from scratch.
+///
+/// Only trusted while the player signals are received, without them there's no telling when the metadata changed. Cleared whenever they were missed, e.g. while paused or after reconnecting.
+#[derive(Default)]
+struct MetadataCache {
+    metadata: HashMap<String, (mpris::Metadata, Instant)>,
+}
+
+impl MetadataCache {
+    /// Reads the player's status with `read`, reading its metadata first unless it's known. Metadata that couldn't be read is left to `read`, and read again next time.
+    fn read(
+        &mut self,
+        player: &mpris::Player,
+        read: impl FnOnce(
+            Result<&mpris::Metadata, mpris::DBusError>,
+        ) -> Result<PlayerStatus, mpris::DBusError>,
+    ) -> Result<PlayerStatus, mpris::DBusError> {
+        let known = self
+            .metadata
+            .get(player.unique_name())
+            .is_some_and(|(_, read_at)| read_at.elapsed() < METADATA_MAX_AGE);
+        if !known {
+            match player.get_metadata() {
+                Ok(metadata) => {
+                    self.metadata
+                        .insert(player.unique_name().to_string(), (metadata, Instant::now()));
+                }
+                Err(e) => {
+                    self.metadata.remove(player.unique_name());
+                    return read(Err(e));
+                }
+            }
+        }
+
+        read(Ok(&self.metadata[player.unique_name()].0))
+    }
+
+    /// Makes the metadata of the player with this unique name get read on its next update.
+    fn invalidate(&mut self, unique_name: &str) {
+        self.metadata.remove(unique_name);
+    }
+
+    /// Forgets the players that aren't attached anymore.
+    fn retain(&mut self, players: &[mpris::Player]) {
+        self.metadata.retain(|unique_name, _| {
+            players
+                .iter()
+                .any(|player| player.unique_name() == unique_name)
+        });
+    }
+}
+
+/// The longest an unresponsive player is left alone before it's tried again.
+const MAX_UNRESPONSIVE_RETRY: Duration = Duration::from_secs(10);
+
+/// How long to leave a player alone after it timed out `times` times in a row.
+fn unresponsive_retry(interval: Duration, times: u32) -> Duration {
+    interval
+        .saturating_mul(2u32.saturating_pow(times))
+        .min(MAX_UNRESPONSIVE_RETRY)
+}
+
+/// Remembers the players that didn't answer in time, so a hung player doesn't hold up every update for the D-Bus timeout. Keyed by unique name, like [`MetadataCache`].
+///
+/// They're left alone for twice their interval, then twice that and so on, keeping their last status flagged as `stale`. A signal from one means it's back, and it's read right away.
+#[derive(Default)]
+struct Unresponsive {
+    /// How many times each player timed out in a row, and when to try it again.
+    players: HashMap<String, (u32, Instant)>,
+}
+
+impl Unresponsive {
+    /// Whether the player timed out and isn't due to be tried again yet.
+    fn is_waiting(&self, unique_name: &str) -> bool {
+        self.players
+            .get(unique_name)
+            .is_some_and(|(_, retry_at)| Instant::now() < *retry_at)
+    }
+
+    /// Notes that the player timed out, returning how long it's left alone.
+    fn timed_out(&mut self, unique_name: &str, interval: Duration) -> Duration {
+        let (times, retry_at) = self
+            .players
+            .entry(unique_name.to_string())
+            .or_insert((0, Instant::now()));
+        *times = times.saturating_add(1);
+
+        let retry_in = unresponsive_retry(interval, *times);
+        *retry_at = Instant::now() + retry_in;
+
+        retry_in
+    }
+
+    /// Notes that the player answered or signalled, returning whether it was unresponsive.
+    fn answered(&mut self, unique_name: &str) -> bool {
+        self.players.remove(unique_name).is_some()
+    }
+
+    /// Forgets the players that weren't tried again in a while, because they quit or aren't searched for anymore.
+    fn prune(&mut self) {
+        self.players
+            .retain(|_, (_, retry_at)| retry_at.elapsed() < MAX_UNRESPONSIVE_RETRY);
+    }
+}
+
+/// How long a player with [`Quirks::ignore_transient_stopped`] has to stay stopped to be reported as such.
+const TRANSIENT_STOP_TIME: Duration = Duration::from_secs(2);
+
+/// Remembers when each player got stopped, to hide short stops from players that report them between tracks.
+#[derive(Default)]
+struct StopFilter {
+    stopped_since: HashMap<String, Instant>,
+}
+
+impl StopFilter {
+    /// Keeps the previous playback state if the player only just stopped.
+    fn filter(
+        &mut self,
+        bus_name: &str,
+        status: &mut PlayerStatus,
+        previous_state: Option<&PlaybackState>,
+    ) {
+        if status.playback_state != PlaybackState::None {
+            self.stopped_since.remove(bus_name);
+            return;
+        }
+
+        let since = self
+            .stopped_since
+            .entry(bus_name.to_string())
+            .or_insert_with(Instant::now);
+
+        if since.elapsed() < TRANSIENT_STOP_TIME {
+            if let Some(previous_state) = previous_state {
+                status.playback_state = previous_state.clone();
+            }
+        }
+    }
+
+    /// Forgets a player that went away or restarted, so a new instance under the same bus name starts afresh.
+    fn forget(&mut self, bus_name: &str) {
+        self.stopped_since.remove(bus_name);
+    }
+
+    /// Forgets the players that aren't attached anymore.
+    fn retain(&mut self, players: &[mpris::Player]) {
+        self.stopped_since
+            .retain(|bus_name, _| players.iter().any(|player| player.bus_name() == bus_name));
+    }
+}
+
+/// Picks which of the ranked players to report, as `(bus name, priority)` pairs. Returns its index.
+fn choose(
+    candidates: &[(&str, u8)],
+    play_starts: &PlayStarts,
+    tie_break: TieBreak,
+) -> Option<usize> {
+    let indexed = || candidates.iter().enumerate();
+    let by_priority = || {
+        indexed()
+            .min_by_key(|(_, (bus_name, priority))| (*priority, *bus_name))
+            .map(|(i, _)| i)
+    };
+
+    match tie_break {
+        TieBreak::Playing => by_priority(),
+        TieBreak::RecentlyStarted => indexed()
+            .filter_map(|(i, (bus_name, _))| Some((i, play_starts.playing_since(bus_name)?)))
+            .max_by_key(|(_, since)| *since)
+            .map(|(i, _)| i)
+            .or_else(by_priority),
+        TieBreak::BusName => indexed()
+            .min_by_key(|(_, (bus_name, _))| *bus_name)
+            .map(|(i, _)| i),
+        TieBreak::LastAttached => indexed()
+            .max_by_key(|(_, (bus_name, _))| play_starts.first_seen(bus_name))
+            .map(|(i, _)| i),
+    }
+}
+
+/// Picks the player the user is most likely to care about, see [`TieBreak`]. Unresponsive players come last, without being asked.
+fn pick_active(
+    players: Vec<mpris::Player>,
+    play_starts: &mut PlayStarts,
+    unresponsive: &mut Unresponsive,
+    options: &PlayerOptions,
+) -> Option<mpris::Player> {
+    // Every candidate is on the bus, the ones that aren't have quit.
+    play_starts.retain(&players);
+
+    let ranked = players
+        .into_iter()
+        .map(|player| {
+            if unresponsive.is_waiting(player.unique_name()) {
+                return (player, priority(&PlaybackState::None, false));
+            }
+
+            let state = match player.get_playback_status() {
+                Ok(state) => PlaybackState::from(state),
+                Err(e) => {
+                    if is_timeout(&e) {
+                        unresponsive
+                            .timed_out(player.unique_name(), poll_interval(&player, options));
+                    }
+                    return (player, priority(&PlaybackState::None, false));
+                }
+            };
+            let has_track =
+                state != PlaybackState::None || player.get_metadata().is_ok_and(|v| !v.is_empty());
+
+            play_starts.observe(player.bus_name(), &state);
+
+            (player, priority(&state, has_track))
+        })
+        .collect::<Vec<_>>();
+
+    let candidates = ranked
+        .iter()
+        .map(|(player, priority)| (player.bus_name(), *priority))
+        .collect::<Vec<_>>();
+    let i = choose(&candidates, play_starts, options.tie_break)?;
+
+    ranked.into_iter().nth(i).map(|(player, _)| player)
+}
+
+/// Attaches to a known bus name, retrying at the minimum retry time until it shows up.
+#[tracing::instrument(skip(options, current_player))]
+fn attach_bus_name(
+    bus_name: &str,
+    options: &PlayerOptions,
+    current_player: Option<&mpris::Player>,
+) -> Result<mpris::Player, Duration> {
+    let retry = min_retry_time(options);
+
+    if current_player.is_some() {
+        return Err(retry);
+    }
+
+    let connection = options.bus.connect_ffidisp().map_err(|e| {
+        log::warn!("Could not connect to D-Bus: {e}");
+        retry
+    })?;
+
+    mpris::Player::new(
+        connection,
+        bus_name.to_string(),
+        timeout_ms(options.dbus_timeout),
+    )
+    .map_err(|e| {
+        log::info!(
+            player = bus_name;
+            "Could not attach to {bus_name}: {e}. Trying again in {} seconds.",
+            options.min_retry_time
+        );
+        retry
+    })
+}
+
+#[tracing::instrument(skip_all)]
+fn find_player(
+    finder: &mut Finder,
+    times_tried: &mut u32,
+    options: &PlayerOptions,
+    current_player: Option<&mpris::Player>,
+    play_starts: &mut PlayStarts,
+    unresponsive: &mut Unresponsive,
+) -> Result<mpris::Player, Duration> {
+    if let Some(bus_name) = &options.bus_name {
+        if options.detached.contains(bus_name) {
+            return Err(retry_time(times_tried, options));
+        }
+        return attach_bus_name(bus_name, options, current_player);
+    }
+
+    if current_player.is_some() && finder.is_slowed() {
+        return Err(min_retry_time(options));
+    }
+
+    let Some(candidates) = find_candidates(finder, options) else {
+        return Err(finder.retry_time(options));
+    };
+
+    if let Some(found) = pick_active(candidates, play_starts, unresponsive, options) {
+        if current_player.is_some_and(|v| {
+            v.bus_name() == found.bus_name() && v.unique_name() == found.unique_name()
+        }) {
+            return Err(min_retry_time(options));
+        }
+
+        return Ok(found);
+    }
+
+    Err(retry_time(times_tried, options))
+}
+
+/// Finds every matching player that isn't attached yet.
+#[tracing::instrument(skip_all)]
+fn find_new_players(
+    finder: &mut Finder,
+    times_tried: &mut u32,
+    options: &PlayerOptions,
+    current_players: &[mpris::Player],
+) -> Result<Vec<mpris::Player>, Duration> {
+    if !current_players.is_empty() && finder.is_slowed() {
+        return Ok(Vec::new());
+    }
+    let Some(found) = find_candidates(finder, options) else {
+        return Err(finder.retry_time(options));
+    };
+
+    if found.is_empty() && current_players.is_empty() {
+        return Err(retry_time(times_tried, options));
+    }
+
+    Ok(found
+        .into_iter()
+        .filter(|player| {
+            current_players.iter().all(|current| {
+                current.bus_name() != player.bus_name()
+                    || current.unique_name() != player.unique_name()
+            })
+        })
+        .collect())
+}
+
+/// What's remembered about the players between updates.
+#[derive(Default)]
+struct PlayerHistory {
+    play_starts: PlayStarts,
+    position_watch: PositionWatch,
+    stop_filter: StopFilter,
+    read_schedule: ReadSchedule,
+    metadata: MetadataCache,
+    unresponsive: Unresponsive,
+    idle_backoff: IdleBackoff,
+}
+
+/// How long to wait between polls while nothing plays and no signals tell when that changes: the usual interval at first, doubling up to --idle-interval the longer nothing plays, so a player that's only paused for a moment is still picked up quickly.
+#[derive(Default)]
+struct IdleBackoff {
+    polls: u32,
+}
+
+impl IdleBackoff {
+    fn next(&mut self, interval: Duration, idle_interval: Duration) -> Duration {
+        let wait = interval
+            .saturating_mul(2u32.saturating_pow(self.polls))
+            .min(idle_interval);
+        self.polls = self.polls.saturating_add(1);
+        wait
+    }
+
+    /// Goes back to the usual interval, once something plays.
+    fn reset(&mut self) {
+        self.polls = 0;
+    }
+}
+
+/// Reads the status of every attached player, dropping the ones that have quit and keeping the last status of the ones that don't answer, see [`Unresponsive`]. With `signals` received, their metadata is only read again after they signal a change, see [`MetadataCache`].
+#[tracing::instrument(skip_all, fields(players = players.len()))]
+fn update_statuses(
+    players: &mut Vec<mpris::Player>,
+    status_tx: &watch::Sender<StatusSnapshot>,
+    events_tx: &broadcast::Sender<Event>,
+    options: &PlayerOptions,
+    history: &mut PlayerHistory,
+    attachments: &mut Attachments,
+    signals: bool,
+) {
+    let mut snapshot = StatusSnapshot::default();
+    let previous = status_tx.borrow().players.clone();
+
+    players.retain(|player| {
+        let interval = poll_interval(player, options);
+        if !history.read_schedule.is_due(player.bus_name(), interval)
+            || history.unresponsive.is_waiting(player.unique_name())
+        {
+            if let Some(status) = previous.get(player.bus_name()) {
+                snapshot
+                    .players
+                    .insert(player.bus_name().to_string(), status.clone());
+                return true;
+            }
+        }
+
+        let quirks = quirks(player, options);
+        let alias = alias(player, options);
+
+        let status = if signals && !quirks.unsignalled_metadata {
+            history.metadata.read(player, |metadata| {
+                read_status_with_metadata(
+                    player,
+                    metadata,
+                    previous.get(player.bus_name()),
+                    &options.artwork,
+                    alias,
+                    &quirks,
+                )
+            })
+        } else {
+            read_status(
+                player,
+                previous.get(player.bus_name()),
+                &options.artwork,
+                alias,
+                &quirks,
+            )
+        };
+
+        let mut status = match status {
+            Ok(status) => status,
+            Err(e) if is_timeout(&e) && player.is_running() => {
+                let retry_in = history
+                    .unresponsive
+                    .timed_out(player.unique_name(), interval);
+                log::warn!(
+                    player = player.bus_name();
+                    "Player {} didn't answer within {} seconds, keeping its last status. Trying again in {} seconds.",
+                    display_name(player, options),
+                    options.dbus_timeout.as_secs_f32(),
+                    retry_in.as_secs_f32()
+                );
+
+                if let Some(status) = previous.get(player.bus_name()) {
+                    snapshot.players.insert(
+                        player.bus_name().to_string(),
+                        PlayerStatus {
+                            stale: true,
+                            ..status.clone()
+                        },
+                    );
+                }
+                return true;
+            }
+            Err(e) => {
+                log::info!(player = player.bus_name(); "Could not read player status: {e}");
+
+                if !player.is_running() {
+                    log::info!(
+                        player = player.bus_name();
+                        "Player {} is not running! Aborting updates.",
+                        display_name(player, options)
+                    );
+
+                    let _ = events_tx.send(Event::Detached {
+                        bus_name: player.bus_name().to_string(),
+                    });
+                    return false;
+                }
+
+                // Still there, probably only a hiccup.
+                if let Some(status) = previous.get(player.bus_name()) {
+                    snapshot.players.insert(
+                        player.bus_name().to_string(),
+                        PlayerStatus {
+                            degraded: true,
+                            ..status.clone()
+                        },
+                    );
+                }
+                return true;
+            }
+        };
+
+        log::debug!(
+            player = player.bus_name();
+            "Updated from player {}.",
+            display_name(player, options)
+        );
+        if history.unresponsive.answered(player.unique_name()) {
+            log::info!(
+                player = player.bus_name();
+                "Player {} answers again.",
+                display_name(player, options)
+            );
+        }
+        history
+            .read_schedule
+            .mark_read(player.bus_name(), options.jitter);
+        status.player.attachment = attachments.of(player);
+
+        if quirks.ignore_transient_stopped {
+            history.stop_filter.filter(
+                player.bus_name(),
+                &mut status,
+                previous
+                    .get(player.bus_name())
+                    .map(|status| &status.playback_state),
+            );
+        }
+
+        history.position_watch.check(player.bus_name(), &mut status);
+        if let Some(event) = Event::track_change(previous.get(player.bus_name()), &status) {
+            let _ = events_tx.send(event);
+        }
+        if !quirks.ignore_position {
+            if let Some(event) = Event::position_jump(previous.get(player.bus_name()), &status) {
+                let _ = events_tx.send(event);
+            }
+        }
+        if status.degraded {
+            log::debug!(
+                player = player.bus_name();
+                "Player {} reports an unreliable position.",
+                display_name(player, options)
+            );
+        }
+
+        snapshot
+            .players
+            .insert(player.bus_name().to_string(), status);
+
+        true
+    });
+    history.metadata.retain(players);
+    history.stop_filter.retain(players);
+    history.read_schedule.retain(players);
+    history.unresponsive.prune();
+    // With a single player, the others are remembered as candidates until they leave the bus, see `pick_active`.
+    if options.all_players {
+        history.play_starts.retain(players);
+    }
+
+    let previous_active = status_tx.borrow().active.clone();
+    let keep_previous = options.sticky
+        && previous_active
+            .as_ref()
+            .and_then(|bus_name| snapshot.players.get(bus_name))
+            .is_some_and(|status| status.playback_state == PlaybackState::Playing);
+
+    for (bus_name, status) in &snapshot.players {
+        history
+            .play_starts
+            .observe(bus_name, &status.playback_state);
+    }
+
+    snapshot.active = if keep_previous {
+        previous_active
+    } else if !options.fallback_chain.is_empty() {
+        fallback_active(players, &snapshot, options)
+    } else {
+        let candidates = players
+            .iter()
+            .filter_map(|player| snapshot.players.get_key_value(player.bus_name()))
+            .map(|(bus_name, status)| {
+                (
+                    bus_name.as_str(),
+                    priority(&status.playback_state, !status.metadata.title.is_empty()),
+                )
+            })
+            .collect::<Vec<_>>();
+
+        choose(&candidates, &history.play_starts, options.tie_break)
+            .map(|i| candidates[i].0.to_string())
+    };
+
+    if !send_changed(status_tx, snapshot) {
+        log::info!("Player status isn't being requested anymore(all connections dropped)! Pausing updates.");
+        detach_all(players, events_tx);
+    }
+}
+
+/// The first player in [`PlayerOptions::fallback_chain`] order that isn't stopped, or the first one at all.
+///
+/// Players that aren't in the chain come after the ones that are.
+fn fallback_active(
+    players: &[mpris::Player],
+    snapshot: &StatusSnapshot,
+    options: &PlayerOptions,
+) -> Option<String> {
+    let mut chain = players
+        .iter()
+        .filter_map(|player| {
+            let status = snapshot.players.get(player.bus_name())?;
+            let rank = options
+                .fallback_chain
+                .iter()
+                .position(|name| {
+                    name.eq_ignore_ascii_case(player.bus_name_trimmed())
+                        || name.eq_ignore_ascii_case(player.identity())
+                })
+                .unwrap_or(options.fallback_chain.len());
+
+            Some((rank, player.bus_name(), status))
+        })
+        .collect::<Vec<_>>();
+    chain.sort_by_key(|(rank, _, _)| *rank);
+
+    chain
+        .iter()
+        .find(|(_, _, status)| status.playback_state != PlaybackState::None)
+        .or_else(|| chain.first())
+        .map(|(_, bus_name, _)| bus_name.to_string())
+}
+
+fn detach_all(players: &mut Vec<mpris::Player>, events_tx: &broadcast::Sender<Event>) {
+    for player in players.drain(..) {
+        let _ = events_tx.send(Event::Detached {
+            bus_name: player.bus_name().to_string(),
+        });
+    }
+}
+
+/// Detaches the player with this bus name if it's no longer owned by the process it was attached to,
+/// so the next search attaches to the new instance instead of keeping a stale proxy.
+fn detach_stale(
+    players: &mut Vec<mpris::Player>,
+    bus_name: &str,
+    new_owner: &str,
+    stop_filter: &mut StopFilter,
+    events_tx: &broadcast::Sender<Event>,
+    options: &PlayerOptions,
+) {
+    let Some(i) = players
+        .iter()
+        .position(|v| v.bus_name() == bus_name && v.unique_name() != new_owner)
+    else {
+        return;
+    };
+
+    let player = players.remove(i);
+    stop_filter.forget(bus_name);
+    if new_owner.is_empty() {
+        log::info!(player = player.bus_name(); "Player {} quit.", display_name(&player, options));
+    } else {
+        log::info!(
+            player = player.bus_name();
+            "Player {} restarted, attaching to the new instance.",
+            display_name(&player, options)
+        );
+    }
+
+    let _ = events_tx.send(Event::Detached {
+        bus_name: player.bus_name().to_string(),
+    });
+}
+
+/// Subscribes to the players' signals, unless disabled.
+fn watch_bus(options: &PlayerOptions) -> Option<BusWatcher> {
+    if !options.events {
+        return None;
+    }
+
+    BusWatcher::new(&options.bus)
+        .inspect_err(|e| {
+            log::warn!("Could not subscribe to player signals, falling back to polling: {e}")
+        })
+        .ok()
+}
+
+/// Asked of the player thread from the admin page, see [`run`].
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum Action {
+    /// Detaches every player and searches for them from scratch, including the detached ones.
+    Rediscover,
+    /// Detaches the player with this bus name and leaves it alone until the next [`Action::Rediscover`] or reload.
+    Detach(String),
+}
+
+/// Finds players on the bus and keeps the attached players' statuses in `status_tx` up to date, telling `events_tx` when one comes or goes. Blocks and never returns, so run it on a thread of its own.
+///
+/// New options sent to `options_rx` take over on the next update, searching for players from scratch, and so do the actions sent to `actions_rx`. While `demand` is idle, nothing is read or searched for at all.
+///
+/// This thread is the only one that owns the discovery connection and the attached players, everything else only sees the channels. Commands for the players (see `control`) don't go through it: each is a single call on a connection of its own, addressed by bus name, so they never wait for an update tick or contend with this loop.
+///
+/// A panic, e.g. on something unexpected from a player, detaches all players and starts over instead of leaving the statuses frozen.
+pub fn run(
+    mut options: PlayerOptions,
+    options_rx: mpsc::Receiver<PlayerOptions>,
+    actions_rx: mpsc::Receiver<Action>,
+    status_tx: watch::Sender<StatusSnapshot>,
+    events_tx: broadcast::Sender<Event>,
+    demand: Demand,
+) {
+    let mut attachments = Attachments::default();
+
+    loop {
+        let Err(panic) = panic::catch_unwind(AssertUnwindSafe(|| {
+            watch_players(
+                &mut options,
+                &options_rx,
+                &actions_rx,
+                &status_tx,
+                &events_tx,
+                &demand,
+                &mut attachments,
+            )
+        })) else {
+            return;
+        };
+
+        log::error!(
+            "Reading the players failed unexpectedly, starting over in {} second(s): {}",
+            PANIC_RESTART_DELAY.as_secs(),
+            panic_message(&*panic)
+        );
+        for bus_name in status_tx.borrow().players.keys() {
+            let _ = events_tx.send(Event::Detached {
+                bus_name: bus_name.clone(),
+            });
+        }
+        thread::sleep(PANIC_RESTART_DELAY);
+    }
+}
+
+/// How long to wait before starting over after [`watch_players`] panicked, so a panic on every update doesn't spin.
+const PANIC_RESTART_DELAY: Duration = Duration::from_secs(1);
+
+/// What a panic was about, if it says.
+fn panic_message(panic: &(dyn Any + Send)) -> &str {
+    panic
+        .downcast_ref::<&str>()
+        .copied()
+        .or_else(|| panic.downcast_ref::<String>().map(String::as_str))
+        .unwrap_or("unknown panic")
+}
+
+/// The loop behind [`run`], only ever left by panicking. The options are kept across panics, in case they were reloaded, and so are the attachments.
+fn watch_players(
+    options: &mut PlayerOptions,
+    options_rx: &mpsc::Receiver<PlayerOptions>,
+    actions_rx: &mpsc::Receiver<Action>,
+    status_tx: &watch::Sender<StatusSnapshot>,
+    events_tx: &broadcast::Sender<Event>,
+    demand: &Demand,
+    attachments: &mut Attachments,
+) {
+    let mut watcher = watch_bus(options);
+
+    let mut finder = Finder::default();
+    let mut players: Vec<mpris::Player> = Vec::new();
+    let mut times_tried = 0;
+    let mut history = PlayerHistory::default();
+
+    loop {
+        if let Some(new_options) = options_rx.try_iter().last() {
+            // The attached players might not match the new filters, search from scratch.
+            detach_all(&mut players, events_tx);
+            history = PlayerHistory::default();
+            times_tried = 0;
+
+            if new_options.events != options.events {
+                watcher = watch_bus(&new_options);
+            }
+            *options = new_options;
+            log::info!("Reloaded the player options!");
+        }
+
+        for action in actions_rx.try_iter() {
+            match action {
+                Action::Rediscover => {
+                    log::info!("Searching for players again, as asked on the admin page.");
+                    detach_all(&mut players, events_tx);
+                    history = PlayerHistory::default();
+                    times_tried = 0;
+                    options.detached.clear();
+                }
+                Action::Detach(bus_name) => {
+                    if let Some(i) = players.iter().position(|v| v.bus_name() == bus_name) {
+                        let player = players.remove(i);
+                        log::info!(
+                            player = player.bus_name();
+                            "Detached from player {}, as asked on the admin page.",
+                            display_name(&player, options)
+                        );
+                        let _ = events_tx.send(Event::Detached {
+                            bus_name: bus_name.clone(),
+                        });
+                    }
+                    if !options.detached.contains(&bus_name) {
+                        options.detached.push(bus_name);
+                    }
+                }
+            }
+        }
+
+        if demand.is_idle() {
+            // Signals would only pile up while paused.
+            drop(watcher.take());
+            demand.wait();
+
+            // Whatever changed in the meantime is read right away.
+            history.read_schedule = ReadSchedule::default();
+            history.metadata = MetadataCache::default();
+            history.idle_backoff.reset();
+            times_tried = 0;
+            watcher = watch_bus(options);
+        }
+
+        update_statuses(
+            &mut players,
+            status_tx,
+            events_tx,
+            options,
+            &mut history,
+            attachments,
+            watcher.is_some(),
+        );
+        demand.caught_up();
+
+        let stuck = options.sticky
+            && status_tx
+                .borrow()
+                .active()
+                .is_some_and(|status| status.playback_state == PlaybackState::Playing);
+
+        let found = if options.all_players {
+            find_new_players(&mut finder, &mut times_tried, options, &players)
+        } else if stuck {
+            Ok(Vec::new())
+        } else {
+            find_player(
+                &mut finder,
+                &mut times_tried,
+                options,
+                players.first(),
+                &mut history.play_starts,
+                &mut history.unresponsive,
+            )
+            .map(|v| vec![v])
+        };
+
+        match found {
+            Ok(new_players) => {
+                if !options.all_players && !new_players.is_empty() {
+                    detach_all(&mut players, events_tx);
+                }
+
+                for new_player in new_players {
+                    // A player that restarted shows up again under the same bus name.
+                    detach_stale(
+                        &mut players,
+                        new_player.bus_name(),
+                        new_player.unique_name(),
+                        &mut history.stop_filter,
+                        events_tx,
+                        options,
+                    );
+
+                    log::info!(
+                        player = new_player.bus_name();
+                        "Found new player \"{} ({})\"!",
+                        display_name(&new_player, options),
+                        new_player.bus_name()
+                    );
+                    let _ = events_tx.send(Event::Attached {
+                        bus_name: new_player.bus_name().to_string(),
+                        identity: new_player.identity().to_string(),
+                    });
+                    players.push(new_player);
+                    times_tried = 0;
+                }
+            }
+            Err(duration) => {
+                if players.is_empty() {
+                    let _ = events_tx.send(Event::Searching {
+                        attempts: times_tried.max(finder.times_failed),
+                        retry_in: duration.as_secs_f32(),
+                    });
+
+                    // Cut the backoff short as soon as a player starts.
+                    match &watcher {
+                        Some(bus_watcher) => match bus_watcher.wait_for_player(duration) {
+                            Ok(true) => log::debug!("A player appeared, searching right away."),
+                            Ok(false) => {}
+                            Err(_) => thread::sleep(duration),
+                        },
+                        None => thread::sleep(duration),
+                    }
+                }
+            }
+        }
+
+        if finder.take_reconnected() && options.events {
+            // Signals were missed while disconnected.
+            history.metadata = MetadataCache::default();
+            watcher = BusWatcher::new(&options.bus)
+                .inspect_err(|e| log::warn!("Could not subscribe to player signals: {e}"))
+                .ok();
+        }
+
+        // Keep polling at full speed while searching, or for position updates while playing.
+        let playing_interval = {
+            let snapshot = status_tx.borrow();
+            players
+                .iter()
+                .filter(|player| {
+                    snapshot
+                        .players
+                        .get(player.bus_name())
+                        .is_some_and(|status| status.playback_state == PlaybackState::Playing)
+                })
+                .map(|player| poll_interval(player, options))
+                .min()
+        };
+        if playing_interval.is_some() {
+            history.idle_backoff.reset();
+        }
+
+        let Some(bus_watcher) = &watcher else {
+            let interval = players
+                .iter()
+                .map(|player| poll_interval(player, options))
+                .min()
+                .unwrap_or(options.update_interval);
+            // Only the polls tell when a player resumes, back off gradually.
+            let interval = match playing_interval {
+                None if !players.is_empty() => {
+                    history.idle_backoff.next(interval, options.idle_interval)
+                }
+                _ => interval,
+            };
+            thread::sleep(interval.mul_f32(jitter_factor(options.jitter)));
+            continue;
+        };
+
+        let timeout = if players.is_empty() {
+            options.update_interval
+        } else {
+            playing_interval.unwrap_or(options.idle_interval)
+        };
+
+        match bus_watcher.wait(timeout.mul_f32(jitter_factor(options.jitter))) {
+            Ok(events) => {
+                for event in events {
+                    log::debug!("Received {event:?}.");
+
+                    match event {
+                        BusEvent::Seeked { sender, position } => {
+                            let Some(player) = players.iter().find(|v| v.unique_name() == sender)
+                            else {
+                                continue;
+                            };
+                            if quirks(player, options).ignore_position {
+                                continue;
+                            }
+
+                            // Pushed right away, some players still report the old position for a moment.
+                            let mut seeked = None;
+                            status_tx.send_if_modified(|snapshot| {
+                                match snapshot.players.get_mut(player.bus_name()) {
+                                    Some(status) => {
+                                        let from = status.position_now();
+                                        (status.position, status.degraded) =
+                                            sanitize_position(position, status.metadata.length);
+                                        status.position_time = SampleTime::now();
+                                        seeked = Some((status.position, from));
+                                        true
+                                    }
+                                    None => false,
+                                }
+                            });
+                            if let Some((position, from)) = seeked {
+                                let _ = events_tx.send(Event::Seeked {
+                                    bus_name: player.bus_name().to_string(),
+                                    position,
+                                    from,
+                                });
+                            }
+                        }
+                        BusEvent::NameOwnerChanged { name, new_owner } => {
+                            detach_stale(
+                                &mut players,
+                                &name,
+                                &new_owner,
+                                &mut history.stop_filter,
+                                events_tx,
+                                options,
+                            );
+                        }
+                        BusEvent::PropertiesChanged { ref sender, .. } => {
+                            if event.may_change("Metadata") {
+                                history.metadata.invalidate(sender);
+                            }
+                            if let Some(player) = players.iter().find(|v| v.unique_name() == sender)
+                            {
+                                history.read_schedule.invalidate(player.bus_name());
+                            }
+                            history.unresponsive.answered(sender);
+                        }
+                    }
+                }
+            }
+            Err(e) => {
+                log::warn!("Lost player signal subscription, falling back to polling: {e}");
+                watcher = None;
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const INTERVAL: Duration = Duration::from_secs(1);
+    const IDLE_INTERVAL: Duration = Duration::from_secs(10);
+
+    fn waits(backoff: &mut IdleBackoff, polls: usize) -> Vec<u64> {
+        (0..polls)
+            .map(|_| backoff.next(INTERVAL, IDLE_INTERVAL).as_secs())
+            .collect()
+    }
+
+    #[test]
+    fn idle_backoff_doubles() {
+        let mut backoff = IdleBackoff::default();
+        assert_eq!(waits(&mut backoff, 4), [1, 2, 4, 8]);
+    }
+
+    #[test]
+    fn idle_backoff_stops_at_the_idle_interval() {
+        let mut backoff = IdleBackoff::default();
+        assert_eq!(waits(&mut backoff, 6), [1, 2, 4, 8, 10, 10]);
+        // Doesn't overflow however long nothing plays.
+        backoff.polls = u32::MAX;
+        assert_eq!(waits(&mut backoff, 2), [10, 10]);
+    }
+
+    #[test]
+    fn idle_backoff_resets_once_something_plays() {
+        let mut backoff = IdleBackoff::default();
+        waits(&mut backoff, 5);
+        backoff.reset();
+        assert_eq!(waits(&mut backoff, 3), [1, 2, 4]);
+    }
+
+    #[test]
+    fn idle_backoff_is_capped_from_the_first_poll() {
+        let mut backoff = IdleBackoff::default();
+        assert_eq!(backoff.next(INTERVAL * 20, IDLE_INTERVAL), IDLE_INTERVAL);
+    }
+}
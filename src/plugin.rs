@@ -0,0 +1,470 @@
+use crate::event::Event;
+use crate::sink::{Sink, SinkContext};
+use crate::status::StatusSnapshot;
+use crate::textfile;
+use crate::udp;
+use futures_util::future::BoxFuture;
+use std::fmt;
+use std::net::SocketAddr;
+use std::path::PathBuf;
+use std::sync::Arc;
+use tokio::io::AsyncWriteExt;
+use tokio::net::{TcpStream, UdpSocket};
+use tokio::sync::broadcast::{self, error::RecvError};
+use tokio::sync::watch;
+use wasmi::{
+    Caller, Config, Engine, Extern, Instance, Linker, Module, Store, StoreLimits,
+    StoreLimitsBuilder, TypedFunc,
+};
+
+/// The module plugins import the host's functions from. Another version of the interface would get another name, so old plugins keep loading.
+const HOST_MODULE: &str = "nowplaying_v1";
+
+/// How many instructions a plugin may run per call, so one stuck in a loop can't hold up the daemon. Tens of milliseconds' worth.
+const FUEL: u64 = 10_000_000;
+
+/// How much memory a plugin may grow to.
+const MAX_MEMORY: usize = 64 * 1024 * 1024;
+
+/// A --plugin value: the WebAssembly module, and where what it emits goes.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct PluginFile {
+    pub path: PathBuf,
+    pub destination: Destination,
+}
+
+/// Where a plugin's output goes.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum Destination {
+    /// Each emit is a datagram.
+    Udp(SocketAddr),
+    /// Emits are written one after the other to a connection, opened again when it drops.
+    Tcp(SocketAddr),
+    /// Each emit replaces the file's contents.
+    File(PathBuf),
+}
+
+impl PluginFile {
+    /// Parses a --plugin value, `<path>=<destination>`.
+    pub fn parse(value: &str) -> Result<Self, String> {
+        let Some((path, destination)) = value.split_once('=') else {
+            return Err(String::from(
+                "expected <path>=<destination>, e.g. `lights.wasm=udp://192.168.1.50:4210`",
+            ));
+        };
+        if path.is_empty() {
+            return Err(String::from("the path is empty"));
+        }
+
+        let address = |address: &str| {
+            address
+                .parse::<SocketAddr>()
+                .map_err(|e| format!("invalid address {address}: {e}"))
+        };
+        let destination = if let Some(target) = destination.strip_prefix("udp://") {
+            Destination::Udp(address(target)?)
+        } else if let Some(target) = destination.strip_prefix("tcp://") {
+            Destination::Tcp(address(target)?)
+        } else if destination.is_empty() {
+            return Err(String::from("the destination is empty"));
+        } else {
+            Destination::File(PathBuf::from(destination))
+        };
+
+        Ok(Self {
+            path: PathBuf::from(path),
+            destination,
+        })
+    }
+}
+
+/// A plugin's module, compiled once and instantiated each time the output starts.
+#[derive(Clone)]
+pub struct PluginModule {
+    path: PathBuf,
+    /// Compared on reload, so the output is restarted when the file changed.
+    wasm: Arc<Vec<u8>>,
+    engine: Engine,
+    module: Arc<Module>,
+}
+
+impl PluginModule {
+    /// Reads and compiles the module, checking that it only imports what the host has.
+    pub fn load(path: PathBuf) -> Result<Self, String> {
+        let wasm = std::fs::read(&path)
+            .map_err(|e| format!("could not read --plugin {}: {e}", path.display()))?;
+        let invalid = |e: &dyn fmt::Display| format!("invalid --plugin {}: {e}", path.display());
+
+        let mut config = Config::default();
+        config.consume_fuel(true);
+        let engine = Engine::new(&config);
+        let module = Module::new(&engine, &wasm).map_err(|e| invalid(&e))?;
+
+        if let Some(import) = module.imports().find(|import| {
+            import.module() != HOST_MODULE || !["emit", "log"].contains(&import.name())
+        }) {
+            return Err(invalid(&format!(
+                "imports {}.{}, but only {HOST_MODULE}.emit and {HOST_MODULE}.log are there",
+                import.module(),
+                import.name()
+            )));
+        }
+
+        Ok(Self {
+            path,
+            wasm: Arc::new(wasm),
+            engine,
+            module: Arc::new(module),
+        })
+    }
+}
+
+impl PartialEq for PluginModule {
+    fn eq(&self, other: &Self) -> bool {
+        self.path == other.path && self.wasm == other.wasm
+    }
+}
+
+impl fmt::Debug for PluginModule {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.debug_tuple("PluginModule").field(&self.path).finish()
+    }
+}
+
+/// A --plugin output.
+#[derive(Debug, Clone, PartialEq)]
+pub struct PluginOptions {
+    pub module: PluginModule,
+    pub destination: Destination,
+}
+
+/// What the host functions work with.
+struct Host {
+    /// What the plugin emitted during the current call.
+    emitted: Vec<Vec<u8>>,
+    limits: StoreLimits,
+}
+
+/// Reads `len` bytes at `ptr` from the calling plugin's memory.
+fn read_memory(caller: &Caller<'_, Host>, ptr: i32, len: i32) -> Result<Vec<u8>, wasmi::Error> {
+    let memory = caller
+        .get_export("memory")
+        .and_then(Extern::into_memory)
+        .ok_or_else(|| wasmi::Error::new("the plugin exports no memory"))?;
+
+    let len = len as u32 as usize;
+    if len > MAX_MEMORY {
+        return Err(wasmi::Error::new("more bytes than the plugin can have"));
+    }
+
+    let mut bytes = vec![0; len];
+    memory
+        .read(caller, ptr as u32 as usize, &mut bytes)
+        .map_err(|e| wasmi::Error::new(e.to_string()))?;
+    Ok(bytes)
+}
+
+/// An instance of a plugin, fed the statuses and events.
+struct Plugin {
+    store: Store<Host>,
+    instance: Instance,
+    alloc: Option<TypedFunc<i32, i32>>,
+    on_status: Option<TypedFunc<(i32, i32), ()>>,
+    on_event: Option<TypedFunc<(i32, i32), ()>>,
+}
+
+impl Plugin {
+    fn new(module: &PluginModule) -> Result<Self, wasmi::Error> {
+        let host = Host {
+            emitted: Vec::new(),
+            limits: StoreLimitsBuilder::new().memory_size(MAX_MEMORY).build(),
+        };
+        let mut store = Store::new(&module.engine, host);
+        store.limiter(|host| &mut host.limits);
+        store.set_fuel(FUEL)?;
+
+        let mut linker = Linker::<Host>::new(&module.engine);
+        linker.func_wrap(
+            HOST_MODULE,
+            "emit",
+            |mut caller: Caller<'_, Host>, ptr: i32, len: i32| {
+                let bytes = read_memory(&caller, ptr, len)?;
+                caller.data_mut().emitted.push(bytes);
+                Ok(())
+            },
+        )?;
+        linker.func_wrap(
+            HOST_MODULE,
+            "log",
+            |caller: Caller<'_, Host>, level: i32, ptr: i32, len: i32| {
+                let bytes = read_memory(&caller, ptr, len)?;
+                let level = match level {
+                    1 => log::Level::Error,
+                    2 => log::Level::Warn,
+                    3 => log::Level::Info,
+                    4 => log::Level::Debug,
+                    _ => log::Level::Trace,
+                };
+                log::log!(level, "{}", String::from_utf8_lossy(&bytes));
+                Ok(())
+            },
+        )?;
+
+        let instance = linker
+            .instantiate(&mut store, &module.module)?
+            .start(&mut store)?;
+
+        let on_status = instance.get_typed_func(&store, "on_status").ok();
+        let on_event = instance.get_typed_func(&store, "on_event").ok();
+        let alloc = instance.get_typed_func(&store, "alloc").ok();
+        if alloc.is_none() && (on_status.is_some() || on_event.is_some()) {
+            return Err(wasmi::Error::new(
+                "the plugin exports on_status or on_event, but no alloc to pass them anything",
+            ));
+        }
+
+        Ok(Self {
+            store,
+            instance,
+            alloc,
+            on_status,
+            on_event,
+        })
+    }
+
+    /// Calls `function` with `input` copied into the plugin's memory, returning what it emitted.
+    fn call(
+        &mut self,
+        function: Option<TypedFunc<(i32, i32), ()>>,
+        input: &[u8],
+    ) -> Result<Vec<Vec<u8>>, wasmi::Error> {
+        let (Some(function), Some(alloc)) = (function, self.alloc) else {
+            return Ok(Vec::new());
+        };
+        self.store.set_fuel(FUEL)?;
+
+        let len = i32::try_from(input.len()).map_err(|e| wasmi::Error::new(e.to_string()))?;
+        let ptr = alloc.call(&mut self.store, len)?;
+        let memory = self
+            .instance
+            .get_memory(&self.store, "memory")
+            .ok_or_else(|| wasmi::Error::new("the plugin exports no memory"))?;
+        memory
+            .write(&mut self.store, ptr as u32 as usize, input)
+            .map_err(|e| wasmi::Error::new(e.to_string()))?;
+
+        let result = function.call(&mut self.store, (ptr, len));
+        let emitted = std::mem::take(&mut self.store.data_mut().emitted);
+        result.map(|()| emitted)
+    }
+
+    fn status(&mut self, snapshot: &StatusSnapshot) -> Result<Vec<Vec<u8>>, wasmi::Error> {
+        let json = serde_json::to_vec(&snapshot.active()).unwrap();
+        self.call(self.on_status, &json)
+    }
+
+    fn event(&mut self, event: &Event) -> Result<Vec<Vec<u8>>, wasmi::Error> {
+        let json = serde_json::to_vec(event).unwrap();
+        self.call(self.on_event, &json)
+    }
+}
+
+/// Sends what a plugin emitted to its destination, warning once while that fails.
+struct Output {
+    destination: Destination,
+    udp: Option<UdpSocket>,
+    tcp: Option<TcpStream>,
+    failing: bool,
+}
+
+impl Output {
+    fn new(destination: Destination) -> Self {
+        Self {
+            destination,
+            udp: None,
+            tcp: None,
+            failing: false,
+        }
+    }
+
+    async fn send(&mut self, bytes: &[u8]) -> std::io::Result<()> {
+        match &self.destination {
+            Destination::Udp(target) => {
+                if self.udp.is_none() {
+                    self.udp = Some(udp::socket(*target).await?);
+                }
+                if let Some(socket) = &self.udp {
+                    socket.send_to(bytes, target).await?;
+                }
+            }
+            Destination::Tcp(target) => {
+                if self.tcp.is_none() {
+                    self.tcp = Some(TcpStream::connect(target).await?);
+                }
+                if let Some(stream) = &mut self.tcp {
+                    if let Err(e) = stream.write_all(bytes).await {
+                        // Connected again for the next one.
+                        self.tcp = None;
+                        return Err(e);
+                    }
+                }
+            }
+            Destination::File(path) => textfile::write_atomically(path, bytes).await?,
+        }
+        Ok(())
+    }
+
+    async fn send_all(&mut self, emitted: Vec<Vec<u8>>) {
+        for bytes in emitted {
+            match self.send(&bytes).await {
+                Ok(()) => self.failing = false,
+                Err(e) if !self.failing => {
+                    log::warn!("Could not send a plugin's output to {}: {e}", self);
+                    self.failing = true;
+                }
+                Err(_) => {}
+            }
+        }
+    }
+}
+
+impl fmt::Display for Output {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match &self.destination {
+            Destination::Udp(target) => write!(f, "udp://{target}"),
+            Destination::Tcp(target) => write!(f, "tcp://{target}"),
+            Destination::File(path) => write!(f, "{}", path.display()),
+        }
+    }
+}
+
+/// Feeds the active player's status and the events to the plugin, sending what it emits on.
+pub async fn run(
+    options: PluginOptions,
+    mut status_rx: watch::Receiver<StatusSnapshot>,
+    mut events_rx: broadcast::Receiver<Event>,
+) {
+    let path = options.module.path.display();
+    let mut plugin = match Plugin::new(&options.module) {
+        Ok(plugin) => plugin,
+        Err(e) => {
+            log::error!("Could not start --plugin {path}: {e}");
+            return;
+        }
+    };
+    let mut output = Output::new(options.destination.clone());
+    log::info!("Running --plugin {path}, sending its output to {output}!");
+
+    let mut failing = false;
+    status_rx.mark_changed();
+    loop {
+        let result = tokio::select! {
+            changed = status_rx.changed() => {
+                if changed.is_err() {
+                    return;
+                }
+                let snapshot = status_rx.borrow_and_update().clone();
+                plugin.status(&snapshot)
+            }
+            event = events_rx.recv() => match event {
+                Ok(event) => plugin.event(&event),
+                Err(RecvError::Lagged(_)) => continue,
+                Err(RecvError::Closed) => return,
+            },
+        };
+
+        match result {
+            Ok(emitted) => {
+                failing = false;
+                output.send_all(emitted).await;
+            }
+            // A plugin that traps on every status would flood the log otherwise.
+            Err(e) if !failing => {
+                log::warn!("--plugin {path} failed: {e}");
+                failing = true;
+            }
+            Err(_) => {}
+        }
+    }
+}
+
+impl Sink for PluginOptions {
+    fn name(&self) -> &'static str {
+        "plugin"
+    }
+
+    fn run(&self, context: &SinkContext) -> BoxFuture<'static, ()> {
+        Box::pin(run(
+            self.clone(),
+            context.status_rx.clone(),
+            context.state.events_tx.subscribe(),
+        ))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    /// Emits the length of each status it's given, and `event` for events.
+    const COUNTER: &str = r#"
+        (module
+            (import "nowplaying_v1" "emit" (func $emit (param i32 i32)))
+            (memory (export "memory") 1)
+            (global $next (mut i32) (i32.const 1024))
+            (data (i32.const 0) "event")
+            (func (export "alloc") (param $len i32) (result i32)
+                (local $ptr i32)
+                (local.set $ptr (global.get $next))
+                (global.set $next (i32.add (global.get $next) (local.get $len)))
+                (local.get $ptr))
+            (func (export "on_status") (param $ptr i32) (param $len i32)
+                (i32.store8 (i32.const 16) (local.get $len))
+                (call $emit (i32.const 16) (i32.const 1)))
+            (func (export "on_event") (param $ptr i32) (param $len i32)
+                (call $emit (i32.const 0) (i32.const 5))))
+    "#;
+
+    fn module(wat: &str) -> Result<PluginModule, String> {
+        let path = std::env::temp_dir().join(format!(
+            "mpris-nowplaying-plugin-{}-{}.wasm",
+            std::process::id(),
+            fastrand::u64(..)
+        ));
+        std::fs::write(&path, wat::parse_str(wat).unwrap()).unwrap();
+        let module = PluginModule::load(path.clone());
+        std::fs::remove_file(path).unwrap();
+        module
+    }
+
+    #[test]
+    fn statuses_and_events() {
+        let mut plugin = Plugin::new(&module(COUNTER).unwrap()).unwrap();
+
+        // `null`, as there's no player.
+        assert_eq!(
+            plugin.status(&StatusSnapshot::default()).unwrap(),
+            [vec![4]]
+        );
+        let event = Event::Detached {
+            bus_name: String::from("org.mpris.MediaPlayer2.mock"),
+        };
+        assert_eq!(plugin.event(&event).unwrap(), [b"event".to_vec()]);
+    }
+
+    #[test]
+    fn runaway_plugins_are_stopped() {
+        let mut plugin = Plugin::new(
+            &module(
+                r#"(module
+                    (memory (export "memory") 1)
+                    (func (export "alloc") (param i32) (result i32) (i32.const 0))
+                    (func (export "on_status") (param i32 i32) (loop (br 0))))"#,
+            )
+            .unwrap(),
+        )
+        .unwrap();
+
+        assert!(plugin.status(&StatusSnapshot::default()).is_err());
+    }
+}
@@ -0,0 +1,131 @@
+use crate::event::Event;
+use crate::sink::{Sink, SinkContext};
+use crate::status::StatusSnapshot;
+use futures_util::future::BoxFuture;
+use serde::{Deserialize, Serialize};
+use std::fs::File;
+use std::io::{self, Write};
+use std::path::{Path, PathBuf};
+use std::time::Instant;
+use tokio::sync::broadcast::{self, error::RecvError};
+use tokio::sync::watch;
+
+/// A line of a --record capture.
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+#[serde(rename_all = "camelCase")]
+pub struct Captured {
+    /// When it happened, in milliseconds since the recording started.
+    pub at: u64,
+    #[serde(flatten)]
+    pub entry: CaptureEntry,
+}
+
+/// What happened: `{"status": ...}` with every player's status, or `{"event": ...}`.
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+#[serde(rename_all = "camelCase")]
+pub enum CaptureEntry {
+    Status(StatusSnapshot),
+    Event(Event),
+}
+
+/// Settings for --record.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct RecordOptions {
+    /// The capture, started over each time the daemon starts.
+    pub path: PathBuf,
+}
+
+/// Writes captured lines to a file, warning once when it starts failing.
+struct Recorder {
+    path: PathBuf,
+    file: File,
+    started: Instant,
+    failing: bool,
+}
+
+impl Recorder {
+    fn create(path: &Path) -> io::Result<Self> {
+        if let Some(dir) = path.parent().filter(|dir| !dir.as_os_str().is_empty()) {
+            std::fs::create_dir_all(dir)?;
+        }
+
+        Ok(Self {
+            path: path.to_path_buf(),
+            file: File::create(path)?,
+            started: Instant::now(),
+            failing: false,
+        })
+    }
+
+    fn write(&mut self, entry: CaptureEntry) {
+        let captured = Captured {
+            at: self.started.elapsed().as_millis() as u64,
+            entry,
+        };
+        let mut line = serde_json::to_string(&captured).unwrap();
+        line.push('\n');
+
+        // In one write, so a capture cut short never ends in half a line.
+        match self.file.write_all(line.as_bytes()) {
+            Ok(()) => self.failing = false,
+            Err(e) => {
+                if !self.failing {
+                    log::warn!("Could not write to --record {}: {e}", self.path.display());
+                    self.failing = true;
+                }
+            }
+        }
+    }
+}
+
+/// Captures every status and event to `options.path`, one JSON line each, for `replay` to play back.
+pub async fn run(
+    options: RecordOptions,
+    mut status_rx: watch::Receiver<StatusSnapshot>,
+    mut events_rx: broadcast::Receiver<Event>,
+) {
+    let mut recorder = match Recorder::create(&options.path) {
+        Ok(recorder) => recorder,
+        Err(e) => {
+            log::warn!("Could not create --record {}: {e}", options.path.display());
+            return;
+        }
+    };
+    log::info!("Recording to {}.", options.path.display());
+
+    let snapshot = status_rx.borrow_and_update().clone();
+    recorder.write(CaptureEntry::Status(snapshot));
+
+    loop {
+        tokio::select! {
+            changed = status_rx.changed() => {
+                if changed.is_err() {
+                    return;
+                }
+                let snapshot = status_rx.borrow_and_update().clone();
+                recorder.write(CaptureEntry::Status(snapshot));
+            }
+            event = events_rx.recv() => match event {
+                Ok(event) => recorder.write(CaptureEntry::Event(event)),
+                Err(RecvError::Lagged(skipped)) => {
+                    log::warn!("--record fell behind, {skipped} event(s) are missing from it.");
+                }
+                Err(RecvError::Closed) => return,
+            },
+        }
+    }
+}
+
+impl Sink for RecordOptions {
+    fn name(&self) -> &'static str {
+        "record"
+    }
+
+    fn run(&self, context: &SinkContext) -> BoxFuture<'static, ()> {
+        Box::pin(run(
+            self.clone(),
+            context.status_rx.clone(),
+            context.state.events_tx.subscribe(),
+        ))
+    }
+}
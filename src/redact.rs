@@ -0,0 +1,311 @@
+use crate::artwork::ArtworkOrigin;
+use crate::event::Event;
+use crate::status::{send_changed, PlayerStatus, StatusMetadata, StatusSnapshot};
+use regex::Regex;
+use tokio::sync::broadcast::{self, error::RecvError};
+use tokio::sync::watch;
+
+/// What text matching a --redact-pattern is replaced with.
+const MASK: &str = "***";
+
+/// A part of the metadata --redact strips.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, clap::ValueEnum)]
+pub enum RedactField {
+    /// The title, and `titleLatin`. The lyrics, MusicBrainz ids and Spotify info go with it, as they tell the track just as well.
+    Title,
+    /// The artist, and `artistLatin`. The lyrics, MusicBrainz ids and Spotify info go with it too.
+    Artist,
+    Album,
+    /// Where the track is from (`url`), whether a local file or a stream.
+    Url,
+    /// The player's id for the track (`trackId`), which some players make from the file's path.
+    TrackId,
+    /// All of the artwork, and Spotify's covers.
+    Artwork,
+    /// The `url` and the artwork only when they're local files, which show the folders they're in. Web ones are kept.
+    LocalFiles,
+}
+
+/// What --redact and --redact-pattern take out of the statuses and events.
+#[derive(Debug, Clone, Default)]
+pub struct Redaction {
+    pub fields: Vec<RedactField>,
+    /// Masked wherever they match the title, artist, album, `url`, `trackId` or an artwork's `src`.
+    pub patterns: Vec<Regex>,
+}
+
+impl Redaction {
+    /// Whether there's nothing to take out.
+    pub fn is_empty(&self) -> bool {
+        self.fields.is_empty() && self.patterns.is_empty()
+    }
+
+    /// Takes what's redacted out of a player's status, including what was looked up about its track.
+    pub fn redact_status(&self, status: &mut PlayerStatus) {
+        self.redact_metadata(&mut status.metadata);
+
+        if self
+            .fields
+            .iter()
+            .any(|field| matches!(field, RedactField::Title | RedactField::Artist))
+        {
+            status.lyrics = None;
+            status.musicbrainz = None;
+            status.spotify = None;
+        }
+        if self.fields.contains(&RedactField::Artwork) {
+            if let Some(spotify) = &mut status.spotify {
+                spotify.images.clear();
+            }
+        }
+    }
+
+    /// Takes what's redacted out of the metadata an event carries.
+    pub fn redact_event(&self, event: &mut Event) {
+        match event {
+            Event::TrackChanged { metadata, .. } | Event::NowPlayingConfirmed { metadata, .. } => {
+                self.redact_metadata(metadata)
+            }
+            Event::Attached { .. }
+            | Event::Detached { .. }
+            | Event::Searching { .. }
+            | Event::Seeked { .. } => {}
+        }
+    }
+
+    fn redact_metadata(&self, metadata: &mut StatusMetadata) {
+        for field in &self.fields {
+            match field {
+                RedactField::Title => {
+                    metadata.title.clear();
+                    metadata.title_latin.clear();
+                }
+                RedactField::Artist => {
+                    metadata.artist.clear();
+                    metadata.artist_latin.clear();
+                }
+                RedactField::Album => metadata.album.clear(),
+                RedactField::Url => metadata.url.clear(),
+                RedactField::TrackId => metadata.track_id.clear(),
+                RedactField::Artwork => metadata.artwork.clear(),
+                RedactField::LocalFiles => {
+                    if is_local(&metadata.url) {
+                        metadata.url.clear();
+                    }
+                    // Embedded covers are read from the track's own file.
+                    metadata.artwork.retain(|artwork| {
+                        artwork.origin != ArtworkOrigin::Embedded && !is_local(&artwork.src)
+                    });
+                }
+            }
+        }
+
+        if self.patterns.is_empty() {
+            return;
+        }
+        let texts = [
+            &mut metadata.title,
+            &mut metadata.title_latin,
+            &mut metadata.artist,
+            &mut metadata.artist_latin,
+            &mut metadata.album,
+            &mut metadata.url,
+            &mut metadata.track_id,
+        ];
+        let sources = metadata.artwork.iter_mut().map(|artwork| &mut artwork.src);
+        for text in texts.into_iter().chain(sources) {
+            for pattern in &self.patterns {
+                if let std::borrow::Cow::Owned(masked) = pattern.replace_all(text, MASK) {
+                    *text = masked;
+                }
+            }
+        }
+    }
+}
+
+/// Whether `src` is a local file, as a `file://` URL or a plain path.
+fn is_local(src: &str) -> bool {
+    src.starts_with("file://") || src.starts_with('/')
+}
+
+/// Forwards every status from `raw_rx` to `status_tx` with `redaction` applied to each player's, so clients and outputs never see what was taken out (--redact).
+pub async fn run(
+    redaction: Redaction,
+    mut raw_rx: watch::Receiver<StatusSnapshot>,
+    status_tx: watch::Sender<StatusSnapshot>,
+) {
+    loop {
+        let mut snapshot = raw_rx.borrow_and_update().clone();
+        for status in snapshot.players.values_mut() {
+            redaction.redact_status(status);
+        }
+        if !send_changed(&status_tx, snapshot) || raw_rx.changed().await.is_err() {
+            return;
+        }
+    }
+}
+
+/// Forwards every event from `raw_rx` to `events_tx` with `redaction` applied, like [`run`] does for the statuses.
+pub async fn events(
+    redaction: Redaction,
+    mut raw_rx: broadcast::Receiver<Event>,
+    events_tx: broadcast::Sender<Event>,
+) {
+    loop {
+        match raw_rx.recv().await {
+            Ok(mut event) => {
+                redaction.redact_event(&mut event);
+                let _ = events_tx.send(event);
+            }
+            Err(RecvError::Lagged(_)) => {}
+            Err(RecvError::Closed) => return,
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use serde_json::json;
+
+    fn status() -> PlayerStatus {
+        serde_json::from_value(json!({
+            "player": { "busName": "org.mpris.MediaPlayer2.mpd", "identity": "Music Player Daemon" },
+            "metadata": {
+                "title": "東京",
+                "titleLatin": "Dong Jing",
+                "artist": "Private Person",
+                "artistLatin": "Private Person",
+                "album": "Home Demos",
+                "artwork": [
+                    { "src": "file:///home/someone/Music/Home%20Demos/cover.jpg" },
+                    { "src": "https://example.com/private/cover.jpg" }
+                ],
+                "length": 180000000,
+                "url": "file:///home/someone/Music/Home%20Demos/track.flac",
+                "trackId": "/org/mpd/Tracks/home/someone/private"
+            },
+            "playbackState": "playing",
+            "position": 0,
+            "degraded": false,
+            "lyrics": { "current": { "time": 0, "text": "A line" }, "next": null },
+            "musicbrainz": { "recordingId": "b1a9c0e9", "artistIds": ["5b11f4ce"] },
+            "spotify": {
+                "explicit": false,
+                "images": [{ "url": "https://i.scdn.co/image/ab67", "width": 640, "height": 640 }]
+            }
+        }))
+        .unwrap()
+    }
+
+    fn redacted(fields: &[RedactField], patterns: &[&str]) -> PlayerStatus {
+        let redaction = Redaction {
+            fields: fields.to_vec(),
+            patterns: patterns.iter().map(|p| Regex::new(p).unwrap()).collect(),
+        };
+        let mut status = status();
+        redaction.redact_status(&mut status);
+        status
+    }
+
+    #[test]
+    fn nothing_to_redact() {
+        assert!(Redaction::default().is_empty());
+        assert_eq!(redacted(&[], &[]), status());
+    }
+
+    #[test]
+    fn title() {
+        let status = redacted(&[RedactField::Title], &[]);
+        assert_eq!(status.metadata.title, "");
+        assert_eq!(status.metadata.title_latin, "");
+        assert_eq!(status.metadata.artist, "Private Person");
+        assert_eq!(status.lyrics, None);
+        assert_eq!(status.musicbrainz, None);
+        assert_eq!(status.spotify, None);
+    }
+
+    #[test]
+    fn artist() {
+        let status = redacted(&[RedactField::Artist], &[]);
+        assert_eq!(status.metadata.artist, "");
+        assert_eq!(status.metadata.artist_latin, "");
+        assert_eq!(status.metadata.title, "東京");
+        assert_eq!(status.lyrics, None);
+        assert_eq!(status.musicbrainz, None);
+        assert_eq!(status.spotify, None);
+    }
+
+    #[test]
+    fn album() {
+        let status = redacted(&[RedactField::Album], &[]);
+        assert_eq!(status.metadata.album, "");
+        assert_eq!(status.metadata.title, "東京");
+        assert!(status.lyrics.is_some());
+    }
+
+    #[test]
+    fn url() {
+        let status = redacted(&[RedactField::Url], &[]);
+        assert_eq!(status.metadata.url, "");
+        assert_eq!(status.metadata.artwork.len(), 2);
+    }
+
+    #[test]
+    fn track_id() {
+        let status = redacted(&[RedactField::TrackId], &[]);
+        assert_eq!(status.metadata.track_id, "");
+        assert_eq!(status.metadata.title, "東京");
+    }
+
+    #[test]
+    fn artwork() {
+        let status = redacted(&[RedactField::Artwork], &[]);
+        assert!(status.metadata.artwork.is_empty());
+        assert!(status.spotify.unwrap().images.is_empty());
+        assert!(status.lyrics.is_some());
+    }
+
+    #[test]
+    fn local_files() {
+        let status = redacted(&[RedactField::LocalFiles], &[]);
+        assert_eq!(status.metadata.url, "");
+        let sources: Vec<_> = status.metadata.artwork.iter().map(|a| &a.src).collect();
+        assert_eq!(sources, ["https://example.com/private/cover.jpg"]);
+    }
+
+    #[test]
+    fn patterns() {
+        let status = redacted(&[], &["(?i)private", "Demos"]);
+        let metadata = &status.metadata;
+        assert_eq!(metadata.artist, "*** Person");
+        assert_eq!(metadata.artist_latin, "*** Person");
+        assert_eq!(metadata.album, "Home ***");
+        assert_eq!(
+            metadata.url,
+            "file:///home/someone/Music/Home%20***/track.flac"
+        );
+        assert_eq!(metadata.track_id, "/org/mpd/Tracks/home/someone/***");
+        assert_eq!(metadata.artwork[1].src, "https://example.com/***/cover.jpg");
+        assert_eq!(metadata.title, "東京");
+    }
+
+    #[test]
+    fn events() {
+        let redaction = Redaction {
+            fields: vec![RedactField::Album, RedactField::Url],
+            patterns: Vec::new(),
+        };
+        let mut event = Event::TrackChanged {
+            bus_name: String::from("org.mpris.MediaPlayer2.mpd"),
+            metadata: status().metadata,
+        };
+        redaction.redact_event(&mut event);
+        let Event::TrackChanged { metadata, .. } = event else {
+            unreachable!();
+        };
+        assert_eq!(metadata.album, "");
+        assert_eq!(metadata.url, "");
+        assert_eq!(metadata.title, "東京");
+    }
+}
@@ -0,0 +1,247 @@
+use crate::event::Event;
+use crate::history::HistoryEntry;
+use crate::player::PlayerOptions;
+use crate::record::{CaptureEntry, Captured};
+use crate::status::{send_changed, PlaybackState, PlayerStatus, SampleTime, StatusSnapshot};
+use serde::de::DeserializeOwned;
+use std::path::Path;
+use std::sync::mpsc;
+use std::thread;
+use std::time::{Duration, Instant};
+use tokio::sync::{broadcast, watch};
+
+/// Longer breaks between recorded plays are cut short, nobody wants to wait hours for the next track.
+const MAX_GAP: Duration = Duration::from_secs(5);
+
+/// What --replay plays back.
+#[derive(Debug, Clone, PartialEq)]
+pub enum Recording {
+    /// Statuses as `--stdout` and `--json-lines` write them, one per line, `null` while no player was attached.
+    Statuses(Vec<Option<PlayerStatus>>),
+    /// Plays as `GET /recent` and `history/<count>` answer them, oldest first.
+    History(Vec<HistoryEntry>),
+    /// Every player's statuses and the events as --record writes them, one per line.
+    Capture(Vec<Captured>),
+}
+
+/// Reads a JSON value from each line that isn't blank.
+fn lines<T: DeserializeOwned>(text: &str) -> Result<Vec<T>, String> {
+    text.lines()
+        .enumerate()
+        .filter(|(_, line)| !line.trim().is_empty())
+        .map(|(index, line)| {
+            serde_json::from_str(line).map_err(|e| format!("line {}: {e}", index + 1))
+        })
+        .collect()
+}
+
+impl Recording {
+    /// Reads a recording, telling the kinds apart by whether it's a JSON array, and whether its first line is a --record one.
+    pub fn load(path: &Path) -> Result<Self, String> {
+        let text = std::fs::read_to_string(path).map_err(|e| e.to_string())?;
+        let first_line = text.lines().find(|line| !line.trim().is_empty());
+
+        let recording = if text.trim_start().starts_with('[') {
+            let mut history: Vec<HistoryEntry> =
+                serde_json::from_str(&text).map_err(|e| e.to_string())?;
+            history.reverse();
+            Self::History(history)
+        } else if first_line.is_some_and(|line| serde_json::from_str::<Captured>(line).is_ok()) {
+            Self::Capture(lines(&text)?)
+        } else {
+            Self::Statuses(lines(&text)?)
+        };
+
+        match &recording {
+            Self::Statuses(statuses) if statuses.is_empty() => {
+                Err(String::from("there's nothing in it"))
+            }
+            Self::History(history) if history.is_empty() => {
+                Err(String::from("there's nothing in it"))
+            }
+            Self::Capture(captured) if captured.is_empty() => {
+                Err(String::from("there's nothing in it"))
+            }
+            _ => Ok(recording),
+        }
+    }
+}
+
+/// Reports statuses like an attached player, telling subscribed clients when one comes and goes.
+struct Replayer {
+    status_tx: watch::Sender<StatusSnapshot>,
+    events_tx: broadcast::Sender<Event>,
+    attached: Option<String>,
+}
+
+impl Replayer {
+    /// Reports `status` as read just now, whenever it was recorded.
+    fn send(&mut self, mut status: Option<PlayerStatus>) {
+        if let Some(status) = &mut status {
+            status.position_time = SampleTime::now();
+        }
+
+        let bus_name = status.as_ref().map(|status| &status.player.bus_name);
+        if bus_name != self.attached.as_ref() {
+            if let Some(bus_name) = self.attached.take() {
+                let _ = self.events_tx.send(Event::Detached { bus_name });
+            }
+            if let Some(status) = &status {
+                let _ = self.events_tx.send(Event::Attached {
+                    bus_name: status.player.bus_name.clone(),
+                    identity: status.player.identity.clone(),
+                });
+                self.attached = Some(status.player.bus_name.clone());
+            }
+        }
+        if let Some(status) = &status {
+            let track_change = Event::track_change(self.status_tx.borrow().active(), status);
+            if let Some(event) = track_change {
+                let _ = self.events_tx.send(event);
+            }
+        }
+
+        send_changed(
+            &self.status_tx,
+            match status {
+                Some(status) => StatusSnapshot {
+                    active: Some(status.player.bus_name.clone()),
+                    players: [(status.player.bus_name.clone(), status)].into(),
+                },
+                None => StatusSnapshot::default(),
+            },
+        );
+    }
+}
+
+impl Replayer {
+    /// Reports what was captured as if it happened just now. The events were captured too, so none are made up.
+    fn replay_captured(&mut self, entry: CaptureEntry) {
+        match entry {
+            CaptureEntry::Status(mut snapshot) => {
+                for status in snapshot.players.values_mut() {
+                    status.position_time = SampleTime::now();
+                }
+                send_changed(&self.status_tx, snapshot);
+            }
+            CaptureEntry::Event(event) => {
+                let _ = self.events_tx.send(event);
+            }
+        }
+    }
+}
+
+/// How long a recorded play took, as far as it can be played back: from its start to its end, but not past the track's end.
+fn play_time(entry: &HistoryEntry) -> Duration {
+    let time = match entry.ended_at {
+        Some(ended_at) => Duration::from_millis(ended_at.saturating_sub(entry.started_at)),
+        None => Duration::from_micros(entry.listened),
+    };
+
+    match entry.metadata.length {
+        0 => time,
+        length => time.min(Duration::from_micros(length)),
+    }
+}
+
+/// Plays `recording` back over and over, `speed` times as fast as it happened, reporting it like an attached player. D-Bus isn't touched.
+///
+/// Recorded statuses are reported one per --interval, the pace they're written at. Recorded plays are played from the start, with ticking positions. Captures are played back at the pace they were recorded at.
+pub fn run(
+    recording: Recording,
+    speed: f32,
+    mut options: PlayerOptions,
+    options_rx: mpsc::Receiver<PlayerOptions>,
+    status_tx: watch::Sender<StatusSnapshot>,
+    events_tx: broadcast::Sender<Event>,
+) {
+    log::info!("Replaying a recording instead of looking for players.");
+    let mut replayer = Replayer {
+        status_tx,
+        events_tx,
+        attached: None,
+    };
+
+    loop {
+        match &recording {
+            Recording::Statuses(statuses) => {
+                for status in statuses {
+                    if let Some(new_options) = options_rx.try_iter().last() {
+                        options = new_options;
+                        log::info!("Reloaded the player options!");
+                    }
+
+                    replayer.send(status.clone());
+                    thread::sleep(options.update_interval.div_f32(speed));
+                }
+            }
+            Recording::History(history) => {
+                for (index, entry) in history.iter().enumerate() {
+                    let time = play_time(entry);
+                    log::debug!(
+                        "Replaying {} – {} for {time:?}.",
+                        entry.metadata.artist,
+                        entry.metadata.title
+                    );
+
+                    let started = Instant::now();
+                    loop {
+                        if let Some(new_options) = options_rx.try_iter().last() {
+                            options = new_options;
+                            log::info!("Reloaded the player options!");
+                        }
+
+                        let position = started.elapsed().mul_f32(speed).min(time);
+                        replayer.send(Some(PlayerStatus {
+                            player: entry.player.clone(),
+                            metadata: entry.metadata.clone(),
+                            playback_state: PlaybackState::Playing,
+                            position: position.as_micros() as u64,
+                            position_time: SampleTime::now(),
+                            degraded: false,
+                            stale: false,
+                            lyrics: None,
+                            musicbrainz: None,
+                            spotify: None,
+                        }));
+                        if position >= time {
+                            break;
+                        }
+                        thread::sleep(options.update_interval);
+                    }
+
+                    // Until the next play started, or the first one again.
+                    let gap = match history.get(index + 1) {
+                        Some(next) => Duration::from_millis(
+                            next.started_at
+                                .saturating_sub(entry.started_at)
+                                .saturating_sub(time.as_millis() as u64),
+                        )
+                        .div_f32(speed),
+                        None => MAX_GAP,
+                    }
+                    .min(MAX_GAP);
+                    if !gap.is_zero() {
+                        replayer.send(None);
+                        thread::sleep(gap);
+                    }
+                }
+            }
+            Recording::Capture(captured) => {
+                let mut last_at = 0;
+                for line in captured {
+                    if let Some(new_options) = options_rx.try_iter().last() {
+                        options = new_options;
+                        log::info!("Reloaded the player options!");
+                    }
+
+                    let gap = Duration::from_millis(line.at.saturating_sub(last_at)).div_f32(speed);
+                    thread::sleep(gap.min(MAX_GAP));
+                    last_at = line.at;
+
+                    replayer.replay_captured(line.entry.clone());
+                }
+            }
+        }
+    }
+}
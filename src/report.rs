@@ -0,0 +1,183 @@
+use crate::exit_code;
+use crate::historydb::{ArtistPlays, DayTime, HistoryDb, HourTime, TrackPlays};
+use clap::ValueEnum;
+use handlebars::html_escape;
+use serde::Serialize;
+use std::fmt::Write;
+use std::path::Path;
+
+/// How many tracks and artists a report lists.
+const TOP_COUNT: usize = 10;
+
+/// What a report covers, up to now.
+#[derive(ValueEnum, Debug, Clone, Copy, PartialEq, Eq)]
+pub enum ReportPeriod {
+    /// The last 24 hours.
+    Day,
+    /// The last 7 days.
+    Week,
+    /// The last 30 days.
+    Month,
+}
+
+impl ReportPeriod {
+    /// As a statistics period, see `stats/top`.
+    fn stats_period(self) -> &'static str {
+        match self {
+            Self::Day => "24h",
+            Self::Week => "7d",
+            Self::Month => "30d",
+        }
+    }
+
+    fn name(self) -> &'static str {
+        match self {
+            Self::Day => "day",
+            Self::Week => "week",
+            Self::Month => "month",
+        }
+    }
+
+    fn title(self) -> &'static str {
+        match self {
+            Self::Day => "Listening report – last 24 hours",
+            Self::Week => "Listening report – last 7 days",
+            Self::Month => "Listening report – last 30 days",
+        }
+    }
+}
+
+/// A summary of what was listened to, from --history-db.
+#[derive(Debug, Clone, PartialEq, Eq, Serialize)]
+#[serde(rename_all = "camelCase")]
+struct Report {
+    /// `day`, `week` or `month`.
+    period: &'static str,
+    /// Where the period starts, in milliseconds since the epoch.
+    since: u64,
+    /// In microseconds like `position`, as are the other listening times.
+    listened: u64,
+    tracks: Vec<TrackPlays>,
+    artists: Vec<ArtistPlays>,
+    days: Vec<DayTime>,
+    /// All 24 hours of the day in order, to find the busiest ones.
+    hours: Vec<HourTime>,
+}
+
+impl Report {
+    fn read(db: &HistoryDb, period: ReportPeriod) -> Result<Self, String> {
+        let top = db.top(period.stats_period(), Some(TOP_COUNT))?;
+        let time = db.time(period.stats_period(), Some(TOP_COUNT))?;
+
+        Ok(Self {
+            period: period.name(),
+            since: top.since.unwrap_or_default(),
+            listened: time.total,
+            tracks: top.tracks,
+            artists: top.artists,
+            days: time.days,
+            hours: db.hours(period.stats_period())?,
+        })
+    }
+
+    /// A page that opens in any browser, without scripts or anything to load.
+    fn html(&self, title: &str) -> String {
+        let mut html = format!(
+            "<!DOCTYPE html>\n<html>\n<head>\n<meta charset=\"utf-8\">\n<title>{title}</title>\n<style>\n{STYLE}</style>\n</head>\n<body>\n<h1>{title}</h1>\n<p class=\"total\">{} listened</p>\n",
+            duration(self.listened)
+        );
+
+        html.push_str("<h2>Top tracks</h2>\n<ol>\n");
+        for track in &self.tracks {
+            let _ = writeln!(
+                html,
+                "<li>{} <span>– {}</span> <small>{}</small></li>",
+                html_escape(&track.title),
+                html_escape(&track.artist),
+                plays(track.plays)
+            );
+        }
+        html.push_str("</ol>\n<h2>Top artists</h2>\n<ol>\n");
+        for artist in &self.artists {
+            let _ = writeln!(
+                html,
+                "<li>{} <small>{}</small></li>",
+                html_escape(&artist.artist),
+                plays(artist.plays)
+            );
+        }
+
+        html.push_str("</ol>\n<h2>Busiest hours</h2>\n<table>\n");
+        let busiest = self.hours.iter().map(|hour| hour.listened).max();
+        for hour in &self.hours {
+            let _ = writeln!(
+                html,
+                "<tr><th>{:02}:00</th><td><div class=\"bar\" style=\"width: {}%\"></div></td><td>{}</td></tr>",
+                hour.hour,
+                (hour.listened * 100)
+                    .checked_div(busiest.unwrap_or_default())
+                    .unwrap_or_default(),
+                duration(hour.listened)
+            );
+        }
+
+        html.push_str("</table>\n<h2>Days</h2>\n<table>\n");
+        for day in &self.days {
+            let _ = writeln!(
+                html,
+                "<tr><th>{}</th><td>{}</td></tr>",
+                html_escape(&day.day),
+                duration(day.listened)
+            );
+        }
+        html.push_str("</table>\n</body>\n</html>\n");
+
+        html
+    }
+}
+
+const STYLE: &str =
+    "body { font-family: sans-serif; max-width: 40em; margin: 2em auto; padding: 0 1em; }
+span, small, .total { color: #666; }
+table { width: 100%; border-collapse: collapse; }
+th { text-align: left; font-weight: normal; width: 6em; }
+td:last-child { text-align: right; width: 7em; }
+.bar { height: 1em; background: #5b8def; }
+";
+
+/// E.g. `12 h 5 min`, or `5 min` under an hour.
+fn duration(micros: u64) -> String {
+    let minutes = micros / 60_000_000;
+    match (minutes / 60, minutes % 60) {
+        (0, minutes) => format!("{minutes} min"),
+        (hours, minutes) => format!("{hours} h {minutes} min"),
+    }
+}
+
+fn plays(plays: u64) -> String {
+    match plays {
+        1 => String::from("1 play"),
+        plays => format!("{plays} plays"),
+    }
+}
+
+/// Prints a report of the --history-db database at `path`, as JSON or as an HTML page. Returns the exit code.
+pub fn print(path: &Path, period: ReportPeriod, html: bool) -> i32 {
+    let report = HistoryDb::open_read_only(path)
+        .map_err(|e| e.to_string())
+        .and_then(|db| Report::read(&db, period));
+    let report = match report {
+        Ok(report) => report,
+        Err(e) => {
+            eprintln!("Could not read the history from {}: {e}", path.display());
+            return exit_code::FAILURE;
+        }
+    };
+
+    if html {
+        print!("{}", report.html(period.title()));
+    } else {
+        println!("{}", serde_json::to_string(&report).unwrap());
+    }
+    exit_code::OK
+}
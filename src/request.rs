@@ -0,0 +1,276 @@
+use std::str;
+
+/// What a websocket client asked for with a message, see the API section of the README.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Request<'a> {
+    /// Anything that isn't one of the others, e.g. `status`: the active player's status.
+    Status,
+    /// `events`, subscribes the connection to the players' events.
+    Events,
+    /// `lyrics`, subscribes the connection to the current line.
+    Lyrics,
+    /// `players`, the status of every attached player.
+    Players,
+    /// `history/<count>`, the last plays.
+    History(usize),
+    /// `stats/<kind>`, optionally followed by `?<query>`.
+    Stats { kind: Stats, query: &'a str },
+    /// `search`, optionally followed by `?<query>`.
+    Search { query: &'a str },
+    /// `time`, optionally followed by `/<client time>` to have it sent back: the server's clocks.
+    Time(Option<u64>),
+    /// `serverStats`, the daemon's own view of its health.
+    ServerStats,
+    /// `playlists`, the active player's playlists.
+    Playlists,
+    /// `activatePlaylist/<id>`.
+    ActivatePlaylist(&'a str),
+    /// `capabilities`, what the active player allows to be controlled.
+    Capabilities,
+    /// `artwork/<index>`, optionally followed by `/refresh` and/or `/chunked`.
+    Artwork {
+        index: usize,
+        refresh: bool,
+        chunked: bool,
+    },
+    /// A `history/`, `time/` or `artwork/` request that doesn't make sense, e.g. with a count that isn't a number, or a message that isn't UTF-8. Not answered.
+    Malformed,
+}
+
+impl<'a> Request<'a> {
+    /// Reads a message, text or binary. Never fails, whatever was sent.
+    pub fn parse(req: &'a [u8]) -> Self {
+        let Ok(req) = str::from_utf8(req) else {
+            return Self::Malformed;
+        };
+
+        match req {
+            "events" => return Self::Events,
+            "lyrics" => return Self::Lyrics,
+            "players" => return Self::Players,
+            "playlists" => return Self::Playlists,
+            "capabilities" => return Self::Capabilities,
+            "time" => return Self::Time(None),
+            "serverStats" => return Self::ServerStats,
+            _ => {}
+        }
+
+        if let Some(client_time) = req.strip_prefix("time/") {
+            return client_time
+                .parse()
+                .map_or(Self::Malformed, |client_time| Self::Time(Some(client_time)));
+        }
+
+        if let Some(count) = req.strip_prefix("history/") {
+            return count.parse().map_or(Self::Malformed, Self::History);
+        }
+
+        if let Some(kind) = req.strip_prefix("stats/") {
+            let (kind, query) = kind.split_once('?').unwrap_or((kind, ""));
+            if let Some(kind) = Stats::parse(kind) {
+                return Self::Stats { kind, query };
+            }
+        }
+
+        if req == "search" || req.starts_with("search?") {
+            let query = req.split_once('?').map_or("", |(_, query)| query);
+            return Self::Search { query };
+        }
+
+        if let Some(id) = req.strip_prefix("activatePlaylist/") {
+            return Self::ActivatePlaylist(id);
+        }
+
+        if let Some(artwork) = req.strip_prefix("artwork/") {
+            return parse_artwork(artwork).unwrap_or(Self::Malformed);
+        }
+
+        Self::Status
+    }
+}
+
+/// Reads what follows `artwork/`.
+fn parse_artwork(req: &str) -> Option<Request<'_>> {
+    let mut parts = req.split('/');
+    let index = parts.next()?.parse().ok()?;
+
+    let mut refresh = false;
+    let mut chunked = false;
+    for part in parts {
+        match part {
+            "refresh" => refresh = true,
+            "chunked" => chunked = true,
+            _ => return None,
+        }
+    }
+
+    Some(Request::Artwork {
+        index,
+        refresh,
+        chunked,
+    })
+}
+
+/// Which statistics `stats/<kind>` asks for.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Stats {
+    /// `stats/top`, the most played tracks and artists.
+    Top,
+    /// `stats/time`, how long was listened to.
+    Time,
+    /// `stats/skips`, how often tracks were skipped.
+    Skips,
+}
+
+impl Stats {
+    pub fn parse(kind: &str) -> Option<Self> {
+        match kind {
+            "top" => Some(Self::Top),
+            "time" => Some(Self::Time),
+            "skips" => Some(Self::Skips),
+            _ => None,
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn parse(req: &str) -> Request<'_> {
+        Request::parse(req.as_bytes())
+    }
+
+    #[test]
+    fn subscriptions_and_lists() {
+        assert_eq!(parse("events"), Request::Events);
+        assert_eq!(parse("lyrics"), Request::Lyrics);
+        assert_eq!(parse("players"), Request::Players);
+        assert_eq!(parse("playlists"), Request::Playlists);
+        assert_eq!(parse("capabilities"), Request::Capabilities);
+        assert_eq!(parse("serverStats"), Request::ServerStats);
+    }
+
+    #[test]
+    fn anything_else_is_the_status() {
+        assert_eq!(parse(""), Request::Status);
+        assert_eq!(parse("status"), Request::Status);
+        assert_eq!(parse("Events"), Request::Status);
+        assert_eq!(parse("events "), Request::Status);
+        assert_eq!(parse("stats/unknown"), Request::Status);
+        assert_eq!(parse("searching"), Request::Status);
+    }
+
+    #[test]
+    fn history() {
+        assert_eq!(parse("history/0"), Request::History(0));
+        assert_eq!(parse("history/10"), Request::History(10));
+        assert_eq!(parse("history/"), Request::Malformed);
+        assert_eq!(parse("history/-1"), Request::Malformed);
+        assert_eq!(parse("history/ten"), Request::Malformed);
+        assert_eq!(parse("history/1/2"), Request::Malformed);
+    }
+
+    #[test]
+    fn time() {
+        assert_eq!(parse("time"), Request::Time(None));
+        assert_eq!(
+            parse("time/1718000000123"),
+            Request::Time(Some(1718000000123))
+        );
+        assert_eq!(parse("time/"), Request::Malformed);
+        assert_eq!(parse("time/12.5"), Request::Malformed);
+    }
+
+    #[test]
+    fn huge_numbers() {
+        let max = usize::MAX.to_string();
+        assert_eq!(
+            parse(&format!("history/{max}")),
+            Request::History(usize::MAX)
+        );
+        assert_eq!(
+            parse(&format!("artwork/{max}")),
+            Request::Artwork {
+                index: usize::MAX,
+                refresh: false,
+                chunked: false,
+            }
+        );
+
+        let overflowing = format!("{max}0");
+        assert_eq!(parse(&format!("history/{overflowing}")), Request::Malformed);
+        assert_eq!(parse(&format!("artwork/{overflowing}")), Request::Malformed);
+        assert_eq!(
+            parse(&format!("history/{}", "9".repeat(4096))),
+            Request::Malformed
+        );
+    }
+
+    #[test]
+    fn stats_and_search() {
+        assert_eq!(
+            parse("stats/top"),
+            Request::Stats {
+                kind: Stats::Top,
+                query: ""
+            }
+        );
+        assert_eq!(
+            parse("stats/skips?period=week&limit=5"),
+            Request::Stats {
+                kind: Stats::Skips,
+                query: "period=week&limit=5"
+            }
+        );
+        assert_eq!(parse("search"), Request::Search { query: "" });
+        assert_eq!(parse("search?"), Request::Search { query: "" });
+        assert_eq!(
+            parse("search?title=a%3Fb?c"),
+            Request::Search {
+                query: "title=a%3Fb?c"
+            }
+        );
+    }
+
+    #[test]
+    fn artwork() {
+        assert_eq!(
+            parse("artwork/1/chunked/refresh"),
+            Request::Artwork {
+                index: 1,
+                refresh: true,
+                chunked: true,
+            }
+        );
+        assert_eq!(
+            parse("artwork/0/refresh"),
+            Request::Artwork {
+                index: 0,
+                refresh: true,
+                chunked: false,
+            }
+        );
+        assert_eq!(parse("artwork/"), Request::Malformed);
+        assert_eq!(parse("artwork//refresh"), Request::Malformed);
+        assert_eq!(parse("artwork/0/"), Request::Malformed);
+        assert_eq!(parse("artwork/0/resize"), Request::Malformed);
+        assert_eq!(parse("artwork/first"), Request::Malformed);
+    }
+
+    #[test]
+    fn playlist_ids_are_kept_whole() {
+        assert_eq!(
+            parse("activatePlaylist//org/mpris/playlist/1"),
+            Request::ActivatePlaylist("/org/mpris/playlist/1")
+        );
+    }
+
+    #[test]
+    fn invalid_utf8() {
+        assert_eq!(Request::parse(b"\xff"), Request::Malformed);
+        assert_eq!(Request::parse(b"history/\xc3"), Request::Malformed);
+        assert_eq!(Request::parse(b"events\xed\xa0\x80"), Request::Malformed);
+        assert_eq!(Request::parse(b"players"), Request::Players);
+    }
+}
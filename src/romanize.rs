@@ -0,0 +1,32 @@
+use crate::status::{send_changed, StatusMetadata, StatusSnapshot};
+use tokio::sync::watch;
+
+/// `text` in Latin letters, e.g. `Dong Jing` for `東京`. Text that already is stays as it is.
+fn latin(text: &str) -> String {
+    match text.is_ascii() {
+        true => text.to_string(),
+        false => deunicode::deunicode(text).trim().to_string(),
+    }
+}
+
+/// Adds `titleLatin` and `artistLatin` to `metadata`.
+fn romanize(metadata: &mut StatusMetadata) {
+    metadata.title_latin = latin(&metadata.title);
+    metadata.artist_latin = latin(&metadata.artist);
+}
+
+/// Forwards every status from `raw_rx` to `status_tx`, with each player's title and artist romanized alongside the originals, for fonts and displays that can't show other scripts (--romanize).
+pub async fn run(
+    mut raw_rx: watch::Receiver<StatusSnapshot>,
+    status_tx: watch::Sender<StatusSnapshot>,
+) {
+    loop {
+        let mut snapshot = raw_rx.borrow_and_update().clone();
+        for status in snapshot.players.values_mut() {
+            romanize(&mut status.metadata);
+        }
+        if !send_changed(&status_tx, snapshot) || raw_rx.changed().await.is_err() {
+            return;
+        }
+    }
+}
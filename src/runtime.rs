@@ -0,0 +1,28 @@
+use std::io;
+use tokio::runtime::{Builder, Runtime};
+
+/// Which Tokio runtime serves the clients and runs the outputs. The players are read on a thread of their own either way.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, clap::ValueEnum)]
+pub enum RuntimeFlavor {
+    /// Everything on the main thread, for single-core machines where more threads only add overhead. File, network and D-Bus calls that block still get threads of their own.
+    CurrentThread,
+    /// Spread over --worker-threads threads, one per CPU core by default.
+    #[default]
+    MultiThread,
+}
+
+/// Builds the runtime for `flavor`. `worker_threads` only applies to [`RuntimeFlavor::MultiThread`].
+pub fn build(flavor: RuntimeFlavor, worker_threads: Option<u32>) -> io::Result<Runtime> {
+    let mut builder = match flavor {
+        RuntimeFlavor::CurrentThread => Builder::new_current_thread(),
+        RuntimeFlavor::MultiThread => {
+            let mut builder = Builder::new_multi_thread();
+            if let Some(threads) = worker_threads {
+                builder.worker_threads(threads as usize);
+            }
+            builder
+        }
+    };
+
+    builder.enable_all().build()
+}
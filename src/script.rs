@@ -0,0 +1,266 @@
+use crate::event::Event;
+use crate::sink::{Sink, SinkContext};
+use crate::status::{self, PlayerStatus, StatusSnapshot};
+use futures_util::future::BoxFuture;
+use rhai::{CallFnOptions, Dynamic, Engine, Scope, AST};
+use serde::de::DeserializeOwned;
+use serde::Serialize;
+use std::any::Any;
+use std::fmt;
+use std::path::PathBuf;
+use std::sync::Arc;
+use tokio::sync::broadcast::{self, error::RecvError};
+use tokio::sync::watch;
+
+/// How many steps a script may take per call, so one stuck in a loop can't hold up its output.
+const MAX_OPERATIONS: u64 = 100_000;
+
+/// Events waiting for an output, like the players' own event channel.
+const EVENT_BUFFER: usize = 64;
+
+/// A --script value: which output the script is for, and the file it's read from.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct ScriptFile {
+    /// An output's name as shown in the logs, e.g. `lastfm` or `text-file`.
+    pub output: String,
+    pub path: PathBuf,
+}
+
+impl ScriptFile {
+    /// Parses a --script value, `<output>=<path>`.
+    pub fn parse(value: &str) -> Result<Self, String> {
+        let Some((output, path)) = value.split_once('=') else {
+            return Err(String::from(
+                "expected <output>=<path>, e.g. `lastfm=~/.config/mpris-nowplaying/scrobble.rhai`",
+            ));
+        };
+        if path.is_empty() {
+            return Err(String::from("the path is empty"));
+        }
+
+        Ok(Self {
+            output: output.trim().to_string(),
+            path: PathBuf::from(path),
+        })
+    }
+}
+
+/// A compiled Rhai script, changing what an output is sent.
+///
+/// `fn status(status)` is called with each player's status (as in the API) and returns it, changed or not, or `()` to leave the player out. `fn event(event)` does the same for each event. Either may be left out to pass everything on as it is.
+#[derive(Clone)]
+pub struct Script {
+    path: PathBuf,
+    /// Compared on reload, so the output is restarted when the file changed.
+    source: String,
+    ast: Arc<AST>,
+}
+
+impl Script {
+    /// Reads and compiles the script.
+    pub fn load(file: &ScriptFile) -> Result<Self, String> {
+        let source = std::fs::read_to_string(&file.path)
+            .map_err(|e| format!("could not read --script {}: {e}", file.path.display()))?;
+        let ast = engine()
+            .compile(&source)
+            .map_err(|e| format!("invalid --script {}: {e}", file.path.display()))?;
+
+        Ok(Self {
+            path: file.path.clone(),
+            source,
+            ast: Arc::new(ast),
+        })
+    }
+
+    fn has_function(&self, name: &str) -> bool {
+        self.ast
+            .iter_functions()
+            .any(|function| function.name == name && function.params.len() == 1)
+    }
+}
+
+impl PartialEq for Script {
+    fn eq(&self, other: &Self) -> bool {
+        self.path == other.path && self.source == other.source
+    }
+}
+
+impl fmt::Debug for Script {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.debug_tuple("Script").field(&self.path).finish()
+    }
+}
+
+/// Scripts can print to the log, and nothing else.
+fn engine() -> Engine {
+    let mut engine = Engine::new();
+    engine.set_max_operations(MAX_OPERATIONS);
+    engine.on_print(|text| log::info!("{text}"));
+    engine.on_debug(|text, _, position| log::debug!("{position}: {text}"));
+    engine
+}
+
+/// Runs a script's functions, warning once when they start failing.
+struct Runner {
+    script: Script,
+    engine: Engine,
+    /// Whether the script has `fn status`.
+    statuses: bool,
+    /// Whether the script has `fn event`.
+    events: bool,
+    failing: bool,
+}
+
+impl Runner {
+    fn new(script: Script) -> Self {
+        Self {
+            statuses: script.has_function("status"),
+            events: script.has_function("event"),
+            script,
+            engine: engine(),
+            failing: false,
+        }
+    }
+
+    /// Calls `function` with `value`, returning what it returned, `None` for `()`. When the script fails, `value` is passed on unchanged.
+    fn call<T: Serialize + DeserializeOwned>(&mut self, function: &str, value: T) -> Option<T> {
+        let result = rhai::serde::to_dynamic(&value)
+            .and_then(|argument| {
+                self.engine.call_fn_with_options::<Dynamic>(
+                    CallFnOptions::new().eval_ast(false),
+                    &mut Scope::new(),
+                    &self.script.ast,
+                    function,
+                    (argument,),
+                )
+            })
+            .and_then(|returned| match returned.is_unit() {
+                true => Ok(None),
+                false => rhai::serde::from_dynamic(&returned).map(Some),
+            });
+
+        match result {
+            Ok(returned) => {
+                self.failing = false;
+                returned
+            }
+            Err(e) => {
+                if !self.failing {
+                    log::warn!(
+                        "{function}() in --script {} failed, passing it on unchanged: {e}",
+                        self.script.path.display()
+                    );
+                    self.failing = true;
+                }
+                Some(value)
+            }
+        }
+    }
+
+    /// Runs each player's status through `fn status`, leaving out the ones it returned `()` for.
+    fn snapshot(&mut self, snapshot: StatusSnapshot) -> StatusSnapshot {
+        if !self.statuses {
+            return snapshot;
+        }
+
+        let mut players = snapshot.players;
+        players.retain(|_, status| {
+            let position_time = status.position_time;
+            match self.call::<PlayerStatus>("status", status.clone()) {
+                Some(changed) => {
+                    // Kept as it was, it only makes it to the script in milliseconds.
+                    *status = PlayerStatus {
+                        position_time,
+                        ..changed
+                    };
+                    true
+                }
+                None => false,
+            }
+        });
+
+        StatusSnapshot {
+            active: snapshot
+                .active
+                .filter(|active| players.contains_key(active)),
+            players,
+        }
+    }
+
+    /// Runs an event through `fn event`, `None` if it returned `()`.
+    fn event(&mut self, event: Event) -> Option<Event> {
+        match self.events {
+            true => self.call("event", event),
+            false => Some(event),
+        }
+    }
+}
+
+/// An output whose statuses and events go through a --script first.
+#[derive(Debug)]
+pub struct Scripted {
+    pub script: Script,
+    pub sink: Box<dyn Sink>,
+}
+
+impl PartialEq for Scripted {
+    fn eq(&self, other: &Self) -> bool {
+        self.script == other.script && self.sink.same_settings(&*other.sink as &dyn Any)
+    }
+}
+
+impl Sink for Scripted {
+    fn name(&self) -> &'static str {
+        self.sink.name()
+    }
+
+    fn run(&self, context: &SinkContext) -> BoxFuture<'static, ()> {
+        let mut runner = Runner::new(self.script.clone());
+        let mut status_rx = context.status_rx.clone();
+        let mut events_rx = context.state.events_tx.subscribe();
+
+        let (status_tx, scripted_status_rx) = watch::channel(StatusSnapshot::default());
+        let (events_tx, _) = broadcast::channel(EVENT_BUFFER);
+        let mut scripted = context.clone();
+        scripted.status_rx = scripted_status_rx;
+        scripted.state.events_tx = events_tx.clone();
+
+        let output = self.sink.run(&scripted);
+
+        Box::pin(async move {
+            status_tx.send_replace(runner.snapshot(status_rx.borrow_and_update().clone()));
+
+            let forward = async {
+                loop {
+                    tokio::select! {
+                        changed = status_rx.changed() => {
+                            if changed.is_err() {
+                                break;
+                            }
+                            let snapshot = status_rx.borrow_and_update().clone();
+                            status::send_changed(&status_tx, runner.snapshot(snapshot));
+                        }
+                        event = events_rx.recv() => match event {
+                            Ok(event) => {
+                                if let Some(event) = runner.event(event) {
+                                    let _ = events_tx.send(event);
+                                }
+                            }
+                            Err(RecvError::Lagged(_)) => {}
+                            Err(RecvError::Closed) => break,
+                        },
+                    }
+                }
+
+                // The output sees the channels close and stops by itself.
+                drop((status_tx, events_tx));
+                std::future::pending::<()>().await
+            };
+
+            tokio::select! {
+                () = output => {}
+                () = forward => {}
+            }
+        })
+    }
+}
@@ -0,0 +1,453 @@
+use crate::status::{PlaybackState, PlayerStatus, StatusSnapshot};
+use serde::{Deserialize, Serialize};
+use std::fmt;
+use std::fs;
+use std::io;
+use std::path::PathBuf;
+use std::sync::Arc;
+use std::time::{Duration, Instant, SystemTime, UNIX_EPOCH};
+use tokio::sync::watch;
+
+/// Tracks this short aren't scrobbled.
+const MIN_LENGTH: Duration = Duration::from_secs(30);
+/// A track is scrobbled once it played for half its length, or this long.
+const SCROBBLE_AFTER: Duration = Duration::from_secs(4 * 60);
+/// Longer gaps between status updates (e.g. after a suspend) don't count as listening.
+const MAX_STEP: Duration = Duration::from_secs(5);
+/// How often queued scrobbles are retried while the service is unreachable.
+const RETRY_INTERVAL: Duration = Duration::from_secs(60);
+
+/// A play to submit.
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub struct Scrobble {
+    pub artist: String,
+    pub track: String,
+    pub album: String,
+    /// In seconds, 0 if unknown.
+    pub duration: u64,
+    /// When it started playing, in seconds since the epoch.
+    pub timestamp: u64,
+    /// The player's displayed name.
+    #[serde(default)]
+    pub player: String,
+}
+
+#[derive(Debug)]
+pub enum Error {
+    /// The service couldn't be reached or is having trouble, trying again later might work.
+    Unavailable(String),
+    /// Not logged in, or the credentials were revoked. Scrobbles are kept until that's fixed.
+    Unauthorized(String),
+    /// The service refused the scrobbles themselves, sending them again won't help.
+    Rejected(String),
+}
+
+impl fmt::Display for Error {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            Error::Unavailable(e) | Error::Unauthorized(e) | Error::Rejected(e) => {
+                write!(f, "{e}")
+            }
+        }
+    }
+}
+
+/// A service scrobbles are submitted to. Calls block.
+pub trait Service: Send + Sync + 'static {
+    /// Shown in logs.
+    const NAME: &'static str;
+    /// The most scrobbles taken in one submission.
+    const BATCH_SIZE: usize;
+    /// Scrobbles older than this are refused by the service, so they're dropped from the queue.
+    const MAX_AGE: Option<Duration>;
+
+    /// Tells the service what started playing.
+    fn now_playing(&self, track: &Scrobble) -> Result<(), Error>;
+    /// Submits finished plays, at most [`Self::BATCH_SIZE`] of them.
+    fn submit(&self, scrobbles: &[Scrobble]) -> Result<(), Error>;
+}
+
+pub fn now() -> u64 {
+    SystemTime::now()
+        .duration_since(UNIX_EPOCH)
+        .unwrap_or_default()
+        .as_secs()
+}
+
+/// Scrobbles that couldn't be sent yet, kept in a file so they survive restarts.
+struct Queue {
+    path: PathBuf,
+    scrobbles: Vec<Scrobble>,
+}
+
+impl Queue {
+    fn load(path: PathBuf) -> Self {
+        let scrobbles = match fs::read_to_string(&path) {
+            Ok(text) => serde_json::from_str(&text).unwrap_or_else(|e| {
+                log::warn!(
+                    "Could not read queued scrobbles from {}, starting over: {e}",
+                    path.display()
+                );
+                Vec::new()
+            }),
+            Err(_) => Vec::new(),
+        };
+
+        Self { path, scrobbles }
+    }
+
+    fn save(&self) {
+        let result = if self.scrobbles.is_empty() {
+            fs::remove_file(&self.path).or_else(|e| match e.kind() {
+                io::ErrorKind::NotFound => Ok(()),
+                _ => Err(e),
+            })
+        } else {
+            self.path
+                .parent()
+                .map_or(Ok(()), fs::create_dir_all)
+                .and_then(|_| fs::write(&self.path, serde_json::to_vec(&self.scrobbles)?))
+        };
+
+        if let Err(e) = result {
+            log::warn!(
+                "Could not store queued scrobbles in {}: {e}",
+                self.path.display()
+            );
+        }
+    }
+}
+
+/// Submits queued scrobbles in batches, keeping those that can be retried. Blocks.
+fn flush<S: Service>(service: &S, queue: &mut Queue) -> Result<(), Error> {
+    if let Some(max_age) = S::MAX_AGE {
+        let oldest = now().saturating_sub(max_age.as_secs());
+        queue
+            .scrobbles
+            .retain(|scrobble| scrobble.timestamp >= oldest);
+    }
+
+    while !queue.scrobbles.is_empty() {
+        let batch = &queue.scrobbles[..queue.scrobbles.len().min(S::BATCH_SIZE)];
+
+        match service.submit(batch) {
+            Ok(()) => {
+                log::debug!("Scrobbled {} track(s) to {}.", batch.len(), S::NAME);
+                queue.scrobbles.drain(..batch.len());
+            }
+            Err(Error::Rejected(e)) => {
+                // Retrying won't help, the batch would block the queue forever.
+                log::warn!("{} rejected {} scrobble(s): {e}", S::NAME, batch.len());
+                queue.scrobbles.drain(..batch.len());
+            }
+            Err(e) => {
+                queue.save();
+                return Err(e);
+            }
+        }
+    }
+
+    queue.save();
+    Ok(())
+}
+
+/// What's playing, and how long it has been listened to.
+struct Play {
+    track: Scrobble,
+    /// The status it was started from, to tell when another track starts.
+    identity: PlayerStatus,
+    listened: Duration,
+    scrobbled: bool,
+}
+
+impl Play {
+    /// Whether it has been listened to long enough to be scrobbled, and wasn't yet.
+    fn is_due(&self) -> bool {
+        let length = Duration::from_secs(self.track.duration);
+        let threshold = match length {
+            Duration::ZERO => SCROBBLE_AFTER,
+            length => (length / 2).min(SCROBBLE_AFTER),
+        };
+
+        !self.scrobbled && (length.is_zero() || length > MIN_LENGTH) && self.listened >= threshold
+    }
+}
+
+/// Counts `step` as listened to `play` if `status` is the same track and playing, or starts a new play when another one plays. Nothing counts while paused or stopped. Returns the track of a new play, for the now playing update.
+fn follow(
+    play: &mut Option<Play>,
+    status: Option<&PlayerStatus>,
+    step: Duration,
+) -> Option<Scrobble> {
+    let playing = status.filter(|status| status.playback_state == PlaybackState::Playing)?;
+    let same_play = play
+        .as_ref()
+        .zip(status)
+        .is_some_and(|(play, status)| play.identity == identity(status));
+
+    if same_play {
+        if let Some(current) = play {
+            current.listened += step;
+        }
+        return None;
+    }
+
+    let track = Scrobble {
+        artist: playing.metadata.artist.clone(),
+        track: playing.metadata.title.clone(),
+        album: playing.metadata.album.clone(),
+        duration: Duration::from_micros(playing.metadata.length).as_secs(),
+        timestamp: now().saturating_sub(Duration::from_micros(playing.position).as_secs()),
+        player: playing.player.identity.clone(),
+    };
+    *play = Some(Play {
+        track: track.clone(),
+        identity: identity(playing),
+        listened: Duration::ZERO,
+        scrobbled: false,
+    });
+    Some(track)
+}
+
+/// The status a play is told apart by: everything but the position, playback state, and what was added by --lyrics, --musicbrainz and --spotify-client-id.
+fn identity(status: &PlayerStatus) -> PlayerStatus {
+    PlayerStatus {
+        position: 0,
+        playback_state: PlaybackState::None,
+        degraded: false,
+        stale: false,
+        lyrics: None,
+        musicbrainz: None,
+        spotify: None,
+        ..status.clone()
+    }
+}
+
+/// Sends now-playing updates and scrobbles for the active player to `service`: a track is scrobbled once it played for half its length or 4 minutes, if it's longer than 30 seconds. Scrobbles that can't be sent are queued in `queue_file` and retried.
+pub async fn run<S: Service>(
+    service: S,
+    queue_file: PathBuf,
+    mut status_rx: watch::Receiver<StatusSnapshot>,
+) {
+    let service = Arc::new(service);
+    let Ok(mut queue) = tokio::task::spawn_blocking(move || Queue::load(queue_file))
+        .await
+        .inspect_err(|e| log::error!("Could not load the scrobble queue, not scrobbling: {e}"))
+    else {
+        return;
+    };
+    let mut play: Option<Play> = None;
+    let mut last_update = Instant::now();
+    // Set while the queue couldn't be sent, to retry it.
+    let mut failing = !queue.scrobbles.is_empty();
+    let mut last_attempt: Option<Instant> = None;
+
+    loop {
+        let status = status_rx
+            .borrow_and_update()
+            .active()
+            .cloned()
+            .filter(|status| {
+                !status.metadata.title.is_empty() && !status.metadata.artist.is_empty()
+            });
+        let step = last_update.elapsed().min(MAX_STEP);
+        last_update = Instant::now();
+
+        let playing = status
+            .as_ref()
+            .is_some_and(|status| status.playback_state == PlaybackState::Playing);
+
+        if let Some(now_playing) = follow(&mut play, status.as_ref(), step) {
+            let service = service.clone();
+            tokio::task::spawn_blocking(move || {
+                if let Err(e) = service.now_playing(&now_playing) {
+                    log::debug!("Could not update the now playing track on {}: {e}", S::NAME);
+                }
+            });
+        }
+
+        let due = play.as_mut().filter(|play| play.is_due());
+        let scrobbling = due.is_some();
+        if let Some(play) = due {
+            play.scrobbled = true;
+            log::info!(
+                "Scrobbling {} – {} to {}.",
+                play.track.artist,
+                play.track.track,
+                S::NAME
+            );
+            queue.scrobbles.push(play.track.clone());
+        }
+
+        let retry_due =
+            failing && last_attempt.is_none_or(|attempt| attempt.elapsed() >= RETRY_INTERVAL);
+        if scrobbling || retry_due {
+            last_attempt = Some(Instant::now());
+
+            let service = service.clone();
+            let flushed = tokio::task::spawn_blocking(move || {
+                // Kept before it's sent, in case sending takes until we're stopped.
+                if scrobbling {
+                    queue.save();
+                }
+                let result = flush(&*service, &mut queue);
+                (queue, result)
+            })
+            .await;
+            let result;
+            (queue, result) = match flushed {
+                Ok(flushed) => flushed,
+                Err(e) => {
+                    log::error!(
+                        "Scrobbling to {} failed unexpectedly, stopping until restarted: {e}",
+                        S::NAME
+                    );
+                    return;
+                }
+            };
+
+            match result {
+                Ok(()) => failing = false,
+                Err(e) => {
+                    if !failing {
+                        log::warn!(
+                            "Could not scrobble to {}, retrying every {} seconds: {e}",
+                            S::NAME,
+                            RETRY_INTERVAL.as_secs()
+                        );
+                    }
+                    failing = true;
+                }
+            }
+        }
+
+        tokio::select! {
+            changed = status_rx.changed() => if changed.is_err() {
+                return;
+            },
+            // Unchanged statuses aren't sent again, keep counting while the same one plays on.
+            () = tokio::time::sleep(MAX_STEP / 2), if playing => {}
+            () = tokio::time::sleep(RETRY_INTERVAL.saturating_sub(
+                last_attempt.map_or(RETRY_INTERVAL, |attempt| attempt.elapsed())
+            )), if failing => {}
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use serde_json::json;
+
+    const MINUTE: Duration = Duration::from_secs(60);
+
+    fn status(title: &str, length: Duration, state: &str) -> PlayerStatus {
+        serde_json::from_value(json!({
+            "player": { "busName": "org.mpris.MediaPlayer2.mpd", "identity": "Music Player Daemon" },
+            "metadata": {
+                "title": title,
+                "artist": "Someone",
+                "album": "Somewhere",
+                "artwork": [],
+                "length": length.as_micros() as u64,
+                "url": ""
+            },
+            "playbackState": state,
+            "position": 0,
+            "degraded": false
+        }))
+        .unwrap()
+    }
+
+    /// Follows `statuses` one after the other, each for `step`, answering whether the play was due after each.
+    fn due_after(statuses: &[&PlayerStatus], step: Duration) -> Vec<bool> {
+        let mut play = None;
+        statuses
+            .iter()
+            .map(|status| {
+                follow(&mut play, Some(status), step);
+                play.as_ref().is_some_and(Play::is_due)
+            })
+            .collect()
+    }
+
+    #[test]
+    fn scrobbled_after_half_the_length() {
+        let playing = status("Neon Tide", 3 * MINUTE, "playing");
+        // The first status only starts the play.
+        let due = due_after(&[&playing; 4], MINUTE / 2);
+        assert_eq!(due, [false, false, false, true]);
+    }
+
+    #[test]
+    fn long_tracks_are_scrobbled_after_4_minutes() {
+        let playing = status("Neon Tide", 20 * MINUTE, "playing");
+        let due = due_after(&[&playing; 5], MINUTE);
+        assert_eq!(due, [false, false, false, false, true]);
+    }
+
+    #[test]
+    fn unknown_length_is_scrobbled_after_4_minutes() {
+        let playing = status("Neon Tide", Duration::ZERO, "playing");
+        let due = due_after(&[&playing; 5], MINUTE);
+        assert_eq!(due, [false, false, false, false, true]);
+    }
+
+    #[test]
+    fn very_short_tracks_are_never_scrobbled() {
+        let playing = status("Jingle", MIN_LENGTH, "playing");
+        let due = due_after(&[&playing; 4], MIN_LENGTH);
+        assert_eq!(due, [false; 4]);
+
+        let just_long_enough = status("Jingle", MIN_LENGTH + Duration::from_secs(2), "playing");
+        let due = due_after(&[&just_long_enough; 2], MIN_LENGTH);
+        assert_eq!(due, [false, true]);
+    }
+
+    #[test]
+    fn paused_time_does_not_count() {
+        let playing = status("Neon Tide", 3 * MINUTE, "playing");
+        let paused = status("Neon Tide", 3 * MINUTE, "paused");
+        let due = due_after(
+            &[
+                &playing, &playing, &paused, &paused, &paused, &playing, &playing,
+            ],
+            MINUTE / 2,
+        );
+        assert_eq!(due, [false, false, false, false, false, false, true]);
+    }
+
+    #[test]
+    fn another_track_starts_over() {
+        let first = status("Neon Tide", 3 * MINUTE, "playing");
+        let second = status("Glass Rain", 3 * MINUTE, "playing");
+        let due = due_after(&[&first, &first, &first, &second, &second], MINUTE / 2);
+        assert_eq!(due, [false, false, false, false, false]);
+    }
+
+    #[test]
+    fn scrobbled_only_once() {
+        let mut play = None;
+        let playing = status("Neon Tide", 3 * MINUTE, "playing");
+        follow(&mut play, Some(&playing), Duration::ZERO);
+        follow(&mut play, Some(&playing), 2 * MINUTE);
+        let current = play.as_mut().unwrap();
+        assert!(current.is_due());
+        current.scrobbled = true;
+        follow(&mut play, Some(&playing), 2 * MINUTE);
+        assert!(!play.unwrap().is_due());
+    }
+
+    #[test]
+    fn a_new_play_is_announced_once() {
+        let mut play = None;
+        let playing = status("Neon Tide", 3 * MINUTE, "playing");
+        let paused = status("Neon Tide", 3 * MINUTE, "paused");
+        let started = follow(&mut play, Some(&playing), MINUTE).unwrap();
+        assert_eq!(started.track, "Neon Tide");
+        assert_eq!(started.duration, 180);
+        assert_eq!(follow(&mut play, Some(&paused), MINUTE), None);
+        assert_eq!(follow(&mut play, Some(&playing), MINUTE), None);
+        assert_eq!(follow(&mut play, None, MINUTE), None);
+    }
+}
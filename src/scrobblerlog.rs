@@ -0,0 +1,103 @@
+use crate::scrobble::{self, Scrobble, Service};
+use crate::sink::{Sink, SinkContext};
+use crate::status::StatusSnapshot;
+use futures_util::future::BoxFuture;
+use std::fs::OpenOptions;
+use std::io::{self, Write};
+use std::path::PathBuf;
+use std::time::Duration;
+use tokio::sync::watch;
+
+/// Starts a new log. Timestamps are written in UTC, so importers don't have to guess the time zone.
+const HEADER: &str = concat!(
+    "#AUDIOSCROBBLER/1.1\n#TZ/UTC\n#CLIENT/",
+    env!("CARGO_PKG_NAME"),
+    " ",
+    env!("CARGO_PKG_VERSION"),
+    "\n"
+);
+
+/// Settings for --scrobbler-log.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct ScrobblerLogOptions {
+    /// The `.scrobbler.log` plays are added to.
+    pub path: PathBuf,
+    /// Where plays that couldn't be written yet are kept.
+    pub queue_file: PathBuf,
+}
+
+/// Fields are separated by tabs and lines by newlines, so neither may show up in a value.
+fn field(value: &str) -> String {
+    value.replace(['\t', '\n', '\r'], " ")
+}
+
+/// A play as a line of the log: artist, album, title, track number, duration, rating (`L` for listened) and timestamp, separated by tabs. The MusicBrainz id at the end is left empty.
+fn line(scrobble: &Scrobble) -> String {
+    format!(
+        "{}\t{}\t{}\t\t{}\tL\t{}\t\n",
+        field(&scrobble.artist),
+        field(&scrobble.album),
+        field(&scrobble.track),
+        scrobble.duration,
+        scrobble.timestamp
+    )
+}
+
+struct ScrobblerLog {
+    path: PathBuf,
+}
+
+impl ScrobblerLog {
+    fn append(&self, scrobbles: &[Scrobble]) -> io::Result<()> {
+        if let Some(dir) = self.path.parent().filter(|dir| !dir.as_os_str().is_empty()) {
+            std::fs::create_dir_all(dir)?;
+        }
+        let mut file = OpenOptions::new()
+            .create(true)
+            .append(true)
+            .open(&self.path)?;
+
+        let mut text = String::new();
+        if file.metadata()?.len() == 0 {
+            text.push_str(HEADER);
+        }
+        text.extend(scrobbles.iter().map(line));
+
+        // In one write, so an importer never sees half a line.
+        file.write_all(text.as_bytes())
+    }
+}
+
+impl Service for ScrobblerLog {
+    const NAME: &'static str = "the scrobbler log";
+    const BATCH_SIZE: usize = 100;
+    const MAX_AGE: Option<Duration> = None;
+
+    /// The log only has finished plays.
+    fn now_playing(&self, _track: &Scrobble) -> Result<(), scrobble::Error> {
+        Ok(())
+    }
+
+    fn submit(&self, scrobbles: &[Scrobble]) -> Result<(), scrobble::Error> {
+        self.append(scrobbles).map_err(|e| {
+            scrobble::Error::Unavailable(format!("could not write {}: {e}", self.path.display()))
+        })
+    }
+}
+
+/// Adds the active player's plays to an Audioscrobbler `.scrobbler.log`, the format offline scrobble importers read, see [`scrobble::run`].
+pub async fn run(options: ScrobblerLogOptions, status_rx: watch::Receiver<StatusSnapshot>) {
+    let service = ScrobblerLog { path: options.path };
+
+    scrobble::run(service, options.queue_file, status_rx).await
+}
+
+impl Sink for ScrobblerLogOptions {
+    fn name(&self) -> &'static str {
+        "scrobbler-log"
+    }
+
+    fn run(&self, context: &SinkContext) -> BoxFuture<'static, ()> {
+        Box::pin(run(self.clone(), context.status_rx.clone()))
+    }
+}
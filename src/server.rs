@@ -0,0 +1,1915 @@
+use crate::admin::{self, Admin, Clients, ConnectedClient};
+use crate::artwork::{self, ArtworkData, ArtworkInfo, ArtworkOptions};
+use crate::bar::Format;
+use crate::bus::BusAddress;
+use crate::casing::FieldCase;
+use crate::control::{self, Capabilities, ControlError, Playlist};
+use crate::demand::Demand;
+use crate::event::Event;
+use crate::health::{Health, ServerStats};
+use crate::history::{self, HistoryEntry};
+use crate::hub::{self, Hub};
+use crate::lyrics::LyricsStatus;
+use crate::outbox::{Outbox, Queue};
+use crate::player;
+use crate::request::{Request as ClientRequest, Stats};
+use crate::session::SessionLock;
+use crate::sink::{Sink, SinkContext};
+use crate::status::{CompactStatus, PlayerStatus, SampleTime, StatusSnapshot};
+use crate::streamdeck::{self, Keys};
+use crate::template::OverlayTemplate;
+use futures_util::future::BoxFuture;
+use futures_util::stream::{SplitSink, SplitStream};
+use futures_util::{SinkExt, StreamExt};
+use http_body_util::combinators::BoxBody;
+use http_body_util::{BodyExt, Full, StreamBody};
+use hyper::body::{Bytes, Frame, Incoming};
+use hyper::header::{
+    HeaderValue, ACCESS_CONTROL_ALLOW_ORIGIN, CACHE_CONTROL, CONNECTION, CONTENT_TYPE, HOST,
+    ORIGIN, SEC_WEBSOCKET_ACCEPT, SEC_WEBSOCKET_KEY, SEC_WEBSOCKET_VERSION, UPGRADE,
+};
+use hyper::server::conn::http1;
+use hyper::service::service_fn;
+use hyper::{Method, Request, Response, StatusCode};
+use hyper_util::rt::{TokioIo, TokioTimer};
+use serde::{Deserialize, Serialize};
+use socket2::{Domain, Protocol, Socket, Type};
+use std::collections::VecDeque;
+use std::convert::Infallible;
+use std::fmt;
+use std::net::{IpAddr, SocketAddr};
+use std::os::unix::fs::FileTypeExt;
+use std::path::Path;
+use std::sync::atomic::{AtomicU64, Ordering};
+use std::sync::{Arc, OnceLock};
+use std::time::{Duration, Instant};
+use std::{fs, io};
+use tokio::io::{AsyncRead, AsyncWrite};
+use tokio::net::{TcpListener, UnixListener};
+use tokio::sync::{broadcast, watch, Semaphore};
+use tokio_rustls::rustls::pki_types::pem::PemObject;
+use tokio_rustls::rustls::pki_types::{CertificateDer, PrivateKeyDer};
+use tokio_rustls::{rustls, TlsAcceptor};
+use tokio_tungstenite::tungstenite;
+use tokio_tungstenite::tungstenite::handshake::derive_accept_key;
+use tokio_tungstenite::tungstenite::protocol::frame::coding::CloseCode;
+use tokio_tungstenite::tungstenite::protocol::{CloseFrame, Role, WebSocketConfig};
+use tokio_tungstenite::tungstenite::Message;
+use tokio_tungstenite::WebSocketStream;
+use tracing::Instrument;
+use url::Url;
+
+/// The line served at `/nowplaying.txt` by default, with the fields of `--format`.
+pub const DEFAULT_NOWPLAYING_FORMAT: &str = "{artist} – {title} [{position}/{length}]";
+
+/// What's served at `/nowplaying.txt` by default when no player is attached.
+pub const DEFAULT_NOWPLAYING_IDLE: &str = "Nothing is playing";
+
+/// What every connection needs to answer requests.
+#[derive(Clone)]
+pub struct ServerState {
+    pub status_rx: watch::Receiver<StatusSnapshot>,
+    pub events_tx: broadcast::Sender<Event>,
+    pub bus: BusAddress,
+    pub artwork_options: ArtworkOptions,
+    pub origins: OriginPolicy,
+    /// Limits how many clients are served at once, if set.
+    pub client_slots: Option<Arc<Semaphore>>,
+    /// How many requests per second each client gets answered, if limited.
+    pub max_requests_per_second: Option<f32>,
+    /// How long a client may stay silent before it's disconnected, if limited. Idle WebSocket clients are pinged halfway through.
+    pub client_timeout: Option<Duration>,
+    /// The largest WebSocket message a client may send, in bytes, if limited. Clients sending larger ones are disconnected.
+    pub max_message_size: Option<usize>,
+    /// How many answers and events may wait to be sent to each client, if limited. Pushed statuses and lyrics never wait, only the newest ones are sent.
+    pub max_queued_messages: Option<usize>,
+    /// The active player's status, numbered for `/poll`.
+    pub revisions: watch::Receiver<Revision>,
+    /// The Stream Deck keys, numbered for `/streamdeck`.
+    pub streamdeck: watch::Receiver<Keys>,
+    /// The last tracks played, most recent first, for `history/<count>`.
+    pub history: watch::Receiver<VecDeque<HistoryEntry>>,
+    /// The path everything is served under (e.g. `/nowplaying`), without a trailing slash. Empty to serve from the root.
+    pub base_path: String,
+    /// Whether --lyrics adds lyrics to the statuses, so clients can subscribe to them.
+    pub lyrics: bool,
+    /// Served at `/overlay/custom`, if set.
+    pub overlay_template: Option<OverlayTemplate>,
+    /// The line served at `/nowplaying.txt`, and what's served instead when no player is attached.
+    pub nowplaying_format: Format,
+    pub nowplaying_idle: String,
+    /// Held by every connection, so --pause-when-idle knows when nobody is listening.
+    pub demand: Demand,
+    /// The WebSocket clients and event streams connected right now.
+    pub clients: Clients,
+    /// What's counted for `serverStats`.
+    pub health: Health,
+    /// How the keys of the statuses, events and answers sent to clients are cased. WebSocket clients and HTTP requests can ask for another one with `?case=`.
+    pub json_case: FieldCase,
+    /// Whether the session is locked, for --pause-when-locked.
+    pub session_lock: SessionLock,
+    /// Serves the admin page, if set (--admin).
+    pub admin: Option<Admin>,
+    /// Takes the statuses other instances push to `/hub`, if set (--hub).
+    pub hub: Option<Hub>,
+    /// The play history kept with --history-db, for `stats/<kind>` and `search`.
+    #[cfg(feature = "history-db")]
+    pub history_db: Option<Arc<std::sync::Mutex<crate::historydb::HistoryDb>>>,
+}
+
+impl ServerState {
+    /// Serves the statuses in `status_rx` and the events in `events_tx` like the command line does without any options: to local pages only, at up to 30 requests per second per client, keeping the last 50 plays in memory. Commands are sent to the players on `bus`.
+    ///
+    /// Spawns the tasks that keep the revisions, Stream Deck keys and history up to date, so it has to be called within a Tokio runtime.
+    pub fn new(
+        status_rx: watch::Receiver<StatusSnapshot>,
+        events_tx: broadcast::Sender<Event>,
+        bus: BusAddress,
+    ) -> Self {
+        let artwork_options = ArtworkOptions::default();
+
+        Self {
+            health: Health::new(&events_tx),
+            revisions: revisions(status_rx.clone()),
+            streamdeck: streamdeck::keys(status_rx.clone(), artwork_options.clone()),
+            history: history::record(status_rx.clone(), history::DEFAULT_SIZE, Vec::new()),
+            status_rx,
+            events_tx,
+            bus,
+            artwork_options,
+            origins: OriginPolicy::Local,
+            client_slots: None,
+            max_requests_per_second: Some(30.0),
+            client_timeout: Some(Duration::from_secs(60)),
+            max_message_size: Some(DEFAULT_MAX_MESSAGE_SIZE),
+            max_queued_messages: Some(DEFAULT_MAX_QUEUED_MESSAGES),
+            base_path: String::new(),
+            lyrics: false,
+            overlay_template: None,
+            nowplaying_format: Format::parse(DEFAULT_NOWPLAYING_FORMAT).unwrap(),
+            nowplaying_idle: String::from(DEFAULT_NOWPLAYING_IDLE),
+            demand: Demand::default(),
+            clients: Clients::default(),
+            json_case: FieldCase::Camel,
+            session_lock: SessionLock::default(),
+            admin: None,
+            hub: None,
+            #[cfg(feature = "history-db")]
+            history_db: None,
+        }
+    }
+}
+
+/// The active player's status, numbered so long-polling clients can tell whether they have seen it.
+#[derive(Debug, Clone, PartialEq, Eq, Serialize)]
+#[serde(rename_all = "camelCase")]
+pub struct Revision {
+    pub revision: u64,
+    pub status: Option<PlayerStatus>,
+}
+
+/// Numbers every actual change of the active player's status, starting at 1.
+pub fn revisions(mut status_rx: watch::Receiver<StatusSnapshot>) -> watch::Receiver<Revision> {
+    let (revisions_tx, revisions_rx) = watch::channel(Revision {
+        revision: 1,
+        status: status_rx.borrow_and_update().active().cloned(),
+    });
+
+    tokio::spawn(async move {
+        while status_rx.changed().await.is_ok() {
+            let status = status_rx.borrow_and_update().active().cloned();
+
+            // Other players changing wake this up too, only count actual changes.
+            revisions_tx.send_if_modified(|current| {
+                if current.status == status {
+                    return false;
+                }
+
+                current.revision += 1;
+                current.status = status;
+                true
+            });
+        }
+    });
+
+    revisions_rx
+}
+
+/// Which web pages may connect, judged by the `Origin` header browsers send.
+/// Clients that don't send one (e.g. scripts) are always let in.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum OriginPolicy {
+    /// Any page may connect.
+    Any,
+    /// Only local files and pages served from this machine may connect.
+    Local,
+    /// Only these origins (e.g. `https://example.com`) may connect.
+    List(Vec<String>),
+}
+
+impl OriginPolicy {
+    pub fn allows(&self, origin: &str) -> bool {
+        match self {
+            OriginPolicy::Any => true,
+            OriginPolicy::Local => {
+                // Local files show up as `null` in most browsers.
+                origin == "null"
+                    || url::Url::parse(origin).is_ok_and(|url| {
+                        url.scheme() == "file"
+                            || matches!(url.host_str(), Some("localhost" | "127.0.0.1" | "[::1]"))
+                    })
+            }
+            OriginPolicy::List(origins) => origins
+                .iter()
+                .any(|allowed| allowed.trim_end_matches('/').eq_ignore_ascii_case(origin)),
+        }
+    }
+}
+
+/// An error reported back to a websocket client in place of the requested data.
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "camelCase", tag = "error")]
+enum ErrorResponse {
+    /// The artwork at this index could not be read, e.g. the file was removed after a track change.
+    #[serde(rename_all = "camelCase")]
+    ArtworkUnavailable { index: usize, src: String },
+    /// Artwork was requested, but --no-artwork is set.
+    ArtworkDisabled,
+    /// Lyrics were subscribed to, but --lyrics isn't set.
+    LyricsDisabled,
+    /// Statistics were asked for, but there's no --history-db to get them from.
+    HistoryDisabled,
+    /// The statistics couldn't be read, e.g. the period isn't valid.
+    #[serde(rename_all = "camelCase")]
+    StatsFailed { message: String },
+    /// The history couldn't be searched, e.g. a day isn't valid.
+    #[serde(rename_all = "camelCase")]
+    SearchFailed { message: String },
+    /// A command needs a player, but none is attached.
+    NoPlayer,
+    /// The player rejected a command or didn't answer.
+    #[serde(rename_all = "camelCase")]
+    CommandFailed { message: String },
+    /// The player says it doesn't allow the command, e.g. `Next` with `CanGoNext` false, so it wasn't sent.
+    #[serde(rename_all = "camelCase")]
+    NotPermitted { command: String },
+}
+
+impl From<ErrorResponse> for Message {
+    fn from(value: ErrorResponse) -> Self {
+        Message::Text(serde_json::to_string(&value).unwrap().into())
+    }
+}
+
+/// A reply to a command sent to the player.
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "camelCase")]
+enum CommandResponse {
+    Playlists(Vec<Playlist>),
+    ActivatedPlaylist(String),
+}
+
+/// The answer to `serverStats`, `{"serverStats": {...}}`.
+#[derive(Debug, Clone, PartialEq, Serialize)]
+#[serde(rename_all = "camelCase")]
+struct ServerStatsResponse {
+    server_stats: ServerStats,
+}
+
+/// The answer to `capabilities`, `{"capabilities": {...}}`.
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "camelCase")]
+struct CapabilitiesResponse {
+    capabilities: Capabilities,
+}
+
+/// The answer to `time`, `{"time": {...}}`, for clients to line their clock up with the daemon's.
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "camelCase")]
+struct TimeResponse {
+    time: ServerTime,
+}
+
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "camelCase")]
+struct ServerTime {
+    /// What the client sent with `time/<client time>`, so it can tell how long the answer took.
+    #[serde(skip_serializing_if = "Option::is_none")]
+    client_time: Option<u64>,
+    /// Milliseconds since the Unix epoch, the clock `positionTime` is on.
+    wall_time: u64,
+    /// Microseconds on a clock that never jumps, counted from the first `time` request.
+    monotonic_time: u64,
+}
+
+impl ServerTime {
+    fn now(client_time: Option<u64>) -> Self {
+        static MONOTONIC_START: OnceLock<Instant> = OnceLock::new();
+        let sample = SampleTime::now();
+        let start = *MONOTONIC_START.get_or_init(|| sample.instant);
+
+        Self {
+            client_time,
+            wall_time: sample.unix_millis(),
+            monotonic_time: sample.instant.duration_since(start).as_micros() as u64,
+        }
+    }
+}
+
+impl From<CommandResponse> for Message {
+    fn from(value: CommandResponse) -> Self {
+        Message::Text(serde_json::to_string(&value).unwrap().into())
+    }
+}
+
+/// Pushed to clients subscribed with `lyrics` whenever the active player's lyric line changes.
+#[derive(Debug, Clone, PartialEq, Eq, Serialize)]
+struct LyricsEvent {
+    event: &'static str,
+    #[serde(flatten)]
+    lyrics: LyricsStatus,
+}
+
+impl From<LyricsStatus> for Message {
+    fn from(lyrics: LyricsStatus) -> Self {
+        let event = LyricsEvent {
+            event: "lyrics",
+            lyrics,
+        };
+        Message::Text(serde_json::to_string(&event).unwrap().into())
+    }
+}
+
+/// Answers `playlists`, or `activatePlaylist/<id>` with an `id`, on the active player.
+fn respond_command(id: Option<&str>, bus: &BusAddress, bus_name: Option<&str>) -> Message {
+    let Some(bus_name) = bus_name else {
+        return ErrorResponse::NoPlayer.into();
+    };
+
+    let result = match id {
+        Some(id) => control::activate_playlist(bus, bus_name, id)
+            .map(|_| CommandResponse::ActivatedPlaylist(id.to_string())),
+        None => control::list_playlists(bus, bus_name).map(CommandResponse::Playlists),
+    };
+
+    match result {
+        Ok(response) => response.into(),
+        Err(e) => {
+            let command = id.map_or(String::from("playlists"), |id| {
+                format!("activatePlaylist/{id}")
+            });
+            log::warn!(player = bus_name; "Command {command} failed on player {bus_name}: {e}");
+
+            ErrorResponse::CommandFailed {
+                message: e.message().unwrap_or_default().to_string(),
+            }
+            .into()
+        }
+    }
+}
+
+/// Answers `stats/<kind>?period=<period>&limit=<count>` from the --history-db database, as JSON.
+#[cfg_attr(not(feature = "history-db"), allow(unused_variables))]
+async fn stats(state: &ServerState, kind: Stats, query: &str) -> Result<String, ErrorResponse> {
+    #[cfg(feature = "history-db")]
+    if let Some(db) = state.history_db.clone() {
+        let mut period = String::from("all");
+        let mut limit = None;
+        for (key, value) in query.split('&').filter_map(|v| v.split_once('=')) {
+            match key {
+                "period" => period = value.to_string(),
+                "limit" => limit = value.parse().ok(),
+                _ => {}
+            }
+        }
+
+        let span = tracing::Span::current();
+        return tokio::task::spawn_blocking(move || {
+            span.in_scope(|| {
+                let db = db.lock().unwrap_or_else(std::sync::PoisonError::into_inner);
+                match kind {
+                    Stats::Top => db
+                        .top(&period, limit)
+                        .map(|top| serde_json::to_string(&top).unwrap()),
+                    Stats::Time => db
+                        .time(&period, limit)
+                        .map(|time| serde_json::to_string(&time).unwrap()),
+                    Stats::Skips => db
+                        .skips(&period, limit)
+                        .map(|skips| serde_json::to_string(&skips).unwrap()),
+                }
+            })
+        })
+        .await
+        .unwrap_or_else(|e| Err(e.to_string()))
+        .map_err(|message| ErrorResponse::StatsFailed { message });
+    }
+
+    Err(ErrorResponse::HistoryDisabled)
+}
+
+/// Answers `search?title=<text>&artist=<text>&from=<day>&to=<day>&limit=<count>&offset=<count>` from the --history-db database, as JSON. The values are URL-encoded.
+#[cfg_attr(not(feature = "history-db"), allow(unused_variables))]
+async fn search(state: &ServerState, query: &str) -> Result<String, ErrorResponse> {
+    #[cfg(feature = "history-db")]
+    if let Some(db) = state.history_db.clone() {
+        let mut search = crate::historydb::Search::default();
+        for (key, value) in url::form_urlencoded::parse(query.as_bytes()) {
+            let invalid = |_| ErrorResponse::SearchFailed {
+                message: format!("invalid {key}"),
+            };
+            match &*key {
+                "title" => search.title = Some(value.into_owned()),
+                "artist" => search.artist = Some(value.into_owned()),
+                "from" => search.from = Some(value.into_owned()),
+                "to" => search.to = Some(value.into_owned()),
+                "limit" => search.limit = Some(value.parse().map_err(invalid)?),
+                "offset" => search.offset = value.parse().map_err(invalid)?,
+                _ => {}
+            }
+        }
+
+        let span = tracing::Span::current();
+        return tokio::task::spawn_blocking(move || {
+            span.in_scope(|| {
+                let db = db.lock().unwrap_or_else(std::sync::PoisonError::into_inner);
+                db.search(&search)
+                    .map(|results| serde_json::to_string(&results).unwrap())
+            })
+        })
+        .await
+        .unwrap_or_else(|e| Err(e.to_string()))
+        .map_err(|message| ErrorResponse::SearchFailed { message });
+    }
+
+    Err(ErrorResponse::HistoryDisabled)
+}
+
+/// Marks the start and end of an artwork sent as a sequence of binary chunks.
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "camelCase", tag = "chunked")]
+enum ChunkMarker {
+    #[serde(rename_all = "camelCase")]
+    Start {
+        index: usize,
+        length: usize,
+        chunks: usize,
+    },
+    #[serde(rename_all = "camelCase")]
+    End { index: usize },
+}
+
+impl From<ChunkMarker> for Message {
+    fn from(value: ChunkMarker) -> Self {
+        Message::Text(serde_json::to_string(&value).unwrap().into())
+    }
+}
+
+/// Splits binary artwork into chunks, each prefixed with its big-endian `u32` sequence number.
+fn chunk_artwork(index: usize, data: &[u8], chunk_size: usize) -> Vec<Message> {
+    let chunks = data.chunks(chunk_size.max(1));
+
+    let mut messages = Vec::with_capacity(chunks.len() + 2);
+    messages.push(
+        ChunkMarker::Start {
+            index,
+            length: data.len(),
+            chunks: chunks.len(),
+        }
+        .into(),
+    );
+    messages.extend(chunks.enumerate().map(|(i, chunk)| {
+        let mut payload = Vec::with_capacity(4 + chunk.len());
+        payload.extend_from_slice(&(i as u32).to_be_bytes());
+        payload.extend_from_slice(chunk);
+        Message::Binary(payload.into())
+    }));
+    messages.push(ChunkMarker::End { index }.into());
+
+    messages
+}
+
+/// Answers an `artwork/<index>` request, optionally followed by `/refresh` and/or `/chunked`.
+///
+/// Returns no messages for indices without artwork.
+fn respond_artwork(
+    status: &PlayerStatus,
+    index: usize,
+    refresh: bool,
+    chunked: bool,
+    current_artwork: &mut Option<ArtworkInfo>,
+    artwork_options: &ArtworkOptions,
+    health: &Health,
+) -> Vec<Message> {
+    let Some(artwork) = status.metadata.artwork.get(index) else {
+        return Vec::new();
+    };
+
+    if !refresh && Some(artwork) == current_artwork.as_ref() {
+        return vec![Message::Text("null".into())];
+    }
+
+    match artwork::load(artwork, artwork_options) {
+        Ok(data) => {
+            *current_artwork = Some(artwork.clone());
+            health.artwork_sent();
+
+            match data {
+                ArtworkData::Binary(data) if chunked => {
+                    chunk_artwork(index, &data, artwork_options.chunk_size)
+                }
+                ArtworkData::Binary(data) => vec![Message::Binary(data)],
+                ArtworkData::Link(src) => vec![Message::Text(src.into())],
+            }
+        }
+        Err(e) => {
+            log::warn!("Could not read artwork {}: {e}", artwork.src);
+
+            *current_artwork = None;
+            vec![ErrorResponse::ArtworkUnavailable {
+                index,
+                src: artwork.src.clone(),
+            }
+            .into()]
+        }
+    }
+}
+
+/// Binds a listener. The IPv6 wildcard address (`[::]`) also accepts IPv4 connections, whatever the system default is.
+pub fn bind(addr: SocketAddr) -> io::Result<TcpListener> {
+    let socket = Socket::new(Domain::for_address(addr), Type::STREAM, Some(Protocol::TCP))?;
+    if addr.is_ipv6() && addr.ip().is_unspecified() {
+        socket.set_only_v6(false)?;
+    }
+    // Same as `TcpListener::bind`, so restarts don't fail while old connections linger.
+    socket.set_reuse_address(true)?;
+    socket.set_nonblocking(true)?;
+    socket.bind(&addr.into())?;
+    socket.listen(1024)?;
+
+    TcpListener::from_std(socket.into())
+}
+
+/// Loads a PEM certificate chain and private key for `--tls-cert` and `--tls-key`.
+///
+/// With a `client_ca` (`--tls-client-ca`), only clients presenting a certificate signed by one of the PEM certificates in it get through the handshake.
+pub fn load_tls(
+    cert: &Path,
+    key: &Path,
+    client_ca: Option<&Path>,
+) -> Result<TlsAcceptor, Box<dyn std::error::Error>> {
+    let certs = CertificateDer::pem_file_iter(cert)?.collect::<Result<Vec<_>, _>>()?;
+    let key = PrivateKeyDer::from_pem_file(key)?;
+    let provider = Arc::new(rustls::crypto::ring::default_provider());
+
+    let builder = rustls::ServerConfig::builder_with_provider(provider.clone())
+        .with_safe_default_protocol_versions()?;
+    let builder = match client_ca {
+        Some(client_ca) => {
+            let mut roots = rustls::RootCertStore::empty();
+            for ca in CertificateDer::pem_file_iter(client_ca)? {
+                roots.add(ca?)?;
+            }
+            let verifier = rustls::server::WebPkiClientVerifier::builder_with_provider(
+                Arc::new(roots),
+                provider,
+            )
+            .build()?;
+            builder.with_client_cert_verifier(verifier)
+        }
+        None => builder.with_no_client_auth(),
+    };
+    let config = builder.with_single_cert(certs, key)?;
+
+    Ok(TlsAcceptor::from(Arc::new(config)))
+}
+
+static NEXT_CLIENT_ID: AtomicU64 = AtomicU64::new(1);
+
+/// A connection, numbered so its log lines can be told apart from those of other clients at the same address.
+#[derive(Debug, Clone)]
+pub struct Client {
+    pub id: u64,
+    /// Who connected, see `client_name`.
+    pub address: String,
+}
+
+impl Client {
+    pub fn new(address: String) -> Self {
+        Self {
+            id: NEXT_CLIENT_ID.fetch_add(1, Ordering::Relaxed),
+            address,
+        }
+    }
+}
+
+impl fmt::Display for Client {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "#{} {}", self.id, self.address)
+    }
+}
+
+/// Accepts WebSocket connections forever, serving each one on its own task.
+///
+/// With a TLS acceptor, clients have to connect with `wss://`.
+pub async fn run(listener: TcpListener, state: ServerState, tls: Option<TlsAcceptor>) {
+    loop {
+        let (stream, addr) = match listener.accept().await {
+            Ok(accepted) => accepted,
+            Err(e) => {
+                accept_failed(e).await;
+                continue;
+            }
+        };
+
+        let state = state.clone();
+        let tls = tls.clone();
+        let local = stream.local_addr().ok();
+        tokio::spawn(async move {
+            match tls {
+                Some(tls) => match tls.accept(stream).await {
+                    Ok(stream) => accept(stream, addr.to_string(), local, state).await,
+                    Err(e) => log::debug!("TLS handshake with {addr} failed: {e}"),
+                },
+                None => accept(stream, addr.to_string(), local, state).await,
+            }
+        });
+    }
+}
+
+/// Like [`run`], but for local clients on a Unix domain socket.
+pub async fn run_unix(listener: UnixListener, state: ServerState) {
+    loop {
+        let stream = match listener.accept().await {
+            Ok((stream, _)) => stream,
+            Err(e) => {
+                accept_failed(e).await;
+                continue;
+            }
+        };
+
+        tokio::spawn(accept(
+            stream,
+            String::from("unix socket"),
+            None,
+            state.clone(),
+        ));
+    }
+}
+
+/// How long to wait after a connection couldn't be accepted, e.g. because we ran out of file descriptors. Trying again right away would only spin.
+const ACCEPT_RETRY_DELAY: Duration = Duration::from_millis(100);
+
+/// Waits a moment before accepting the next connection, the listener itself is still fine.
+pub async fn accept_failed(e: io::Error) {
+    log::warn!("Could not accept a connection: {e}");
+    tokio::time::sleep(ACCEPT_RETRY_DELAY).await;
+}
+
+/// Binds a Unix domain socket, replacing a stale socket file left behind by a previous run.
+pub fn bind_unix(path: &Path) -> io::Result<UnixListener> {
+    if path.exists() && fs::metadata(path)?.file_type().is_socket() {
+        fs::remove_file(path)?;
+    }
+
+    UnixListener::bind(path)
+}
+
+/// The WebSocket server --connect connects out to.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct ConnectOptions {
+    pub url: Url,
+}
+
+impl Sink for ConnectOptions {
+    fn name(&self) -> &'static str {
+        "connect"
+    }
+
+    fn run(&self, context: &SinkContext) -> BoxFuture<'static, ()> {
+        Box::pin(connect(self.url.clone(), context.state.clone()))
+    }
+}
+
+/// The largest WebSocket message a client may send by default, in bytes. Requests are a few dozen bytes.
+pub const DEFAULT_MAX_MESSAGE_SIZE: usize = 64 * 1024;
+
+/// How many answers and events may wait to be sent to each client by default. Artwork is sent in chunks, which count as one each.
+pub const DEFAULT_MAX_QUEUED_MESSAGES: usize = 256;
+
+/// Limits the messages read from a WebSocket connection to [`ServerState::max_message_size`], so they're never buffered whole before being turned down.
+fn websocket_config(state: &ServerState) -> WebSocketConfig {
+    WebSocketConfig::default()
+        .max_message_size(state.max_message_size)
+        .max_frame_size(state.max_message_size)
+}
+
+/// How long to wait before connecting to a --connect URL again.
+const RECONNECT_DELAY: Duration = Duration::from_secs(5);
+
+/// Connects out to a WebSocket server, serving it like any client and pushing the status whenever it changes. Reconnects when the connection drops.
+pub async fn connect(url: Url, state: ServerState) {
+    let mut failing = false;
+
+    loop {
+        let config = websocket_config(&state);
+        match tokio_tungstenite::connect_async_with_config(url.as_str(), Some(config), false).await
+        {
+            Ok((ws_stream, _)) => {
+                log::info!("Connected to {url}!");
+                failing = false;
+
+                let client = Client::new(url.to_string());
+                serve(ws_stream, state.clone(), &client, true, state.json_case).await;
+                log::warn!(
+                    "Lost connection to {url}, reconnecting in {} seconds.",
+                    RECONNECT_DELAY.as_secs()
+                );
+            }
+            Err(e) => {
+                if !failing {
+                    log::warn!(
+                        "Could not connect to {url}, retrying every {} seconds: {e}",
+                        RECONNECT_DELAY.as_secs()
+                    );
+                    failing = true;
+                }
+            }
+        }
+
+        tokio::time::sleep(RECONNECT_DELAY).await;
+    }
+}
+
+type Body = BoxBody<Bytes, Infallible>;
+
+/// A ready-made overlay page, for OBS browser sources and the like.
+const OVERLAY: &str = include_str!("overlay.html");
+/// A remote control page for phones, driving the active player.
+const REMOTE: &str = include_str!("remote.html");
+
+/// How long `/poll` waits for a change before answering with the same revision. Below common proxy timeouts.
+const POLL_TIMEOUT: Duration = Duration::from_secs(25);
+
+/// How many tracks `/recent` answers without a `limit`.
+const DEFAULT_RECENT_LIMIT: usize = 10;
+
+/// Sent on idle event streams so proxies don't time them out.
+const SSE_KEEPALIVE: Duration = Duration::from_secs(15);
+
+/// Set by reverse proxies to the address of the client they forward for.
+const X_FORWARDED_FOR: &str = "x-forwarded-for";
+/// Reloads the page after this many seconds. Not standard, but every browser follows it.
+const REFRESH: &str = "refresh";
+
+/// Serves HTTP on the connection, upgrading to a WebSocket when the client asks for one. `peer` is who connected, for logs, and `local` the address it came in on, if it's a TCP connection.
+async fn accept<S: AsyncRead + AsyncWrite + Unpin + Send + 'static>(
+    stream: S,
+    peer: String,
+    local: Option<SocketAddr>,
+    state: ServerState,
+) {
+    let _demand = state.demand.hold().await;
+
+    // Every request on the connection has the same id, proxies may forward several clients over one.
+    let id = Client::new(peer.clone()).id;
+    let service = service_fn(|req| {
+        let state = state.clone();
+        let client = Client {
+            id,
+            address: client_name(&req, &peer),
+        };
+        async move { Ok::<_, Infallible>(route(req, state, client, local).await) }
+    });
+
+    let mut builder = http1::Builder::new();
+    builder
+        .timer(TokioTimer::new())
+        .header_read_timeout(state.client_timeout);
+    if let Err(e) = builder
+        .serve_connection(TokioIo::new(stream), service)
+        .with_upgrades()
+        .await
+    {
+        log::debug!(
+            client_id = id, client:% = peer;
+            "HTTP connection from #{id} {peer} failed: {e}"
+        );
+    }
+}
+
+/// Who sent a request, for logs. Behind a reverse proxy, that's the first address in `X-Forwarded-For`.
+fn client_name(req: &Request<Incoming>, peer: &str) -> String {
+    let forwarded_for = req
+        .headers()
+        .get(X_FORWARDED_FOR)
+        .and_then(|v| v.to_str().ok())
+        .and_then(|v| v.split(',').next())
+        .map(str::trim)
+        .filter(|v| !v.is_empty());
+
+    match forwarded_for {
+        Some(client) => format!("{client} (via {peer})"),
+        None => peer.to_string(),
+    }
+}
+
+/// Whether `origin` is a page this daemon served, so it may always connect back: it's from the `Host` the request was sent to, and that's a loopback address, `localhost` or the address the connection came in on (`local`).
+///
+/// Any other name could be a site that pointed it at this machine (DNS rebinding), which sends a matching `Origin` and `Host` too.
+fn own_page(origin: &str, host: Option<&str>, local: Option<SocketAddr>) -> bool {
+    if host.is_none() || origin.split_once("://").map(|(_, host)| host) != host {
+        return false;
+    }
+    let Ok(url) = Url::parse(origin) else {
+        return false;
+    };
+
+    let ip = match url.host() {
+        Some(url::Host::Domain(domain)) => return domain.eq_ignore_ascii_case("localhost"),
+        Some(url::Host::Ipv4(ip)) => IpAddr::V4(ip),
+        Some(url::Host::Ipv6(ip)) => IpAddr::V6(ip),
+        None => return false,
+    };
+    ip.is_loopback()
+        || local.is_some_and(|local| {
+            local.ip().to_canonical() == ip && Some(local.port()) == url.port_or_known_default()
+        })
+}
+
+/// Answers an HTTP request, turning away pages the origin policy doesn't allow. `local` is the address the connection came in on, see [`own_page`].
+#[tracing::instrument(skip_all, fields(client_id = client.id, method = %req.method(), path = req.uri().path()))]
+async fn route(
+    req: Request<Incoming>,
+    state: ServerState,
+    client: Client,
+    local: Option<SocketAddr>,
+) -> Response<Body> {
+    let origin = req
+        .headers()
+        .get(ORIGIN)
+        .map(|origin| origin.to_str().unwrap_or_default().to_string());
+
+    // Pages served by this daemon (like the overlay) can always connect back to it. Not by `X-Forwarded-Host`, which any client can set.
+    let host = req.headers().get(HOST).and_then(|host| host.to_str().ok());
+    if let Some(origin) = &origin {
+        if !own_page(origin, host, local) && !state.origins.allows(origin) {
+            log::warn!(
+                client_id = client.id, client:% = client.address;
+                "Rejected a connection from {client} with origin {origin}."
+            );
+            return text_response(StatusCode::FORBIDDEN, "origin not allowed");
+        }
+    }
+
+    // Everything lives under the base path, at its root or below a slash.
+    let path = match req.uri().path().strip_prefix(state.base_path.as_str()) {
+        Some("") => "/",
+        Some(path) if path.starts_with('/') => path,
+        _ => return text_response(StatusCode::NOT_FOUND, "not found"),
+    };
+
+    // For the statuses and plays answered below, the WebSocket asks on its own.
+    let case =
+        FieldCase::from_query(req.uri().query().unwrap_or_default()).unwrap_or(state.json_case);
+
+    let mut res = if req.headers().contains_key(SEC_WEBSOCKET_KEY) {
+        match (path, state.hub.clone()) {
+            ("/hub", Some(hub)) => {
+                // Named by the pushing instance, e.g. `?name=laptop`, or by its address.
+                let name =
+                    url::form_urlencoded::parse(req.uri().query().unwrap_or_default().as_bytes())
+                        .find_map(|(key, value)| (key == "name").then(|| value.into_owned()));
+                match name {
+                    Some(name) if name.is_empty() || name.contains('/') => {
+                        text_response(StatusCode::BAD_REQUEST, "invalid name")
+                    }
+                    name => {
+                        let source = name.unwrap_or_else(|| client.address.clone());
+                        upgrade(req, state, client, Some((hub, source)))
+                    }
+                }
+            }
+            _ => upgrade(req, state, client, None),
+        }
+    } else {
+        match (req.method(), path) {
+            (&Method::GET, "/healthz") => text_response(StatusCode::OK, "ok"),
+            (&Method::GET, "/readyz") => {
+                let require_player = req
+                    .uri()
+                    .query()
+                    .is_some_and(|query| query.split('&').any(|v| v == "player"));
+                readiness(&state, require_player).await
+            }
+            (&Method::GET, "/status") => {
+                let status = state.status_rx.borrow().active().cloned();
+                cased_json_response(&status, case)
+            }
+            (&Method::GET, "/nowplaying.txt") => {
+                let line = match state.status_rx.borrow().active() {
+                    Some(status) => state
+                        .nowplaying_format
+                        .render(&CompactStatus::from(Some(status))),
+                    None => state.nowplaying_idle.clone(),
+                };
+
+                let mut res = Response::new(Full::from(line).boxed());
+                let headers = res.headers_mut();
+                headers.insert(
+                    CONTENT_TYPE,
+                    HeaderValue::from_static("text/plain; charset=utf-8"),
+                );
+                headers.insert(CACHE_CONTROL, HeaderValue::from_static("no-cache"));
+                res
+            }
+            (&Method::GET, "/recent") => {
+                let limit = req
+                    .uri()
+                    .query()
+                    .and_then(|query| query.split('&').find_map(|v| v.strip_prefix("limit=")));
+                match limit.map_or(Ok(DEFAULT_RECENT_LIMIT), str::parse::<usize>) {
+                    Ok(limit) => {
+                        let history = state.history.borrow();
+                        cased_json_response(&history.iter().take(limit).collect::<Vec<_>>(), case)
+                    }
+                    Err(_) => text_response(StatusCode::BAD_REQUEST, "invalid limit"),
+                }
+            }
+            (&Method::GET, path) if path.starts_with("/stats/") => {
+                let Some(kind) = Stats::parse(&path["/stats/".len()..]) else {
+                    return text_response(StatusCode::NOT_FOUND, "not found");
+                };
+                history_response(
+                    stats(&state, kind, req.uri().query().unwrap_or_default()).await,
+                    case,
+                )
+            }
+            (&Method::GET, "/search") => history_response(
+                search(&state, req.uri().query().unwrap_or_default()).await,
+                case,
+            ),
+            (&Method::GET, "/events") => status_events(state, &client, case),
+            (&Method::GET, "/poll") => {
+                let since = req.uri().query().and_then(|query| {
+                    query
+                        .split('&')
+                        .find_map(|v| v.strip_prefix("since="))
+                        .and_then(|v| v.parse().ok())
+                });
+                poll(state, since, case).await
+            }
+            (&Method::GET, "/streamdeck") => {
+                let since = req.uri().query().and_then(|query| {
+                    query
+                        .split('&')
+                        .find_map(|v| v.strip_prefix("since="))
+                        .and_then(|v| v.parse().ok())
+                });
+                streamdeck_keys(state, since).await
+            }
+            (&Method::POST, path) if path.starts_with("/streamdeck/") => {
+                streamdeck_action(&state, &path["/streamdeck/".len()..]).await
+            }
+            (&Method::GET, "/overlay") => {
+                let mut res = Response::new(Full::from(OVERLAY).boxed());
+                res.headers_mut().insert(
+                    CONTENT_TYPE,
+                    HeaderValue::from_static("text/html; charset=utf-8"),
+                );
+                res
+            }
+            (&Method::GET, "/remote") => {
+                let mut res = Response::new(Full::from(REMOTE).boxed());
+                res.headers_mut().insert(
+                    CONTENT_TYPE,
+                    HeaderValue::from_static("text/html; charset=utf-8"),
+                );
+                res
+            }
+            (method, path) if path.starts_with("/remote/") => {
+                remote_action(&state, method, &path["/remote/".len()..], req.uri().query()).await
+            }
+            (&Method::GET, "/overlay/custom") => match &state.overlay_template {
+                Some(template) => custom_overlay(template, &state).await,
+                None => text_response(StatusCode::NOT_FOUND, "not found"),
+            },
+            (method, path) if path == "/admin" || path.starts_with("/admin/") => {
+                match &state.admin {
+                    Some(admin) => admin_response(&state, admin, method, path, req.uri().query()),
+                    None => text_response(StatusCode::NOT_FOUND, "not found"),
+                }
+            }
+            _ => text_response(StatusCode::NOT_FOUND, "not found"),
+        }
+    };
+
+    // Lets allowed pages read the response with `fetch`.
+    if let Some(origin) = origin.and_then(|origin| HeaderValue::from_str(&origin).ok()) {
+        res.headers_mut()
+            .insert(ACCESS_CONTROL_ALLOW_ORIGIN, origin);
+    }
+
+    res
+}
+
+fn text_response(status: StatusCode, text: &'static str) -> Response<Body> {
+    let mut res = Response::new(Full::from(text).boxed());
+    *res.status_mut() = status;
+    res
+}
+
+fn json_response(value: &impl Serialize) -> Response<Body> {
+    cased_json_response(value, FieldCase::Camel)
+}
+
+/// Like [`json_response`], with the keys in `case`.
+fn cased_json_response(value: &impl Serialize, case: FieldCase) -> Response<Body> {
+    let mut res = Response::new(Full::from(case.to_string(value)).boxed());
+    res.headers_mut()
+        .insert(CONTENT_TYPE, HeaderValue::from_static("application/json"));
+    res
+}
+
+/// Answers `/stats/<kind>` and `/search` with the JSON from the --history-db database, or the error with a fitting status.
+fn history_response(result: Result<String, ErrorResponse>, case: FieldCase) -> Response<Body> {
+    match result {
+        Ok(json) => {
+            let mut res = Response::new(Full::from(case.recase(json)).boxed());
+            res.headers_mut()
+                .insert(CONTENT_TYPE, HeaderValue::from_static("application/json"));
+            res
+        }
+        Err(e) => {
+            let mut res = json_response(&e);
+            *res.status_mut() = match e {
+                ErrorResponse::HistoryDisabled => StatusCode::NOT_FOUND,
+                _ => StatusCode::BAD_REQUEST,
+            };
+            res
+        }
+    }
+}
+
+/// Renders the --overlay-template page, which reloads itself every few seconds.
+async fn custom_overlay(template: &OverlayTemplate, state: &ServerState) -> Response<Body> {
+    let status = state.status_rx.borrow().active().cloned();
+
+    match template.render(status.as_ref()).await {
+        Ok(page) => {
+            let mut res = Response::new(Full::from(page).boxed());
+            let headers = res.headers_mut();
+            headers.insert(
+                CONTENT_TYPE,
+                HeaderValue::from_static("text/html; charset=utf-8"),
+            );
+            headers.insert(CACHE_CONTROL, HeaderValue::from_static("no-cache"));
+            headers.insert(REFRESH, HeaderValue::from(template.refresh));
+            res
+        }
+        Err(e) => {
+            log::warn!(
+                "Could not render the overlay template {}: {e}",
+                template.path.display()
+            );
+            let mut res = Response::new(Full::from(e).boxed());
+            *res.status_mut() = StatusCode::INTERNAL_SERVER_ERROR;
+            res.headers_mut()
+                .insert(REFRESH, HeaderValue::from(template.refresh));
+            res
+        }
+    }
+}
+
+/// What `/readyz` checked.
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "camelCase")]
+struct Readiness {
+    dbus: bool,
+    player: bool,
+}
+
+/// Ready when D-Bus can be reached, and with `require_player`, when a player is attached.
+async fn readiness(state: &ServerState, require_player: bool) -> Response<Body> {
+    let bus = state.bus.clone();
+    let readiness = Readiness {
+        dbus: tokio::task::spawn_blocking(move || bus.connect().is_ok())
+            .await
+            .unwrap_or(false),
+        // The last status from before a restart doesn't count.
+        player: state
+            .status_rx
+            .borrow()
+            .active()
+            .is_some_and(|status| !status.stale),
+    };
+
+    let mut res = json_response(&readiness);
+    if !readiness.dbus || (require_player && !readiness.player) {
+        *res.status_mut() = StatusCode::SERVICE_UNAVAILABLE;
+    }
+    res
+}
+
+/// Answers with the status once its revision isn't `since` anymore, or after [`POLL_TIMEOUT`] with the same one.
+///
+/// Any other revision counts as outdated, so clients don't get stuck when the daemon restarts and counts from 1 again.
+async fn poll(mut state: ServerState, since: Option<u64>, case: FieldCase) -> Response<Body> {
+    if let Some(since) = since {
+        let _ = tokio::time::timeout(
+            POLL_TIMEOUT,
+            state
+                .revisions
+                .wait_for(|current| current.revision != since),
+        )
+        .await;
+    }
+
+    let mut res = cased_json_response(&*state.revisions.borrow(), case);
+    res.headers_mut()
+        .insert(CACHE_CONTROL, HeaderValue::from_static("no-cache"));
+    res
+}
+
+/// Answers with the Stream Deck keys once their revision isn't `since` anymore, or after [`POLL_TIMEOUT`] with the same one, like [`poll`].
+async fn streamdeck_keys(mut state: ServerState, since: Option<u64>) -> Response<Body> {
+    if let Some(since) = since {
+        let _ = tokio::time::timeout(
+            POLL_TIMEOUT,
+            state
+                .streamdeck
+                .wait_for(|current| current.revision != since),
+        )
+        .await;
+    }
+
+    let mut res = json_response(&*state.streamdeck.borrow());
+    res.headers_mut()
+        .insert(CACHE_CONTROL, HeaderValue::from_static("no-cache"));
+    res
+}
+
+/// Runs a Stream Deck action (`playPause`, `next` or `previous`) on the active player, answering 204 once the player took it.
+async fn streamdeck_action(state: &ServerState, action: &str) -> Response<Body> {
+    let Some(method) = streamdeck::ACTIONS
+        .iter()
+        .find(|(name, _)| *name == action)
+        .map(|(_, method)| *method)
+    else {
+        return text_response(StatusCode::NOT_FOUND, "not found");
+    };
+
+    match control_active(state, method, move |bus, status| {
+        control::call_player(bus, &status.player.bus_name, method)
+    })
+    .await
+    {
+        Ok(()) => text_response(StatusCode::NO_CONTENT, ""),
+        Err(res) => res,
+    }
+}
+
+/// The active player's volume, from 0 to 1.
+#[derive(Serialize)]
+struct Volume {
+    volume: f64,
+}
+
+/// Serves the `/remote` page's actions: the Stream Deck ones, `seek?position=<microseconds>` and `volume`, read with GET and set with `POST volume?level=<0 to 1>`, and `GET capabilities` to hide what the player doesn't allow.
+async fn remote_action(
+    state: &ServerState,
+    method: &Method,
+    action: &str,
+    query: Option<&str>,
+) -> Response<Body> {
+    let param = |name: &str| {
+        url::form_urlencoded::parse(query.unwrap_or_default().as_bytes())
+            .find_map(|(key, value)| (key == name).then(|| value.parse::<f64>().ok()))
+            .flatten()
+    };
+
+    let result = match (method, action) {
+        (&Method::GET, "volume") => {
+            return match control_active(state, "Volume", |bus, status| {
+                Ok(control::volume(bus, &status.player.bus_name)?)
+            })
+            .await
+            {
+                Ok(volume) => json_response(&Volume { volume }),
+                Err(res) => res,
+            };
+        }
+        (&Method::GET, "capabilities") => {
+            return match control_active(state, "GetAll", |bus, status| {
+                Ok(control::capabilities(bus, &status.player.bus_name)?)
+            })
+            .await
+            {
+                Ok(capabilities) => json_response(&capabilities),
+                Err(res) => res,
+            };
+        }
+        (&Method::POST, "volume") => {
+            let Some(level) = param("level").filter(|level| (0.0..=1.0).contains(level)) else {
+                return text_response(StatusCode::BAD_REQUEST, "invalid level");
+            };
+            control_active(state, "SetVolume", move |bus, status| {
+                control::set_volume(bus, &status.player.bus_name, level)
+            })
+            .await
+        }
+        (&Method::POST, "seek") => {
+            let Some(position) = param("position").filter(|position| *position >= 0.0) else {
+                return text_response(StatusCode::BAD_REQUEST, "invalid position");
+            };
+            control_active(state, "SetPosition", move |bus, status| {
+                control::set_position(
+                    bus,
+                    &status.player.bus_name,
+                    &status.metadata.track_id,
+                    position as u64,
+                    status.position_now(),
+                )
+            })
+            .await
+        }
+        (&Method::POST, action) => return streamdeck_action(state, action).await,
+        _ => return text_response(StatusCode::NOT_FOUND, "not found"),
+    };
+
+    match result {
+        Ok(()) => text_response(StatusCode::NO_CONTENT, ""),
+        Err(res) => res,
+    }
+}
+
+/// Runs `call` with the active player's status off the runtime, with the response to answer with when there's no player (409), it doesn't allow the command (403) or it refused (502).
+async fn control_active<T: Send + 'static>(
+    state: &ServerState,
+    method: &'static str,
+    call: impl FnOnce(&BusAddress, &PlayerStatus) -> Result<T, ControlError> + Send + 'static,
+) -> Result<T, Response<Body>> {
+    let Some(status) = state.status_rx.borrow().active().cloned() else {
+        let mut res = json_response(&ErrorResponse::NoPlayer);
+        *res.status_mut() = StatusCode::CONFLICT;
+        return Err(res);
+    };
+
+    let bus = state.bus.clone();
+    let span = tracing::Span::current();
+    tokio::task::spawn_blocking(move || {
+        let bus_name = &status.player.bus_name;
+        span.in_scope(|| call(&bus, &status)).inspect_err(|e| match e {
+            ControlError::NotPermitted(_) => {
+                log::info!(player = bus_name.as_str(); "Not sending {method} to player {bus_name}, it doesn't allow it.");
+            }
+            ControlError::Failed(e) => {
+                log::warn!(player = bus_name.as_str(); "{method} failed on player {bus_name}: {e}");
+            }
+        })
+    })
+    .await
+    .unwrap_or_else(|e| Err(dbus::Error::new_failed(&e.to_string()).into()))
+    .map_err(|e| {
+        let (error, status) = match e {
+            ControlError::NotPermitted(command) => (
+                ErrorResponse::NotPermitted { command },
+                StatusCode::FORBIDDEN,
+            ),
+            ControlError::Failed(e) => (
+                ErrorResponse::CommandFailed {
+                    message: e.message().unwrap_or_default().to_string(),
+                },
+                StatusCode::BAD_GATEWAY,
+            ),
+        };
+        let mut res = json_response(&error);
+        *res.status_mut() = status;
+        res
+    })
+}
+
+/// Serves the admin page and its actions, with --admin.
+fn admin_response(
+    state: &ServerState,
+    admin: &Admin,
+    method: &Method,
+    path: &str,
+    query: Option<&str>,
+) -> Response<Body> {
+    let action = match (method, path) {
+        (&Method::GET, "/admin") => {
+            let mut res = Response::new(Full::from(admin::PAGE).boxed());
+            res.headers_mut().insert(
+                CONTENT_TYPE,
+                HeaderValue::from_static("text/html; charset=utf-8"),
+            );
+            return res;
+        }
+        (&Method::GET, "/admin/state") => {
+            let json = admin::state_json(admin, &state.status_rx.borrow(), &state.clients);
+            let mut res = Response::new(Full::from(json).boxed());
+            let headers = res.headers_mut();
+            headers.insert(CONTENT_TYPE, HeaderValue::from_static("application/json"));
+            headers.insert(CACHE_CONTROL, HeaderValue::from_static("no-cache"));
+            return res;
+        }
+        (&Method::POST, "/admin/rediscover") => player::Action::Rediscover,
+        (&Method::POST, "/admin/detach") => {
+            let bus_name = url::form_urlencoded::parse(query.unwrap_or_default().as_bytes())
+                .find_map(|(key, value)| (key == "player").then_some(value));
+            match bus_name {
+                Some(bus_name) => player::Action::Detach(bus_name.into_owned()),
+                None => return text_response(StatusCode::BAD_REQUEST, "missing player"),
+            }
+        }
+        _ => return text_response(StatusCode::NOT_FOUND, "not found"),
+    };
+
+    match admin.actions_tx.send(action) {
+        Ok(()) => text_response(StatusCode::ACCEPTED, ""),
+        Err(_) => text_response(StatusCode::CONFLICT, "no players are watched"),
+    }
+}
+
+/// Streams the active player's status as Server-Sent Events, one event whenever it changes.
+fn status_events(state: ServerState, client: &Client, case: FieldCase) -> Response<Body> {
+    let slot = match &state.client_slots {
+        Some(slots) => match slots.clone().try_acquire_owned() {
+            Ok(slot) => Some(slot),
+            Err(_) => {
+                log::warn!(
+                    client_id = client.id, client:% = client.address;
+                    "Too many clients connected, turning {client} away."
+                );
+                return text_response(StatusCode::SERVICE_UNAVAILABLE, "too many clients");
+            }
+        },
+        None => None,
+    };
+
+    let connected = state.clients.connect(client, "events", vec!["status"]);
+    // The slot and the listing are held for as long as the stream is.
+    let events = futures_util::stream::unfold(
+        (state.status_rx, None, (slot, connected)),
+        move |(mut status_rx, last_sent, held)| async move {
+            loop {
+                if last_sent.is_some() {
+                    match tokio::time::timeout(SSE_KEEPALIVE, status_rx.changed()).await {
+                        Ok(Ok(())) => {}
+                        Ok(Err(_)) => return None,
+                        Err(_) => {
+                            let comment = Frame::data(Bytes::from_static(b":\n\n"));
+                            return Some((Ok(comment), (status_rx, last_sent, held)));
+                        }
+                    }
+                }
+
+                // Other players changing wake this up too, only send actual changes.
+                let status = case.to_string(&status_rx.borrow_and_update().active());
+                if last_sent.as_ref() == Some(&status) {
+                    continue;
+                }
+
+                let event = Frame::data(Bytes::from(format!("data: {status}\n\n")));
+                held.1.active();
+                return Some((Ok(event), (status_rx, Some(status), held)));
+            }
+        },
+    );
+
+    let mut res = Response::new(BodyExt::boxed(StreamBody::new(events)));
+    let headers = res.headers_mut();
+    headers.insert(CONTENT_TYPE, HeaderValue::from_static("text/event-stream"));
+    headers.insert(CACHE_CONTROL, HeaderValue::from_static("no-cache"));
+    res
+}
+
+/// Accepts a WebSocket handshake, then serves the client on the upgraded connection, or takes what it pushes as a `hub` source.
+fn upgrade(
+    req: Request<Incoming>,
+    state: ServerState,
+    client: Client,
+    hub_source: Option<(Hub, String)>,
+) -> Response<Body> {
+    if req
+        .headers()
+        .get(SEC_WEBSOCKET_VERSION)
+        .map(HeaderValue::as_bytes)
+        != Some(b"13")
+    {
+        return text_response(StatusCode::BAD_REQUEST, "unsupported websocket version");
+    }
+    let accept_key = derive_accept_key(req.headers()[SEC_WEBSOCKET_KEY].as_bytes());
+    let case =
+        FieldCase::from_query(req.uri().query().unwrap_or_default()).unwrap_or(state.json_case);
+
+    tokio::spawn(async move {
+        let upgraded = match hyper::upgrade::on(req).await {
+            Ok(upgraded) => upgraded,
+            Err(e) => {
+                log::debug!(
+                    client_id = client.id, client:% = client.address;
+                    "WebSocket upgrade for {client} failed: {e}"
+                );
+                return;
+            }
+        };
+        // The HTTP connection lets go of it once upgraded.
+        let _demand = state.demand.hold_now();
+        let config = websocket_config(&state);
+        let mut ws_stream =
+            WebSocketStream::from_raw_socket(TokioIo::new(upgraded), Role::Server, Some(config))
+                .await;
+
+        let _slot = match &state.client_slots {
+            Some(slots) => match slots.clone().try_acquire_owned() {
+                Ok(slot) => Some(slot),
+                Err(_) => {
+                    log::warn!(
+                        client_id = client.id, client:% = client.address;
+                        "Too many clients connected, turning {client} away."
+                    );
+
+                    let _ = ws_stream
+                        .close(Some(CloseFrame {
+                            code: CloseCode::Again,
+                            reason: "too many clients".into(),
+                        }))
+                        .await;
+                    return;
+                }
+            },
+            None => None,
+        };
+
+        if let Some((hub, source)) = hub_source {
+            let connected = state.clients.connect(&client, "hub", Vec::new());
+            hub::receive(
+                ws_stream,
+                &hub,
+                &source,
+                &client,
+                &connected,
+                state.client_timeout,
+            )
+            .await;
+            return;
+        }
+
+        log::debug!(client_id = client.id, client:% = client.address; "{client} connected.");
+        serve(ws_stream, state, &client, false, case).await;
+        log::debug!(client_id = client.id, client:% = client.address; "{client} disconnected.");
+    });
+
+    let mut res = Response::new(Full::default().boxed());
+    *res.status_mut() = StatusCode::SWITCHING_PROTOCOLS;
+    let headers = res.headers_mut();
+    headers.insert(CONNECTION, HeaderValue::from_static("Upgrade"));
+    headers.insert(UPGRADE, HeaderValue::from_static("websocket"));
+    headers.insert(
+        SEC_WEBSOCKET_ACCEPT,
+        HeaderValue::from_str(&accept_key).unwrap(),
+    );
+    res
+}
+
+/// Spaces out a client's requests once it sends more than its share, instead of answering them as fast as they come.
+struct RateLimiter {
+    per_second: f32,
+    /// How many requests can be answered right away, refilled over time up to `per_second`.
+    allowance: f32,
+    last_request: Instant,
+    throttled: bool,
+}
+
+impl RateLimiter {
+    fn new(per_second: f32) -> Self {
+        Self {
+            per_second,
+            allowance: per_second,
+            last_request: Instant::now(),
+            throttled: false,
+        }
+    }
+
+    /// Waits until the next request may be answered.
+    async fn wait(&mut self, client: &Client) {
+        let now = Instant::now();
+        let refill = now.duration_since(self.last_request).as_secs_f32() * self.per_second;
+        self.allowance = (self.allowance + refill).min(self.per_second);
+        self.last_request = now;
+
+        if self.allowance >= 1.0 {
+            self.allowance -= 1.0;
+            self.throttled = false;
+            return;
+        }
+
+        if !self.throttled {
+            log::warn!(
+                client_id = client.id, client:% = client.address;
+                "{client} is sending more than {} requests per second, slowing it down.",
+                self.per_second
+            );
+            self.throttled = true;
+        }
+
+        tokio::time::sleep(
+            Duration::try_from_secs_f32((1.0 - self.allowance) / self.per_second)
+                .unwrap_or_default(),
+        )
+        .await;
+        self.allowance = 0.0;
+        self.last_request = Instant::now();
+    }
+}
+
+/// Answers a client's requests until it disconnects.
+///
+/// Only awaits the stream and the channels, anything blocking is left to [`respond`], so many clients don't hold up each other. What's sent goes through an [`Outbox`], so a client that stops reading only holds up itself. Its keys are put in `case` on the way out.
+#[tracing::instrument(name = "connection", skip_all, fields(client_id = client.id, client = %client.address))]
+async fn serve<S: AsyncRead + AsyncWrite + Unpin>(
+    ws_stream: WebSocketStream<S>,
+    state: ServerState,
+    client: &Client,
+    push_status: bool,
+    case: FieldCase,
+) {
+    let (mut sink, stream) = ws_stream.split();
+    let outbox = Outbox::new(state.max_queued_messages);
+    // Only the server --connect connects to is pushed statuses.
+    let connected = if push_status {
+        state.clients.connect(client, "connect", vec!["status"])
+    } else {
+        state.clients.connect(client, "webSocket", Vec::new())
+    };
+
+    let close = tokio::select! {
+        close = read_requests(stream, &state, client, &connected, push_status, &outbox) => close,
+        () = send_queued(&mut sink, &outbox, &state.health, case) => return,
+    };
+
+    // A client that stopped reading won't read this either.
+    if let Some(close) = close {
+        let _ = tokio::time::timeout(CLOSE_TIMEOUT, sink.send(Message::Close(Some(close)))).await;
+    }
+}
+
+/// How long a client gets to take the close frame before it's dropped anyway.
+const CLOSE_TIMEOUT: Duration = Duration::from_secs(5);
+
+/// Sends what's queued in `outbox` until the client is gone, with the keys in `case`, counting the messages in `health`.
+async fn send_queued<S: AsyncRead + AsyncWrite + Unpin>(
+    sink: &mut SplitSink<WebSocketStream<S>, Message>,
+    outbox: &Outbox,
+    health: &Health,
+    case: FieldCase,
+) {
+    loop {
+        let (queue, message) = outbox.next().await;
+        if !message.is_ping() {
+            health.message_sent();
+        }
+        // Everything is queued as the daemon names its fields.
+        let message = match message {
+            Message::Text(text) if case != FieldCase::Camel => {
+                Message::Text(case.recase(text.as_str().to_owned()).into())
+            }
+            message => message,
+        };
+        let sent = if queue == Queue::Status {
+            sink.send(message)
+                .instrument(tracing::info_span!("push_status"))
+                .await
+        } else {
+            sink.send(message).await
+        };
+        if sent.is_err() {
+            return;
+        }
+    }
+}
+
+/// Reads a client's requests and queues the answers, the pushed statuses and whatever it subscribed to in `outbox`, until it disconnects. Returns why it should be disconnected, if it's still there.
+async fn read_requests<S: AsyncRead + AsyncWrite + Unpin>(
+    mut stream: SplitStream<WebSocketStream<S>>,
+    state: &ServerState,
+    client: &Client,
+    connected: &ConnectedClient,
+    push_status: bool,
+    outbox: &Outbox,
+) -> Option<CloseFrame> {
+    let mut current_artwork = None;
+    let mut events_rx: Option<broadcast::Receiver<Event>> = None;
+    let mut lyrics_rx: Option<watch::Receiver<StatusSnapshot>> = None;
+    let mut last_lyrics = None;
+    let mut dropping_events = false;
+    let mut rate_limiter = state.max_requests_per_second.map(RateLimiter::new);
+
+    let mut status_rx = state.status_rx.clone();
+    let mut last_pushed = None;
+    if push_status {
+        status_rx.mark_changed();
+    }
+
+    let mut last_read = tokio::time::Instant::now();
+    let mut pinged = false;
+
+    loop {
+        // Clients only receiving (pushed statuses, events) still answer pings, browsers do so on their own.
+        let idle_deadline = state
+            .client_timeout
+            .map(|timeout| last_read + if pinged { timeout } else { timeout / 2 });
+        // Clients not reading the answers don't get to send more requests. They still time out if they don't read the ping either.
+        let has_room = outbox.has_room();
+
+        let msg = tokio::select! {
+            msg = stream.next(), if has_room => match msg {
+                Some(Ok(msg)) => {
+                    last_read = tokio::time::Instant::now();
+                    pinged = false;
+                    connected.active();
+                    msg
+                }
+                Some(Err(tungstenite::Error::Capacity(e))) => {
+                    log::debug!(
+                        client_id = client.id, client:% = client.address;
+                        "{client} sent too much, disconnecting it: {e}"
+                    );
+                    return Some(CloseFrame {
+                        code: CloseCode::Size,
+                        reason: "message too big".into(),
+                    });
+                }
+                _ => return None,
+            },
+            () = outbox.wait_for_room(), if !has_room => continue,
+            () = async { tokio::time::sleep_until(idle_deadline.unwrap()).await }, if idle_deadline.is_some() => {
+                if pinged {
+                    log::debug!(
+                        client_id = client.id, client:% = client.address;
+                        "{client} didn't send anything for {} seconds, disconnecting it.",
+                        state.client_timeout.unwrap_or_default().as_secs_f32()
+                    );
+                    return Some(CloseFrame {
+                        code: CloseCode::Policy,
+                        reason: "timed out".into(),
+                    });
+                }
+
+                outbox.reply(vec![Message::Ping(Bytes::new())]);
+                pinged = true;
+                continue;
+            }
+            changed = status_rx.changed(), if push_status => {
+                if changed.is_err() {
+                    return None;
+                }
+
+                // Other players changing wake this up too, only send actual changes.
+                let status = serde_json::to_string(&status_rx.borrow_and_update().active()).unwrap();
+                if last_pushed.as_ref() != Some(&status) {
+                    outbox.status(Message::Text(status.clone().into()));
+                    last_pushed = Some(status);
+                }
+                continue;
+            }
+            event = async { Some(events_rx.as_mut()?.recv().await) }, if events_rx.is_some() => {
+                match event {
+                    Some(Ok(event)) => {
+                        let dropped = outbox.event(Message::Text(serde_json::to_string(&event).unwrap().into()));
+                        if dropped && !dropping_events {
+                            log::warn!(
+                                client_id = client.id, client:% = client.address;
+                                "{client} isn't reading its events, dropping the oldest ones."
+                            );
+                        }
+                        dropping_events = dropped;
+                    }
+                    Some(Err(broadcast::error::RecvError::Lagged(skipped))) => {
+                        log::warn!(
+                            client_id = client.id, client:% = client.address;
+                            "{client} fell behind, skipped {skipped} event(s)."
+                        );
+                    }
+                    _ => events_rx = None,
+                }
+                continue;
+            }
+            changed = async { lyrics_rx.as_mut().unwrap().changed().await }, if lyrics_rx.is_some() => {
+                if changed.is_err() {
+                    return None;
+                }
+
+                let lyrics = lyrics_rx
+                    .as_mut()
+                    .unwrap()
+                    .borrow_and_update()
+                    .active()
+                    .and_then(|status| status.lyrics.clone())
+                    .unwrap_or_default();
+                if last_lyrics.as_ref() != Some(&lyrics) {
+                    outbox.lyrics(lyrics.clone().into());
+                    last_lyrics = Some(lyrics);
+                }
+                continue;
+            }
+        };
+
+        // Pings are answered, pongs only keep the connection alive and closing is answered on its own, none of them is a request.
+        if msg.is_ping() || msg.is_pong() || msg.is_close() {
+            continue;
+        }
+        let req = msg.into_data();
+
+        if let Some(rate_limiter) = &mut rate_limiter {
+            rate_limiter.wait(client).await;
+        }
+
+        let responses = respond(
+            ClientRequest::parse(&req),
+            state,
+            &mut current_artwork,
+            &mut events_rx,
+            &mut lyrics_rx,
+        )
+        .await;
+        outbox.reply(responses);
+
+        if events_rx.is_some() {
+            connected.subscribe("events");
+        }
+        if lyrics_rx.is_some() {
+            connected.subscribe("lyrics");
+        }
+    }
+}
+
+/// Answers a single request. Requests that block (D-Bus calls, reading files) run off the executor.
+#[tracing::instrument(skip(state, current_artwork, events_rx, lyrics_rx))]
+async fn respond(
+    req: ClientRequest<'_>,
+    state: &ServerState,
+    current_artwork: &mut Option<ArtworkInfo>,
+    events_rx: &mut Option<broadcast::Receiver<Event>>,
+    lyrics_rx: &mut Option<watch::Receiver<StatusSnapshot>>,
+) -> Vec<Message> {
+    match req {
+        ClientRequest::Malformed => Vec::new(),
+        ClientRequest::Events => {
+            *events_rx = Some(state.events_tx.subscribe());
+            Vec::new()
+        }
+        ClientRequest::Lyrics => {
+            if !state.lyrics {
+                return vec![ErrorResponse::LyricsDisabled.into()];
+            }
+
+            // Answered right away with the current line by the subscription.
+            let mut status_rx = state.status_rx.clone();
+            status_rx.mark_changed();
+            *lyrics_rx = Some(status_rx);
+            Vec::new()
+        }
+        ClientRequest::Players => {
+            let players = state.status_rx.borrow().players.clone();
+            vec![Message::Text(
+                serde_json::to_string(&players).unwrap().into(),
+            )]
+        }
+        ClientRequest::History(count) => {
+            let history = state.history.borrow();
+            let entries = history.iter().take(count).collect::<Vec<_>>();
+            vec![Message::Text(
+                serde_json::to_string(&entries).unwrap().into(),
+            )]
+        }
+        ClientRequest::Stats { kind, query } => vec![match stats(state, kind, query).await {
+            Ok(stats) => Message::Text(stats.into()),
+            Err(e) => e.into(),
+        }],
+        ClientRequest::Search { query } => vec![match search(state, query).await {
+            Ok(results) => Message::Text(results.into()),
+            Err(e) => e.into(),
+        }],
+        ClientRequest::ServerStats => {
+            let server_stats = state
+                .health
+                .stats(&state.status_rx.borrow(), state.clients.count());
+            vec![Message::Text(
+                serde_json::to_string(&ServerStatsResponse { server_stats })
+                    .unwrap()
+                    .into(),
+            )]
+        }
+        ClientRequest::Capabilities => {
+            let Some(bus_name) = state.status_rx.borrow().active.clone() else {
+                return vec![ErrorResponse::NoPlayer.into()];
+            };
+            let bus = state.bus.clone();
+            let span = tracing::Span::current();
+
+            let capabilities = tokio::task::spawn_blocking(move || {
+                span.in_scope(|| control::capabilities(&bus, &bus_name))
+            })
+            .await
+            .unwrap_or_else(|e| Err(dbus::Error::new_failed(&e.to_string())));
+            vec![match capabilities {
+                Ok(capabilities) => Message::Text(
+                    serde_json::to_string(&CapabilitiesResponse { capabilities })
+                        .unwrap()
+                        .into(),
+                ),
+                Err(e) => ErrorResponse::CommandFailed {
+                    message: e.message().unwrap_or_default().to_string(),
+                }
+                .into(),
+            }]
+        }
+        ClientRequest::Time(client_time) => {
+            let time = ServerTime::now(client_time);
+            vec![Message::Text(
+                serde_json::to_string(&TimeResponse { time })
+                    .unwrap()
+                    .into(),
+            )]
+        }
+        ClientRequest::Playlists | ClientRequest::ActivatePlaylist(_) => {
+            let active = state.status_rx.borrow().active.clone();
+            let id = match req {
+                ClientRequest::ActivatePlaylist(id) => Some(id.to_string()),
+                _ => None,
+            };
+            let bus = state.bus.clone();
+            // Blocking threads don't carry the request's span over on their own.
+            let span = tracing::Span::current();
+
+            let response = tokio::task::spawn_blocking(move || {
+                span.in_scope(|| respond_command(id.as_deref(), &bus, active.as_deref()))
+            })
+            .await
+            .unwrap_or_else(|e| {
+                ErrorResponse::CommandFailed {
+                    message: e.to_string(),
+                }
+                .into()
+            });
+            vec![response]
+        }
+        ClientRequest::Artwork { .. } if state.artwork_options.disabled => {
+            vec![ErrorResponse::ArtworkDisabled.into()]
+        }
+        ClientRequest::Artwork {
+            index,
+            refresh,
+            chunked,
+        } => {
+            let Some(status) = state.status_rx.borrow().active().cloned() else {
+                return vec![Message::Text("null".into())];
+            };
+
+            let src = status
+                .metadata
+                .artwork
+                .get(index)
+                .map(|artwork| artwork.src.clone())
+                .unwrap_or_default();
+            let mut artwork = current_artwork.take();
+            let artwork_options = state.artwork_options.clone();
+            let health = state.health.clone();
+            let span = tracing::Span::current();
+
+            let (responses, artwork) = tokio::task::spawn_blocking(move || {
+                let _span = span.enter();
+                let responses = respond_artwork(
+                    &status,
+                    index,
+                    refresh,
+                    chunked,
+                    &mut artwork,
+                    &artwork_options,
+                    &health,
+                );
+                (responses, artwork)
+            })
+            .await
+            .unwrap_or_else(|e| {
+                log::warn!("Could not read artwork {src}: {e}");
+                (
+                    vec![ErrorResponse::ArtworkUnavailable { index, src }.into()],
+                    None,
+                )
+            });
+
+            *current_artwork = artwork;
+            responses
+        }
+        ClientRequest::Status => {
+            let status = state.status_rx.borrow().active().cloned();
+            vec![Message::Text(
+                serde_json::to_string(&status).unwrap().into(),
+            )]
+        }
+    }
+}
@@ -0,0 +1,193 @@
+use crate::sink::{Sink, SinkContext};
+use crate::status::{self, StatusSnapshot};
+use dbus::blocking::stdintf::org_freedesktop_dbus::{Properties, PropertiesPropertiesChanged};
+use dbus::blocking::Connection;
+use dbus::message::SignalArgs;
+use dbus::Path;
+use futures_util::future::BoxFuture;
+use std::any::Any;
+use std::sync::{Arc, OnceLock};
+use std::thread;
+use std::time::Duration;
+use tokio::sync::broadcast::{self, error::RecvError};
+use tokio::sync::watch;
+
+const LOGIN1: &str = "org.freedesktop.login1";
+const MANAGER_PATH: &str = "/org/freedesktop/login1";
+const MANAGER_INTERFACE: &str = "org.freedesktop.login1.Manager";
+const USER_INTERFACE: &str = "org.freedesktop.login1.User";
+const SESSION_INTERFACE: &str = "org.freedesktop.login1.Session";
+const CALL_TIMEOUT: Duration = Duration::from_secs(2);
+
+/// How often the lock is read again even without logind saying it changed, and logind tried again after it couldn't be reached.
+const RECHECK_INTERVAL: Duration = Duration::from_secs(5);
+
+/// Events waiting for an output, like the players' own event channel.
+const EVENT_BUFFER: usize = 64;
+
+/// Whether the login session is locked, as logind tells it, for --pause-when-locked.
+///
+/// Only watched once something asks, so the system bus isn't touched without --pause-when-locked.
+#[derive(Clone, Default)]
+pub struct SessionLock(Arc<OnceLock<watch::Receiver<bool>>>);
+
+impl SessionLock {
+    /// Whether the session is locked from now on. Taken as locked until logind answered, so nothing slips out at startup, and as unlocked when it can't be reached.
+    pub fn watch(&self) -> watch::Receiver<bool> {
+        self.0
+            .get_or_init(|| {
+                let (locked_tx, locked_rx) = watch::channel(true);
+                if let Err(e) = thread::Builder::new()
+                    .name(String::from("session-lock"))
+                    .spawn(move || follow(locked_tx))
+                {
+                    log::warn!("Could not watch whether the session is locked: {e}");
+                }
+                locked_rx
+            })
+            .clone()
+    }
+}
+
+/// Follows `LockedHint` of the session into `locked_tx`, connecting to logind again whenever it's lost.
+fn follow(locked_tx: watch::Sender<bool>) {
+    let mut failing = false;
+
+    loop {
+        let result = Connection::new_system()
+            .and_then(|connection| watch_session(&connection, &locked_tx, &mut failing));
+        if let Err(e) = result {
+            if !failing {
+                log::warn!(
+                    "Could not tell whether the session is locked, not pausing any outputs: {e}"
+                );
+                failing = true;
+            }
+            locked_tx.send_if_modified(|locked| std::mem::replace(locked, false));
+        }
+        thread::sleep(RECHECK_INTERVAL);
+    }
+}
+
+/// Reads the session's lock whenever its properties change, and every [`RECHECK_INTERVAL`] in case a change was missed, until the bus fails.
+fn watch_session(
+    connection: &Connection,
+    locked_tx: &watch::Sender<bool>,
+    failing: &mut bool,
+) -> Result<(), dbus::Error> {
+    let path = session(connection)?;
+    let rule =
+        PropertiesPropertiesChanged::match_rule(Some(&LOGIN1.into()), Some(&path)).static_clone();
+    connection.add_match(rule, |_: PropertiesPropertiesChanged, _, _| true)?;
+
+    let proxy = connection.with_proxy(LOGIN1, path, CALL_TIMEOUT);
+    loop {
+        let locked: bool = proxy.get(SESSION_INTERFACE, "LockedHint")?;
+        *failing = false;
+        locked_tx.send_if_modified(|current| {
+            if *current == locked {
+                return false;
+            }
+            match locked {
+                true => log::info!("The session was locked, pausing --pause-when-locked outputs."),
+                false => {
+                    log::info!("The session was unlocked, resuming --pause-when-locked outputs.")
+                }
+            }
+            *current = locked;
+            true
+        });
+
+        connection.process(RECHECK_INTERVAL)?;
+    }
+}
+
+/// The user's graphical session, or else the one the daemon runs in.
+fn session(connection: &Connection) -> Result<Path<'static>, dbus::Error> {
+    let user = connection.with_proxy(LOGIN1, "/org/freedesktop/login1/user/self", CALL_TIMEOUT);
+    // Run as a user service, the daemon is in no session of its own.
+    if let Ok((_, path)) = user.get::<(String, Path<'static>)>(USER_INTERFACE, "Display") {
+        if &*path != "/" {
+            return Ok(path);
+        }
+    }
+
+    // Signals are sent from the session's own path, not from this alias.
+    let auto = connection.with_proxy(LOGIN1, "/org/freedesktop/login1/session/auto", CALL_TIMEOUT);
+    let id: String = auto.get(SESSION_INTERFACE, "Id")?;
+    let manager = connection.with_proxy(LOGIN1, MANAGER_PATH, CALL_TIMEOUT);
+    let (path,): (Path<'static>,) = manager.method_call(MANAGER_INTERFACE, "GetSession", (id,))?;
+    Ok(path)
+}
+
+/// An output that's sent no players and no events while the session is locked (--pause-when-locked), and everything again once it's unlocked.
+#[derive(Debug)]
+pub struct Locked {
+    pub sink: Box<dyn Sink>,
+}
+
+impl PartialEq for Locked {
+    fn eq(&self, other: &Self) -> bool {
+        self.sink.same_settings(&*other.sink as &dyn Any)
+    }
+}
+
+impl Sink for Locked {
+    fn name(&self) -> &'static str {
+        self.sink.name()
+    }
+
+    fn run(&self, context: &SinkContext) -> BoxFuture<'static, ()> {
+        let mut locked_rx = context.state.session_lock.watch();
+        let mut status_rx = context.status_rx.clone();
+        let mut events_rx = context.state.events_tx.subscribe();
+
+        let (status_tx, shown_status_rx) = watch::channel(StatusSnapshot::default());
+        let (events_tx, _) = broadcast::channel(EVENT_BUFFER);
+        let mut shown = context.clone();
+        shown.status_rx = shown_status_rx;
+        shown.state.events_tx = events_tx.clone();
+
+        let output = self.sink.run(&shown);
+
+        Box::pin(async move {
+            let forward = async {
+                loop {
+                    let snapshot = status_rx.borrow_and_update().clone();
+                    let snapshot = match *locked_rx.borrow_and_update() {
+                        true => StatusSnapshot::default(),
+                        false => snapshot,
+                    };
+                    status::send_changed(&status_tx, snapshot);
+
+                    tokio::select! {
+                        changed = locked_rx.changed() => if changed.is_err() {
+                            break;
+                        },
+                        changed = status_rx.changed() => if changed.is_err() {
+                            break;
+                        },
+                        event = events_rx.recv() => match event {
+                            Ok(event) => {
+                                if !*locked_rx.borrow() {
+                                    let _ = events_tx.send(event);
+                                }
+                            }
+                            Err(RecvError::Lagged(_)) => {}
+                            Err(RecvError::Closed) => break,
+                        },
+                    }
+                }
+
+                // The output sees the channels close and stops by itself.
+                drop((status_tx, events_tx));
+                std::future::pending::<()>().await
+            };
+
+            tokio::select! {
+                () = output => {}
+                () = forward => {}
+            }
+        })
+    }
+}
@@ -0,0 +1,80 @@
+use crate::demand::DemandGuard;
+use crate::server::ServerState;
+use crate::status::StatusSnapshot;
+use futures_util::future::BoxFuture;
+use std::any::Any;
+use std::fmt;
+use tokio::sync::watch;
+use tokio::task::JoinHandle;
+
+/// What a sink is started with.
+#[derive(Clone)]
+pub struct SinkContext {
+    /// The statuses as the players report them, rather than the served ones in `state`.
+    pub status_rx: watch::Receiver<StatusSnapshot>,
+    /// The bus, artwork settings and players' events (`state.events_tx`), and everything else the server has.
+    pub state: ServerState,
+}
+
+/// Somewhere the statuses are sent by itself, e.g. files, an MQTT broker, Discord or a scrobbling service.
+///
+/// Implemented by each output's settings, so every output is configured and enabled on its own, and only restarted on reload when its settings changed.
+pub trait Sink: SameSettings + Any + fmt::Debug + Send + Sync {
+    /// Shown in the logs, e.g. `mqtt`.
+    fn name(&self) -> &'static str;
+
+    /// Sends the statuses from `context` until the task is stopped.
+    fn run(&self, context: &SinkContext) -> BoxFuture<'static, ()>;
+}
+
+/// Compares a sink's settings to another one's, see [`Sinks::replace`]. Implemented for any settings that can be compared.
+pub trait SameSettings {
+    fn same_settings(&self, other: &dyn Any) -> bool;
+}
+
+impl<T: PartialEq + Any> SameSettings for T {
+    fn same_settings(&self, other: &dyn Any) -> bool {
+        other.downcast_ref::<T>() == Some(self)
+    }
+}
+
+/// The sinks that are running, each wanting the statuses for --pause-when-idle. Stopped when dropped.
+#[derive(Default)]
+pub struct Sinks {
+    running: Vec<(Box<dyn Sink>, JoinHandle<()>, DemandGuard)>,
+}
+
+impl Sinks {
+    /// Runs `sinks` from now on: the ones running with the same settings already keep going, the others are started, and the running ones that aren't among them anymore are stopped.
+    pub fn replace(&mut self, sinks: Vec<Box<dyn Sink>>, context: &SinkContext) {
+        let mut previous = std::mem::take(&mut self.running);
+
+        for sink in sinks {
+            let same = previous
+                .iter()
+                .position(|(running, ..)| running.same_settings(&*sink as &dyn Any));
+            match same {
+                Some(index) => self.running.push(previous.swap_remove(index)),
+                None => {
+                    log::debug!("Starting the {} output.", sink.name());
+                    let demand = context.state.demand.hold_now();
+                    let task = tokio::spawn(sink.run(context));
+                    self.running.push((sink, task, demand));
+                }
+            }
+        }
+
+        for (sink, task, _) in previous {
+            log::debug!("Stopping the {} output.", sink.name());
+            task.abort();
+        }
+    }
+}
+
+impl Drop for Sinks {
+    fn drop(&mut self) {
+        for (_, task, _) in &self.running {
+            task.abort();
+        }
+    }
+}
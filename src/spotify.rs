@@ -0,0 +1,233 @@
+use crate::enrich::{self, Source};
+use crate::status::{PlayerStatus, StatusSnapshot};
+use serde::{Deserialize, Serialize};
+use std::sync::{Mutex, PoisonError};
+use std::time::{Duration, Instant};
+use tokio::sync::watch;
+
+pub const DEFAULT_API_URL: &str = "https://api.spotify.com";
+const DEFAULT_TOKEN_URL: &str = "https://accounts.spotify.com/api/token";
+
+const HTTP_TIMEOUT: Duration = Duration::from_secs(15);
+/// Tokens are renewed this long before they expire, so none runs out mid-request.
+const TOKEN_MARGIN: Duration = Duration::from_secs(60);
+
+/// Settings for --spotify-client-id.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct SpotifyOptions {
+    pub api_url: String,
+    pub client_id: String,
+    pub client_secret: String,
+}
+
+/// One size of a track's album cover.
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "camelCase")]
+pub struct SpotifyImage {
+    pub url: String,
+    #[serde(default)]
+    pub width: Option<u32>,
+    #[serde(default)]
+    pub height: Option<u32>,
+}
+
+/// What the Spotify Web API knows about a track that MPRIS doesn't tell.
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "camelCase")]
+pub struct SpotifyInfo {
+    /// The year the album was released.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub release_year: Option<u32>,
+    pub explicit: bool,
+    /// From 0 to 100, missing when Spotify doesn't give it.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub popularity: Option<u32>,
+    /// The album cover in every size Spotify has, largest first.
+    pub images: Vec<SpotifyImage>,
+}
+
+#[derive(Deserialize)]
+struct Token {
+    access_token: String,
+    /// In seconds.
+    expires_in: u64,
+}
+
+#[derive(Deserialize)]
+struct Track {
+    #[serde(default)]
+    explicit: bool,
+    #[serde(default)]
+    popularity: Option<u32>,
+    album: Album,
+}
+
+#[derive(Deserialize)]
+struct Album {
+    /// `YYYY`, `YYYY-MM` or `YYYY-MM-DD`.
+    #[serde(default)]
+    release_date: String,
+    #[serde(default)]
+    images: Vec<SpotifyImage>,
+}
+
+/// The Spotify track id in a track's URL, `https://open.spotify.com/track/<id>` or `spotify:track:<id>`.
+fn track_id(url: &str) -> Option<String> {
+    let id = url
+        .strip_prefix("spotify:track:")
+        .or_else(|| url.strip_prefix("https://open.spotify.com/track/"))?;
+    let id = id.split(['?', '#', '/']).next().unwrap_or_default();
+
+    (!id.is_empty() && id.chars().all(|c| c.is_ascii_alphanumeric())).then(|| id.to_string())
+}
+
+fn user_agent() -> &'static str {
+    concat!(
+        env!("CARGO_PKG_NAME"),
+        "/",
+        env!("CARGO_PKG_VERSION"),
+        " (",
+        env!("CARGO_PKG_REPOSITORY"),
+        ")"
+    )
+}
+
+struct Spotify {
+    options: SpotifyOptions,
+    agent: ureq::Agent,
+    /// The access token and when it has to be renewed.
+    token: Mutex<Option<(String, Instant)>>,
+}
+
+impl Spotify {
+    /// Where tokens are requested. Mock servers given as --spotify-api-url serve them too.
+    fn token_url(&self) -> String {
+        match url::Url::parse(&self.options.api_url) {
+            Ok(url) if url.host_str() != Some("api.spotify.com") => {
+                format!("{}/api/token", url.origin().ascii_serialization())
+            }
+            _ => String::from(DEFAULT_TOKEN_URL),
+        }
+    }
+
+    /// A valid access token, requesting a new one with the client credentials when needed. Blocks.
+    fn access_token(&self) -> Result<String, String> {
+        let mut token = self.token.lock().unwrap_or_else(PoisonError::into_inner);
+        if let Some((access_token, _)) = token
+            .as_ref()
+            .filter(|(_, renew_at)| *renew_at > Instant::now())
+        {
+            return Ok(access_token.clone());
+        }
+
+        let mut response = self
+            .agent
+            .post(self.token_url())
+            .header("User-Agent", user_agent())
+            .send_form([
+                ("grant_type", "client_credentials"),
+                ("client_id", self.options.client_id.as_str()),
+                ("client_secret", self.options.client_secret.as_str()),
+            ])
+            .map_err(|e| e.to_string())?;
+
+        let status = response.status().as_u16();
+        let body = response
+            .body_mut()
+            .read_to_string()
+            .map_err(|e| e.to_string())?;
+        if status != 200 {
+            return Err(format!(
+                "Spotify answered {status} when asked for a token: {body}"
+            ));
+        }
+
+        let new = serde_json::from_str::<Token>(&body).map_err(|e| e.to_string())?;
+        let renew_at =
+            Instant::now() + Duration::from_secs(new.expires_in).saturating_sub(TOKEN_MARGIN);
+        *token = Some((new.access_token.clone(), renew_at));
+
+        Ok(new.access_token)
+    }
+}
+
+impl Source for Spotify {
+    type Key = String;
+    type Found = SpotifyInfo;
+
+    const NAME: &'static str = "Spotify";
+
+    fn key(&self, status: &PlayerStatus) -> Option<String> {
+        track_id(&status.metadata.url)
+    }
+
+    fn lookup(&self, id: &String) -> Result<Option<SpotifyInfo>, String> {
+        let access_token = self.access_token()?;
+
+        let mut response = self
+            .agent
+            .get(format!(
+                "{}/v1/tracks/{id}",
+                self.options.api_url.trim_end_matches('/')
+            ))
+            .header("Authorization", format!("Bearer {access_token}"))
+            .header("User-Agent", user_agent())
+            .call()
+            .map_err(|e| e.to_string())?;
+
+        let status = response.status().as_u16();
+        let body = response
+            .body_mut()
+            .read_to_string()
+            .map_err(|e| e.to_string())?;
+        match status {
+            200 => {}
+            // Local files and podcast episodes aren't tracks.
+            400 | 404 => return Ok(None),
+            401 => {
+                *self.token.lock().unwrap_or_else(PoisonError::into_inner) = None;
+                return Err(String::from("Spotify didn't accept the access token"));
+            }
+            status => return Err(format!("Spotify answered {status} {body}")),
+        }
+
+        let track = serde_json::from_str::<Track>(&body).map_err(|e| e.to_string())?;
+        let mut images = track.album.images;
+        images.sort_by_key(|image| std::cmp::Reverse(image.width.unwrap_or_default()));
+
+        Ok(Some(SpotifyInfo {
+            release_year: track
+                .album
+                .release_date
+                .split('-')
+                .next()
+                .and_then(|year| year.parse().ok()),
+            explicit: track.explicit,
+            popularity: track.popularity,
+            images,
+        }))
+    }
+
+    fn apply(&self, status: &mut PlayerStatus, found: &SpotifyInfo) {
+        status.spotify = Some(found.clone());
+    }
+}
+
+/// Looks up the active player's Spotify track on the Spotify Web API when it changes, adding what was found to the status as `spotify`, see [`enrich::run`].
+pub async fn run(
+    options: SpotifyOptions,
+    raw_rx: watch::Receiver<StatusSnapshot>,
+    status_tx: watch::Sender<StatusSnapshot>,
+) {
+    let source = Spotify {
+        options,
+        agent: ureq::Agent::config_builder()
+            .http_status_as_error(false)
+            .timeout_global(Some(HTTP_TIMEOUT))
+            .build()
+            .into(),
+        token: Mutex::new(None),
+    };
+
+    enrich::run(source, raw_rx, status_tx).await
+}
@@ -0,0 +1,439 @@
+use crate::artwork::{self, ArtworkInfo, ArtworkOptions};
+use crate::config::Quirks;
+use crate::lyrics::LyricsStatus;
+use crate::musicbrainz::MusicbrainzIds;
+use crate::spotify::SpotifyInfo;
+use serde::{Deserialize, Deserializer, Serialize, Serializer};
+use std::collections::BTreeMap;
+use std::time::{Duration, Instant, SystemTime, UNIX_EPOCH};
+use tokio::sync::watch;
+
+#[derive(Debug, Clone, PartialEq, Eq, PartialOrd, Ord, Serialize, Deserialize)]
+#[serde(rename_all = "camelCase")]
+pub enum PlaybackState {
+    /// A track is currently playing.
+    Playing,
+    /// A track is currently paused.
+    Paused,
+    /// There is no track currently playing.
+    None,
+}
+
+impl From<mpris::PlaybackStatus> for PlaybackState {
+    fn from(value: mpris::PlaybackStatus) -> Self {
+        match value {
+            mpris::PlaybackStatus::Playing => Self::Playing,
+            mpris::PlaybackStatus::Paused => Self::Paused,
+            mpris::PlaybackStatus::Stopped => Self::None,
+        }
+    }
+}
+
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "camelCase")]
+pub struct StatusMetadata {
+    pub title: String,
+    pub artist: String,
+    pub album: String,
+    pub artwork: Vec<ArtworkInfo>,
+    pub length: u64,
+    /// Where the track is from (MPRIS `xesam:url`), if the player says.
+    #[serde(default, skip_serializing_if = "String::is_empty")]
+    pub url: String,
+    /// The player's id for the track (MPRIS `mpris:trackid`), if it gives one.
+    #[serde(default, skip_serializing_if = "String::is_empty")]
+    pub track_id: String,
+    /// The title in Latin letters, with --romanize.
+    #[serde(default, skip_serializing_if = "String::is_empty")]
+    pub title_latin: String,
+    /// The artist in Latin letters, with --romanize.
+    #[serde(default, skip_serializing_if = "String::is_empty")]
+    pub artist_latin: String,
+}
+
+impl StatusMetadata {
+    /// Whether `other` is still this track. Details the player only fills in later (e.g. the artwork or the album) don't make it another one.
+    pub fn is_same_track(&self, other: &StatusMetadata) -> bool {
+        self.track_id == other.track_id && self.title == other.title && self.url == other.url
+    }
+}
+
+/// Which player a status comes from.
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "camelCase")]
+pub struct PlayerInfo {
+    pub bus_name: String,
+    /// The player's displayed name (MPRIS `Identity`).
+    pub identity: String,
+    /// The friendly name set in the config's `aliases`, if any.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub alias: Option<String>,
+    /// Tells attachments to the player apart: it changes when the bus name is taken over by a new process (e.g. the player restarted), not when the same one is attached again. Not sent to clients.
+    #[serde(skip)]
+    pub attachment: u64,
+}
+
+/// When a position was read from the player. While playing, the position has moved on by the time since then, however late the status arrives.
+///
+/// Sent to clients as milliseconds since the Unix epoch. Compares equal to any other, so a position that reads the same as before (e.g. while paused) doesn't make a new status.
+#[derive(Debug, Clone, Copy)]
+pub struct SampleTime {
+    /// For moving the position on within the daemon, unaffected by the system clock changing.
+    pub instant: Instant,
+    /// For clients.
+    pub system_time: SystemTime,
+}
+
+impl SampleTime {
+    pub fn now() -> Self {
+        Self {
+            instant: Instant::now(),
+            system_time: SystemTime::now(),
+        }
+    }
+
+    /// How long ago the position was read.
+    pub fn elapsed(&self) -> Duration {
+        self.instant.elapsed()
+    }
+
+    /// When the position was read, in milliseconds since the Unix epoch, as clients get it.
+    pub fn unix_millis(&self) -> u64 {
+        self.system_time
+            .duration_since(UNIX_EPOCH)
+            .unwrap_or_default()
+            .as_millis() as u64
+    }
+}
+
+impl Default for SampleTime {
+    fn default() -> Self {
+        Self::now()
+    }
+}
+
+impl PartialEq for SampleTime {
+    fn eq(&self, _: &Self) -> bool {
+        true
+    }
+}
+
+impl Eq for SampleTime {}
+
+impl Serialize for SampleTime {
+    fn serialize<S: Serializer>(&self, serializer: S) -> Result<S::Ok, S::Error> {
+        serializer.serialize_u64(self.unix_millis())
+    }
+}
+
+impl<'de> Deserialize<'de> for SampleTime {
+    /// Times from before the daemon started count as now on its own clock.
+    fn deserialize<D: Deserializer<'de>>(deserializer: D) -> Result<Self, D::Error> {
+        let system_time = UNIX_EPOCH + Duration::from_millis(u64::deserialize(deserializer)?);
+        let age = SystemTime::now()
+            .duration_since(system_time)
+            .unwrap_or_default();
+
+        Ok(Self {
+            instant: Instant::now().checked_sub(age).unwrap_or_else(Instant::now),
+            system_time,
+        })
+    }
+}
+
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "camelCase")]
+pub struct PlayerStatus {
+    pub player: PlayerInfo,
+    pub metadata: StatusMetadata,
+    pub playback_state: PlaybackState,
+    pub position: u64,
+    /// When `position` was read, see [`PlayerStatus::position_now`].
+    #[serde(default)]
+    pub position_time: SampleTime,
+    /// Set when the player reports positions that can't be trusted (out of range, or frozen while playing), or when some of its status couldn't be read and was kept from before.
+    pub degraded: bool,
+    /// Set on the status saved before the daemon last stopped, reported until a player is attached, and on the last status of a player that stopped answering, until it does again.
+    #[serde(default)]
+    pub stale: bool,
+    /// The current and next synced lyric lines, with --lyrics. Only set for the active player, once lyrics were found.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub lyrics: Option<LyricsStatus>,
+    /// The MusicBrainz ids the track was matched to, with --musicbrainz. Only set for the active player.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub musicbrainz: Option<MusicbrainzIds>,
+    /// What the Spotify Web API knows about the track, with --spotify-client-id. Only set for the active player, when it plays a Spotify track.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub spotify: Option<SpotifyInfo>,
+}
+
+impl PlayerStatus {
+    /// The position by now: moved on by the time since it was read while playing, but not past the track's end.
+    pub fn position_now(&self) -> u64 {
+        if self.playback_state != PlaybackState::Playing {
+            return self.position;
+        }
+
+        let position = self
+            .position
+            .saturating_add(self.position_time.elapsed().as_micros() as u64);
+        match self.metadata.length {
+            0 => position,
+            length => position.min(length),
+        }
+    }
+}
+
+/// The statuses of all attached players.
+#[derive(Debug, Clone, Default, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "camelCase")]
+pub struct StatusSnapshot {
+    /// Bus name of the player reported by default.
+    pub active: Option<String>,
+    /// Status of every attached player, keyed by bus name.
+    pub players: BTreeMap<String, PlayerStatus>,
+}
+
+impl StatusSnapshot {
+    /// The status of the player reported by default.
+    pub fn active(&self) -> Option<&PlayerStatus> {
+        self.players.get(self.active.as_ref()?)
+    }
+}
+
+/// Sends `snapshot` unless it's the same as the last one, e.g. a paused player read again, so clients and outputs aren't woken up on every update tick for nothing.
+///
+/// Returns whether anything still receives the statuses.
+pub fn send_changed(status_tx: &watch::Sender<StatusSnapshot>, snapshot: StatusSnapshot) -> bool {
+    status_tx.send_if_modified(|current| {
+        if *current == snapshot {
+            return false;
+        }
+
+        *current = snapshot;
+        true
+    });
+
+    !status_tx.is_closed()
+}
+
+/// The active player's status without artwork, small enough for outputs with tight size limits (e.g. a single UDP datagram).
+#[derive(Debug, Clone, PartialEq, Eq, Serialize)]
+#[serde(rename_all = "camelCase")]
+pub struct CompactStatus {
+    /// The player's alias, or its displayed name. Empty when no player is attached.
+    pub player: String,
+    pub title: String,
+    pub artist: String,
+    pub album: String,
+    pub playback_state: PlaybackState,
+    pub position: u64,
+    pub length: u64,
+    /// The title and artist in Latin letters, with --romanize.
+    #[serde(skip_serializing_if = "String::is_empty")]
+    pub title_latin: String,
+    #[serde(skip_serializing_if = "String::is_empty")]
+    pub artist_latin: String,
+}
+
+impl From<Option<&PlayerStatus>> for CompactStatus {
+    fn from(status: Option<&PlayerStatus>) -> Self {
+        let Some(status) = status else {
+            return Self {
+                player: String::new(),
+                title: String::new(),
+                artist: String::new(),
+                album: String::new(),
+                playback_state: PlaybackState::None,
+                position: 0,
+                length: 0,
+                title_latin: String::new(),
+                artist_latin: String::new(),
+            };
+        };
+
+        Self {
+            player: status
+                .player
+                .alias
+                .clone()
+                .unwrap_or_else(|| status.player.identity.clone()),
+            title: status.metadata.title.clone(),
+            artist: status.metadata.artist.clone(),
+            album: status.metadata.album.clone(),
+            playback_state: status.playback_state.clone(),
+            position: status.position,
+            length: status.metadata.length,
+            title_latin: status.metadata.title_latin.clone(),
+            artist_latin: status.metadata.artist_latin.clone(),
+        }
+    }
+}
+
+/// Clamps a reported position to `[0, length]`, returning whether it was out of range.
+///
+/// A length of 0 means the track's length is unknown, so only negative positions are corrected then.
+pub fn sanitize_position(position: i64, length: u64) -> (u64, bool) {
+    if position < 0 {
+        (0, true)
+    } else if length > 0 && position as u64 > length {
+        (length, true)
+    } else {
+        (position as u64, false)
+    }
+}
+
+/// Whether a D-Bus call failed because the player didn't answer in time, rather than because it's gone or broken.
+pub fn is_timeout(error: &mpris::DBusError) -> bool {
+    matches!(error, mpris::DBusError::TransportError(e) if matches!(
+        e.name(),
+        Some("org.freedesktop.DBus.Error.NoReply" | "org.freedesktop.DBus.Error.Timeout")
+    ))
+}
+
+/// Reads the player's status.
+///
+/// Calls that fail while others are answered keep what `previous`, the player's last status, said, and flag the status as `degraded`, so a passing D-Bus hiccup doesn't blank it. Fails when nothing was answered, when a call timed out, or when a failed call leaves nothing to go on.
+#[tracing::instrument(skip_all, fields(player = player.bus_name()))]
+pub fn read_status(
+    player: &mpris::Player,
+    previous: Option<&PlayerStatus>,
+    artwork_options: &ArtworkOptions,
+    alias: Option<&str>,
+    quirks: &Quirks,
+) -> Result<PlayerStatus, mpris::DBusError> {
+    let metadata = player.get_metadata();
+    let metadata = match metadata {
+        Ok(ref metadata) => Ok(metadata),
+        Err(e) => Err(e),
+    };
+
+    read_status_with_metadata(player, metadata, previous, artwork_options, alias, quirks)
+}
+
+/// Like [`read_status`], with `metadata` from an earlier read (or why it couldn't be read), so only the playback status and position are read from the player.
+#[tracing::instrument(skip_all, fields(player = player.bus_name()))]
+pub fn read_status_with_metadata(
+    player: &mpris::Player,
+    metadata: Result<&mpris::Metadata, mpris::DBusError>,
+    previous: Option<&PlayerStatus>,
+    artwork_options: &ArtworkOptions,
+    alias: Option<&str>,
+    quirks: &Quirks,
+) -> Result<PlayerStatus, mpris::DBusError> {
+    let mut reads = Reads::default();
+    let playback_state = reads
+        .note(player.get_playback_status())
+        .map(PlaybackState::from)
+        .or_else(|| previous.map(|status| status.playback_state.clone()));
+    let metadata = reads
+        .note(metadata)
+        .map(|metadata| status_metadata(metadata, artwork_options, quirks))
+        .or_else(|| previous.map(|status| status.metadata.clone()));
+
+    let (position, degraded) = if quirks.ignore_position {
+        (0, true)
+    } else {
+        // The D-Bus value is signed, `mpris` just casts it.
+        match reads.note(player.get_position_in_microseconds()) {
+            Some(position) => sanitize_position(
+                position as i64,
+                metadata.as_ref().map_or(0, |metadata| metadata.length),
+            ),
+            None => (previous.map_or(0, PlayerStatus::position_now), true),
+        }
+    };
+    let position_time = SampleTime::now();
+
+    let partial = reads.check()?;
+    let (Some(playback_state), Some(metadata)) = (playback_state, metadata) else {
+        // Only missing if its call failed.
+        return Err(partial.unwrap());
+    };
+    if let Some(e) = &partial {
+        log::debug!(
+            player = player.bus_name();
+            "Could not read all of the player's status, keeping the rest from before: {e}"
+        );
+    }
+
+    Ok(PlayerStatus {
+        player: PlayerInfo {
+            bus_name: player.bus_name().to_string(),
+            identity: player.identity().to_string(),
+            alias: alias.map(str::to_string),
+            // Set by the player loop, which keeps track of attachments.
+            attachment: 0,
+        },
+        metadata,
+        playback_state,
+        position,
+        position_time,
+        degraded: degraded || partial.is_some(),
+        stale: false,
+        lyrics: None,
+        musicbrainz: None,
+        spotify: None,
+    })
+}
+
+/// The calls of a read that failed, so a read where only some of them did still makes a status.
+#[derive(Default)]
+struct Reads {
+    answered: bool,
+    /// The first call that failed, or the first that timed out.
+    error: Option<mpris::DBusError>,
+}
+
+impl Reads {
+    fn note<T>(&mut self, read: Result<T, mpris::DBusError>) -> Option<T> {
+        match read {
+            Ok(value) => {
+                self.answered = true;
+                Some(value)
+            }
+            Err(e) => {
+                let timed_out = self.error.as_ref().is_some_and(is_timeout);
+                if self.error.is_none() || !timed_out && is_timeout(&e) {
+                    self.error = Some(e);
+                }
+                None
+            }
+        }
+    }
+
+    /// Fails the read if nothing was answered or a call timed out, see [`PlayerStatus::stale`]. Otherwise returns why it's partial, if it is.
+    fn check(self) -> Result<Option<mpris::DBusError>, mpris::DBusError> {
+        match self.error {
+            Some(e) if !self.answered || is_timeout(&e) => Err(e),
+            error => Ok(error),
+        }
+    }
+}
+
+fn status_metadata(
+    metadata: &mpris::Metadata,
+    artwork_options: &ArtworkOptions,
+    quirks: &Quirks,
+) -> StatusMetadata {
+    let mut length = metadata.length_in_microseconds().unwrap_or_default();
+    if quirks.length_in_millis {
+        length = length.saturating_mul(1000);
+    }
+
+    StatusMetadata {
+        title: metadata.title().unwrap_or_default().to_string(),
+        artist: metadata.artists().unwrap_or_default().join(", "),
+        album: metadata.album_name().unwrap_or_default().to_string(),
+        artwork: artwork::resolve(metadata, artwork_options),
+        length,
+        url: metadata.url().unwrap_or_default().to_string(),
+        track_id: metadata
+            .track_id()
+            .filter(|id| *id != mpris::TrackID::no_track())
+            .map(String::from)
+            .unwrap_or_default(),
+        title_latin: String::new(),
+        artist_latin: String::new(),
+    }
+}
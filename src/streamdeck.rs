@@ -0,0 +1,150 @@
+use crate::artwork::{self, ArtworkData, ArtworkInfo, ArtworkOptions};
+use crate::status::{PlaybackState, PlayerStatus, StatusSnapshot};
+use base64::Engine;
+use image::ImageFormat;
+use serde::Serialize;
+use std::io::Cursor;
+use tokio::sync::watch;
+
+/// Stream Deck keys are 72px, 144px on the XL and high DPI models. The software scales down on its own.
+const KEY_IMAGE_SIZE: u32 = 144;
+/// About how many characters fit on a line of a key at the default title size.
+const KEY_LINE_LENGTH: usize = 10;
+
+/// Player methods the Stream Deck actions run, by action name.
+pub const ACTIONS: [(&str, &str); 3] = [
+    ("playPause", "PlayPause"),
+    ("next", "Next"),
+    ("previous", "Previous"),
+];
+
+/// What a Stream Deck plugin shows on its keys, numbered so it can long-poll for changes.
+#[derive(Debug, Clone, PartialEq, Eq, Serialize)]
+#[serde(rename_all = "camelCase")]
+pub struct Keys {
+    pub revision: u64,
+    pub now_playing: NowPlayingKey,
+    pub play_pause: PlayPauseKey,
+}
+
+/// The key showing the track.
+#[derive(Debug, Clone, Default, PartialEq, Eq, Serialize)]
+#[serde(rename_all = "camelCase")]
+pub struct NowPlayingKey {
+    /// The title and artist, shortened to fit the key. Empty when no player is attached.
+    pub title: String,
+    /// The artwork as a `data:` URL for `setImage`, if it's a local file that could be read.
+    pub image: Option<String>,
+}
+
+/// The play/pause key, a two state action.
+#[derive(Debug, Clone, Default, PartialEq, Eq, Serialize)]
+#[serde(rename_all = "camelCase")]
+pub struct PlayPauseKey {
+    /// 0 (showing play) unless the player is playing, then 1 (showing pause).
+    pub state: u8,
+}
+
+/// Cuts `text` down to a line of a key.
+fn key_line(text: &str) -> String {
+    if text.chars().count() <= KEY_LINE_LENGTH {
+        return text.to_string();
+    }
+
+    let mut line = text.chars().take(KEY_LINE_LENGTH - 1).collect::<String>();
+    line.push('…');
+    line
+}
+
+fn key_title(status: &PlayerStatus) -> String {
+    [&status.metadata.title, &status.metadata.artist]
+        .into_iter()
+        .filter(|text| !text.is_empty())
+        .map(|text| key_line(text))
+        .collect::<Vec<_>>()
+        .join("\n")
+}
+
+/// The artwork scaled down to a key, as a JPEG `data:` URL. Blocks.
+fn key_image(artwork: &ArtworkInfo, options: &ArtworkOptions) -> Option<String> {
+    // The Stream Deck software doesn't fetch URLs.
+    let ArtworkData::Binary(data) = artwork::load(artwork, options).ok()? else {
+        return None;
+    };
+
+    let image = image::load_from_memory(&data)
+        .ok()?
+        .thumbnail(KEY_IMAGE_SIZE, KEY_IMAGE_SIZE)
+        .to_rgb8();
+    let mut jpeg = Cursor::new(Vec::new());
+    image.write_to(&mut jpeg, ImageFormat::Jpeg).ok()?;
+
+    Some(format!(
+        "data:image/jpeg;base64,{}",
+        base64::engine::general_purpose::STANDARD.encode(jpeg.into_inner())
+    ))
+}
+
+/// Keeps the keys for the active player's status, counting every change starting at 1. Artwork is only scaled down again when it changes.
+pub fn keys(
+    mut status_rx: watch::Receiver<StatusSnapshot>,
+    artwork_options: ArtworkOptions,
+) -> watch::Receiver<Keys> {
+    let (keys_tx, keys_rx) = watch::channel(Keys {
+        revision: 1,
+        now_playing: NowPlayingKey::default(),
+        play_pause: PlayPauseKey::default(),
+    });
+
+    tokio::spawn(async move {
+        let mut current_artwork: Option<ArtworkInfo> = None;
+        let mut image: Option<String> = None;
+
+        loop {
+            let status = status_rx.borrow_and_update().active().cloned();
+
+            let artwork = status
+                .as_ref()
+                .and_then(|status| status.metadata.artwork.first())
+                .cloned();
+            if artwork != current_artwork {
+                current_artwork = artwork.clone();
+                let options = artwork_options.clone();
+                image = tokio::task::spawn_blocking(move || {
+                    artwork.and_then(|artwork| key_image(&artwork, &options))
+                })
+                .await
+                .unwrap_or_default();
+            }
+
+            let now_playing = NowPlayingKey {
+                title: status.as_ref().map(key_title).unwrap_or_default(),
+                image: image.clone(),
+            };
+            let play_pause = PlayPauseKey {
+                state: status
+                    .as_ref()
+                    .is_some_and(|status| status.playback_state == PlaybackState::Playing)
+                    .into(),
+            };
+
+            // Most changes don't touch the keys, only count the ones that do.
+            keys_tx.send_if_modified(|keys| {
+                if keys.now_playing == now_playing && keys.play_pause == play_pause {
+                    return false;
+                }
+
+                keys.revision += 1;
+                keys.now_playing = now_playing;
+                keys.play_pause = play_pause;
+                true
+            });
+
+            if status_rx.changed().await.is_err() {
+                return;
+            }
+        }
+    });
+
+    keys_rx
+}
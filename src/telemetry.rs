@@ -0,0 +1,47 @@
+use opentelemetry::trace::TracerProvider;
+use opentelemetry_otlp::WithExportConfig;
+use opentelemetry_sdk::trace::SdkTracerProvider;
+use opentelemetry_sdk::Resource;
+use tracing::Level;
+use tracing_subscriber::filter::Targets;
+use tracing_subscriber::layer::SubscriberExt;
+use tracing_subscriber::Layer;
+use url::Url;
+
+/// Exports spans while alive, and sends what's left when dropped.
+pub struct Telemetry {
+    provider: SdkTracerProvider,
+}
+
+impl Drop for Telemetry {
+    fn drop(&mut self) {
+        if let Err(e) = self.provider.shutdown() {
+            log::warn!("Could not send the last spans to the OpenTelemetry collector: {e}");
+        }
+    }
+}
+
+/// Sends the `tracing` spans to the OpenTelemetry collector at `endpoint` over OTLP/gRPC. Spans are batched and sent in the background. Needs the Tokio runtime.
+pub fn init(endpoint: &Url) -> Result<Telemetry, Box<dyn std::error::Error>> {
+    let exporter = opentelemetry_otlp::SpanExporter::builder()
+        .with_tonic()
+        .with_endpoint(endpoint.as_str())
+        .build()?;
+
+    let provider = SdkTracerProvider::builder()
+        .with_batch_exporter(exporter)
+        .with_resource(
+            Resource::builder()
+                .with_service_name(env!("CARGO_PKG_NAME"))
+                .build(),
+        )
+        .build();
+
+    // Only our own spans, the exporter's HTTP/2 client has spans too and would report on itself.
+    let layer = tracing_opentelemetry::layer()
+        .with_tracer(provider.tracer(env!("CARGO_PKG_NAME")))
+        .with_filter(Targets::new().with_target(env!("CARGO_CRATE_NAME"), Level::TRACE));
+    tracing::subscriber::set_global_default(tracing_subscriber::registry().with(layer))?;
+
+    Ok(Telemetry { provider })
+}
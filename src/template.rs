@@ -0,0 +1,74 @@
+use crate::bar;
+use crate::status::{CompactStatus, PlayerStatus};
+use handlebars::{Handlebars, Template};
+use serde::Serialize;
+use std::path::PathBuf;
+
+/// Settings for --overlay-template.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct OverlayTemplate {
+    pub path: PathBuf,
+    /// How often the page reloads, in seconds.
+    pub refresh: u32,
+}
+
+/// What templates are rendered with.
+#[derive(Debug, Serialize)]
+#[serde(rename_all = "camelCase")]
+struct Context<'a> {
+    player: String,
+    title: String,
+    artist: String,
+    album: String,
+    /// `playing`, `paused` or `none`.
+    state: &'static str,
+    /// As `m:ss`.
+    position: String,
+    length: String,
+    /// How far into the track, from 0 to 100.
+    progress: u64,
+    /// The first artwork, if the browser can load it by itself.
+    artwork: Option<&'a str>,
+    /// The whole status as the API sends it, null when no player is attached.
+    status: Option<&'a PlayerStatus>,
+}
+
+impl OverlayTemplate {
+    /// Checks that the template can be read and compiled, so mistakes show up right away rather than on the page.
+    pub fn check(&self) -> Result<(), String> {
+        let source = std::fs::read_to_string(&self.path).map_err(|e| e.to_string())?;
+        Template::compile(&source).map_err(|e| e.to_string())?;
+        Ok(())
+    }
+
+    /// Reads the template and renders it with `status`. It's read again every time, so changes show up on the next reload.
+    pub async fn render(&self, status: Option<&PlayerStatus>) -> Result<String, String> {
+        let source = tokio::fs::read_to_string(&self.path)
+            .await
+            .map_err(|e| e.to_string())?;
+
+        let compact = CompactStatus::from(status);
+        let context = Context {
+            player: compact.player.clone(),
+            title: compact.title.clone(),
+            artist: compact.artist.clone(),
+            album: compact.album.clone(),
+            state: bar::state_name(&compact.playback_state),
+            position: bar::clock(compact.position),
+            length: bar::clock(compact.length),
+            progress: (compact.position * 100)
+                .checked_div(compact.length)
+                .unwrap_or_default()
+                .min(100),
+            artwork: status
+                .and_then(|status| status.metadata.artwork.first())
+                .map(|artwork| artwork.src.as_str())
+                .filter(|src| src.starts_with("http://") || src.starts_with("https://")),
+            status,
+        };
+
+        Handlebars::new()
+            .render_template(&source, &context)
+            .map_err(|e| e.to_string())
+    }
+}
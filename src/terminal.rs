@@ -0,0 +1,298 @@
+use crate::bar::{clock, state_name};
+use crate::event::Event;
+use crate::exit_code;
+use crate::status::{PlaybackState, PlayerStatus};
+use futures_util::{SinkExt, StreamExt};
+use std::io::{IsTerminal, Write};
+use std::time::Duration;
+use tokio_tungstenite::tungstenite::Message;
+use url::Url;
+
+/// How long to wait before connecting again.
+const RECONNECT_DELAY: Duration = Duration::from_secs(5);
+/// How often the status is asked for. WebSocket clients are only pushed events, and playing or pausing isn't one.
+const POLL_INTERVAL: Duration = Duration::from_secs(1);
+/// How often the progress bar moves on between statuses.
+const REDRAW_INTERVAL: Duration = Duration::from_millis(250);
+/// How many characters the progress bar takes.
+const BAR_WIDTH: usize = 30;
+
+/// What the server sent.
+enum Incoming {
+    /// The answer to `status`: the active player's status, `None` when no player is attached.
+    Status(Option<Box<PlayerStatus>>),
+    Event(Box<Event>),
+    /// Anything else, e.g. an error.
+    Other(String),
+}
+
+impl Incoming {
+    fn parse(text: &str) -> Self {
+        if let Ok(event) = serde_json::from_str(text) {
+            return Self::Event(event);
+        }
+        match serde_json::from_str(text) {
+            Ok(status) => Self::Status(status),
+            Err(_) => Self::Other(text.to_string()),
+        }
+    }
+}
+
+/// Writes to stdout, with colors and a progress bar redrawn in place when it's a terminal.
+struct Screen {
+    terminal: bool,
+    colors: bool,
+    status: Option<Box<PlayerStatus>>,
+    /// What was last printed about the status, so it's only printed again when it changed.
+    heading: Option<String>,
+    /// Whether the last line is the progress bar, to be overwritten.
+    progress_shown: bool,
+}
+
+impl Screen {
+    fn new() -> Self {
+        let terminal = std::io::stdout().is_terminal();
+        Self {
+            terminal,
+            colors: terminal && std::env::var_os("NO_COLOR").is_none_or(|v| v.is_empty()),
+            status: None,
+            heading: None,
+            progress_shown: false,
+        }
+    }
+
+    /// `text` in the SGR style `code`, e.g. `1` for bold, if colors are on.
+    fn paint(&self, code: &str, text: &str) -> String {
+        match self.colors {
+            true => format!("\x1b[{code}m{text}\x1b[0m"),
+            false => text.to_string(),
+        }
+    }
+
+    /// Prints `text` above the progress bar.
+    fn line(&mut self, text: &str) {
+        let mut stdout = std::io::stdout().lock();
+        if self.progress_shown {
+            let _ = write!(stdout, "\r\x1b[2K");
+            self.progress_shown = false;
+        }
+        let _ = writeln!(stdout, "{text}");
+        let _ = stdout.flush();
+    }
+
+    fn show_status(&mut self, status: Option<Box<PlayerStatus>>) {
+        self.status = status;
+        let heading = self.heading();
+        if self.heading.as_ref() != Some(&heading) {
+            self.line(&heading);
+            self.heading = Some(heading);
+        }
+        self.show_progress();
+    }
+
+    /// The player, track and playback state, without the position.
+    fn heading(&self) -> String {
+        let Some(status) = &self.status else {
+            return self.paint("2", "No player attached.");
+        };
+
+        let (symbol, color) = match status.playback_state {
+            PlaybackState::Playing => ("▶", "32"),
+            PlaybackState::Paused => ("⏸", "33"),
+            PlaybackState::None => ("■", "2"),
+        };
+        let metadata = &status.metadata;
+        let title = match metadata.title.is_empty() {
+            true => "(no title)",
+            false => &metadata.title,
+        };
+
+        let mut heading = format!("{} {}", self.paint(color, symbol), self.paint("1", title));
+        let details: Vec<&str> = [&metadata.artist, &metadata.album]
+            .into_iter()
+            .filter(|value| !value.is_empty())
+            .map(String::as_str)
+            .collect();
+        if !details.is_empty() {
+            heading += &format!("\n  {}", self.paint("36", &details.join(" · ")));
+        }
+
+        let player = status
+            .player
+            .alias
+            .as_ref()
+            .unwrap_or(&status.player.identity);
+        let mut about = format!(
+            "{player} ({}), {}",
+            status.player.bus_name,
+            state_name(&status.playback_state)
+        );
+        if status.stale {
+            about += ", stale";
+        }
+        if status.degraded {
+            about += ", degraded";
+        }
+        heading += &format!("\n  {}", self.paint("2", &about));
+        heading
+    }
+
+    /// Redraws the progress bar in place, on terminals only.
+    fn show_progress(&mut self) {
+        let Some(status) = self.status.as_ref().filter(|_| self.terminal) else {
+            return;
+        };
+        let length = status.metadata.length;
+        let position = status.position_now();
+
+        let progress = match length {
+            0 => clock(position),
+            _ => {
+                let filled =
+                    ((position as f64 / length as f64) * BAR_WIDTH as f64).round() as usize;
+                let filled = filled.min(BAR_WIDTH);
+                format!(
+                    "{}{} {} / {}",
+                    self.paint("32", &"━".repeat(filled)),
+                    self.paint("2", &"─".repeat(BAR_WIDTH - filled)),
+                    clock(position),
+                    clock(length)
+                )
+            }
+        };
+
+        let mut stdout = std::io::stdout().lock();
+        let _ = write!(stdout, "\r\x1b[2K  {progress}");
+        let _ = stdout.flush();
+        self.progress_shown = true;
+    }
+
+    fn show_event(&mut self, event: &Event) {
+        let text = match event {
+            Event::Attached { bus_name, identity } => {
+                format!("attached to {identity} ({bus_name})")
+            }
+            Event::Detached { bus_name } => format!("detached from {bus_name}"),
+            Event::Searching { attempts, retry_in } => {
+                format!("no player found after {attempts} tries, retrying in {retry_in}s")
+            }
+            Event::TrackChanged { bus_name, metadata } => {
+                format!("{bus_name} changed track to {}", metadata.title)
+            }
+            Event::NowPlayingConfirmed { bus_name, metadata } => {
+                format!("{bus_name} confirmed playing {}", metadata.title)
+            }
+            Event::Seeked {
+                bus_name,
+                position,
+                from,
+            } => format!(
+                "{bus_name} seeked from {} to {}",
+                clock(*from),
+                clock(*position)
+            ),
+        };
+        let text = self.paint("35", &format!("• {text}"));
+        self.line(&text);
+    }
+}
+
+/// Connects to `url` and shows the active player's status and the players' events as they come in, until interrupted. Reconnects when the connection drops.
+///
+/// Works like any WebSocket client would: it subscribes with `events`, and asks for the `status` on every event and every second, since playing and pausing send none.
+pub async fn watch(url: Url) -> i32 {
+    let mut screen = Screen::new();
+    let mut failing = false;
+
+    loop {
+        let connection = tokio::select! {
+            connection = tokio_tungstenite::connect_async(url.as_str()) => connection,
+            _ = tokio::signal::ctrl_c() => break,
+        };
+        match connection {
+            Ok((ws_stream, _)) => {
+                screen.line(&screen.paint("2", &format!("Connected to {url}.")));
+                screen.heading = None;
+                failing = false;
+
+                match follow(ws_stream, &mut screen).await {
+                    Ok(true) => break,
+                    Ok(false) => screen.line(&screen.paint(
+                        "31",
+                        &format!(
+                            "Lost the connection, reconnecting in {} seconds.",
+                            RECONNECT_DELAY.as_secs()
+                        ),
+                    )),
+                    Err(e) => screen.line(&screen.paint(
+                        "31",
+                        &format!(
+                            "Lost the connection, reconnecting in {} seconds: {e}",
+                            RECONNECT_DELAY.as_secs()
+                        ),
+                    )),
+                }
+            }
+            Err(e) => {
+                if !failing {
+                    screen.line(&screen.paint(
+                        "31",
+                        &format!(
+                            "Could not connect to {url}, retrying every {} seconds: {e}",
+                            RECONNECT_DELAY.as_secs()
+                        ),
+                    ));
+                    failing = true;
+                }
+            }
+        }
+
+        tokio::select! {
+            () = tokio::time::sleep(RECONNECT_DELAY) => {}
+            _ = tokio::signal::ctrl_c() => break,
+        }
+    }
+
+    // Leaves the prompt on a line of its own.
+    if screen.progress_shown {
+        println!();
+    }
+    exit_code::OK
+}
+
+/// Shows what comes in on the connection until it's closed, `Ok(true)` if that was because of Ctrl-C.
+async fn follow<S>(
+    ws_stream: tokio_tungstenite::WebSocketStream<S>,
+    screen: &mut Screen,
+) -> Result<bool, tokio_tungstenite::tungstenite::Error>
+where
+    S: tokio::io::AsyncRead + tokio::io::AsyncWrite + Unpin,
+{
+    let (mut sink, mut stream) = ws_stream.split();
+    sink.send(Message::text("events")).await?;
+
+    // Asks for the status right away, then every second.
+    let mut poll = tokio::time::interval(POLL_INTERVAL);
+    let mut redraw = tokio::time::interval(REDRAW_INTERVAL);
+
+    loop {
+        tokio::select! {
+            message = stream.next() => match message {
+                Some(Ok(Message::Text(text))) => match Incoming::parse(&text) {
+                    Incoming::Status(status) => screen.show_status(status),
+                    Incoming::Event(event) => {
+                        screen.show_event(&event);
+                        sink.send(Message::text("status")).await?;
+                    }
+                    Incoming::Other(text) => screen.line(&screen.paint("31", &text)),
+                },
+                Some(Ok(_)) => {}
+                Some(Err(e)) => return Err(e),
+                None => return Ok(false),
+            },
+            _ = poll.tick() => sink.send(Message::text("status")).await?,
+            _ = redraw.tick() => screen.show_progress(),
+            _ = tokio::signal::ctrl_c() => return Ok(true),
+        }
+    }
+}
@@ -0,0 +1,150 @@
+use crate::bar::Format;
+use crate::casing::FieldCase;
+use crate::sink::{Sink, SinkContext};
+use crate::status::{CompactStatus, StatusSnapshot};
+use futures_util::future::BoxFuture;
+use std::collections::{HashMap, HashSet};
+use std::io;
+use std::path::{Path, PathBuf};
+use tokio::sync::watch;
+
+/// A file kept up to date with the active player's status, rendered from a format.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct TextFile {
+    pub path: PathBuf,
+    pub format: Format,
+}
+
+impl TextFile {
+    /// Parses a --text-file value, `<path>=<format>`.
+    pub fn parse(value: &str) -> Result<Self, String> {
+        let Some((path, format)) = value.split_once('=') else {
+            return Err(String::from(
+                "expected <path>=<format>, e.g. `song.txt={artist} – {title}`",
+            ));
+        };
+        if path.is_empty() {
+            return Err(String::from("the path is empty"));
+        }
+
+        Ok(Self {
+            path: PathBuf::from(path),
+            format: Format::parse(format)?,
+        })
+    }
+}
+
+/// Replaces the file's contents in one go, so readers never see it half written.
+pub async fn write_atomically(path: &Path, contents: &[u8]) -> io::Result<()> {
+    // In the same folder, since renaming across file systems isn't atomic.
+    let mut temporary = path.as_os_str().to_owned();
+    temporary.push(".tmp");
+
+    tokio::fs::write(&temporary, contents).await?;
+    tokio::fs::rename(&temporary, path).await
+}
+
+/// Writes files, remembering what each holds so unchanged text isn't written again.
+#[derive(Default)]
+struct Writer {
+    written: HashMap<PathBuf, String>,
+    /// Files that couldn't be written last time, so it's only warned about once.
+    failing: HashSet<PathBuf>,
+}
+
+impl Writer {
+    async fn write(&mut self, path: &Path, text: String) {
+        if self.written.get(path) == Some(&text) {
+            return;
+        }
+
+        match write_atomically(path, text.as_bytes()).await {
+            Ok(()) => {
+                self.failing.remove(path);
+                self.written.insert(path.to_path_buf(), text);
+            }
+            Err(e) => {
+                if self.failing.insert(path.to_path_buf()) {
+                    log::warn!("Could not write {}: {e}", path.display());
+                }
+                self.written.remove(path);
+            }
+        }
+    }
+}
+
+/// The files --text-file writes to.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct TextFiles(pub Vec<TextFile>);
+
+/// The files --json-file writes to.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct JsonFiles(pub Vec<PathBuf>);
+
+/// Writes the active player's status to every file whenever its text changes. Files are emptied when no player is attached.
+pub async fn run(files: Vec<TextFile>, mut status_rx: watch::Receiver<StatusSnapshot>) {
+    let mut writer = Writer::default();
+
+    loop {
+        let status = status_rx.borrow_and_update().active().cloned();
+        let compact = CompactStatus::from(status.as_ref());
+
+        for file in &files {
+            let text = match status {
+                Some(_) => file.format.render(&compact),
+                None => String::new(),
+            };
+            // Most updates only move the position, which few formats show.
+            writer.write(&file.path, text).await;
+        }
+
+        if status_rx.changed().await.is_err() {
+            return;
+        }
+    }
+}
+
+/// Writes the active player's status as JSON with its keys in `case` to every file whenever it changes, `null` when no player is attached.
+pub async fn run_json(
+    paths: Vec<PathBuf>,
+    mut status_rx: watch::Receiver<StatusSnapshot>,
+    case: FieldCase,
+) {
+    let mut writer = Writer::default();
+
+    loop {
+        let json = case.to_string(&status_rx.borrow_and_update().active());
+
+        for path in &paths {
+            writer.write(path, json.clone()).await;
+        }
+
+        if status_rx.changed().await.is_err() {
+            return;
+        }
+    }
+}
+
+impl Sink for TextFiles {
+    fn name(&self) -> &'static str {
+        "text-file"
+    }
+
+    fn run(&self, context: &SinkContext) -> BoxFuture<'static, ()> {
+        Box::pin(run(self.0.clone(), context.status_rx.clone()))
+    }
+}
+
+impl Sink for JsonFiles {
+    fn name(&self) -> &'static str {
+        "json-file"
+    }
+
+    fn run(&self, context: &SinkContext) -> BoxFuture<'static, ()> {
+        Box::pin(run_json(
+            self.0.clone(),
+            context.status_rx.clone(),
+            context.state.json_case,
+        ))
+    }
+}
@@ -0,0 +1,735 @@
+use crate::bar::{self, Format};
+use crate::bus::BusAddress;
+use crate::control;
+use crate::sink::{Sink, SinkContext};
+use crate::status::{CompactStatus, PlaybackState, PlayerStatus, StatusSnapshot};
+use dbus::arg::{PropMap, RefArg, Variant};
+use dbus::blocking::Connection;
+use dbus::message::{MatchRule, MessageType};
+use dbus::Message;
+use futures_util::future::BoxFuture;
+use std::env;
+use std::ffi::CString;
+use std::io;
+use std::os::fd::{AsRawFd, RawFd};
+use std::process::Stdio;
+use std::time::Duration;
+use tokio::io::unix::AsyncFd;
+use tokio::io::AsyncWriteExt;
+use tokio::sync::watch;
+
+const ITEM_PATH: &str = "/StatusNotifierItem";
+const ITEM_INTERFACE: &str = "org.kde.StatusNotifierItem";
+const MENU_PATH: &str = "/MenuBar";
+const MENU_INTERFACE: &str = "com.canonical.dbusmenu";
+const WATCHER_NAME: &str = "org.kde.StatusNotifierWatcher";
+const WATCHER_PATH: &str = "/StatusNotifierWatcher";
+const PROPERTIES_INTERFACE: &str = "org.freedesktop.DBus.Properties";
+const INTROSPECTABLE_INTERFACE: &str = "org.freedesktop.DBus.Introspectable";
+const DBUS_TIMEOUT: Duration = Duration::from_secs(5);
+
+const ITEM_INTROSPECTION: &str = r#"<node>
+  <interface name="org.kde.StatusNotifierItem">
+    <property name="Category" type="s" access="read"/>
+    <property name="Id" type="s" access="read"/>
+    <property name="Title" type="s" access="read"/>
+    <property name="Status" type="s" access="read"/>
+    <property name="WindowId" type="i" access="read"/>
+    <property name="IconName" type="s" access="read"/>
+    <property name="IconPixmap" type="a(iiay)" access="read"/>
+    <property name="OverlayIconName" type="s" access="read"/>
+    <property name="AttentionIconName" type="s" access="read"/>
+    <property name="AttentionIconPixmap" type="a(iiay)" access="read"/>
+    <property name="AttentionMovieName" type="s" access="read"/>
+    <property name="ToolTip" type="(sa(iiay)ss)" access="read"/>
+    <property name="ItemIsMenu" type="b" access="read"/>
+    <property name="Menu" type="o" access="read"/>
+    <property name="IconThemePath" type="s" access="read"/>
+    <method name="ContextMenu"><arg name="x" type="i" direction="in"/><arg name="y" type="i" direction="in"/></method>
+    <method name="Activate"><arg name="x" type="i" direction="in"/><arg name="y" type="i" direction="in"/></method>
+    <method name="SecondaryActivate"><arg name="x" type="i" direction="in"/><arg name="y" type="i" direction="in"/></method>
+    <method name="Scroll"><arg name="delta" type="i" direction="in"/><arg name="orientation" type="s" direction="in"/></method>
+    <signal name="NewTitle"/>
+    <signal name="NewIcon"/>
+    <signal name="NewAttentionIcon"/>
+    <signal name="NewOverlayIcon"/>
+    <signal name="NewToolTip"/>
+    <signal name="NewStatus"><arg name="status" type="s"/></signal>
+  </interface>
+</node>"#;
+
+const MENU_INTROSPECTION: &str = r#"<node>
+  <interface name="com.canonical.dbusmenu">
+    <property name="Version" type="u" access="read"/>
+    <property name="TextDirection" type="s" access="read"/>
+    <property name="Status" type="s" access="read"/>
+    <property name="IconThemePath" type="as" access="read"/>
+    <method name="GetLayout">
+      <arg name="parentId" type="i" direction="in"/>
+      <arg name="recursionDepth" type="i" direction="in"/>
+      <arg name="propertyNames" type="as" direction="in"/>
+      <arg name="revision" type="u" direction="out"/>
+      <arg name="layout" type="(ia{sv}av)" direction="out"/>
+    </method>
+    <method name="GetGroupProperties">
+      <arg name="ids" type="ai" direction="in"/>
+      <arg name="propertyNames" type="as" direction="in"/>
+      <arg name="properties" type="a(ia{sv})" direction="out"/>
+    </method>
+    <method name="GetProperty">
+      <arg name="id" type="i" direction="in"/>
+      <arg name="name" type="s" direction="in"/>
+      <arg name="value" type="v" direction="out"/>
+    </method>
+    <method name="Event">
+      <arg name="id" type="i" direction="in"/>
+      <arg name="eventId" type="s" direction="in"/>
+      <arg name="data" type="v" direction="in"/>
+      <arg name="timestamp" type="u" direction="in"/>
+    </method>
+    <method name="EventGroup">
+      <arg name="events" type="a(isvu)" direction="in"/>
+      <arg name="idErrors" type="ai" direction="out"/>
+    </method>
+    <method name="AboutToShow">
+      <arg name="id" type="i" direction="in"/>
+      <arg name="needUpdate" type="b" direction="out"/>
+    </method>
+    <method name="AboutToShowGroup">
+      <arg name="ids" type="ai" direction="in"/>
+      <arg name="updatesNeeded" type="ai" direction="out"/>
+      <arg name="idErrors" type="ai" direction="out"/>
+    </method>
+    <signal name="ItemsPropertiesUpdated">
+      <arg name="updatedProps" type="a(ia{sv})"/>
+      <arg name="removedProps" type="a(ias)"/>
+    </signal>
+    <signal name="LayoutUpdated">
+      <arg name="revision" type="u"/>
+      <arg name="parent" type="i"/>
+    </signal>
+    <signal name="ItemActivationRequested">
+      <arg name="id" type="i"/>
+      <arg name="timestamp" type="u"/>
+    </signal>
+  </interface>
+</node>"#;
+
+/// The menu's items, by id. The root is 0.
+const TRACK_ITEM: i32 = 1;
+const PLAY_PAUSE_ITEM: i32 = 2;
+const NEXT_ITEM: i32 = 3;
+const COPY_ITEM: i32 = 4;
+const FIRST_SEPARATOR: i32 = 5;
+const SECOND_SEPARATOR: i32 = 6;
+
+/// Clipboard tools tried in order, each only if the display server it's for is running.
+const CLIPBOARD_COMMANDS: [(&str, &[&str]); 3] = [
+    ("WAYLAND_DISPLAY", &["wl-copy"]),
+    ("DISPLAY", &["xclip", "-selection", "clipboard"]),
+    ("DISPLAY", &["xsel", "--clipboard", "--input"]),
+];
+
+/// Something picked in the tray.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+enum Action {
+    PlayPause,
+    Next,
+    Copy,
+}
+
+/// What the tray shows for the active player.
+#[derive(Debug, Clone, PartialEq, Eq)]
+struct View {
+    /// Bus name of the active player, `None` if there's none.
+    bus_name: Option<String>,
+    playback_state: PlaybackState,
+    /// The tooltip's first line.
+    title: String,
+    /// The tooltip's body, as markup.
+    description: String,
+    /// The disabled first item of the menu.
+    label: String,
+    /// What "Copy track info" copies.
+    copy_text: String,
+}
+
+impl View {
+    fn new(status: Option<&PlayerStatus>, copy_format: &Format) -> Self {
+        let Some(status) = status else {
+            return Self {
+                bus_name: None,
+                playback_state: PlaybackState::None,
+                title: String::from("Nothing playing"),
+                description: String::new(),
+                label: String::from("Nothing playing"),
+                copy_text: String::new(),
+            };
+        };
+
+        let compact = CompactStatus::from(Some(status));
+        let mut description = bar::escape_markup(&status.metadata.artist);
+        if !status.metadata.album.is_empty() {
+            description.push('\n');
+            description.push_str(&bar::escape_markup(&status.metadata.album));
+        }
+
+        let label = match (
+            status.metadata.artist.is_empty(),
+            status.metadata.title.is_empty(),
+        ) {
+            (_, true) => compact.player.clone(),
+            (true, false) => status.metadata.title.clone(),
+            (false, false) => format!("{} – {}", status.metadata.artist, status.metadata.title),
+        };
+
+        Self {
+            bus_name: Some(status.player.bus_name.clone()),
+            playback_state: status.playback_state.clone(),
+            title: match status.metadata.title.as_str() {
+                "" => compact.player.clone(),
+                title => title.to_string(),
+            },
+            description,
+            // Underscores mark access keys in menus.
+            label: label.replace('_', "__"),
+            copy_text: copy_format.render(&compact),
+        }
+    }
+
+    fn icon_name(&self) -> &'static str {
+        match (&self.bus_name, &self.playback_state) {
+            (Some(_), PlaybackState::Playing) => "media-playback-start",
+            (Some(_), PlaybackState::Paused) => "media-playback-pause",
+            _ => "audio-x-generic",
+        }
+    }
+
+    /// Hosts may hide passive items until something plays.
+    fn item_status(&self) -> &'static str {
+        match self.bus_name {
+            Some(_) => "Active",
+            None => "Passive",
+        }
+    }
+}
+
+fn item_properties(view: &View) -> PropMap {
+    let no_pixmaps = Vec::<(i32, i32, Vec<u8>)>::new;
+    let properties: [(&str, Box<dyn RefArg>); 15] = [
+        ("Category", Box::new(String::from("ApplicationStatus"))),
+        ("Id", Box::new(String::from(env!("CARGO_PKG_NAME")))),
+        ("Title", Box::new(String::from(env!("CARGO_PKG_NAME")))),
+        ("Status", Box::new(String::from(view.item_status()))),
+        ("WindowId", Box::new(0i32)),
+        ("IconName", Box::new(String::from(view.icon_name()))),
+        ("IconPixmap", Box::new(no_pixmaps())),
+        ("OverlayIconName", Box::new(String::new())),
+        ("AttentionIconName", Box::new(String::new())),
+        ("AttentionIconPixmap", Box::new(no_pixmaps())),
+        ("AttentionMovieName", Box::new(String::new())),
+        (
+            "ToolTip",
+            Box::new((
+                String::from(view.icon_name()),
+                no_pixmaps(),
+                view.title.clone(),
+                view.description.clone(),
+            )),
+        ),
+        ("ItemIsMenu", Box::new(false)),
+        ("Menu", Box::new(dbus::Path::from(MENU_PATH))),
+        ("IconThemePath", Box::new(String::new())),
+    ];
+
+    properties
+        .into_iter()
+        .map(|(name, value)| (name.to_string(), Variant(value)))
+        .collect()
+}
+
+fn menu_properties() -> PropMap {
+    let properties: [(&str, Box<dyn RefArg>); 4] = [
+        ("Version", Box::new(3u32)),
+        ("TextDirection", Box::new(String::from("ltr"))),
+        ("Status", Box::new(String::from("normal"))),
+        ("IconThemePath", Box::new(Vec::<String>::new())),
+    ];
+
+    properties
+        .into_iter()
+        .map(|(name, value)| (name.to_string(), Variant(value)))
+        .collect()
+}
+
+/// The menu's items in order, with their properties.
+fn menu_items(view: &View) -> Vec<(i32, PropMap)> {
+    let item = |properties: Vec<(&str, Box<dyn RefArg>)>| -> PropMap {
+        properties
+            .into_iter()
+            .map(|(name, value)| (name.to_string(), Variant(value)))
+            .collect()
+    };
+    let separator = || item(vec![("type", Box::new(String::from("separator")))]);
+    let has_player = view.bus_name.is_some();
+    let playing = view.playback_state == PlaybackState::Playing;
+
+    vec![
+        (
+            TRACK_ITEM,
+            item(vec![
+                ("label", Box::new(view.label.clone())),
+                ("enabled", Box::new(false)),
+            ]),
+        ),
+        (FIRST_SEPARATOR, separator()),
+        (
+            PLAY_PAUSE_ITEM,
+            item(vec![
+                (
+                    "label",
+                    Box::new(String::from(if playing { "Pause" } else { "Play" })),
+                ),
+                (
+                    "icon-name",
+                    Box::new(String::from(if playing {
+                        "media-playback-pause"
+                    } else {
+                        "media-playback-start"
+                    })),
+                ),
+                ("enabled", Box::new(has_player)),
+            ]),
+        ),
+        (
+            NEXT_ITEM,
+            item(vec![
+                ("label", Box::new(String::from("Next"))),
+                ("icon-name", Box::new(String::from("media-skip-forward"))),
+                ("enabled", Box::new(has_player)),
+            ]),
+        ),
+        (SECOND_SEPARATOR, separator()),
+        (
+            COPY_ITEM,
+            item(vec![
+                ("label", Box::new(String::from("Copy track info"))),
+                ("icon-name", Box::new(String::from("edit-copy"))),
+                ("enabled", Box::new(!view.copy_text.is_empty())),
+            ]),
+        ),
+    ]
+}
+
+/// A dbusmenu layout node, `(ia{sv}av)`.
+type Layout = (i32, PropMap, Vec<Variant<Box<dyn RefArg>>>);
+
+/// The layout from `parent_id` down. The menu is flat, so that's the root with every item, or a single item.
+fn menu_layout(view: &View, parent_id: i32) -> Layout {
+    let mut items = menu_items(view);
+    if let Some(index) = items.iter().position(|(id, _)| *id == parent_id) {
+        let (id, properties) = items.swap_remove(index);
+        return (id, properties, Vec::new());
+    }
+
+    let mut root = PropMap::new();
+    root.insert(
+        String::from("children-display"),
+        Variant(Box::new(String::from("submenu"))),
+    );
+    let children = items
+        .into_iter()
+        .map(|(id, properties)| {
+            let child: Layout = (id, properties, Vec::new());
+            Variant(Box::new(child) as Box<dyn RefArg>)
+        })
+        .collect();
+
+    (0, root, children)
+}
+
+fn action(id: i32) -> Option<Action> {
+    match id {
+        PLAY_PAUSE_ITEM => Some(Action::PlayPause),
+        NEXT_ITEM => Some(Action::Next),
+        COPY_ITEM => Some(Action::Copy),
+        _ => None,
+    }
+}
+
+/// Puts `text` on the clipboard with the first clipboard tool available.
+async fn copy_to_clipboard(text: &str) -> Result<(), String> {
+    let commands = CLIPBOARD_COMMANDS
+        .iter()
+        .filter(|(display, _)| env::var_os(display).is_some());
+
+    for (_, command) in commands {
+        let child = tokio::process::Command::new(command[0])
+            .args(&command[1..])
+            .stdin(Stdio::piped())
+            .stdout(Stdio::null())
+            .stderr(Stdio::null())
+            .spawn();
+        let mut child = match child {
+            Ok(child) => child,
+            Err(e) if e.kind() == io::ErrorKind::NotFound => continue,
+            Err(e) => return Err(format!("could not run {}: {e}", command[0])),
+        };
+
+        if let Some(mut stdin) = child.stdin.take() {
+            stdin
+                .write_all(text.as_bytes())
+                .await
+                .map_err(|e| e.to_string())?;
+        }
+        let status = child.wait().await.map_err(|e| e.to_string())?;
+
+        return match status.success() {
+            true => Ok(()),
+            false => Err(format!("{} failed: {status}", command[0])),
+        };
+    }
+
+    Err(String::from(
+        "no clipboard tool found, install wl-clipboard, xclip or xsel",
+    ))
+}
+
+/// Carries out an action without waiting for it, logging failures.
+fn perform(action: Action, view: &View, player_bus: &BusAddress) {
+    let method = match action {
+        Action::PlayPause => "PlayPause",
+        Action::Next => "Next",
+        Action::Copy => {
+            let text = view.copy_text.clone();
+            tokio::spawn(async move {
+                if let Err(e) = copy_to_clipboard(&text).await {
+                    log::warn!("Could not copy the track info: {e}");
+                }
+            });
+            return;
+        }
+    };
+
+    let Some(bus_name) = view.bus_name.clone() else {
+        return;
+    };
+    let bus = player_bus.clone();
+    tokio::task::spawn_blocking(move || {
+        if let Err(e) = control::call_player(&bus, &bus_name, method) {
+            log::warn!(player = bus_name.as_str(); "{method} failed on player {bus_name}: {e}");
+        }
+    });
+}
+
+fn error_reply(message: &Message, name: &str, text: &str) -> Message {
+    message.error(&name.into(), &CString::new(text).unwrap_or_default())
+}
+
+/// Answers a property request on the item or the menu.
+fn properties_reply(message: &Message, mut properties: PropMap) -> Message {
+    match message.member().as_deref() {
+        Some("GetAll") => message.method_return().append1(properties),
+        Some("Get") => {
+            let name = message.read2::<&str, &str>().map(|(_, name)| name);
+            match name.ok().and_then(|name| properties.remove(name)) {
+                Some(value) => message.method_return().append1(value),
+                None => error_reply(
+                    message,
+                    "org.freedesktop.DBus.Error.UnknownProperty",
+                    "no such property",
+                ),
+            }
+        }
+        _ => error_reply(
+            message,
+            "org.freedesktop.DBus.Error.PropertyReadOnly",
+            "properties are read-only",
+        ),
+    }
+}
+
+/// Answers a call to the item or the menu, returning what was picked, if anything.
+fn handle(message: &Message, view: &View, revision: u32) -> (Option<Message>, Option<Action>) {
+    let path = message.path();
+    let (Some(path), Some(interface), Some(member)) =
+        (path.as_deref(), message.interface(), message.member())
+    else {
+        return (dbus::channel::default_reply(message), None);
+    };
+
+    match (path, &*interface, &*member) {
+        (ITEM_PATH, INTROSPECTABLE_INTERFACE, "Introspect") => (
+            Some(message.method_return().append1(ITEM_INTROSPECTION)),
+            None,
+        ),
+        (MENU_PATH, INTROSPECTABLE_INTERFACE, "Introspect") => (
+            Some(message.method_return().append1(MENU_INTROSPECTION)),
+            None,
+        ),
+        (ITEM_PATH, PROPERTIES_INTERFACE, _) => {
+            (Some(properties_reply(message, item_properties(view))), None)
+        }
+        (MENU_PATH, PROPERTIES_INTERFACE, _) => {
+            (Some(properties_reply(message, menu_properties())), None)
+        }
+
+        (ITEM_PATH, ITEM_INTERFACE, "Activate") => {
+            (Some(message.method_return()), Some(Action::PlayPause))
+        }
+        (ITEM_PATH, ITEM_INTERFACE, "SecondaryActivate") => {
+            (Some(message.method_return()), Some(Action::Next))
+        }
+        // The host shows the menu itself, and scrolling does nothing.
+        (ITEM_PATH, ITEM_INTERFACE, "ContextMenu" | "Scroll") => {
+            (Some(message.method_return()), None)
+        }
+
+        (MENU_PATH, MENU_INTERFACE, "GetLayout") => {
+            let parent_id = message.get1::<i32>().unwrap_or_default();
+            let reply = message
+                .method_return()
+                .append2(revision, menu_layout(view, parent_id));
+            (Some(reply), None)
+        }
+        (MENU_PATH, MENU_INTERFACE, "GetGroupProperties") => {
+            let ids = message.get1::<Vec<i32>>().unwrap_or_default();
+            let properties = menu_items(view)
+                .into_iter()
+                .filter(|(id, _)| ids.is_empty() || ids.contains(id))
+                .collect::<Vec<_>>();
+            (Some(message.method_return().append1(properties)), None)
+        }
+        (MENU_PATH, MENU_INTERFACE, "GetProperty") => {
+            let reply = message
+                .read2::<i32, &str>()
+                .ok()
+                .and_then(|(id, name)| {
+                    menu_items(view)
+                        .into_iter()
+                        .find(|(item, _)| *item == id)
+                        .and_then(|(_, mut properties)| properties.remove(name))
+                })
+                .map(|value| message.method_return().append1(value))
+                .unwrap_or_else(|| {
+                    error_reply(
+                        message,
+                        "org.freedesktop.DBus.Error.InvalidArgs",
+                        "no such property",
+                    )
+                });
+            (Some(reply), None)
+        }
+        (MENU_PATH, MENU_INTERFACE, "Event") => {
+            let picked = message
+                .read2::<i32, &str>()
+                .ok()
+                .filter(|(_, event)| *event == "clicked")
+                .and_then(|(id, _)| action(id));
+            (Some(message.method_return()), picked)
+        }
+        (MENU_PATH, MENU_INTERFACE, "EventGroup") => {
+            let events = message
+                .get1::<Vec<(i32, String, Variant<Box<dyn RefArg>>, u32)>>()
+                .unwrap_or_default();
+            let picked = events
+                .iter()
+                .filter(|(_, event, _, _)| event == "clicked")
+                .find_map(|(id, _, _, _)| action(*id));
+            (
+                Some(message.method_return().append1(Vec::<i32>::new())),
+                picked,
+            )
+        }
+        (MENU_PATH, MENU_INTERFACE, "AboutToShow") => {
+            (Some(message.method_return().append1(false)), None)
+        }
+        (MENU_PATH, MENU_INTERFACE, "AboutToShowGroup") => (
+            Some(
+                message
+                    .method_return()
+                    .append2(Vec::<i32>::new(), Vec::<i32>::new()),
+            ),
+            None,
+        ),
+
+        _ => (dbus::channel::default_reply(message), None),
+    }
+}
+
+/// Tells the host the item and menu changed.
+fn announce(connection: &Connection, view: &View, revision: u32) {
+    let mut signals = ["NewToolTip", "NewIcon", "NewTitle"]
+        .into_iter()
+        .filter_map(|signal| Message::new_signal(ITEM_PATH, ITEM_INTERFACE, signal).ok())
+        .collect::<Vec<_>>();
+    signals.extend(
+        Message::new_signal(ITEM_PATH, ITEM_INTERFACE, "NewStatus")
+            .ok()
+            .map(|signal| signal.append1(view.item_status())),
+    );
+    signals.extend(
+        Message::new_signal(MENU_PATH, MENU_INTERFACE, "LayoutUpdated")
+            .ok()
+            .map(|signal| signal.append2(revision, 0i32)),
+    );
+
+    for signal in signals {
+        let _ = connection.channel().send(signal);
+    }
+}
+
+/// Asks the host's watcher to show the item.
+fn register(connection: &Connection, name: &str) -> Result<(), dbus::Error> {
+    let proxy = connection.with_proxy(WATCHER_NAME, WATCHER_PATH, DBUS_TIMEOUT);
+    proxy.method_call(WATCHER_NAME, "RegisterStatusNotifierItem", (name,))
+}
+
+/// The bus connection's socket, to wait for messages on.
+struct Socket(RawFd);
+
+impl AsRawFd for Socket {
+    fn as_raw_fd(&self) -> RawFd {
+        self.0
+    }
+}
+
+/// What --tray's menu copies.
+#[derive(Debug, Clone, PartialEq)]
+pub struct TrayOptions {
+    pub copy_format: Format,
+}
+
+/// Shows a tray icon (StatusNotifierItem) on `bus` with the active player's track as its tooltip, and a menu to play or pause, skip to the next track, and copy the track info as `copy_format`. Clicking the icon plays or pauses, middle-clicking skips. Players are controlled on `player_bus`.
+pub async fn run(
+    copy_format: Format,
+    bus: BusAddress,
+    player_bus: BusAddress,
+    mut status_rx: watch::Receiver<StatusSnapshot>,
+) {
+    let connection = match bus.channel() {
+        Ok(mut channel) => {
+            channel.set_watch_enabled(true);
+            Connection::from(channel)
+        }
+        Err(e) => {
+            log::warn!("Could not connect to the bus for the tray icon: {e}");
+            return;
+        }
+    };
+
+    let name = format!("org.kde.StatusNotifierItem-{}-1", std::process::id());
+    if let Err(e) = connection.request_name(&name, false, true, true) {
+        log::warn!("Could not take the bus name {name} for the tray icon: {e}");
+        return;
+    }
+
+    // The watcher comes and goes with the panel, the item has to be registered again each time.
+    let watcher_rule = format!(
+        "{},arg0='{WATCHER_NAME}'",
+        MatchRule::new_signal("org.freedesktop.DBus", "NameOwnerChanged")
+            .with_sender("org.freedesktop.DBus")
+            .match_str()
+    );
+    if let Err(e) = connection.add_match_no_cb(&watcher_rule) {
+        log::debug!("Could not watch for the tray: {e}");
+    }
+    match register(&connection, &name) {
+        Ok(()) => log::info!("Showing the tray icon."),
+        Err(e) => log::warn!("No tray to show the icon in, waiting for one: {e}"),
+    }
+
+    let socket = match AsyncFd::new(Socket(connection.channel().watch().fd)) {
+        Ok(socket) => socket,
+        Err(e) => {
+            log::warn!("Could not watch the bus for the tray icon: {e}");
+            return;
+        }
+    };
+
+    let mut view = View::new(status_rx.borrow_and_update().active(), &copy_format);
+    let mut revision = 0u32;
+
+    loop {
+        if connection
+            .channel()
+            .read_write(Some(Duration::ZERO))
+            .is_err()
+        {
+            log::warn!("Lost the bus connection, the tray icon is gone.");
+            return;
+        }
+
+        while let Some(message) = connection.channel().pop_message() {
+            match message.msg_type() {
+                MessageType::MethodCall => {
+                    let (reply, picked) = handle(&message, &view, revision);
+                    if let Some(reply) = reply {
+                        let _ = connection.channel().send(reply);
+                    }
+                    if let Some(picked) = picked {
+                        perform(picked, &view, &player_bus);
+                    }
+                }
+                MessageType::Signal => {
+                    let new_owner = message
+                        .read3::<&str, &str, &str>()
+                        .ok()
+                        .filter(|(changed, _, _)| *changed == WATCHER_NAME)
+                        .map(|(_, _, new_owner)| new_owner);
+                    if new_owner.is_some_and(|owner| !owner.is_empty()) {
+                        match register(&connection, &name) {
+                            Ok(()) => log::info!("Showing the tray icon."),
+                            Err(e) => log::warn!("Could not show the tray icon: {e}"),
+                        }
+                    }
+                }
+                _ => {}
+            }
+        }
+        connection.channel().flush();
+
+        tokio::select! {
+            ready = socket.readable() => match ready {
+                Ok(mut guard) => guard.clear_ready(),
+                Err(e) => {
+                    log::warn!("Could not watch the bus for the tray icon: {e}");
+                    return;
+                }
+            },
+            changed = status_rx.changed() => {
+                if changed.is_err() {
+                    return;
+                }
+
+                let new_view = View::new(status_rx.borrow_and_update().active(), &copy_format);
+                if new_view != view {
+                    view = new_view;
+                    revision = revision.wrapping_add(1);
+                    announce(&connection, &view, revision);
+                }
+            }
+        }
+    }
+}
+
+impl Sink for TrayOptions {
+    fn name(&self) -> &'static str {
+        "tray"
+    }
+
+    fn run(&self, context: &SinkContext) -> BoxFuture<'static, ()> {
+        // Trays live on the session bus, only --dbus-address can point elsewhere.
+        let bus = match &context.state.bus {
+            BusAddress::System => BusAddress::Session,
+            bus => bus.clone(),
+        };
+
+        Box::pin(run(
+            self.copy_format.clone(),
+            bus,
+            context.state.bus.clone(),
+            context.status_rx.clone(),
+        ))
+    }
+}
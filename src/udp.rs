@@ -0,0 +1,106 @@
+use crate::casing::FieldCase;
+use crate::sink::{Sink, SinkContext};
+use crate::status::{CompactStatus, StatusSnapshot};
+use futures_util::future::BoxFuture;
+use std::io;
+use std::net::{Ipv4Addr, Ipv6Addr, SocketAddr};
+use std::time::Duration;
+use tokio::net::UdpSocket;
+use tokio::sync::watch;
+
+/// A socket for sending to `target`, broadcast addresses included.
+pub async fn socket(target: SocketAddr) -> io::Result<UdpSocket> {
+    let socket = match target {
+        SocketAddr::V4(_) => UdpSocket::bind((Ipv4Addr::UNSPECIFIED, 0)).await?,
+        SocketAddr::V6(_) => UdpSocket::bind((Ipv6Addr::UNSPECIFIED, 0)).await?,
+    };
+
+    // Sending to a broadcast address fails without it. Multicast and unicast don't mind.
+    if target.is_ipv4() {
+        socket.set_broadcast(true)?;
+    }
+
+    Ok(socket)
+}
+
+/// Sends the compact status to `target` every `interval`, and right away whenever it changes.
+/// Where --udp-target sends the status, and how often at most.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct UdpOptions {
+    pub target: SocketAddr,
+    pub interval: Duration,
+}
+
+pub async fn run(
+    target: SocketAddr,
+    interval: Duration,
+    mut status_rx: watch::Receiver<StatusSnapshot>,
+    case: FieldCase,
+) {
+    let socket = match socket(target).await {
+        Ok(socket) => socket,
+        Err(e) => {
+            log::error!("Could not open a UDP socket for {target}: {e}");
+            return;
+        }
+    };
+
+    log::info!("Sending status to {target} over UDP!");
+
+    let mut ticker = tokio::time::interval(interval);
+    let mut last_sent = None;
+    let mut failing = false;
+
+    loop {
+        let tick = tokio::select! {
+            _ = ticker.tick() => true,
+            changed = status_rx.changed() => {
+                if changed.is_err() {
+                    return;
+                }
+                false
+            }
+        };
+
+        let status = CompactStatus::from(status_rx.borrow_and_update().active());
+        // Position changes don't count, receivers move it along themselves until the next tick.
+        let unchanged = last_sent.as_ref().is_some_and(|last: &CompactStatus| {
+            CompactStatus {
+                position: last.position,
+                ..status.clone()
+            } == *last
+        });
+        if unchanged && !tick {
+            continue;
+        }
+
+        let payload = case.to_string(&status);
+        match socket.send_to(payload.as_bytes(), target).await {
+            Ok(_) => failing = false,
+            // Only logged once, a display that's switched off shouldn't flood the log.
+            Err(e) if !failing => {
+                log::warn!("Could not send status to {target}: {e}");
+                failing = true;
+            }
+            Err(_) => {}
+        }
+
+        last_sent = Some(status);
+        ticker.reset();
+    }
+}
+
+impl Sink for UdpOptions {
+    fn name(&self) -> &'static str {
+        "udp"
+    }
+
+    fn run(&self, context: &SinkContext) -> BoxFuture<'static, ()> {
+        Box::pin(run(
+            self.target,
+            self.interval,
+            context.status_rx.clone(),
+            context.state.json_case,
+        ))
+    }
+}
@@ -0,0 +1,201 @@
+use crate::bar::Format;
+use crate::casing::FieldCase;
+use crate::sink::{Sink, SinkContext};
+use crate::status::{CompactStatus, PlayerStatus, StatusSnapshot};
+use futures_util::future::BoxFuture;
+use std::fmt;
+use std::time::Duration;
+use tokio::sync::watch;
+use tokio::task::JoinSet;
+use url::Url;
+
+const HTTP_TIMEOUT: Duration = Duration::from_secs(15);
+/// How often a change is tried before giving up on it.
+const MAX_ATTEMPTS: u32 = 5;
+/// The wait before the first retry, doubling for every one after.
+const FIRST_RETRY_DELAY: Duration = Duration::from_secs(1);
+
+/// What --webhook sends.
+#[derive(Debug, Clone, PartialEq)]
+pub struct WebhookOptions {
+    pub urls: Vec<Url>,
+    /// Sent instead of the status JSON, with the values escaped for JSON strings.
+    pub body: Option<Format>,
+}
+
+#[derive(Debug)]
+struct Error {
+    message: String,
+    /// Whether sending it again might work.
+    retryable: bool,
+}
+
+impl fmt::Display for Error {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(f, "{}", self.message)
+    }
+}
+
+/// Escapes a value for use inside a JSON string.
+fn escape_json(value: &str) -> String {
+    let quoted = serde_json::to_string(value).unwrap();
+    quoted[1..quoted.len() - 1].to_string()
+}
+
+fn body(status: &PlayerStatus, format: Option<&Format>, case: FieldCase) -> String {
+    match format {
+        Some(format) => format.render_escaped(&CompactStatus::from(Some(status)), escape_json),
+        None => case.to_string(status),
+    }
+}
+
+/// The part of a URL that's safe to log, webhook URLs often carry a token in their path.
+fn redacted(url: &Url) -> String {
+    url.origin().ascii_serialization()
+}
+
+/// Sends `body` to `url`. Blocks.
+fn post(agent: &ureq::Agent, url: &Url, body: &str) -> Result<(), Error> {
+    let mut response = agent
+        .post(url.as_str())
+        .header("Content-Type", "application/json")
+        .send(body)
+        .map_err(|e| Error {
+            message: e.to_string(),
+            retryable: true,
+        })?;
+
+    let status = response.status();
+    if status.is_success() {
+        return Ok(());
+    }
+
+    let text = response.body_mut().read_to_string().unwrap_or_default();
+    Err(Error {
+        message: format!("{status} {text}").trim_end().to_string(),
+        retryable: status.is_server_error() || [408, 429].contains(&status.as_u16()),
+    })
+}
+
+/// Sends every body to `url`, retrying with a growing delay. A newer body replaces one still being retried.
+async fn deliver(url: Url, mut body_rx: watch::Receiver<Option<String>>) {
+    let agent: ureq::Agent = ureq::Agent::config_builder()
+        .http_status_as_error(false)
+        .timeout_global(Some(HTTP_TIMEOUT))
+        .build()
+        .into();
+    let mut failing = false;
+
+    loop {
+        if body_rx.changed().await.is_err() {
+            return;
+        }
+        let Some(body) = body_rx.borrow_and_update().clone() else {
+            continue;
+        };
+
+        let mut delay = FIRST_RETRY_DELAY;
+        for attempt in 1..=MAX_ATTEMPTS {
+            let (agent, url_, body) = (agent.clone(), url.clone(), body.clone());
+            let result = tokio::task::spawn_blocking(move || post(&agent, &url_, &body))
+                .await
+                .unwrap_or_else(|e| {
+                    Err(Error {
+                        message: e.to_string(),
+                        retryable: false,
+                    })
+                });
+
+            match result {
+                Ok(()) => {
+                    if failing {
+                        log::info!("The webhook at {} works again.", redacted(&url));
+                        failing = false;
+                    }
+                    break;
+                }
+                Err(e) if e.retryable && attempt < MAX_ATTEMPTS => {
+                    log::debug!(
+                        "The webhook at {} failed, retrying in {} seconds: {e}",
+                        redacted(&url),
+                        delay.as_secs()
+                    );
+
+                    tokio::select! {
+                        () = tokio::time::sleep(delay) => delay *= 2,
+                        changed = body_rx.changed() => {
+                            if changed.is_err() {
+                                return;
+                            }
+                            // Sent next instead.
+                            body_rx.mark_changed();
+                            break;
+                        }
+                    }
+                }
+                Err(e) => {
+                    if !failing {
+                        log::warn!(
+                            "The webhook at {} failed after {attempt} attempt(s): {e}",
+                            redacted(&url)
+                        );
+                        failing = true;
+                    }
+                    break;
+                }
+            }
+        }
+    }
+}
+
+/// POSTs the active player's status to every webhook whenever the track, the player or the playback state changes.
+pub async fn run(
+    options: WebhookOptions,
+    mut status_rx: watch::Receiver<StatusSnapshot>,
+    case: FieldCase,
+) {
+    let (body_tx, _) = watch::channel(None);
+    // Dropped along with this task, which stops them.
+    let mut deliveries = JoinSet::new();
+    for url in &options.urls {
+        deliveries.spawn(deliver(url.clone(), body_tx.subscribe()));
+    }
+
+    let mut last = None;
+    loop {
+        let status = status_rx.borrow_and_update().active().cloned();
+        // Positions move all the time, they aren't a change worth a request.
+        let current = status.as_ref().map(|status| {
+            (
+                status.player.bus_name.clone(),
+                status.metadata.clone(),
+                status.playback_state.clone(),
+            )
+        });
+
+        if current != last {
+            if let Some(status) = &status {
+                body_tx.send_replace(Some(body(status, options.body.as_ref(), case)));
+            }
+            last = current;
+        }
+
+        if status_rx.changed().await.is_err() {
+            return;
+        }
+    }
+}
+
+impl Sink for WebhookOptions {
+    fn name(&self) -> &'static str {
+        "webhook"
+    }
+
+    fn run(&self, context: &SinkContext) -> BoxFuture<'static, ()> {
+        Box::pin(run(
+            self.clone(),
+            context.status_rx.clone(),
+            context.state.json_case,
+        ))
+    }
+}
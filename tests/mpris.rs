@@ -0,0 +1,964 @@
+//! Finding, reading and controlling players, against made-up ones on a private bus.
+
+mod support;
+
+use dbus::arg::{PropMap, RefArg};
+use dbus::blocking::stdintf::org_freedesktop_dbus::Properties;
+use mpris_nowplaying::bus::BusAddress;
+use mpris_nowplaying::config::{Config, Quirks};
+use mpris_nowplaying::control;
+use mpris_nowplaying::demand::Demand;
+use mpris_nowplaying::event::Event;
+use mpris_nowplaying::player::{self, Action, PlayerOptions};
+use mpris_nowplaying::status::{PlaybackState, StatusSnapshot};
+use std::process::{Command, Stdio};
+use std::sync::mpsc;
+use std::thread;
+use std::time::{Duration, Instant};
+use support::{wait_for, MockLogind, MockPlayer, MockState, TestBus, Track};
+use tokio::sync::{broadcast, watch};
+
+/// Watches the players on `bus` like the server does, checking often so the tests are quick.
+fn watch_players(
+    bus: &TestBus,
+    config: Config,
+) -> (watch::Receiver<StatusSnapshot>, broadcast::Receiver<Event>) {
+    let (status_rx, events_rx, _actions_tx) = watch_players_with_actions(bus, config);
+    (status_rx, events_rx)
+}
+
+/// Like [`watch_players`], taking actions like the admin page's.
+fn watch_players_with_actions(
+    bus: &TestBus,
+    config: Config,
+) -> (
+    watch::Receiver<StatusSnapshot>,
+    broadcast::Receiver<Event>,
+    mpsc::Sender<Action>,
+) {
+    let options = PlayerOptions {
+        bus: BusAddress::Custom(bus.address.clone()),
+        min_retry_time: 0.1,
+        max_retry_time: 0.1,
+        update_interval: Duration::from_millis(50),
+        idle_interval: Duration::from_millis(50),
+        config,
+        ..Default::default()
+    };
+
+    let (status_tx, status_rx) = watch::channel(StatusSnapshot::default());
+    let (events_tx, events_rx) = broadcast::channel(64);
+    let (actions_tx, actions_rx) = mpsc::channel();
+    thread::spawn(move || {
+        // Nothing is reloaded.
+        let (_options_tx, options_rx) = mpsc::channel();
+        player::run(
+            options,
+            options_rx,
+            actions_rx,
+            status_tx,
+            events_tx,
+            Demand::default(),
+        )
+    });
+
+    (status_rx, events_rx, actions_tx)
+}
+
+fn events(events_rx: &mut broadcast::Receiver<Event>) -> Vec<Event> {
+    std::iter::from_fn(|| events_rx.try_recv().ok()).collect()
+}
+
+fn has_title(title: &str) -> impl Fn(&StatusSnapshot) -> bool + '_ {
+    move |snapshot| {
+        snapshot
+            .active()
+            .is_some_and(|status| status.metadata.title == title)
+    }
+}
+
+#[test]
+fn reads_the_status() {
+    let Some(bus) = TestBus::start() else {
+        return;
+    };
+    let track = Track {
+        album: String::from("Coastline Static"),
+        url: String::from("file:///music/neon-tide.flac"),
+        ..Track::new(0, "Neon Tide", "The Midnight Arcade")
+    };
+    let _player = MockPlayer::start(
+        &bus,
+        "mock",
+        MockState {
+            position: 5_000_000,
+            ..MockState::playing("Mock Player", track)
+        },
+    );
+
+    let (status_rx, mut events_rx) = watch_players(&bus, Config::default());
+    let snapshot = wait_for(&status_rx, has_title("Neon Tide"));
+
+    let status = snapshot.active().unwrap();
+    assert_eq!(status.player.bus_name, "org.mpris.MediaPlayer2.mock");
+    assert_eq!(status.player.identity, "Mock Player");
+    assert_eq!(status.metadata.artist, "The Midnight Arcade");
+    assert_eq!(status.metadata.album, "Coastline Static");
+    assert_eq!(status.metadata.length, 180_000_000);
+    assert_eq!(status.metadata.url, "file:///music/neon-tide.flac");
+    assert_eq!(status.metadata.track_id, "/org/mpris/MediaPlayer2/mock/0");
+    assert_eq!(status.playback_state, PlaybackState::Playing);
+    assert_eq!(status.position, 5_000_000);
+    assert!(!status.degraded);
+
+    let events = events(&mut events_rx);
+    assert!(matches!(&events[0], Event::Attached { bus_name, identity }
+        if bus_name == "org.mpris.MediaPlayer2.mock" && identity == "Mock Player"));
+    assert!(
+        matches!(&events[1], Event::TrackChanged { metadata, .. } if metadata.title == "Neon Tide")
+    );
+}
+
+#[test]
+fn follows_changes() {
+    let Some(bus) = TestBus::start() else {
+        return;
+    };
+    let player = MockPlayer::start(
+        &bus,
+        "mock",
+        MockState::playing("Mock Player", Track::new(0, "First", "Someone")),
+    );
+
+    let (status_rx, mut events_rx) = watch_players(&bus, Config::default());
+    wait_for(&status_rx, has_title("First"));
+
+    player.update(|state| {
+        state.track = Track::new(1, "Second", "Someone Else");
+        state.playback_status = String::from("Paused");
+    });
+    let snapshot = wait_for(&status_rx, has_title("Second"));
+    let status = snapshot.active().unwrap();
+    assert_eq!(status.metadata.artist, "Someone Else");
+    assert_eq!(status.playback_state, PlaybackState::Paused);
+
+    let track_changes = events(&mut events_rx)
+        .into_iter()
+        .filter_map(|event| match event {
+            Event::TrackChanged { metadata, .. } => Some(metadata.title),
+            _ => None,
+        })
+        .collect::<Vec<_>>();
+    assert_eq!(track_changes, ["First", "Second"]);
+}
+
+#[test]
+fn unsignalled_seeks() {
+    let Some(bus) = TestBus::start() else {
+        return;
+    };
+    let mut state = MockState::playing("Mock Player", Track::new(0, "Neon Tide", "Someone"));
+    state.playback_status = String::from("Paused");
+    state.position = 10_000_000;
+    let player = MockPlayer::start(&bus, "mock", state);
+
+    let (status_rx, mut events_rx) = watch_players(&bus, Config::default());
+    wait_for(&status_rx, has_title("Neon Tide"));
+
+    // Without a Seeked signal, only the polled position tells.
+    player.update_quietly(|state| state.position = 90_000_000);
+    wait_for(&status_rx, |snapshot| {
+        snapshot
+            .active()
+            .is_some_and(|status| status.position == 90_000_000)
+    });
+
+    let seeks = events(&mut events_rx)
+        .into_iter()
+        .filter_map(|event| match event {
+            Event::Seeked { position, from, .. } => Some((from, position)),
+            _ => None,
+        })
+        .collect::<Vec<_>>();
+    assert_eq!(seeks, [(10_000_000, 90_000_000)]);
+}
+
+#[test]
+fn detaches_from_players_that_quit() {
+    let Some(bus) = TestBus::start() else {
+        return;
+    };
+    let player = MockPlayer::start(
+        &bus,
+        "mock",
+        MockState::playing("Mock Player", Track::new(0, "Neon Tide", "Someone")),
+    );
+
+    let (status_rx, mut events_rx) = watch_players(&bus, Config::default());
+    wait_for(&status_rx, has_title("Neon Tide"));
+
+    drop(player);
+    wait_for(&status_rx, |snapshot| snapshot.players.is_empty());
+    assert!(events(&mut events_rx).iter().any(|event| matches!(event,
+        Event::Detached { bus_name } if bus_name == "org.mpris.MediaPlayer2.mock")));
+}
+
+#[test]
+fn players_without_metadata() {
+    let Some(bus) = TestBus::start() else {
+        return;
+    };
+    let _player = MockPlayer::start(
+        &bus,
+        "bare",
+        MockState {
+            playback_status: String::from("Stopped"),
+            ..MockState::playing("Bare", Track::default())
+        },
+    );
+
+    let (status_rx, _events_rx) = watch_players(&bus, Config::default());
+    let snapshot = wait_for(&status_rx, |snapshot| snapshot.active().is_some());
+
+    let status = snapshot.active().unwrap();
+    assert_eq!(status.metadata.title, "");
+    assert_eq!(status.metadata.length, 0);
+    assert_eq!(status.metadata.artist, "");
+    assert_eq!(status.playback_state, PlaybackState::None);
+}
+
+#[test]
+fn positions_past_the_end_are_degraded() {
+    let Some(bus) = TestBus::start() else {
+        return;
+    };
+    let _player = MockPlayer::start(
+        &bus,
+        "mock",
+        MockState {
+            position: 400_000_000,
+            ..MockState::playing("Mock Player", Track::new(0, "Neon Tide", "Someone"))
+        },
+    );
+
+    let (status_rx, _events_rx) = watch_players(&bus, Config::default());
+    let snapshot = wait_for(&status_rx, has_title("Neon Tide"));
+
+    let status = snapshot.active().unwrap();
+    assert_eq!(status.position, 180_000_000);
+    assert!(status.degraded);
+}
+
+#[test]
+fn hung_players_keep_their_last_status() {
+    let Some(bus) = TestBus::start() else {
+        return;
+    };
+    let player = MockPlayer::start(
+        &bus,
+        "mock",
+        MockState::playing("Mock Player", Track::new(0, "Neon Tide", "Someone")),
+    );
+
+    let (status_rx, mut events_rx) = watch_players(&bus, Config::default());
+    wait_for(&status_rx, has_title("Neon Tide"));
+
+    player.update_quietly(|state| state.hung = true);
+    let snapshot = wait_for(&status_rx, |snapshot| {
+        snapshot.active().is_some_and(|status| status.stale)
+    });
+    assert_eq!(snapshot.active().unwrap().metadata.title, "Neon Tide");
+    assert!(!events(&mut events_rx)
+        .iter()
+        .any(|event| matches!(event, Event::Detached { .. })));
+
+    // Answering again is signalled, which has it read right away.
+    player.update(|state| {
+        state.hung = false;
+        state.track = Track::new(1, "Second", "Someone");
+    });
+    let snapshot = wait_for(&status_rx, has_title("Second"));
+    assert!(!snapshot.active().unwrap().stale);
+}
+
+#[test]
+fn partial_reads_keep_the_rest() {
+    let Some(bus) = TestBus::start() else {
+        return;
+    };
+    let player = MockPlayer::start(
+        &bus,
+        "mock",
+        MockState::playing("Mock Player", Track::new(0, "Neon Tide", "Someone")),
+    );
+
+    let (status_rx, mut events_rx) = watch_players(&bus, Config::default());
+    wait_for(&status_rx, has_title("Neon Tide"));
+
+    player.update(|state| {
+        state.failing = vec![String::from("Metadata")];
+        state.track = Track::new(1, "Second", "Someone");
+        state.playback_status = String::from("Paused");
+    });
+    let snapshot = wait_for(&status_rx, |snapshot| {
+        snapshot
+            .active()
+            .is_some_and(|status| status.playback_state == PlaybackState::Paused)
+    });
+    let status = snapshot.active().unwrap();
+    assert_eq!(status.metadata.title, "Neon Tide");
+    assert!(status.degraded);
+
+    // Nothing answered, but the player is still there.
+    player.update_quietly(|state| {
+        state.failing = ["PlaybackStatus", "Metadata", "Position"]
+            .map(String::from)
+            .into();
+    });
+    thread::sleep(Duration::from_millis(300));
+    assert_eq!(
+        status_rx.borrow().active().unwrap().metadata.title,
+        "Neon Tide"
+    );
+    assert!(!events(&mut events_rx)
+        .iter()
+        .any(|event| matches!(event, Event::Detached { .. })));
+
+    player.update(|state| state.failing.clear());
+    let snapshot = wait_for(&status_rx, has_title("Second"));
+    assert!(!snapshot.active().unwrap().degraded);
+}
+
+#[test]
+fn detached_players_are_left_alone() {
+    let Some(bus) = TestBus::start() else {
+        return;
+    };
+    let _first = MockPlayer::start(
+        &bus,
+        "first",
+        MockState::playing("First", Track::new(0, "Neon Tide", "Someone")),
+    );
+    let _second = MockPlayer::start(
+        &bus,
+        "second",
+        MockState {
+            playback_status: String::from("Paused"),
+            ..MockState::playing("Second", Track::new(1, "Second", "Someone"))
+        },
+    );
+
+    let (status_rx, _events_rx, actions_tx) = watch_players_with_actions(&bus, Config::default());
+    wait_for(&status_rx, has_title("Neon Tide"));
+
+    actions_tx
+        .send(Action::Detach(String::from("org.mpris.MediaPlayer2.first")))
+        .unwrap();
+    wait_for(&status_rx, has_title("Second"));
+    thread::sleep(Duration::from_millis(300));
+    assert_eq!(
+        status_rx.borrow().active().unwrap().metadata.title,
+        "Second"
+    );
+
+    actions_tx.send(Action::Rediscover).unwrap();
+    wait_for(&status_rx, has_title("Neon Tide"));
+}
+
+#[test]
+fn metadata_is_only_read_after_a_change() {
+    let Some(bus) = TestBus::start() else {
+        return;
+    };
+    let player = MockPlayer::start(
+        &bus,
+        "mock",
+        MockState::playing("Mock Player", Track::new(0, "First", "Someone")),
+    );
+
+    let (status_rx, _events_rx) = watch_players(&bus, Config::default());
+    wait_for(&status_rx, has_title("First"));
+    let reads = player.reads("Metadata");
+
+    // The position isn't signalled, but still read on every update.
+    player.update_quietly(|state| state.position = 60_000_000);
+    wait_for(&status_rx, |snapshot| {
+        snapshot
+            .active()
+            .is_some_and(|status| status.position == 60_000_000)
+    });
+    thread::sleep(Duration::from_millis(200));
+    assert_eq!(player.reads("Metadata"), reads);
+
+    player.update(|state| state.track = Track::new(1, "Second", "Someone"));
+    wait_for(&status_rx, has_title("Second"));
+    assert!(player.reads("Metadata") > reads);
+}
+
+#[test]
+fn unsignalled_metadata() {
+    let Some(bus) = TestBus::start() else {
+        return;
+    };
+    let player = MockPlayer::start(
+        &bus,
+        "quiet",
+        MockState::playing("Quiet", Track::new(0, "First", "Someone")),
+    );
+
+    let config = Config {
+        quirks: [(
+            String::from("quiet"),
+            Quirks {
+                unsignalled_metadata: true,
+                ..Default::default()
+            },
+        )]
+        .into(),
+        ..Default::default()
+    };
+    let (status_rx, _events_rx) = watch_players(&bus, config);
+    wait_for(&status_rx, has_title("First"));
+
+    player.update_quietly(|state| state.track = Track::new(1, "Second", "Someone"));
+    wait_for(&status_rx, has_title("Second"));
+}
+
+#[test]
+fn builtin_quirks() {
+    let Some(bus) = TestBus::start() else {
+        return;
+    };
+    // Spotify's position is always ignored.
+    let _player = MockPlayer::start(
+        &bus,
+        "spotify",
+        MockState {
+            position: 30_000_000,
+            ..MockState::playing("Spotify", Track::new(0, "Neon Tide", "Someone"))
+        },
+    );
+
+    let (status_rx, _events_rx) = watch_players(&bus, Config::default());
+    let snapshot = wait_for(&status_rx, has_title("Neon Tide"));
+    assert_eq!(snapshot.active().unwrap().position, 0);
+}
+
+#[test]
+fn configured_quirks() {
+    let Some(bus) = TestBus::start() else {
+        return;
+    };
+    let _player = MockPlayer::start(
+        &bus,
+        "buggy",
+        MockState::playing(
+            "Buggy",
+            Track {
+                length: 180_000,
+                ..Track::new(0, "Neon Tide", "Someone")
+            },
+        ),
+    );
+
+    let config = Config {
+        quirks: [(
+            String::from("buggy"),
+            Quirks {
+                length_in_millis: true,
+                ..Default::default()
+            },
+        )]
+        .into(),
+        ..Default::default()
+    };
+    let (status_rx, _events_rx) = watch_players(&bus, config);
+    let snapshot = wait_for(&status_rx, has_title("Neon Tide"));
+    assert_eq!(snapshot.active().unwrap().metadata.length, 180_000_000);
+}
+
+/// Runs the command on `bus`, returning what it printed.
+fn command(bus: &TestBus, args: &[&str]) -> String {
+    let output = Command::new(env!("CARGO_BIN_EXE_mpris-nowplaying"))
+        .args(["--dbus-address", &bus.address])
+        .args(args)
+        .output()
+        .unwrap();
+    assert!(output.status.success(), "{output:?}");
+
+    String::from_utf8(output.stdout).unwrap()
+}
+
+#[test]
+fn commands() {
+    let Some(bus) = TestBus::start() else {
+        return;
+    };
+    let player = MockPlayer::start(
+        &bus,
+        "mock",
+        MockState::playing("Mock Player", Track::new(0, "Neon Tide", "Someone")),
+    );
+
+    let status: serde_json::Value = serde_json::from_str(&command(&bus, &["status"])).unwrap();
+    assert_eq!(status["metadata"]["title"], "Neon Tide");
+
+    command(&bus, &["play-pause"]);
+    command(&bus, &["next"]);
+    assert_eq!(player.calls(), ["PlayPause", "Next"]);
+}
+
+#[test]
+fn seeking_and_volume() {
+    let Some(bus) = TestBus::start() else {
+        return;
+    };
+    let player = MockPlayer::start(
+        &bus,
+        "mock",
+        MockState::playing("Mock Player", Track::new(0, "Neon Tide", "Someone")),
+    );
+    let address = BusAddress::Custom(bus.address.clone());
+    let bus_name = "org.mpris.MediaPlayer2.mock";
+
+    control::set_position(
+        &address,
+        bus_name,
+        "/org/mpris/MediaPlayer2/mock/0",
+        60_000_000,
+        0,
+    )
+    .unwrap();
+    // Without a track id, it's a seek from where the player was.
+    control::set_position(&address, bus_name, "", 90_000_000, 60_000_000).unwrap();
+    assert_eq!(player.calls(), ["SetPosition", "Seek"]);
+    assert_eq!(player.position(), 90_000_000);
+
+    assert_eq!(control::volume(&address, bus_name).unwrap(), 1.0);
+    control::set_volume(&address, bus_name, 0.25).unwrap();
+    assert_eq!(control::volume(&address, bus_name).unwrap(), 0.25);
+}
+
+#[test]
+fn mpris_bridge() {
+    let Some(bus) = TestBus::start() else {
+        return;
+    };
+    let player = MockPlayer::start(
+        &bus,
+        "mock",
+        MockState::playing("Mock Player", Track::new(0, "Neon Tide", "Someone")),
+    );
+    let mut daemon = Command::new(env!("CARGO_BIN_EXE_mpris-nowplaying"))
+        .args(["--dbus-address", &bus.address])
+        .args(["--ip", "127.0.0.1:0", "--no-last-status", "--mpris-bridge"])
+        .stderr(Stdio::null())
+        .spawn()
+        .unwrap();
+
+    let address = BusAddress::Custom(bus.address.clone());
+    let bridge = "org.mpris.MediaPlayer2.nowplaying";
+    let title = || -> Option<String> {
+        let connection = address.connect().ok()?;
+        let proxy =
+            connection.with_proxy(bridge, "/org/mpris/MediaPlayer2", Duration::from_secs(1));
+        let metadata: PropMap = proxy
+            .get("org.mpris.MediaPlayer2.Player", "Metadata")
+            .ok()?;
+        metadata.get("xesam:title")?.as_str().map(String::from)
+    };
+    let started = Instant::now();
+    while title().as_deref() != Some("Neon Tide") && started.elapsed() < Duration::from_secs(10) {
+        thread::sleep(Duration::from_millis(50));
+    }
+    let shown = title();
+
+    // Passed on to the player without waiting for it.
+    control::call_player(&address, bridge, "Next").unwrap();
+    let started = Instant::now();
+    while player.calls().is_empty() && started.elapsed() < Duration::from_secs(10) {
+        thread::sleep(Duration::from_millis(50));
+    }
+
+    daemon.kill().unwrap();
+    daemon.wait().unwrap();
+    assert_eq!(shown.as_deref(), Some("Neon Tide"));
+    assert_eq!(player.calls(), ["Next"]);
+}
+
+#[test]
+fn skipped_tracks_are_not_confirmed() {
+    let Some(bus) = TestBus::start() else {
+        return;
+    };
+    let player = MockPlayer::start(
+        &bus,
+        "mock",
+        MockState::playing("Mock Player", Track::new(0, "Neon Tide", "Someone")),
+    );
+    let confirmed =
+        std::env::temp_dir().join(format!("mpris-nowplaying-confirmed-{}", std::process::id()));
+    let _ = std::fs::remove_file(&confirmed);
+    let hook = format!(
+        "track-confirmed=echo \"$MPRIS_NOWPLAYING_TITLE\" >> '{}'",
+        confirmed.display()
+    );
+    let mut daemon = Command::new(env!("CARGO_BIN_EXE_mpris-nowplaying"))
+        .args(["--dbus-address", &bus.address])
+        .args([
+            "--ip",
+            "127.0.0.1:0",
+            "--no-last-status",
+            "--interval",
+            "0.05",
+        ])
+        .args(["--confirm-after", "1", "--hook", &hook])
+        .stderr(Stdio::null())
+        .spawn()
+        .unwrap();
+
+    // Skipped long before it played for a second.
+    thread::sleep(Duration::from_millis(300));
+    player.update(|state| state.track = Track::new(1, "Glass Harbor", "Someone"));
+
+    let started = Instant::now();
+    while !confirmed.exists() && started.elapsed() < Duration::from_secs(10) {
+        thread::sleep(Duration::from_millis(50));
+    }
+    // Anything confirmed twice would show up by now.
+    thread::sleep(Duration::from_millis(300));
+
+    daemon.kill().unwrap();
+    daemon.wait().unwrap();
+    let lines = std::fs::read_to_string(&confirmed).unwrap_or_default();
+    let _ = std::fs::remove_file(&confirmed);
+    assert_eq!(lines, "Glass Harbor\n");
+}
+
+#[test]
+fn outputs_are_paused_while_the_session_is_locked() {
+    let Some(bus) = TestBus::start() else {
+        return;
+    };
+    let _player = MockPlayer::start(
+        &bus,
+        "mock",
+        MockState::playing("Mock Player", Track::new(0, "Neon Tide", "Someone")),
+    );
+    let logind = MockLogind::start(&bus, false);
+    let file = std::env::temp_dir().join(format!(
+        "mpris-nowplaying-locked-{}.json",
+        std::process::id()
+    ));
+    let _ = std::fs::remove_file(&file);
+    let mut daemon = Command::new(env!("CARGO_BIN_EXE_mpris-nowplaying"))
+        .env("DBUS_SYSTEM_BUS_ADDRESS", &bus.address)
+        .args(["--dbus-address", &bus.address])
+        .args([
+            "--ip",
+            "127.0.0.1:0",
+            "--no-last-status",
+            "--interval",
+            "0.05",
+        ])
+        .args(["--json-file", file.to_str().unwrap()])
+        .args(["--pause-when-locked", "json-file"])
+        .stderr(Stdio::null())
+        .spawn()
+        .unwrap();
+
+    let contents = |wanted: &dyn Fn(&str) -> bool| {
+        let started = Instant::now();
+        loop {
+            let contents = std::fs::read_to_string(&file).unwrap_or_default();
+            if wanted(&contents) || started.elapsed() > Duration::from_secs(10) {
+                return contents;
+            }
+            thread::sleep(Duration::from_millis(50));
+        }
+    };
+
+    let unlocked = contents(&|contents| contents.contains("Neon Tide"));
+    logind.set_locked(true);
+    let locked = contents(&|contents| !contents.contains("Neon Tide"));
+    logind.set_locked(false);
+    let resumed = contents(&|contents| contents.contains("Neon Tide"));
+
+    daemon.kill().unwrap();
+    daemon.wait().unwrap();
+    let _ = std::fs::remove_file(&file);
+    assert!(unlocked.contains("Neon Tide"), "{unlocked}");
+    assert!(!locked.contains("Neon Tide"), "{locked}");
+    assert!(resumed.contains("Neon Tide"), "{resumed}");
+}
+
+/// Answers `GET <path>` from the server on `port`, the body only.
+fn get(port: u16, path: &str) -> Option<String> {
+    send(port, "GET", path)
+}
+
+/// Answers `<method> <path>` from the server on `port`, the body only.
+fn send(port: u16, method: &str, path: &str) -> Option<String> {
+    request(
+        port,
+        &format!("{method} {path} HTTP/1.1\r\nHost: localhost"),
+    )
+    .and_then(|response| {
+        response
+            .split_once("\r\n\r\n")
+            .map(|(_, body)| body.to_string())
+    })
+}
+
+/// Sends a request with the method, path and headers in `head` to the server on `port`, answering the whole response.
+fn request(port: u16, head: &str) -> Option<String> {
+    use std::io::{Read, Write};
+
+    let mut stream = std::net::TcpStream::connect(("127.0.0.1", port)).ok()?;
+    write!(stream, "{head}\r\nConnection: close\r\n\r\n").ok()?;
+    let mut response = String::new();
+    stream.read_to_string(&mut response).ok()?;
+    Some(response)
+}
+
+#[test]
+fn pages_from_other_hosts_are_turned_away() {
+    let port = std::net::TcpListener::bind("127.0.0.1:0")
+        .unwrap()
+        .local_addr()
+        .unwrap()
+        .port();
+    let mut daemon = Command::new(env!("CARGO_BIN_EXE_mpris-nowplaying"))
+        .args(["--demo", "--no-last-status"])
+        .args(["--ip", &format!("127.0.0.1:{port}")])
+        .args(["--allowed-origins", "https://example.com"])
+        .stderr(Stdio::null())
+        .spawn()
+        .unwrap();
+
+    let started = Instant::now();
+    while get(port, "/status").is_none() && started.elapsed() < Duration::from_secs(10) {
+        thread::sleep(Duration::from_millis(50));
+    }
+    let status_from = |origin: &str, host: &str| {
+        request(
+            port,
+            &format!("GET /status HTTP/1.1\r\nHost: {host}\r\nOrigin: {origin}"),
+        )
+        .and_then(|response| response.split(' ').nth(1).map(str::to_string))
+        .unwrap_or_default()
+    };
+    // A site whose name was pointed at this machine sends a matching `Origin` and `Host`.
+    let rebound = status_from(
+        &format!("http://evil.example:{port}"),
+        &format!("evil.example:{port}"),
+    );
+    let own = status_from(
+        &format!("http://127.0.0.1:{port}"),
+        &format!("127.0.0.1:{port}"),
+    );
+    let allowed = status_from("https://example.com", &format!("127.0.0.1:{port}"));
+
+    daemon.kill().unwrap();
+    daemon.wait().unwrap();
+    assert_eq!(rebound, "403");
+    assert_eq!(own, "200");
+    assert_eq!(allowed, "200");
+}
+
+#[test]
+fn stale_status_is_kept_after_the_player_quits() {
+    let Some(bus) = TestBus::start() else {
+        return;
+    };
+    let player = MockPlayer::start(
+        &bus,
+        "mock",
+        MockState::playing("Mock Player", Track::new(0, "Neon Tide", "Someone")),
+    );
+    let port = std::net::TcpListener::bind("127.0.0.1:0")
+        .unwrap()
+        .local_addr()
+        .unwrap()
+        .port();
+    let mut daemon = Command::new(env!("CARGO_BIN_EXE_mpris-nowplaying"))
+        .args(["--dbus-address", &bus.address])
+        .args(["--ip", &format!("127.0.0.1:{port}")])
+        .args(["--no-last-status", "--interval", "0.05"])
+        .args(["--on-player-loss", "stale"])
+        .stderr(Stdio::null())
+        .spawn()
+        .unwrap();
+
+    let status = |wanted: &dyn Fn(&serde_json::Value) -> bool| {
+        let started = Instant::now();
+        loop {
+            let status = get(port, "/status")
+                .and_then(|body| serde_json::from_str(&body).ok())
+                .unwrap_or_default();
+            if wanted(&status) || started.elapsed() > Duration::from_secs(10) {
+                return status;
+            }
+            thread::sleep(Duration::from_millis(50));
+        }
+    };
+
+    let playing = status(&|status| status["metadata"]["title"] == "Neon Tide");
+    drop(player);
+    let lost = status(&|status| status["stale"] == true);
+
+    daemon.kill().unwrap();
+    daemon.wait().unwrap();
+    assert_eq!(playing["stale"], false);
+    assert_eq!(lost["metadata"]["title"], "Neon Tide");
+    assert_eq!(lost["playbackState"], "paused");
+}
+
+#[test]
+fn commands_the_player_does_not_allow_are_refused() {
+    let Some(bus) = TestBus::start() else {
+        return;
+    };
+    let mut state = MockState::playing("Mock Player", Track::new(0, "Neon Tide", "Someone"));
+    state.denied = vec![String::from("CanGoNext")];
+    let player = MockPlayer::start(&bus, "mock", state);
+    let port = std::net::TcpListener::bind("127.0.0.1:0")
+        .unwrap()
+        .local_addr()
+        .unwrap()
+        .port();
+    let mut daemon = Command::new(env!("CARGO_BIN_EXE_mpris-nowplaying"))
+        .args(["--dbus-address", &bus.address])
+        .args(["--ip", &format!("127.0.0.1:{port}")])
+        .args(["--no-last-status", "--interval", "0.05"])
+        .stderr(Stdio::null())
+        .spawn()
+        .unwrap();
+
+    let started = Instant::now();
+    let mut capabilities = serde_json::Value::Null;
+    while capabilities.is_null() && started.elapsed() < Duration::from_secs(10) {
+        thread::sleep(Duration::from_millis(50));
+        capabilities = get(port, "/remote/capabilities")
+            .and_then(|body| serde_json::from_str(&body).ok())
+            .filter(|body: &serde_json::Value| body.get("error").is_none())
+            .unwrap_or_default();
+    }
+    let next = send(port, "POST", "/streamdeck/next").unwrap_or_default();
+    let play_pause = send(port, "POST", "/streamdeck/playPause").unwrap_or_default();
+    // Refused for every caller, not only the server's.
+    let direct = control::call_player(
+        &BusAddress::Custom(bus.address.clone()),
+        "org.mpris.MediaPlayer2.mock",
+        "Next",
+    );
+
+    daemon.kill().unwrap();
+    daemon.wait().unwrap();
+    assert_eq!(capabilities["canGoNext"], false);
+    assert_eq!(capabilities["canPause"], true);
+    let next: serde_json::Value = serde_json::from_str(&next).unwrap();
+    assert_eq!(next["error"], "notPermitted");
+    assert_eq!(next["command"], "Next");
+    assert_eq!(play_pause, "");
+    assert!(
+        matches!(&direct, Err(control::ControlError::NotPermitted(command)) if command == "Next"),
+        "{direct:?}"
+    );
+    assert_eq!(player.calls(), ["PlayPause"]);
+}
+
+#[test]
+fn one_off_commands_the_player_does_not_allow_fail() {
+    let Some(bus) = TestBus::start() else {
+        return;
+    };
+    let mut state = MockState::playing("Mock Player", Track::new(0, "Neon Tide", "Someone"));
+    state.denied = vec![String::from("CanGoNext")];
+    let player = MockPlayer::start(&bus, "mock", state);
+
+    let refused = |args: &[&str]| {
+        let output = Command::new(env!("CARGO_BIN_EXE_mpris-nowplaying"))
+            .args(["--dbus-address", &bus.address])
+            .args(args)
+            .output()
+            .unwrap();
+        (
+            output.status.code(),
+            String::from_utf8(output.stderr).unwrap(),
+        )
+    };
+
+    let (next_code, next_error) = refused(&["next"]);
+    command(&bus, &["play-pause"]);
+    assert_eq!(next_code, Some(1));
+    assert!(
+        next_error.contains("Next isn't allowed by the player"),
+        "{next_error}"
+    );
+    assert_eq!(player.calls(), ["PlayPause"]);
+    drop(player);
+
+    // Stopping needs `CanControl`, like every other command.
+    let mut state = MockState::playing("Other Player", Track::new(0, "Neon Tide", "Someone"));
+    state.denied = vec![String::from("CanControl")];
+    let player = MockPlayer::start(&bus, "other", state);
+    let (stop_code, stop_error) = refused(&["stop"]);
+    assert_eq!(stop_code, Some(1));
+    assert!(
+        stop_error.contains("Stop isn't allowed by the player"),
+        "{stop_error}"
+    );
+    assert!(player.calls().is_empty());
+}
+
+#[test]
+fn redacted_metadata_is_never_served() {
+    let Some(bus) = TestBus::start() else {
+        return;
+    };
+    let mut track = Track::new(0, "Neon Tide", "Someone Private");
+    track.album = String::from("Demos 2019");
+    track.url = String::from("file:///home/someone/Music/Demos%202019/neon-tide.flac");
+    track.art_url = String::from("https://example.com/cover.jpg");
+    let _player = MockPlayer::start(&bus, "mock", MockState::playing("Mock Player", track));
+    let port = std::net::TcpListener::bind("127.0.0.1:0")
+        .unwrap()
+        .local_addr()
+        .unwrap()
+        .port();
+    let mut daemon = Command::new(env!("CARGO_BIN_EXE_mpris-nowplaying"))
+        .args(["--dbus-address", &bus.address])
+        .args(["--ip", &format!("127.0.0.1:{port}")])
+        .args(["--no-last-status", "--interval", "0.05"])
+        .args([
+            "--redact",
+            "album,local-files",
+            "--redact-pattern",
+            "(?i)private",
+        ])
+        .stderr(Stdio::null())
+        .spawn()
+        .unwrap();
+
+    let started = Instant::now();
+    let mut status = serde_json::Value::Null;
+    while status.is_null() && started.elapsed() < Duration::from_secs(10) {
+        thread::sleep(Duration::from_millis(50));
+        status = get(port, "/status")
+            .and_then(|body| serde_json::from_str(&body).ok())
+            .unwrap_or_default();
+    }
+
+    daemon.kill().unwrap();
+    daemon.wait().unwrap();
+    let metadata = &status["metadata"];
+    assert_eq!(metadata["title"], "Neon Tide");
+    assert_eq!(metadata["artist"], "Someone ***");
+    assert_eq!(metadata["album"], "");
+    assert!(metadata.get("url").is_none(), "{metadata}");
+    assert_eq!(
+        metadata["artwork"][0]["src"],
+        "https://example.com/cover.jpg"
+    );
+}
@@ -0,0 +1,494 @@
+//! A private D-Bus bus with made-up MPRIS players on it, so finding and reading players and controlling them can be tested without a real media player.
+
+use dbus::arg::{PropMap, RefArg, Variant};
+use dbus::blocking::stdintf::org_freedesktop_dbus::PropertiesPropertiesChanged;
+use dbus::blocking::Connection;
+use dbus::channel::{Channel, MatchingReceiver, Sender};
+use dbus::message::{MatchRule, SignalArgs};
+use dbus::Message;
+use mpris_nowplaying::status::StatusSnapshot;
+use std::ffi::CString;
+use std::io::{BufRead, BufReader};
+use std::process::{Child, Command, Stdio};
+use std::sync::atomic::{AtomicBool, Ordering};
+use std::sync::{Arc, Mutex};
+use std::thread::{self, JoinHandle};
+use std::time::{Duration, Instant};
+use tokio::sync::watch;
+
+const PATH: &str = "/org/mpris/MediaPlayer2";
+const ROOT_INTERFACE: &str = "org.mpris.MediaPlayer2";
+const PLAYER_INTERFACE: &str = "org.mpris.MediaPlayer2.Player";
+
+/// How long [`wait_for`] waits, generous for slow CI machines.
+const TIMEOUT: Duration = Duration::from_secs(10);
+
+/// A `dbus-daemon` of our own, stopped when dropped.
+pub struct TestBus {
+    daemon: Child,
+    pub address: String,
+}
+
+impl TestBus {
+    /// Starts a bus, or returns `None` when `dbus-daemon` isn't installed, so the tests are skipped instead of failing.
+    pub fn start() -> Option<Self> {
+        let mut daemon = match Command::new("dbus-daemon")
+            .args(["--session", "--nofork", "--nopidfile", "--print-address"])
+            .stdout(Stdio::piped())
+            .stderr(Stdio::null())
+            .spawn()
+        {
+            Ok(daemon) => daemon,
+            Err(e) => {
+                eprintln!("Skipped, could not start dbus-daemon: {e}");
+                return None;
+            }
+        };
+
+        let mut address = String::new();
+        BufReader::new(daemon.stdout.take().unwrap())
+            .read_line(&mut address)
+            .unwrap();
+
+        Some(Self {
+            daemon,
+            address: address.trim().to_string(),
+        })
+    }
+}
+
+impl Drop for TestBus {
+    fn drop(&mut self) {
+        let _ = self.daemon.kill();
+        let _ = self.daemon.wait();
+    }
+}
+
+/// The track a [`MockPlayer`] plays. Empty fields are left out of its metadata, like some players do.
+#[derive(Debug, Clone, Default)]
+pub struct Track {
+    pub id: String,
+    pub title: String,
+    pub artists: Vec<String>,
+    pub album: String,
+    /// In microseconds, or whatever the player thinks.
+    pub length: i64,
+    pub url: String,
+    pub art_url: String,
+}
+
+impl Track {
+    pub fn new(id: u32, title: &str, artist: &str) -> Self {
+        Self {
+            id: format!("/org/mpris/MediaPlayer2/mock/{id}"),
+            title: title.to_string(),
+            artists: vec![artist.to_string()],
+            length: 180_000_000,
+            ..Default::default()
+        }
+    }
+
+    fn metadata(&self) -> PropMap {
+        let mut metadata = PropMap::new();
+        let mut insert = |key: &str, value: Box<dyn RefArg>| {
+            metadata.insert(key.to_string(), Variant(value));
+        };
+
+        if !self.id.is_empty() {
+            insert("mpris:trackid", Box::new(dbus::Path::from(self.id.clone())));
+        }
+        if !self.title.is_empty() {
+            insert("xesam:title", Box::new(self.title.clone()));
+        }
+        if !self.artists.is_empty() {
+            insert("xesam:artist", Box::new(self.artists.clone()));
+        }
+        if !self.album.is_empty() {
+            insert("xesam:album", Box::new(self.album.clone()));
+        }
+        if self.length != 0 {
+            insert("mpris:length", Box::new(self.length));
+        }
+        if !self.url.is_empty() {
+            insert("xesam:url", Box::new(self.url.clone()));
+        }
+        if !self.art_url.is_empty() {
+            insert("mpris:artUrl", Box::new(self.art_url.clone()));
+        }
+
+        metadata
+    }
+}
+
+/// What a [`MockPlayer`] reports, and what was asked of it.
+#[derive(Debug, Clone)]
+pub struct MockState {
+    pub identity: String,
+    /// `Playing`, `Paused` or `Stopped`.
+    pub playback_status: String,
+    pub track: Track,
+    /// In microseconds.
+    pub position: i64,
+    /// From 0 to 1.
+    pub volume: f64,
+    /// The methods called on it, e.g. `PlayPause`, oldest first.
+    pub calls: Vec<String>,
+    /// The properties read from it one at a time, e.g. `Metadata`, oldest first.
+    pub reads: Vec<String>,
+    /// Leaves every method call unanswered, like a player that hung. Still sends signals.
+    pub hung: bool,
+    /// Properties that fail to be read one at a time, e.g. `Metadata`, like a player having a hiccup.
+    pub failing: Vec<String>,
+    /// Capabilities it says it doesn't have, e.g. `CanGoNext`. It still does what it's asked to.
+    pub denied: Vec<String>,
+}
+
+impl MockState {
+    pub fn playing(identity: &str, track: Track) -> Self {
+        Self {
+            identity: identity.to_string(),
+            playback_status: String::from("Playing"),
+            track,
+            position: 0,
+            volume: 1.0,
+            calls: Vec::new(),
+            reads: Vec::new(),
+            hung: false,
+            failing: Vec::new(),
+            denied: Vec::new(),
+        }
+    }
+
+    fn properties(&self, interface: &str) -> PropMap {
+        let mut properties = PropMap::new();
+        let mut insert = |key: &str, value: Box<dyn RefArg>| {
+            properties.insert(key.to_string(), Variant(value));
+        };
+
+        match interface {
+            ROOT_INTERFACE => {
+                insert("Identity", Box::new(self.identity.clone()));
+                insert("CanQuit", Box::new(false));
+                insert("CanRaise", Box::new(false));
+                insert("HasTrackList", Box::new(false));
+                insert("SupportedUriSchemes", Box::<Vec<String>>::default());
+                insert("SupportedMimeTypes", Box::<Vec<String>>::default());
+            }
+            PLAYER_INTERFACE => {
+                insert("PlaybackStatus", Box::new(self.playback_status.clone()));
+                insert("Metadata", Box::new(self.track.metadata()));
+                insert("Position", Box::new(self.position));
+                insert("Rate", Box::new(1.0));
+                insert("MinimumRate", Box::new(1.0));
+                insert("MaximumRate", Box::new(1.0));
+                insert("Volume", Box::new(self.volume));
+                for capability in [
+                    "CanGoNext",
+                    "CanGoPrevious",
+                    "CanPlay",
+                    "CanPause",
+                    "CanSeek",
+                    "CanControl",
+                ] {
+                    let allowed = !self.denied.iter().any(|denied| denied == capability);
+                    insert(capability, Box::new(allowed));
+                }
+            }
+            _ => {}
+        }
+
+        properties
+    }
+
+    /// Does what a player would, and notes it down.
+    fn call(&mut self, method: &str) {
+        self.calls.push(method.to_string());
+        match method {
+            "Play" => self.playback_status = String::from("Playing"),
+            "Pause" => self.playback_status = String::from("Paused"),
+            "Stop" => self.playback_status = String::from("Stopped"),
+            "PlayPause" if self.playback_status == "Playing" => {
+                self.playback_status = String::from("Paused");
+            }
+            "PlayPause" => self.playback_status = String::from("Playing"),
+            _ => {}
+        }
+    }
+}
+
+/// A made-up player on a [`TestBus`], answering on a thread of its own until dropped, which is like it quitting.
+pub struct MockPlayer {
+    state: Arc<Mutex<MockState>>,
+    changed: Arc<AtomicBool>,
+    stop: Arc<AtomicBool>,
+    thread: Option<JoinHandle<()>>,
+}
+
+impl MockPlayer {
+    /// Takes `org.mpris.MediaPlayer2.<name>` on `bus`.
+    pub fn start(bus: &TestBus, name: &str, state: MockState) -> Self {
+        let mut channel = Channel::open_private(&bus.address).unwrap();
+        channel.register().unwrap();
+        let connection = Connection::from(channel);
+        connection
+            .request_name(format!("org.mpris.MediaPlayer2.{name}"), false, true, true)
+            .unwrap();
+
+        let state = Arc::new(Mutex::new(state));
+        let changed = Arc::new(AtomicBool::new(false));
+        let stop = Arc::new(AtomicBool::new(false));
+
+        {
+            let (state, changed) = (state.clone(), changed.clone());
+            connection.start_receive(
+                MatchRule::new_method_call(),
+                Box::new(move |call, connection| {
+                    let mut state = state.lock().unwrap();
+                    if !state.hung {
+                        let _ = connection.send(answer(&call, &mut state, &changed));
+                    }
+                    true
+                }),
+            );
+        }
+
+        let thread = {
+            let (state, changed, stop) = (state.clone(), changed.clone(), stop.clone());
+            thread::spawn(move || {
+                while !stop.load(Ordering::Relaxed) {
+                    connection.process(Duration::from_millis(20)).unwrap();
+
+                    if changed.swap(false, Ordering::Relaxed) {
+                        let state = state.lock().unwrap();
+                        let signal = PropertiesPropertiesChanged {
+                            interface_name: PLAYER_INTERFACE.to_string(),
+                            changed_properties: state.properties(PLAYER_INTERFACE),
+                            invalidated_properties: Vec::new(),
+                        };
+                        let _ = connection.send(signal.to_emit_message(&PATH.into()));
+                    }
+                }
+            })
+        };
+
+        Self {
+            state,
+            changed,
+            stop,
+            thread: Some(thread),
+        }
+    }
+
+    /// Changes what the player reports, telling the bus like a player would.
+    pub fn update(&self, update: impl FnOnce(&mut MockState)) {
+        update(&mut self.state.lock().unwrap());
+        self.changed.store(true, Ordering::Relaxed);
+    }
+
+    /// Changes what the player reports without telling the bus, like players do with the position.
+    pub fn update_quietly(&self, update: impl FnOnce(&mut MockState)) {
+        update(&mut self.state.lock().unwrap());
+    }
+
+    /// The methods called on the player so far.
+    pub fn calls(&self) -> Vec<String> {
+        self.state.lock().unwrap().calls.clone()
+    }
+
+    /// Where the player is, in microseconds.
+    pub fn position(&self) -> i64 {
+        self.state.lock().unwrap().position
+    }
+
+    /// How many times `property` was read so far.
+    pub fn reads(&self, property: &str) -> usize {
+        let state = self.state.lock().unwrap();
+        state.reads.iter().filter(|read| *read == property).count()
+    }
+}
+
+impl Drop for MockPlayer {
+    fn drop(&mut self) {
+        self.stop.store(true, Ordering::Relaxed);
+        if let Some(thread) = self.thread.take() {
+            let _ = thread.join();
+        }
+    }
+}
+
+/// A made-up logind, telling whether the session is locked.
+pub struct MockLogind {
+    locked: Arc<AtomicBool>,
+    changed: Arc<AtomicBool>,
+    stop: Arc<AtomicBool>,
+    thread: Option<JoinHandle<()>>,
+}
+
+impl MockLogind {
+    const SESSION_PATH: &'static str = "/org/freedesktop/login1/session/_31";
+
+    /// Takes `org.freedesktop.login1` on `bus`, with a graphical session that's `locked` or not.
+    pub fn start(bus: &TestBus, locked: bool) -> Self {
+        let mut channel = Channel::open_private(&bus.address).unwrap();
+        channel.register().unwrap();
+        let connection = Connection::from(channel);
+        connection
+            .request_name("org.freedesktop.login1", false, true, true)
+            .unwrap();
+
+        let locked = Arc::new(AtomicBool::new(locked));
+        let changed = Arc::new(AtomicBool::new(false));
+        let stop = Arc::new(AtomicBool::new(false));
+
+        {
+            let locked = locked.clone();
+            connection.start_receive(
+                MatchRule::new_method_call(),
+                Box::new(move |call, connection| {
+                    let property = match call.read2::<&str, &str>() {
+                        Ok(("org.freedesktop.login1.User", "Display")) => Some(Box::new((
+                            String::from("1"),
+                            dbus::Path::from(Self::SESSION_PATH),
+                        ))
+                            as Box<dyn RefArg>),
+                        Ok(("org.freedesktop.login1.Session", "LockedHint")) => {
+                            Some(Box::new(locked.load(Ordering::Relaxed)) as Box<dyn RefArg>)
+                        }
+                        _ => None,
+                    };
+                    let _ = connection.send(match property {
+                        Some(value) => call.method_return().append1(Variant(value)),
+                        None => unknown(&call, "org.freedesktop.DBus.Error.UnknownProperty"),
+                    });
+                    true
+                }),
+            );
+        }
+
+        let thread = {
+            let (locked, changed, stop) = (locked.clone(), changed.clone(), stop.clone());
+            thread::spawn(move || {
+                while !stop.load(Ordering::Relaxed) {
+                    connection.process(Duration::from_millis(20)).unwrap();
+
+                    if changed.swap(false, Ordering::Relaxed) {
+                        let mut changed_properties = PropMap::new();
+                        changed_properties.insert(
+                            String::from("LockedHint"),
+                            Variant(Box::new(locked.load(Ordering::Relaxed))),
+                        );
+                        let signal = PropertiesPropertiesChanged {
+                            interface_name: String::from("org.freedesktop.login1.Session"),
+                            changed_properties,
+                            invalidated_properties: Vec::new(),
+                        };
+                        let _ = connection.send(signal.to_emit_message(&Self::SESSION_PATH.into()));
+                    }
+                }
+            })
+        };
+
+        Self {
+            locked,
+            changed,
+            stop,
+            thread: Some(thread),
+        }
+    }
+
+    /// Locks or unlocks the session, telling the bus like logind does.
+    pub fn set_locked(&self, locked: bool) {
+        self.locked.store(locked, Ordering::Relaxed);
+        self.changed.store(true, Ordering::Relaxed);
+    }
+}
+
+impl Drop for MockLogind {
+    fn drop(&mut self) {
+        self.stop.store(true, Ordering::Relaxed);
+        if let Some(thread) = self.thread.take() {
+            let _ = thread.join();
+        }
+    }
+}
+
+/// Answers a method call from `state`.
+fn answer(call: &Message, state: &mut MockState, changed: &AtomicBool) -> Message {
+    let interface = call.interface().map(|v| v.to_string()).unwrap_or_default();
+    let member = call.member().map(|v| v.to_string()).unwrap_or_default();
+
+    match (interface.as_str(), member.as_str()) {
+        ("org.freedesktop.DBus.Peer", "Ping") => call.method_return(),
+        ("org.freedesktop.DBus.Properties", "Get") => {
+            let (interface, name): (&str, &str) = call.read2().unwrap();
+            state.reads.push(name.to_string());
+            if state.failing.iter().any(|failing| failing == name) {
+                return unknown(call, "org.freedesktop.DBus.Error.Failed");
+            }
+            match state.properties(interface).remove(name) {
+                Some(value) => call.method_return().append1(value),
+                None => unknown(call, "org.freedesktop.DBus.Error.UnknownProperty"),
+            }
+        }
+        ("org.freedesktop.DBus.Properties", "GetAll") => {
+            let interface: &str = call.read1().unwrap();
+            call.method_return().append1(state.properties(interface))
+        }
+        ("org.freedesktop.DBus.Properties", "Set") => {
+            let (_, name, value): (&str, &str, Variant<f64>) = call.read3().unwrap();
+            match name {
+                "Volume" => {
+                    state.volume = value.0;
+                    changed.store(true, Ordering::Relaxed);
+                    call.method_return()
+                }
+                _ => unknown(call, "org.freedesktop.DBus.Error.PropertyReadOnly"),
+            }
+        }
+        (PLAYER_INTERFACE, "SetPosition") => {
+            let (track_id, position): (dbus::Path, i64) = call.read2().unwrap();
+            state.call("SetPosition");
+            if *track_id == *state.track.id {
+                state.position = position;
+            }
+            call.method_return()
+        }
+        (PLAYER_INTERFACE, "Seek") => {
+            let offset: i64 = call.read1().unwrap();
+            state.call("Seek");
+            state.position = (state.position + offset).max(0);
+            call.method_return()
+        }
+        (PLAYER_INTERFACE | ROOT_INTERFACE, method) => {
+            state.call(method);
+            changed.store(true, Ordering::Relaxed);
+            call.method_return()
+        }
+        _ => unknown(call, "org.freedesktop.DBus.Error.UnknownMethod"),
+    }
+}
+
+fn unknown(call: &Message, error: &str) -> Message {
+    call.error(
+        &error.into(),
+        &CString::new("not implemented by the mock player").unwrap(),
+    )
+}
+
+/// Waits until the statuses are as `wanted`, failing the test with the last ones after a while.
+pub fn wait_for(
+    status_rx: &watch::Receiver<StatusSnapshot>,
+    wanted: impl Fn(&StatusSnapshot) -> bool,
+) -> StatusSnapshot {
+    let started = Instant::now();
+    loop {
+        let snapshot = status_rx.borrow().clone();
+        if wanted(&snapshot) {
+            return snapshot;
+        }
+        if started.elapsed() > TIMEOUT {
+            panic!("Gave up waiting, the statuses are {snapshot:#?}");
+        }
+        thread::sleep(Duration::from_millis(20));
+    }
+}